@@ -0,0 +1,164 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use vulkano::{
+    buffer::BufferUsage,
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        CopyBufferInfo,
+    },
+    descriptor_set::WriteDescriptorSet,
+    sync::{self, GpuFuture},
+};
+use renderer::compute::{readback_buffer, storage_buffer_from_iter, ComputePipeline};
+use renderer::render_device::RenderDevice;
+use renderer::render_system::RenderSystem;
+
+/// Deliberately not a multiple of the shader's local size.
+const PARTICLE_COUNT: u32 = 1000;
+const STEPS: u32 = 120;
+const DELTA_TIME: f32 = 1.0 / 60.0;
+const GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
+const RESTITUTION: f32 = 0.8;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct Particle {
+    position: [f32; 4],
+    velocity: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct SimulationPushConstants {
+    gravity: [f32; 4],
+    delta_time: f32,
+    count: u32,
+    restitution: f32,
+}
+
+/// Runs a particle simulation on the GPU without a window, and checks it against the same
+/// simulation on the CPU. Exits with a panic on mismatch, so it can run in CI on lavapipe with
+/// `cargo run -p renderer --example compute_particles`.
+fn main() {
+    let render_system = RenderSystem::new_headless();
+    let render_device = RenderDevice::new_headless(&render_system);
+    let pipeline = ComputePipeline::from_glsl(
+        &render_device,
+        include_str!("compute_particles.comp"),
+    )
+    .unwrap();
+    println!("Local size: {:?}", pipeline.local_size);
+    for binding in &pipeline.bindings {
+        println!("Binding: {:?}", binding);
+    }
+
+    let initial = initial_particles();
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+    let mut builder = AutoCommandBufferBuilder::primary(
+        &command_buffer_allocator,
+        render_device.present_queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    let particles = storage_buffer_from_iter(
+        &render_device,
+        initial.iter().copied(),
+        BufferUsage {
+            transfer_src: true,
+            ..BufferUsage::empty()
+        },
+        &mut builder,
+    );
+    let readback = readback_buffer::<Particle>(&render_device, PARTICLE_COUNT as u64);
+    let descriptor_set = pipeline.descriptor_set(
+        &render_device,
+        0,
+        [WriteDescriptorSet::buffer(0, particles.clone())],
+    );
+
+    pipeline.bind(&mut builder, vec![descriptor_set]);
+    pipeline.push_constants(
+        &mut builder,
+        SimulationPushConstants {
+            gravity: GRAVITY.extend(0.0).to_array(),
+            delta_time: DELTA_TIME,
+            count: PARTICLE_COUNT,
+            restitution: RESTITUTION,
+        },
+    );
+    for _ in 0..STEPS {
+        pipeline.dispatch(&mut builder, [PARTICLE_COUNT, 1, 1]);
+    }
+    builder
+        .copy_buffer(CopyBufferInfo::buffers(particles, readback.clone()))
+        .unwrap();
+    let command_buffer = builder.build().unwrap();
+    sync::now(render_device.device.clone())
+        .then_execute(render_device.present_queue.clone(), command_buffer)
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    let expected: Vec<Particle> = initial
+        .iter()
+        .map(|&particle| (0..STEPS).fold(particle, |particle, _| step(particle)))
+        .collect();
+    let gpu = readback.read().unwrap();
+    // The GPU may fuse multiplies and adds, so allow for some rounding difference.
+    let mut max_error: f32 = 0.0;
+    for (i, (gpu, cpu)) in gpu.iter().zip(&expected).enumerate() {
+        for (a, b) in gpu.position.iter().zip(&cpu.position).take(3) {
+            let error = (a - b).abs() / b.abs().max(1.0);
+            assert!(
+                error < 1.0e-3,
+                "Particle {} differs: GPU {:?}, CPU {:?}",
+                i,
+                gpu,
+                cpu
+            );
+            max_error = max_error.max(error);
+        }
+    }
+    println!(
+        "{} particles over {} steps match the CPU, max relative error {:e}",
+        PARTICLE_COUNT, STEPS, max_error
+    );
+}
+
+/// Particles spread over a 10 by 10 area, 1 to 5 units up, with small random velocities.
+fn initial_particles() -> Vec<Particle> {
+    // A fixed linear congruential generator keeps runs reproducible.
+    let mut state: u32 = 12345;
+    let mut random = move || {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 8) as f32 / (1 << 24) as f32
+    };
+    (0..PARTICLE_COUNT)
+        .map(|_| Particle {
+            position: [
+                random() * 10.0 - 5.0,
+                random() * 4.0 + 1.0,
+                random() * 10.0 - 5.0,
+                1.0,
+            ],
+            velocity: [random() * 2.0 - 1.0, random() * 2.0, random() * 2.0 - 1.0, 0.0],
+        })
+        .collect()
+}
+
+/// One step of the simulation in `compute_particles.comp`.
+fn step(particle: Particle) -> Particle {
+    let mut velocity = Vec3::from_slice(&particle.velocity) + GRAVITY * DELTA_TIME;
+    let mut position = Vec3::from_slice(&particle.position) + velocity * DELTA_TIME;
+    if position.y < 0.0 {
+        position.y = -position.y;
+        velocity.y = -velocity.y * RESTITUTION;
+    }
+    Particle {
+        position: position.extend(particle.position[3]).to_array(),
+        velocity: velocity.extend(particle.velocity[3]).to_array(),
+    }
+}
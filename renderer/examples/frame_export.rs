@@ -0,0 +1,115 @@
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process::Command;
+use std::sync::Arc;
+
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, ClearColorImageInfo,
+        CommandBufferUsage, CopyImageToBufferInfo,
+    },
+    format::{ClearColorValue, Format},
+};
+use renderer::compute::readback_buffer;
+use renderer::frame_export::{FrameExporter, FrameImporter};
+use renderer::render_device::RenderDevice;
+use renderer::render_system::RenderSystem;
+
+const EXTENT: [u32; 2] = [64, 64];
+const FORMAT: Format = Format::R8G8B8A8_UNORM;
+const FRAME_COUNT: u32 = 3;
+const FRAMES: u32 = 60;
+
+/// Renders frames in this process and checks them in a second one, which imports the images
+/// and semaphores instead of copying the pixels over. Runs without a window, and exits with a
+/// panic on mismatch, so it can run in CI on lavapipe with
+/// `cargo run -p renderer --example frame_export`.
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("--consume") => consume(&args[2]),
+        _ => produce(),
+    }
+}
+
+/// Clears frame `k` to red `k`, and starts the consumer.
+fn produce() {
+    let socket_path = std::env::temp_dir().join(format!("frame_export_{}", std::process::id()));
+    let listener = UnixListener::bind(&socket_path).unwrap();
+    let mut consumer = Command::new(std::env::current_exe().unwrap())
+        .arg("--consume")
+        .arg(&socket_path)
+        .spawn()
+        .unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    std::fs::remove_file(&socket_path).unwrap();
+
+    let render_system = RenderSystem::new_headless();
+    let render_device = RenderDevice::new_headless(&render_system);
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+    let mut exporter =
+        FrameExporter::new(&render_device, stream, EXTENT, FORMAT, FRAME_COUNT).unwrap();
+    println!(
+        "Exported {} frames of {} bytes",
+        FRAME_COUNT, exporter.description.allocation_size
+    );
+
+    for k in 0..FRAMES {
+        let index = exporter.acquire().unwrap();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            render_device.present_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .clear_color_image(ClearColorImageInfo {
+                clear_value: ClearColorValue::Float([k as f32 / 255.0, 0.0, 0.0, 1.0]),
+                ..ClearColorImageInfo::image(exporter.frames[index].image.clone())
+            })
+            .unwrap();
+        let command_buffer = builder.build().unwrap();
+        exporter.present(&render_device, index, Arc::new(command_buffer)).unwrap();
+    }
+
+    drop(exporter);
+    assert!(consumer.wait().unwrap().success(), "The consumer failed");
+    println!("The consumer received {} frames", FRAMES);
+}
+
+/// Copies every frame to the CPU, checking it is the next one the producer cleared.
+fn consume(socket_path: &str) {
+    let stream = UnixStream::connect(socket_path).unwrap();
+    let render_system = RenderSystem::new_headless();
+    let render_device = RenderDevice::new_headless(&render_system);
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+    let mut importer = FrameImporter::new(&render_device, stream, FORMAT).unwrap();
+    let readback = readback_buffer::<[u8; 4]>(&render_device, (EXTENT[0] * EXTENT[1]) as u64);
+
+    for k in 0..FRAMES {
+        let index = importer.next_frame().unwrap();
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            render_device.present_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                importer.frames[index].image.clone(),
+                readback.clone(),
+            ))
+            .unwrap();
+        let command_buffer = builder.build().unwrap();
+        let fence = importer.release(&render_device, index, Arc::new(command_buffer)).unwrap();
+        fence.wait(None).unwrap();
+        let pixels = readback.read().unwrap();
+        assert!(
+            pixels.iter().all(|&pixel| pixel == [k as u8, 0, 0, 255]),
+            "Frame {} has pixel {:?}",
+            k,
+            pixels[0]
+        );
+    }
+}
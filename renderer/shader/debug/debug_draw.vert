@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 v_color;
+
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    mat4 inverse_view;
+    mat4 inverse_projection;
+    vec4 position;
+    vec2 viewport_size;
+    float time;
+} u_view;
+
+void main() {
+    gl_Position = u_view.view_projection * vec4(position, 1.0);
+    v_color = color;
+}
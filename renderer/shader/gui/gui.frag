@@ -0,0 +1,35 @@
+#version 450
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 1) in vec4 v_color;
+
+layout(location = 0) out vec4 f_color;
+
+// sRGB textures, so samples are already linear.
+layout(set = 0, binding = 0) uniform sampler2D u_texture;
+
+layout(push_constant) uniform GuiPushConstants {
+    vec2 screen_size;
+    uint srgb_output;
+} pc;
+
+vec3 linear_from_srgb(vec3 srgb) {
+    vec3 lower = srgb / 12.92;
+    vec3 higher = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(higher, lower, lessThan(srgb, vec3(0.04045)));
+}
+
+vec3 srgb_from_linear(vec3 linear) {
+    vec3 lower = linear * 12.92;
+    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(higher, lower, lessThan(linear, vec3(0.0031308)));
+}
+
+void main() {
+    vec4 color = vec4(linear_from_srgb(v_color.rgb), v_color.a) * texture(u_texture, v_tex_coords);
+    // A UNORM target stores what we write, so encode to sRGB ourselves.
+    if (pc.srgb_output == 0) {
+        color.rgb = srgb_from_linear(color.rgb);
+    }
+    f_color = color;
+}
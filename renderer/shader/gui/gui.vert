@@ -0,0 +1,21 @@
+#version 450
+
+// egui vertices: positions in points from the top left, premultiplied sRGB colors.
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coords;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 v_tex_coords;
+layout(location = 1) out vec4 v_color;
+
+layout(push_constant) uniform GuiPushConstants {
+    vec2 screen_size;
+    uint srgb_output;
+} pc;
+
+void main() {
+    gl_Position = vec4(position / pc.screen_size * 2.0 - 1.0, 0.0, 1.0);
+    v_tex_coords = tex_coords;
+    v_color = color;
+}
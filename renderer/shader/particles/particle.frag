@@ -0,0 +1,50 @@
+#version 450
+
+layout(location = 0) in vec4 v_color;
+layout(location = 1) in vec2 v_corner;
+layout(location = 2) in float v_view_depth;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    mat4 inverse_view;
+    mat4 inverse_projection;
+    vec4 position;
+    vec2 viewport_size;
+    float time;
+} u_view;
+
+layout(set = 1, binding = 2) uniform Appearance {
+    vec4 colors[16];
+    vec4 sizes[4];
+    float softness;
+} u_appearance;
+
+// texelFetch reads sample 0 of a multisampled depth, and mip 0 otherwise.
+#ifdef MULTISAMPLED_DEPTH
+layout(set = 1, binding = 3) uniform sampler2DMS scene_depth;
+#else
+layout(set = 1, binding = 3) uniform sampler2D scene_depth;
+#endif
+
+void main() {
+    float falloff = 1.0 - dot(v_corner, v_corner);
+    if (falloff <= 0.0) {
+        discard;
+    }
+
+    // Distance to the scene behind the particle, in view space. Works for any projection,
+    // including reversed depth.
+    float depth = texelFetch(scene_depth, ivec2(gl_FragCoord.xy), 0).r;
+    vec2 ndc = gl_FragCoord.xy / u_view.viewport_size * 2.0 - 1.0;
+    vec4 scene_position = u_view.inverse_projection * vec4(ndc, depth, 1.0);
+    float scene_view_depth = -scene_position.z / scene_position.w;
+
+    // Fading out towards the scene also hides particles behind it.
+    float softness = max(u_appearance.softness, 1.0e-4);
+    float fade = clamp((scene_view_depth - v_view_depth) / softness, 0.0, 1.0);
+    f_color = vec4(v_color.rgb, v_color.a * falloff * fade);
+}
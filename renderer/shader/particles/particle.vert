@@ -0,0 +1,66 @@
+#version 450
+
+struct Particle {
+    vec4 position;
+    vec4 velocity;
+};
+
+layout(location = 0) out vec4 v_color;
+layout(location = 1) out vec2 v_corner;
+layout(location = 2) out float v_view_depth;
+
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    mat4 inverse_view;
+    mat4 inverse_projection;
+    vec4 position;
+    vec2 viewport_size;
+    float time;
+} u_view;
+
+layout(set = 1, binding = 0) readonly buffer Particles {
+    Particle particles[];
+};
+
+layout(set = 1, binding = 1) readonly buffer Alive {
+    uint alive[];
+};
+
+layout(set = 1, binding = 2) uniform Appearance {
+    // 16 samples of the color over the normalized age.
+    vec4 colors[16];
+    // 16 samples of the size over the normalized age.
+    vec4 sizes[4];
+    float softness;
+} u_appearance;
+
+vec4 sample_color(float x) {
+    uint i = min(uint(x), 14u);
+    return mix(u_appearance.colors[i], u_appearance.colors[i + 1u], x - float(i));
+}
+
+float sample_size(float x) {
+    uint i = min(uint(x), 14u);
+    float a = u_appearance.sizes[i / 4u][i % 4u];
+    float b = u_appearance.sizes[(i + 1u) / 4u][(i + 1u) % 4u];
+    return mix(a, b, x - float(i));
+}
+
+void main() {
+    Particle particle = particles[alive[gl_InstanceIndex]];
+    float x = clamp(particle.position.w / particle.velocity.w, 0.0, 1.0) * 15.0;
+
+    // A camera facing quad, drawn as a triangle strip.
+    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1) * 2.0 - 1.0;
+    vec3 right = u_view.inverse_view[0].xyz;
+    vec3 up = u_view.inverse_view[1].xyz;
+    vec3 offset = (right * corner.x + up * corner.y) * sample_size(x) * 0.5;
+    vec4 world_position = vec4(particle.position.xyz + offset, 1.0);
+
+    gl_Position = u_view.view_projection * world_position;
+    v_color = sample_color(x);
+    v_corner = corner;
+    v_view_depth = -(u_view.view * world_position).z;
+}
@@ -0,0 +1,238 @@
+#version 450
+
+// Forward metallic-roughness shading, following the glTF 2.0 BRDF: GGX distribution,
+// height-correlated Smith visibility and Schlick Fresnel, plus image-based ambient light from
+// an equirectangular environment map.
+
+layout(location = 0) in vec3 v_world_position;
+layout(location = 1) in vec3 v_normal;
+layout(location = 2) in vec2 v_uv;
+layout(location = 3) in vec4 v_tangent;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    mat4 inverse_view;
+    mat4 inverse_projection;
+    vec4 position;
+    vec2 viewport_size;
+    float time;
+} u_view;
+
+const uint LIGHT_DIRECTIONAL = 0;
+const uint LIGHT_POINT = 1;
+const uint LIGHT_SPOT = 2;
+
+struct Light {
+    // xyz: world position, w: range (0 means unlimited).
+    vec4 position_range;
+    // xyz: direction the light travels in, w: light type.
+    vec4 direction_type;
+    // rgb: linear color, a: intensity.
+    vec4 color_intensity;
+    // x: cosine of the inner cone angle, y: cosine of the outer cone angle.
+    vec4 spot_cone;
+};
+
+layout(set = 1, binding = 0) uniform LightingUniform {
+    uint light_count;
+    float environment_intensity;
+} u_lighting;
+
+layout(std430, set = 1, binding = 1) readonly buffer LightBuffer {
+    Light lights[];
+} b_lights;
+
+layout(set = 1, binding = 2) uniform sampler2D u_environment;
+
+const uint MAX_CASCADES = 4;
+
+layout(set = 1, binding = 3) uniform ShadowUniform {
+    mat4 cascade_view_projection[MAX_CASCADES];
+    // View-space distance at which each cascade ends.
+    vec4 cascade_splits;
+    // World-space size of a shadow map texel in each cascade.
+    vec4 cascade_texel_sizes;
+    uint cascade_count;
+    // Index of the light casting shadows, or -1.
+    int light_index;
+    int pcf_radius;
+    float depth_bias;
+    float normal_bias;
+} u_shadow;
+
+layout(set = 1, binding = 4) uniform sampler2DArrayShadow u_shadow_map;
+
+layout(set = 2, binding = 0) uniform MaterialUniform {
+    vec4 base_color_factor;
+    vec4 emissive_factor;
+    float metallic_factor;
+    float roughness_factor;
+    float normal_scale;
+    float occlusion_strength;
+    float alpha_cutoff;
+    uint alpha_mask;
+} u_material;
+
+layout(set = 2, binding = 1) uniform sampler2D u_base_color;
+layout(set = 2, binding = 2) uniform sampler2D u_metallic_roughness;
+layout(set = 2, binding = 3) uniform sampler2D u_normal;
+layout(set = 2, binding = 4) uniform sampler2D u_occlusion;
+layout(set = 2, binding = 5) uniform sampler2D u_emissive;
+
+const float PI = 3.14159265359;
+
+float distribution_ggx(float n_dot_h, float alpha) {
+    float a2 = alpha * alpha;
+    float f = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * f * f);
+}
+
+float visibility_smith_ggx(float n_dot_v, float n_dot_l, float alpha) {
+    float a2 = alpha * alpha;
+    float ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    float ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    float ggx = ggx_v + ggx_l;
+    return ggx > 0.0 ? 0.5 / ggx : 0.0;
+}
+
+vec3 fresnel_schlick(vec3 f0, float v_dot_h) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - v_dot_h, 0.0, 1.0), 5.0);
+}
+
+// Analytic approximation of the split-sum environment BRDF (Karis, "Physically Based Shading
+// on Mobile").
+vec3 environment_brdf(vec3 f0, float roughness, float n_dot_v) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    vec2 ab = vec2(-1.04, 1.04) * a004 + r.zw;
+    return f0 * ab.x + ab.y;
+}
+
+vec2 equirectangular_uv(vec3 direction) {
+    return vec2(atan(direction.z, direction.x) / (2.0 * PI) + 0.5, acos(clamp(direction.y, -1.0, 1.0)) / PI);
+}
+
+// KHR_lights_punctual range attenuation.
+float range_attenuation(float range, float distance) {
+    if (range <= 0.0) {
+        return 1.0 / (distance * distance);
+    }
+    return clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0) / (distance * distance);
+}
+
+// Fraction of light reaching `world_position`, filtered over a (2 * pcf_radius + 1)^2 texel
+// kernel of hardware-compared taps.
+float shadow_factor(vec3 world_position, vec3 geometric_normal) {
+    float view_depth = -(u_view.view * vec4(world_position, 1.0)).z;
+    uint cascade = 0;
+    while (cascade < u_shadow.cascade_count && view_depth > u_shadow.cascade_splits[cascade]) {
+        cascade++;
+    }
+    if (cascade == u_shadow.cascade_count) {
+        return 1.0;
+    }
+
+    vec3 offset_position = world_position + geometric_normal * u_shadow.normal_bias * u_shadow.cascade_texel_sizes[cascade];
+    vec4 shadow_position = u_shadow.cascade_view_projection[cascade] * vec4(offset_position, 1.0);
+    vec3 coords = shadow_position.xyz / shadow_position.w;
+    vec2 uv = coords.xy * 0.5 + 0.5;
+    float reference = coords.z - u_shadow.depth_bias;
+    if (reference >= 1.0) {
+        return 1.0;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(u_shadow_map, 0).xy);
+    float lit = 0.0;
+    for (int y = -u_shadow.pcf_radius; y <= u_shadow.pcf_radius; y++) {
+        for (int x = -u_shadow.pcf_radius; x <= u_shadow.pcf_radius; x++) {
+            lit += texture(u_shadow_map, vec4(uv + vec2(x, y) * texel, float(cascade), reference));
+        }
+    }
+    float taps = float(2 * u_shadow.pcf_radius + 1);
+    return lit / (taps * taps);
+}
+
+void main() {
+    vec4 base_color = u_material.base_color_factor * texture(u_base_color, v_uv);
+    if (u_material.alpha_mask != 0 && base_color.a < u_material.alpha_cutoff) {
+        discard;
+    }
+
+    vec4 metallic_roughness = texture(u_metallic_roughness, v_uv);
+    float metallic = clamp(u_material.metallic_factor * metallic_roughness.b, 0.0, 1.0);
+    float roughness = clamp(u_material.roughness_factor * metallic_roughness.g, 0.04, 1.0);
+    float alpha = roughness * roughness;
+
+    vec3 n = normalize(v_normal);
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    vec3 geometric_normal = n;
+    vec3 t = normalize(v_tangent.xyz - n * dot(n, v_tangent.xyz));
+    vec3 b = cross(n, t) * v_tangent.w;
+    vec3 tangent_normal = texture(u_normal, v_uv).xyz * 2.0 - 1.0;
+    tangent_normal.xy *= u_material.normal_scale;
+    n = normalize(mat3(t, b, n) * tangent_normal);
+
+    vec3 v = normalize(u_view.position.xyz - v_world_position);
+    float n_dot_v = max(dot(n, v), 1e-4);
+
+    vec3 diffuse_color = base_color.rgb * (1.0 - metallic);
+    vec3 f0 = mix(vec3(0.04), base_color.rgb, metallic);
+
+    vec3 color = vec3(0.0);
+    for (uint i = 0; i < u_lighting.light_count; i++) {
+        Light light = b_lights.lights[i];
+        uint type = uint(light.direction_type.w);
+        vec3 l;
+        float attenuation = 1.0;
+        if (type == LIGHT_DIRECTIONAL) {
+            l = -normalize(light.direction_type.xyz);
+        } else {
+            vec3 to_light = light.position_range.xyz - v_world_position;
+            float distance = length(to_light);
+            l = to_light / distance;
+            attenuation = range_attenuation(light.position_range.w, distance);
+            if (type == LIGHT_SPOT) {
+                float cos_angle = dot(normalize(light.direction_type.xyz), -l);
+                attenuation *= smoothstep(light.spot_cone.y, light.spot_cone.x, cos_angle);
+            }
+        }
+        float n_dot_l = dot(n, l);
+        if (n_dot_l <= 0.0 || attenuation <= 0.0) {
+            continue;
+        }
+        vec3 h = normalize(l + v);
+        float n_dot_h = max(dot(n, h), 0.0);
+        float v_dot_h = max(dot(v, h), 0.0);
+
+        vec3 f = fresnel_schlick(f0, v_dot_h);
+        vec3 diffuse = (1.0 - f) * diffuse_color / PI;
+        vec3 specular = f * distribution_ggx(n_dot_h, alpha) * visibility_smith_ggx(n_dot_v, n_dot_l, alpha);
+        if (int(i) == u_shadow.light_index) {
+            attenuation *= shadow_factor(v_world_position, geometric_normal);
+        }
+        vec3 radiance = light.color_intensity.rgb * light.color_intensity.a * attenuation;
+        color += (diffuse + specular) * radiance * n_dot_l;
+    }
+
+    // Image-based ambient light: the blurriest mips stand in for the irradiance and the
+    // prefiltered radiance of the environment.
+    float max_lod = float(textureQueryLevels(u_environment) - 1);
+    vec3 irradiance = textureLod(u_environment, equirectangular_uv(n), max_lod).rgb;
+    vec3 r = reflect(-v, n);
+    vec3 prefiltered = textureLod(u_environment, equirectangular_uv(r), roughness * max_lod).rgb;
+    vec3 ambient = irradiance * diffuse_color + prefiltered * environment_brdf(f0, roughness, n_dot_v);
+    float occlusion = mix(1.0, texture(u_occlusion, v_uv).r, u_material.occlusion_strength);
+    color += ambient * u_lighting.environment_intensity * occlusion;
+
+    color += u_material.emissive_factor.rgb * texture(u_emissive, v_uv).rgb;
+
+    f_color = vec4(color, base_color.a);
+}
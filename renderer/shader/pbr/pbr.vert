@@ -0,0 +1,42 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in vec4 tangent;
+
+// Per instance. The normal matrix is the inverse transpose of the model matrix.
+layout(location = 4) in vec4 model_x;
+layout(location = 5) in vec4 model_y;
+layout(location = 6) in vec4 model_z;
+layout(location = 7) in vec4 model_w;
+layout(location = 8) in vec4 normal_x;
+layout(location = 9) in vec4 normal_y;
+layout(location = 10) in vec4 normal_z;
+
+layout(location = 0) out vec3 v_world_position;
+layout(location = 1) out vec3 v_normal;
+layout(location = 2) out vec2 v_uv;
+layout(location = 3) out vec4 v_tangent;
+
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    mat4 inverse_view;
+    mat4 inverse_projection;
+    vec4 position;
+    vec2 viewport_size;
+    float time;
+} u_view;
+
+void main() {
+    mat4 model = mat4(model_x, model_y, model_z, model_w);
+    mat3 normal_matrix = mat3(normal_x.xyz, normal_y.xyz, normal_z.xyz);
+    vec4 world_position = model * vec4(position, 1.0);
+    v_world_position = world_position.xyz;
+    v_normal = normalize(normal_matrix * normal);
+    v_tangent = vec4(normalize(mat3(model) * tangent.xyz), tangent.w);
+    v_uv = uv;
+    gl_Position = u_view.view_projection * world_position;
+}
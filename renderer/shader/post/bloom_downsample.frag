@@ -0,0 +1,52 @@
+#version 450
+
+// 13-tap downsample from "Next Generation Post Processing in Call of Duty: Advanced Warfare".
+// The first pass also applies a soft brightness threshold.
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D u_source;
+
+layout(push_constant) uniform BloomDownsamplePushConstants {
+    vec2 source_texel_size;
+    float threshold;
+    float knee;
+    uint prefilter;
+} pc;
+
+vec3 prefilter(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - pc.threshold + pc.knee, 0.0, 2.0 * pc.knee);
+    soft = soft * soft / (4.0 * pc.knee + 1e-4);
+    float contribution = max(soft, brightness - pc.threshold) / max(brightness, 1e-4);
+    return color * contribution;
+}
+
+void main() {
+    vec2 t = pc.source_texel_size;
+    vec3 a = texture(u_source, v_uv + t * vec2(-2.0, -2.0)).rgb;
+    vec3 b = texture(u_source, v_uv + t * vec2(0.0, -2.0)).rgb;
+    vec3 c = texture(u_source, v_uv + t * vec2(2.0, -2.0)).rgb;
+    vec3 d = texture(u_source, v_uv + t * vec2(-2.0, 0.0)).rgb;
+    vec3 e = texture(u_source, v_uv).rgb;
+    vec3 f = texture(u_source, v_uv + t * vec2(2.0, 0.0)).rgb;
+    vec3 g = texture(u_source, v_uv + t * vec2(-2.0, 2.0)).rgb;
+    vec3 h = texture(u_source, v_uv + t * vec2(0.0, 2.0)).rgb;
+    vec3 i = texture(u_source, v_uv + t * vec2(2.0, 2.0)).rgb;
+    vec3 j = texture(u_source, v_uv + t * vec2(-1.0, -1.0)).rgb;
+    vec3 k = texture(u_source, v_uv + t * vec2(1.0, -1.0)).rgb;
+    vec3 l = texture(u_source, v_uv + t * vec2(-1.0, 1.0)).rgb;
+    vec3 m = texture(u_source, v_uv + t * vec2(1.0, 1.0)).rgb;
+
+    vec3 color = e * 0.125;
+    color += (a + c + g + i) * 0.03125;
+    color += (b + d + f + h) * 0.0625;
+    color += (j + k + l + m) * 0.125;
+
+    if (pc.prefilter != 0) {
+        color = prefilter(color);
+    }
+    f_color = vec4(max(color, vec3(0.0)), 1.0);
+}
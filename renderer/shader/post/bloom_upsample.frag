@@ -0,0 +1,28 @@
+#version 450
+
+// 3x3 tent filter upsample. The result is added onto the next larger mip by blending.
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D u_source;
+
+layout(push_constant) uniform BloomUpsamplePushConstants {
+    vec2 source_texel_size;
+    float radius;
+} pc;
+
+void main() {
+    vec2 t = pc.source_texel_size * pc.radius;
+    vec3 color = texture(u_source, v_uv).rgb * 4.0;
+    color += texture(u_source, v_uv + t * vec2(-1.0, 0.0)).rgb * 2.0;
+    color += texture(u_source, v_uv + t * vec2(1.0, 0.0)).rgb * 2.0;
+    color += texture(u_source, v_uv + t * vec2(0.0, -1.0)).rgb * 2.0;
+    color += texture(u_source, v_uv + t * vec2(0.0, 1.0)).rgb * 2.0;
+    color += texture(u_source, v_uv + t * vec2(-1.0, -1.0)).rgb;
+    color += texture(u_source, v_uv + t * vec2(1.0, -1.0)).rgb;
+    color += texture(u_source, v_uv + t * vec2(-1.0, 1.0)).rgb;
+    color += texture(u_source, v_uv + t * vec2(1.0, 1.0)).rgb;
+    f_color = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,116 @@
+#version 450
+
+// Brings the HDR scene to display range: bloom, exposure, tonemapping and color grading.
+// Writes linear color; the sRGB encoding is left to the attachment format unless
+// `ENCODE_SRGB` is set.
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D u_hdr;
+layout(set = 0, binding = 1) uniform sampler2D u_bloom;
+layout(set = 0, binding = 2) uniform sampler3D u_lut;
+
+const uint TONEMAPPER_NONE = 0;
+const uint TONEMAPPER_ACES = 1;
+const uint TONEMAPPER_AGX = 2;
+
+const uint BLOOM = 1;
+const uint COLOR_GRADING = 2;
+const uint ENCODE_SRGB = 4;
+
+layout(push_constant) uniform CompositePushConstants {
+    float exposure;
+    float bloom_intensity;
+    uint tonemapper;
+    uint flags;
+} pc;
+
+// ACES fit by Stephen Hill, including the sRGB <-> ACEScg conversions.
+vec3 tonemap_aces(vec3 color) {
+    const mat3 input_matrix = mat3(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777
+    );
+    const mat3 output_matrix = mat3(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602
+    );
+    color = input_matrix * color;
+    vec3 a = color * (color + 0.0245786) - 0.000090537;
+    vec3 b = color * (0.983729 * color + 0.4329510) + 0.238081;
+    color = output_matrix * (a / b);
+    return clamp(color, 0.0, 1.0);
+}
+
+// Minimal AgX by Benjamin Wrensch, with the default look.
+vec3 agx_contrast(vec3 x) {
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+vec3 tonemap_agx(vec3 color) {
+    const mat3 inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104
+    );
+    const mat3 outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+    );
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+    color = inset * max(color, vec3(1e-10));
+    color = clamp((log2(color) - min_ev) / (max_ev - min_ev), 0.0, 1.0);
+    color = agx_contrast(color);
+    color = outset * color;
+    // The curve outputs display-encoded values.
+    return pow(max(color, vec3(0.0)), vec3(2.2));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(color, vec3(0.04045)));
+}
+
+void main() {
+    vec3 color = texture(u_hdr, v_uv).rgb;
+    if ((pc.flags & BLOOM) != 0) {
+        color += texture(u_bloom, v_uv).rgb * pc.bloom_intensity;
+    }
+    color *= pc.exposure;
+
+    if (pc.tonemapper == TONEMAPPER_ACES) {
+        color = tonemap_aces(color);
+    } else if (pc.tonemapper == TONEMAPPER_AGX) {
+        color = tonemap_agx(color);
+    } else {
+        color = clamp(color, 0.0, 1.0);
+    }
+
+    // Color grading LUTs map sRGB-encoded colors to sRGB-encoded colors. Sample texel centers.
+    if ((pc.flags & COLOR_GRADING) != 0) {
+        float size = float(textureSize(u_lut, 0).x);
+        vec3 coords = linear_to_srgb(color) * ((size - 1.0) / size) + 0.5 / size;
+        color = srgb_to_linear(texture(u_lut, coords).rgb);
+    }
+
+    if ((pc.flags & ENCODE_SRGB) != 0) {
+        color = linear_to_srgb(color);
+    }
+    f_color = vec4(color, 1.0);
+}
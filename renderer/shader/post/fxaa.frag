@@ -0,0 +1,144 @@
+#version 450
+
+// FXAA 3.11 style edge detection and blending, on luma computed from the linear input.
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D u_source;
+
+layout(push_constant) uniform FxaaPushConstants {
+    vec2 texel_size;
+    uint encode_srgb;
+} pc;
+
+const float EDGE_THRESHOLD = 0.125;
+const float EDGE_THRESHOLD_MIN = 0.0312;
+const float SUBPIXEL_QUALITY = 0.75;
+const int SEARCH_STEPS = 10;
+
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+float luma_at(vec2 uv) {
+    return luma(texture(u_source, uv).rgb);
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+vec3 fxaa() {
+    vec2 t = pc.texel_size;
+    vec3 center_color = texture(u_source, v_uv).rgb;
+    float center = luma(center_color);
+    float north = luma_at(v_uv + vec2(0.0, -t.y));
+    float south = luma_at(v_uv + vec2(0.0, t.y));
+    float west = luma_at(v_uv + vec2(-t.x, 0.0));
+    float east = luma_at(v_uv + vec2(t.x, 0.0));
+
+    float luma_min = min(center, min(min(north, south), min(west, east)));
+    float luma_max = max(center, max(max(north, south), max(west, east)));
+    float range = luma_max - luma_min;
+    if (range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD)) {
+        return center_color;
+    }
+
+    float north_west = luma_at(v_uv + vec2(-t.x, -t.y));
+    float north_east = luma_at(v_uv + vec2(t.x, -t.y));
+    float south_west = luma_at(v_uv + vec2(-t.x, t.y));
+    float south_east = luma_at(v_uv + vec2(t.x, t.y));
+
+    float horizontal = abs(north_west + south_west - 2.0 * west)
+        + 2.0 * abs(north + south - 2.0 * center)
+        + abs(north_east + south_east - 2.0 * east);
+    float vertical = abs(north_west + north_east - 2.0 * north)
+        + 2.0 * abs(west + east - 2.0 * center)
+        + abs(south_west + south_east - 2.0 * south);
+    bool is_horizontal = horizontal >= vertical;
+
+    // Step across the edge towards the side with the steepest gradient.
+    float luma_negative = is_horizontal ? north : west;
+    float luma_positive = is_horizontal ? south : east;
+    float gradient_negative = abs(luma_negative - center);
+    float gradient_positive = abs(luma_positive - center);
+    float step_length = is_horizontal ? t.y : t.x;
+    float luma_local;
+    float gradient;
+    if (gradient_negative >= gradient_positive) {
+        step_length = -step_length;
+        luma_local = 0.5 * (luma_negative + center);
+        gradient = gradient_negative;
+    } else {
+        luma_local = 0.5 * (luma_positive + center);
+        gradient = gradient_positive;
+    }
+
+    vec2 edge_uv = v_uv;
+    if (is_horizontal) {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+
+    // Walk along the edge in both directions until its end.
+    vec2 offset = is_horizontal ? vec2(t.x, 0.0) : vec2(0.0, t.y);
+    vec2 uv_negative = edge_uv - offset;
+    vec2 uv_positive = edge_uv + offset;
+    float end_negative = luma_at(uv_negative) - luma_local;
+    float end_positive = luma_at(uv_positive) - luma_local;
+    float scaled_gradient = gradient * 0.25;
+    bool done_negative = abs(end_negative) >= scaled_gradient;
+    bool done_positive = abs(end_positive) >= scaled_gradient;
+    for (int i = 0; i < SEARCH_STEPS && !(done_negative && done_positive); i++) {
+        float step_scale = i < 2 ? 1.0 : 2.0;
+        if (!done_negative) {
+            uv_negative -= offset * step_scale;
+            end_negative = luma_at(uv_negative) - luma_local;
+            done_negative = abs(end_negative) >= scaled_gradient;
+        }
+        if (!done_positive) {
+            uv_positive += offset * step_scale;
+            end_positive = luma_at(uv_positive) - luma_local;
+            done_positive = abs(end_positive) >= scaled_gradient;
+        }
+    }
+
+    float distance_negative = is_horizontal ? v_uv.x - uv_negative.x : v_uv.y - uv_negative.y;
+    float distance_positive = is_horizontal ? uv_positive.x - v_uv.x : uv_positive.y - v_uv.y;
+    bool negative_closer = distance_negative < distance_positive;
+    float closest = min(distance_negative, distance_positive);
+    float edge_length = distance_negative + distance_positive;
+
+    // Only blend if the luma variation at the closest edge end agrees with the center.
+    bool center_smaller = center < luma_local;
+    bool correct_variation = ((negative_closer ? end_negative : end_positive) < 0.0) != center_smaller;
+    float edge_offset = correct_variation ? 0.5 - closest / edge_length : 0.0;
+
+    // Subpixel aliasing, estimated from the 3x3 neighbourhood average.
+    float average = (2.0 * (north + south + west + east) + north_west + north_east + south_west + south_east) / 12.0;
+    float subpixel = clamp(abs(average - center) / range, 0.0, 1.0);
+    subpixel = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+    float subpixel_offset = subpixel * subpixel * SUBPIXEL_QUALITY;
+
+    float final_offset = max(edge_offset, subpixel_offset);
+    vec2 final_uv = v_uv;
+    if (is_horizontal) {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    return texture(u_source, final_uv).rgb;
+}
+
+void main() {
+    vec3 color = fxaa();
+    if (pc.encode_srgb != 0) {
+        color = linear_to_srgb(color);
+    }
+    f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+
+// Per instance.
+layout(location = 1) in vec4 model_x;
+layout(location = 2) in vec4 model_y;
+layout(location = 3) in vec4 model_z;
+layout(location = 4) in vec4 model_w;
+
+layout(push_constant) uniform ShadowPushConstants {
+    mat4 cascade_view_projection;
+} pc;
+
+void main() {
+    mat4 model = mat4(model_x, model_y, model_z, model_w);
+    gl_Position = pc.cascade_view_projection * model * vec4(position, 1.0);
+}
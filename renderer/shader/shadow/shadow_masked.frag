@@ -0,0 +1,25 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+
+// The material set of pbr.frag, of which only the base color is read.
+layout(set = 2, binding = 0) uniform MaterialUniform {
+    vec4 base_color_factor;
+    vec4 emissive_factor;
+    float metallic_factor;
+    float roughness_factor;
+    float normal_scale;
+    float occlusion_strength;
+    float alpha_cutoff;
+    uint alpha_mask;
+} u_material;
+
+layout(set = 2, binding = 1) uniform sampler2D u_base_color;
+
+// Depth-only pass, with cut out texels letting the light through.
+void main() {
+    float alpha = u_material.base_color_factor.a * texture(u_base_color, v_uv).a;
+    if (alpha < u_material.alpha_cutoff) {
+        discard;
+    }
+}
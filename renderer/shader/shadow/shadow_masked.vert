@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 2) in vec2 uv;
+
+// Per instance.
+layout(location = 4) in vec4 model_x;
+layout(location = 5) in vec4 model_y;
+layout(location = 6) in vec4 model_z;
+layout(location = 7) in vec4 model_w;
+
+layout(location = 0) out vec2 v_uv;
+
+layout(push_constant) uniform ShadowPushConstants {
+    mat4 cascade_view_projection;
+} pc;
+
+void main() {
+    mat4 model = mat4(model_x, model_y, model_z, model_w);
+    v_uv = uv;
+    gl_Position = pc.cascade_view_projection * model * vec4(position, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_tint;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 1, binding = 0) uniform sampler2D u_atlas;
+
+void main() {
+    f_color = texture(u_atlas, v_uv) * v_tint;
+}
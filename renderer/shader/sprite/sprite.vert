@@ -0,0 +1,48 @@
+#version 450
+
+// One instance per sprite, expanded into a quad drawn as a 4 vertex triangle strip.
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in float rotation;
+layout(location = 2) in vec2 size;
+layout(location = 3) in vec2 anchor;
+layout(location = 4) in vec4 uv_rect;
+layout(location = 5) in vec4 tint;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_tint;
+
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 view_projection;
+    mat4 inverse_view;
+    mat4 inverse_projection;
+    vec4 position;
+    vec2 viewport_size;
+    float time;
+} u_view;
+
+layout(push_constant) uniform SpritePushConstants {
+    uint pixel_snap;
+} pc;
+
+void main() {
+    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1);
+    vec2 local = (corner - anchor) * size;
+    float s = sin(rotation);
+    float c = cos(rotation);
+    vec2 world = position.xy + vec2(c * local.x - s * local.y, s * local.x + c * local.y);
+    vec4 clip = u_view.view_projection * vec4(world, position.z, 1.0);
+
+    // Round corners to whole pixels, so that sprites never straddle pixel boundaries.
+    if (pc.pixel_snap != 0) {
+        vec2 pixel = (clip.xy / clip.w * 0.5 + 0.5) * u_view.viewport_size;
+        clip.xy = ((floor(pixel + 0.5) / u_view.viewport_size) * 2.0 - 1.0) * clip.w;
+    }
+    gl_Position = clip;
+
+    // The top of the sprite (corner.y = 1) samples the top of its rectangle (min.y).
+    v_uv = mix(uv_rect.xy, uv_rect.zw, vec2(corner.x, 1.0 - corner.y));
+    v_tint = tint;
+}
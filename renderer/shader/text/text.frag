@@ -0,0 +1,20 @@
+#version 450
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
+layout(location = 2) flat in float v_sdf;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D u_glyphs;
+
+void main() {
+    float value = texture(u_glyphs, v_uv).r;
+    float coverage = value;
+    if (v_sdf != 0.0) {
+        // The edge lies at 0.5; antialias over about one screen pixel at any scale.
+        float width = max(fwidth(value) * 0.75, 1e-4);
+        coverage = smoothstep(0.5 - width, 0.5 + width, value);
+    }
+    f_color = vec4(v_color.rgb, v_color.a * coverage);
+}
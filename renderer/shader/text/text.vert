@@ -0,0 +1,27 @@
+#version 450
+
+// One instance per glyph, expanded into a quad drawn as a 4 vertex triangle strip. Positions
+// are in pixels, with the origin at the top left of the viewport and y pointing down.
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 size;
+layout(location = 2) in vec4 uv_rect;
+layout(location = 3) in vec4 color;
+layout(location = 4) in float sdf;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_color;
+layout(location = 2) flat out float v_sdf;
+
+layout(push_constant) uniform TextPushConstants {
+    vec2 viewport_size;
+} pc;
+
+void main() {
+    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1);
+    vec2 pixel = position + corner * size;
+    gl_Position = vec4(pixel / pc.viewport_size * 2.0 - 1.0, 0.0, 1.0);
+    v_uv = mix(uv_rect.xy, uv_rect.zw, corner);
+    v_color = color;
+    v_sdf = sdf;
+}
@@ -0,0 +1,359 @@
+use std::fmt;
+use std::path::Path;
+use glam::Vec2;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::sampler::{Filter, SamplerAddressMode, SamplerMipmapMode};
+use crate::render_device::RenderDevice;
+use crate::texture::{ImageData, SamplerDesc, Texture};
+
+/// Normalized texture coordinates of a rectangle, `min` being the top left corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl UvRect {
+    /// The whole texture.
+    pub const FULL: UvRect = UvRect {
+        min: Vec2::ZERO,
+        max: Vec2::ONE,
+    };
+}
+
+impl Default for UvRect {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+/// Where one of the packed images ended up in the atlas.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtlasRegion {
+    /// Top left corner, in pixels.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub uv_rect: UvRect,
+}
+
+#[derive(Debug)]
+pub enum AtlasPackError {
+    /// The images do not fit in a `max_size` by `max_size` atlas.
+    DoesNotFit { max_size: u32 },
+    /// Image `index` has no pixels.
+    EmptyImage { index: usize },
+}
+
+impl fmt::Display for AtlasPackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtlasPackError::DoesNotFit { max_size } => {
+                write!(f, "images do not fit in a {0}x{0} atlas", max_size)
+            }
+            AtlasPackError::EmptyImage { index } => write!(f, "image {} is empty", index),
+        }
+    }
+}
+
+impl std::error::Error for AtlasPackError {}
+
+/// Combines loose images into one atlas image at runtime.
+///
+/// Images are packed on shelves, tallest first, into the smallest power of two square that
+/// fits them. Each image is surrounded by `padding` pixels that repeat its border, so that
+/// filtering never bleeds neighbours into it.
+#[derive(Clone, Debug)]
+pub struct AtlasPacker {
+    pub images: Vec<ImageData>,
+    pub padding: u32,
+    pub max_size: u32,
+}
+
+impl Default for AtlasPacker {
+    fn default() -> Self {
+        Self {
+            images: Vec::new(),
+            padding: 1,
+            max_size: 4096,
+        }
+    }
+}
+
+/// Result of [`AtlasPacker::pack`].
+#[derive(Clone, Debug)]
+pub struct PackedAtlas {
+    pub image: ImageData,
+    /// Indexed like [`AtlasPacker::images`].
+    pub regions: Vec<AtlasRegion>,
+}
+
+impl AtlasPacker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an image and returns the index of its future region.
+    pub fn add(&mut self, image: ImageData) -> usize {
+        self.images.push(image);
+        self.images.len() - 1
+    }
+
+    /// Adds an image file and returns the index of its future region.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> image::ImageResult<usize> {
+        let image = image::open(path)?.into_rgba8();
+        Ok(self.add(ImageData {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+        }))
+    }
+
+    pub fn pack(&self) -> Result<PackedAtlas, AtlasPackError> {
+        if let Some(index) = self
+            .images
+            .iter()
+            .position(|image| image.width == 0 || image.height == 0)
+        {
+            return Err(AtlasPackError::EmptyImage { index });
+        }
+        let mut order: Vec<usize> = (0..self.images.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.images[i].height));
+
+        let mut size = 64.min(self.max_size);
+        let positions = loop {
+            if let Some(positions) = self.place(&order, size) {
+                break positions;
+            }
+            if size >= self.max_size {
+                return Err(AtlasPackError::DoesNotFit {
+                    max_size: self.max_size,
+                });
+            }
+            size = (size * 2).min(self.max_size);
+        };
+
+        let mut pixels = vec![0; (size * size * 4) as usize];
+        let mut regions = Vec::with_capacity(self.images.len());
+        for (image, &(x, y)) in self.images.iter().zip(&positions) {
+            self.blit(&mut pixels, size, image, x, y);
+            regions.push(AtlasRegion {
+                x,
+                y,
+                width: image.width,
+                height: image.height,
+                uv_rect: UvRect {
+                    min: Vec2::new(x as f32, y as f32) / size as f32,
+                    max: Vec2::new((x + image.width) as f32, (y + image.height) as f32)
+                        / size as f32,
+                },
+            });
+        }
+        Ok(PackedAtlas {
+            image: ImageData {
+                width: size,
+                height: size,
+                pixels,
+            },
+            regions,
+        })
+    }
+
+    /// Shelf placement of the images in `order` into a `size` square. Returns the top left
+    /// corner of every image, indexed like [`Self::images`].
+    fn place(&self, order: &[usize], size: u32) -> Option<Vec<(u32, u32)>> {
+        let mut positions = vec![(0, 0); self.images.len()];
+        let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+        for &i in order {
+            let width = self.images[i].width + 2 * self.padding;
+            let height = self.images[i].height + 2 * self.padding;
+            if width > size {
+                return None;
+            }
+            if shelf_x + width > size {
+                shelf_y += shelf_height;
+                shelf_x = 0;
+                shelf_height = 0;
+            }
+            if shelf_y + height > size {
+                return None;
+            }
+            positions[i] = (shelf_x + self.padding, shelf_y + self.padding);
+            shelf_x += width;
+            shelf_height = shelf_height.max(height);
+        }
+        Some(positions)
+    }
+
+    /// Copies `image` to `(x, y)`, extruding its edges into the padding.
+    fn blit(&self, pixels: &mut [u8], atlas_size: u32, image: &ImageData, x: u32, y: u32) {
+        let padding = self.padding as i64;
+        for dy in -padding..image.height as i64 + padding {
+            for dx in -padding..image.width as i64 + padding {
+                let source_x = dx.clamp(0, image.width as i64 - 1) as usize;
+                let source_y = dy.clamp(0, image.height as i64 - 1) as usize;
+                let source = (source_y * image.width as usize + source_x) * 4;
+                let target_x = (x as i64 + dx) as usize;
+                let target_y = (y as i64 + dy) as usize;
+                let target = (target_y * atlas_size as usize + target_x) * 4;
+                pixels[target..target + 4].copy_from_slice(&image.pixels[source..source + 4]);
+            }
+        }
+    }
+}
+
+/// A packed atlas uploaded to the GPU.
+pub struct TextureAtlas {
+    pub texture: Texture,
+    pub regions: Vec<AtlasRegion>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TextureAtlas {
+    /// Records the upload of `atlas` into `builder`. Pixel art should use [`Filter::Nearest`].
+    ///
+    /// The texture has no mipmaps, as lower levels would blend images into their neighbours
+    /// across the padding.
+    pub fn new(
+        render_device: &RenderDevice,
+        atlas: &PackedAtlas,
+        filter: Filter,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let sampler = SamplerDesc {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_mode: SamplerMipmapMode::Nearest,
+            address_mode_u: SamplerAddressMode::ClampToEdge,
+            address_mode_v: SamplerAddressMode::ClampToEdge,
+        };
+        Self {
+            texture: Texture::without_mipmaps(render_device, &atlas.image, &sampler, true, builder),
+            regions: atlas.regions.clone(),
+            width: atlas.image.width,
+            height: atlas.image.height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> ImageData {
+        ImageData {
+            width,
+            height,
+            pixels: vec![value; (width * height * 4) as usize],
+        }
+    }
+
+    fn texel(image: &ImageData, x: u32, y: u32) -> &[u8] {
+        let start = ((y * image.width + x) * 4) as usize;
+        &image.pixels[start..start + 4]
+    }
+
+    #[test]
+    fn shelves_fill_left_to_right_tallest_first() {
+        let mut packer = AtlasPacker::new();
+        packer.padding = 0;
+        let short = packer.add(solid(32, 16, 1));
+        let tall = packer.add(solid(32, 32, 2));
+        let next_shelf = packer.add(solid(16, 16, 3));
+        let order = [tall, short, next_shelf];
+        let positions = packer.place(&order, 64).unwrap();
+        assert_eq!(positions[tall], (0, 0));
+        assert_eq!(positions[short], (32, 0));
+        assert_eq!(positions[next_shelf], (0, 32));
+    }
+
+    #[test]
+    fn place_fails_when_a_shelf_overflows() {
+        let mut packer = AtlasPacker::new();
+        packer.padding = 0;
+        packer.add(solid(64, 40, 1));
+        packer.add(solid(64, 40, 2));
+        assert_eq!(packer.place(&[0, 1], 64), None);
+        packer.images[1].height = 24;
+        assert_eq!(packer.place(&[0, 1], 64), Some(vec![(0, 0), (0, 40)]));
+    }
+
+    #[test]
+    fn padding_surrounds_every_image() {
+        let mut packer = AtlasPacker::new();
+        packer.padding = 2;
+        packer.add(solid(10, 10, 1));
+        packer.add(solid(10, 10, 2));
+        let positions = packer.place(&[0, 1], 64).unwrap();
+        assert_eq!(positions, vec![(2, 2), (16, 2)]);
+        // The padded width no longer fits next to the first image.
+        assert_eq!(packer.place(&[0, 1], 24), None);
+    }
+
+    #[test]
+    fn padding_repeats_the_border() {
+        let mut packer = AtlasPacker::new();
+        packer.add(ImageData {
+            width: 2,
+            height: 1,
+            pixels: vec![10, 10, 10, 255, 20, 20, 20, 255],
+        });
+        let atlas = packer.pack().unwrap();
+        assert_eq!(atlas.regions[0].x, 1);
+        assert_eq!(atlas.regions[0].y, 1);
+        assert_eq!(texel(&atlas.image, 0, 0), [10, 10, 10, 255]);
+        assert_eq!(texel(&atlas.image, 1, 1), [10, 10, 10, 255]);
+        assert_eq!(texel(&atlas.image, 2, 1), [20, 20, 20, 255]);
+        assert_eq!(texel(&atlas.image, 3, 2), [20, 20, 20, 255]);
+        assert_eq!(texel(&atlas.image, 4, 1), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn pack_grows_to_the_smallest_fitting_size() {
+        let mut packer = AtlasPacker::new();
+        packer.padding = 0;
+        packer.add(solid(48, 48, 1));
+        packer.add(solid(48, 48, 2));
+        let atlas = packer.pack().unwrap();
+        assert_eq!((atlas.image.width, atlas.image.height), (128, 128));
+        let region = atlas.regions[1];
+        assert_eq!((region.x, region.y, region.width, region.height), (48, 0, 48, 48));
+        assert_eq!(region.uv_rect.min, Vec2::new(0.375, 0.0));
+        assert_eq!(region.uv_rect.max, Vec2::new(0.75, 0.375));
+    }
+
+    #[test]
+    fn pack_reports_images_that_do_not_fit() {
+        let mut packer = AtlasPacker::new();
+        packer.max_size = 128;
+        packer.add(solid(127, 8, 1));
+        match packer.pack() {
+            Err(AtlasPackError::DoesNotFit { max_size }) => assert_eq!(max_size, 128),
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("a 129 pixel wide padded image fit in 128"),
+        }
+    }
+
+    #[test]
+    fn pack_stays_within_small_max_sizes() {
+        let mut packer = AtlasPacker::new();
+        packer.max_size = 16;
+        packer.add(solid(8, 8, 1));
+        let atlas = packer.pack().unwrap();
+        assert_eq!((atlas.image.width, atlas.image.height), (16, 16));
+    }
+
+    #[test]
+    fn pack_rejects_empty_images() {
+        let mut packer = AtlasPacker::new();
+        packer.add(solid(8, 8, 1));
+        packer.add(solid(0, 8, 2));
+        match packer.pack() {
+            Err(AtlasPackError::EmptyImage { index }) => assert_eq!(index, 1),
+            Err(error) => panic!("unexpected error: {}", error),
+            Ok(_) => panic!("packed an empty image"),
+        }
+    }
+}
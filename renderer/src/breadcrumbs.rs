@@ -0,0 +1,343 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use vulkano::buffer::sys::BufferMemory;
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, FillBufferInfo, PrimaryAutoCommandBuffer};
+use crate::debug_utils::set_buffer_name;
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+
+/// Labels of the most recent breadcrumbs kept to name the markers read back after a crash.
+pub const BREADCRUMB_HISTORY: usize = 256;
+
+/// Where the GPU was when it stopped, from [`Breadcrumbs::report`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BreadcrumbReport {
+    /// Marker of the last pass the GPU started, zero if none.
+    pub begun: u32,
+    /// Marker of the last pass the GPU finished, zero if none or no longer in the history.
+    pub completed: u32,
+    /// Marker of [`Self::first_incomplete`], zero if unknown.
+    pub incomplete: u32,
+    pub last_completed: Option<String>,
+    /// The pass that began or ended next after the last completed one, which likely hung or
+    /// faulted. With nested passes, that is the enclosing pass if the completed one was its
+    /// last child.
+    pub first_incomplete: Option<String>,
+}
+
+impl fmt::Display for BreadcrumbReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = |label: &Option<String>| label.clone().unwrap_or_else(|| "unknown".to_owned());
+        write!(
+            f,
+            "GPU breadcrumbs: last completed pass {} ({}), first incomplete pass {} ({}), \
+             last begun marker {}",
+            label(&self.last_completed),
+            self.completed,
+            label(&self.first_incomplete),
+            self.incomplete,
+            self.begun
+        )
+    }
+}
+
+/// A marker write, in recording order.
+#[derive(Clone, Copy, Debug)]
+struct MarkerEvent {
+    /// Marker of the pass.
+    marker: u32,
+    /// Number of the end, counted over every pass, or `None` for the begin of the pass.
+    end: Option<u32>,
+}
+
+/// What [`Breadcrumbs`] recorded, to name the markers it reads back.
+struct BreadcrumbHistory {
+    /// Markers and labels of recent passes, oldest first.
+    labels: VecDeque<(u32, String)>,
+    /// Begins and ends of the passes in `labels`, oldest first.
+    events: VecDeque<MarkerEvent>,
+    /// Markers of the open passes.
+    stack: Vec<u32>,
+    next_marker: u32,
+    next_end: u32,
+}
+
+impl BreadcrumbHistory {
+    fn new() -> Self {
+        Self {
+            labels: VecDeque::with_capacity(BREADCRUMB_HISTORY),
+            events: VecDeque::with_capacity(2 * BREADCRUMB_HISTORY),
+            stack: Vec::new(),
+            next_marker: 1,
+            next_end: 1,
+        }
+    }
+
+    /// Opens pass `label` and returns its marker.
+    fn begin(&mut self, label: &str) -> u32 {
+        let marker = self.next_marker;
+        self.next_marker = self.next_marker.wrapping_add(1).max(1);
+        if self.labels.len() == BREADCRUMB_HISTORY {
+            let (evicted, _) = self.labels.pop_front().unwrap();
+            // Ends at the front belong to passes whose begin is already gone.
+            while self
+                .events
+                .front()
+                .is_some_and(|event| event.marker == evicted || event.end.is_some())
+            {
+                self.events.pop_front();
+            }
+        }
+        self.labels.push_back((marker, label.to_owned()));
+        self.events.push_back(MarkerEvent { marker, end: None });
+        self.stack.push(marker);
+        marker
+    }
+
+    /// Closes the innermost open pass and returns the number of its end.
+    fn end(&mut self) -> Option<u32> {
+        let marker = self.stack.pop()?;
+        let end = self.next_end;
+        self.next_end = self.next_end.wrapping_add(1).max(1);
+        self.events.push_back(MarkerEvent {
+            marker,
+            end: Some(end),
+        });
+        Some(end)
+    }
+
+    fn label(&self, marker: u32) -> Option<String> {
+        self.labels
+            .iter()
+            .find(|(m, _)| *m == marker)
+            .map(|(_, label)| label.clone())
+    }
+
+    /// Names the pass the GPU began last, and the passes around the end it completed last.
+    fn report(&self, begun: u32, completed_end: u32) -> BreadcrumbReport {
+        let completed_index = self
+            .events
+            .iter()
+            .position(|event| completed_end != 0 && event.end == Some(completed_end));
+        let completed = completed_index.map_or(0, |i| self.events[i].marker);
+        // Without a completed end, the first recorded pass is the first incomplete one.
+        let next = match completed_index {
+            Some(i) => self.events.get(i + 1),
+            None if completed_end == 0 => self.events.front(),
+            None => None,
+        };
+        let incomplete = next.map_or(0, |event| event.marker);
+        BreadcrumbReport {
+            begun,
+            completed,
+            incomplete,
+            last_completed: self.label(completed),
+            first_incomplete: self.label(incomplete),
+        }
+    }
+}
+
+/// Markers written by the GPU into host-visible memory around passes, to tell which pass was
+/// executing when the device was lost.
+///
+/// Before a pass the marker of the pass is written with `vkCmdFillBuffer`, and after it the
+/// number of its end with `vkCmdUpdateBuffer`, so that the order nested passes end in is
+/// known. Both are transfer commands, recorded outside of rendering. vulkano only
+/// places barriers between commands that access the same resources, so passes may overlap their
+/// markers a little, and the report brackets the failing pass rather than pinpoint it.
+pub struct Breadcrumbs {
+    /// Markers are not recorded while not set.
+    pub enabled: bool,
+    /// The last begun marker and the last completed end.
+    buffer: Arc<CpuAccessibleBuffer<[u32]>>,
+    history: BreadcrumbHistory,
+}
+
+impl Breadcrumbs {
+    pub fn new(render_device: &RenderDevice) -> Self {
+        let buffer = CpuAccessibleBuffer::from_iter(
+            &render_device.memory_allocator,
+            BufferUsage {
+                transfer_dst: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            [0u32; 2],
+        )
+        .unwrap();
+        set_buffer_name(buffer.as_ref(), "breadcrumbs");
+        render_device
+            .memory_tracker
+            .track_buffer(buffer.as_ref(), MemoryCategory::Staging, "breadcrumbs");
+        Self {
+            enabled: true,
+            buffer,
+            history: BreadcrumbHistory::new(),
+        }
+    }
+
+    /// Marks the start of pass `label`, closed by [`Self::end`]. Must be recorded outside of
+    /// rendering. Passes nest.
+    pub fn begin(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        label: &str,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let marker = self.history.begin(label);
+        builder
+            .fill_buffer(FillBufferInfo {
+                data: marker,
+                size: 4,
+                ..FillBufferInfo::dst_buffer(self.buffer.clone())
+            })
+            .unwrap();
+    }
+
+    /// Marks the end of the pass opened by the last [`Self::begin`].
+    pub fn end(&mut self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        if let Some(end) = self.history.end() {
+            let data: Box<[u32]> = Box::new([end]);
+            builder.update_buffer(data, self.buffer.clone(), 4).unwrap();
+        }
+    }
+
+    /// Wraps the commands recorded by `record` in markers of pass `label`.
+    pub fn pass<R>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        label: &str,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> R,
+    ) -> R {
+        self.begin(builder, label);
+        let result = record(builder);
+        self.end(builder);
+        result
+    }
+
+    /// Reads back the markers and names the passes around the point the GPU stopped at. Meant
+    /// for after the device was lost, when the GPU no longer writes to them.
+    pub fn report(&self) -> BreadcrumbReport {
+        let [begun, completed_end] = self.read_markers();
+        self.history.report(begun, completed_end)
+    }
+
+    /// Reads the mapped memory directly. `CpuAccessibleBuffer::read` would refuse, since the
+    /// submissions of a lost device never finish and keep the buffer locked.
+    fn read_markers(&self) -> [u32; 2] {
+        let buffer = self.buffer.inner();
+        let alloc = match buffer.buffer.memory() {
+            BufferMemory::Normal(alloc) => alloc,
+            BufferMemory::Sparse => unreachable!(),
+        };
+        // Nothing runs on the GPU anymore, so nothing writes to the memory while it is read.
+        unsafe {
+            alloc.invalidate_range(0..alloc.size()).unwrap();
+            let bytes = &alloc.mapped_slice().unwrap()[buffer.offset as usize..][..8];
+            [
+                u32::from_ne_bytes(bytes[0..4].try_into().unwrap()),
+                u32::from_ne_bytes(bytes[4..8].try_into().unwrap()),
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(report: &BreadcrumbReport) -> (Option<&str>, Option<&str>) {
+        (
+            report.last_completed.as_deref(),
+            report.first_incomplete.as_deref(),
+        )
+    }
+
+    #[test]
+    fn the_pass_after_the_last_completed_one_is_incomplete() {
+        let mut history = BreadcrumbHistory::new();
+        history.begin("shadows");
+        history.end();
+        history.begin("scene");
+        let end = history.end().unwrap();
+        let post = history.begin("post");
+        let report = history.report(post, end);
+        assert_eq!((report.completed, report.incomplete), (2, 3));
+        assert_eq!(labels(&report), (Some("scene"), Some("post")));
+    }
+
+    #[test]
+    fn nested_passes_end_in_order() {
+        let mut history = BreadcrumbHistory::new();
+        let outer = history.begin("outer");
+        let inner = history.begin("inner");
+        let inner_end = history.end().unwrap();
+        let outer_end = history.end().unwrap();
+        let next = history.begin("next");
+
+        // Both ended, so the next pass hung.
+        let report = history.report(next, outer_end);
+        assert_eq!((report.completed, report.incomplete), (outer, next));
+        assert_eq!(labels(&report), (Some("outer"), Some("next")));
+        // Only the inner one ended, so the rest of the outer one hung.
+        let report = history.report(inner, inner_end);
+        assert_eq!((report.completed, report.incomplete), (inner, outer));
+        assert_eq!(labels(&report), (Some("inner"), Some("outer")));
+    }
+
+    #[test]
+    fn nothing_completed_blames_the_first_pass() {
+        let mut history = BreadcrumbHistory::new();
+        let first = history.begin("first");
+        history.begin("second");
+        let report = history.report(first, 0);
+        assert_eq!((report.completed, report.incomplete), (0, first));
+        assert_eq!(labels(&report), (None, Some("first")));
+        assert_eq!(BreadcrumbHistory::new().report(0, 0), BreadcrumbReport::default());
+    }
+
+    #[test]
+    fn history_forgets_the_oldest_passes() {
+        let mut history = BreadcrumbHistory::new();
+        let outer = history.begin("outer");
+        let ends: Vec<u32> = (0..BREADCRUMB_HISTORY + 10)
+            .map(|i| {
+                history.begin(&format!("pass {}", i));
+                history.end().unwrap()
+            })
+            .collect();
+        let outer_end = history.end().unwrap();
+        assert_eq!(history.labels.len(), BREADCRUMB_HISTORY);
+        assert!(history.events.len() <= 2 * BREADCRUMB_HISTORY + 1);
+
+        let report = history.report(0, ends[5]);
+        assert_eq!((report.completed, report.incomplete), (0, 0));
+        assert_eq!(labels(&report), (None, None));
+        let last = ends.len() - 1;
+        let report = history.report(0, ends[last]);
+        assert_eq!(labels(&report), (Some(&*format!("pass {}", last)), None));
+        assert_eq!(report.incomplete, outer);
+        // The outer pass outlived its label.
+        assert_eq!(history.report(0, outer_end).completed, outer);
+    }
+
+    #[test]
+    fn reports_print_markers_and_labels() {
+        let report = BreadcrumbReport {
+            begun: 4,
+            completed: 2,
+            incomplete: 3,
+            last_completed: Some("scene".to_owned()),
+            first_incomplete: None,
+        };
+        assert_eq!(
+            report.to_string(),
+            "GPU breadcrumbs: last completed pass scene (2), first incomplete pass unknown (3), \
+             last begun marker 4"
+        );
+    }
+}
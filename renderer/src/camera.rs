@@ -0,0 +1,316 @@
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Quat, Vec3};
+use vulkano::buffer::CpuBufferPool;
+use vulkano::descriptor_set::layout::{
+    DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorType,
+};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::shader::ShaderStages;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+
+/// Descriptor set index the per-view uniform buffer is always bound at.
+pub const VIEW_SET: u32 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view, in radians.
+        fov_y: f32,
+        near: f32,
+        far: f32,
+    },
+    Orthographic {
+        /// Height of the view volume in world units. The width follows the aspect ratio.
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub projection: Projection,
+    /// Map the near plane to depth 1 and the far plane to depth 0, which spreads floating point
+    /// depth precision much more evenly. Depth testing must then use `Greater` and clear to 0.
+    pub reverse_z: bool,
+}
+
+impl Camera {
+    pub fn perspective(fov_y: f32, near: f32, far: f32) -> Self {
+        Self {
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            projection: Projection::Perspective { fov_y, near, far },
+            reverse_z: false,
+        }
+    }
+
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Self {
+        Self {
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            projection: Projection::Orthographic { height, near, far },
+            reverse_z: false,
+        }
+    }
+
+    /// Points the camera at `target`, keeping `up` as the up direction.
+    pub fn look_at(&mut self, target: Vec3, up: Vec3) {
+        let view = Mat4::look_at_rh(self.position, target, up);
+        self.rotation = Quat::from_mat4(&view.inverse());
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.rotation, self.position).inverse()
+    }
+
+    /// Projection into Vulkan clip space: Y points down and depth lies in `0..1`.
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        let projection = match self.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                if self.reverse_z {
+                    Mat4::perspective_rh(fov_y, aspect_ratio, far, near)
+                } else {
+                    Mat4::perspective_rh(fov_y, aspect_ratio, near, far)
+                }
+            }
+            Projection::Orthographic { height, near, far } => {
+                let half_height = height * 0.5;
+                let half_width = half_height * aspect_ratio;
+                let (near, far) = if self.reverse_z { (far, near) } else { (near, far) };
+                Mat4::orthographic_rh(-half_width, half_width, -half_height, half_height, near, far)
+            }
+        };
+        Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0)) * projection
+    }
+
+    /// Depth value the depth attachment should be cleared to.
+    pub fn clear_depth(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    /// Depth comparison that keeps the closest fragment.
+    pub fn depth_compare_op(&self) -> CompareOp {
+        if self.reverse_z {
+            CompareOp::Greater
+        } else {
+            CompareOp::Less
+        }
+    }
+}
+
+/// Contents of the per-view uniform buffer. Matches the std140 layout of `ViewUniform` in GLSL:
+///
+/// ```glsl
+/// layout(set = 0, binding = 0) uniform ViewUniform {
+///     mat4 view;
+///     mat4 projection;
+///     mat4 view_projection;
+///     mat4 inverse_view;
+///     mat4 inverse_projection;
+///     vec4 position;
+///     vec2 viewport_size;
+///     float time;
+/// } u_view;
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct ViewUniform {
+    pub view: [[f32; 4]; 4],
+    pub projection: [[f32; 4]; 4],
+    pub view_projection: [[f32; 4]; 4],
+    pub inverse_view: [[f32; 4]; 4],
+    pub inverse_projection: [[f32; 4]; 4],
+    pub position: [f32; 4],
+    pub viewport_size: [f32; 2],
+    pub time: f32,
+    pub _pad: f32,
+}
+
+impl ViewUniform {
+    pub fn new(camera: &Camera, viewport_size: [f32; 2], time: f32) -> Self {
+        let view = camera.view_matrix();
+        let projection = camera.projection_matrix(viewport_size[0] / viewport_size[1].max(1.0));
+        Self {
+            view: view.to_cols_array_2d(),
+            projection: projection.to_cols_array_2d(),
+            view_projection: (projection * view).to_cols_array_2d(),
+            inverse_view: view.inverse().to_cols_array_2d(),
+            inverse_projection: projection.inverse().to_cols_array_2d(),
+            position: camera.position.extend(1.0).to_array(),
+            viewport_size,
+            time,
+            _pad: 0.0,
+        }
+    }
+}
+
+/// Per-view uniform buffer, rewritten every frame and bound at [`VIEW_SET`].
+pub struct ViewUniforms {
+    pub layout: Arc<DescriptorSetLayout>,
+    pub uniform: ViewUniform,
+    /// Descriptor set holding this frame's uniform buffer. Replaced by every [`Self::update`].
+    pub descriptor_set: Arc<PersistentDescriptorSet>,
+    pool: CpuBufferPool<ViewUniform, StandardMemoryAllocator>,
+}
+
+impl ViewUniforms {
+    pub fn new(render_device: &RenderDevice) -> Self {
+        let layout = DescriptorSetLayout::new(
+            render_device.device.clone(),
+            view_set_layout_create_info(),
+        )
+        .unwrap();
+        let pool = CpuBufferPool::uniform_buffer(render_device.memory_allocator.clone());
+        let uniform = ViewUniform::default();
+        let descriptor_set = Self::write_set(render_device, &layout, &pool, uniform);
+        Self {
+            layout,
+            uniform,
+            descriptor_set,
+            pool,
+        }
+    }
+
+    /// Uploads this frame's view data. The aspect ratio and viewport size always follow the
+    /// current swapchain extent of `render_output`, so resizes are picked up automatically.
+    pub fn update(
+        &mut self,
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        camera: &Camera,
+        time: f32,
+    ) {
+        let [width, height] = render_output.swapchain.image_extent();
+        self.uniform = ViewUniform::new(camera, [width as f32, height as f32], time);
+        self.descriptor_set =
+            Self::write_set(render_device, &self.layout, &self.pool, self.uniform);
+    }
+
+    fn write_set(
+        render_device: &RenderDevice,
+        layout: &Arc<DescriptorSetLayout>,
+        pool: &CpuBufferPool<ViewUniform, StandardMemoryAllocator>,
+        uniform: ViewUniform,
+    ) -> Arc<PersistentDescriptorSet> {
+        let buffer = pool.from_data(uniform).unwrap();
+        PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            layout.clone(),
+            [WriteDescriptorSet::buffer(0, buffer)],
+        )
+        .unwrap()
+    }
+}
+
+fn view_binding() -> DescriptorSetLayoutBinding {
+    DescriptorSetLayoutBinding {
+        stages: ShaderStages::all_graphics(),
+        ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer)
+    }
+}
+
+pub fn view_set_layout_create_info() -> DescriptorSetLayoutCreateInfo {
+    DescriptorSetLayoutCreateInfo {
+        bindings: [(0, view_binding())].into(),
+        ..Default::default()
+    }
+}
+
+/// Makes the view set of an automatically inferred pipeline layout identical to the one of
+/// [`ViewUniforms`], so its descriptor set can be bound to any pipeline. Pass this to
+/// `GraphicsPipelineBuilder::with_auto_layout`.
+pub fn apply_view_set_layout(set_layouts: &mut [DescriptorSetLayoutCreateInfo]) {
+    if let Some(set_layout) = set_layouts.get_mut(VIEW_SET as usize) {
+        set_layout.bindings.insert(0, view_binding());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+    use super::*;
+
+    /// Normalized device coordinates of a view space point.
+    fn project(camera: &Camera, point: Vec3) -> Vec3 {
+        let clip = camera.projection_matrix(2.0) * point.extend(1.0);
+        clip.truncate() / clip.w
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1.0e-5), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn perspective_maps_near_and_far_to_depth_range() {
+        let mut camera = Camera::perspective(std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -0.1)), Vec3::ZERO);
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -100.0)), Vec3::Z);
+        camera.reverse_z = true;
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -0.1)), Vec3::Z);
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -100.0)), Vec3::ZERO);
+    }
+
+    #[test]
+    fn reverse_z_keeps_closer_points_greater() {
+        let mut camera = Camera::perspective(1.0, 0.1, 100.0);
+        camera.reverse_z = true;
+        let depths: Vec<f32> = [0.5, 1.0, 10.0, 50.0]
+            .iter()
+            .map(|&distance| project(&camera, Vec3::new(0.0, 0.0, -distance)).z)
+            .collect();
+        assert!(depths.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", depths);
+        assert_eq!(camera.clear_depth(), 0.0);
+        assert_eq!(camera.depth_compare_op(), CompareOp::Greater);
+    }
+
+    #[test]
+    fn reverse_z_leaves_x_and_y_alone() {
+        let mut camera = Camera::perspective(std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let point = Vec3::new(1.0, 1.0, -2.0);
+        let forward = project(&camera, point);
+        camera.reverse_z = true;
+        let reversed = project(&camera, point);
+        assert_near(forward, Vec3::new(0.25, -0.5, forward.z));
+        assert_near(reversed, Vec3::new(0.25, -0.5, reversed.z));
+    }
+
+    #[test]
+    fn orthographic_depth_is_linear_and_reversible() {
+        let mut camera = Camera::orthographic(4.0, 1.0, 11.0);
+        assert_near(project(&camera, Vec3::new(4.0, 2.0, -1.0)), Vec3::new(1.0, -1.0, 0.0));
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -6.0)), Vec3::new(0.0, 0.0, 0.5));
+        camera.reverse_z = true;
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -1.0)), Vec3::Z);
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -8.5)), Vec3::new(0.0, 0.0, 0.25));
+        assert_near(project(&camera, Vec3::new(0.0, 0.0, -11.0)), Vec3::ZERO);
+    }
+
+    #[test]
+    fn default_depth_is_cleared_to_far() {
+        let camera = Camera::orthographic(1.0, 0.0, 1.0);
+        assert_eq!(camera.clear_depth(), 1.0);
+        assert_eq!(camera.depth_compare_op(), CompareOp::Less);
+    }
+
+    #[test]
+    fn view_uniform_inverts_the_projection() {
+        let mut camera = Camera::perspective(1.0, 0.1, 100.0);
+        camera.reverse_z = true;
+        let uniform = ViewUniform::new(&camera, [200.0, 100.0], 0.0);
+        let projection = Mat4::from_cols_array_2d(&uniform.projection);
+        let inverse = Mat4::from_cols_array_2d(&uniform.inverse_projection);
+        let point = Vec4::new(0.3, -0.2, -5.0, 1.0);
+        assert!((inverse * (projection * point)).abs_diff_eq(point, 1.0e-4));
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CopyImageToBufferInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::format::Format;
+use crate::compute::readback_buffer;
+use crate::debug_utils::set_buffer_name;
+use crate::destruction_queue::FrameFence;
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+
+/// Where captured frames go. Frames are converted to 8-bit RGB, without alpha.
+#[derive(Clone, Debug)]
+pub enum CaptureOutput {
+    /// `frame_00000.png`, `frame_00001.png`, ... in a directory, created if missing.
+    PngSequence(PathBuf),
+    /// A YUV4MPEG2 file with 4:4:4 chroma, which most players and encoders read.
+    Y4m(PathBuf),
+    /// Packed RGB rows, frame after frame, without a header.
+    RawRgb(PathBuf),
+    /// Pipes raw RGB into a spawned `ffmpeg`, which encodes the file as its extension says.
+    Ffmpeg(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+pub struct CaptureSettings {
+    pub output: CaptureOutput,
+    pub frame_count: u32,
+    /// Frames per second of the output, and the inverse of [`FrameCapture::delta_time`].
+    pub frame_rate: u32,
+}
+
+/// Why [`FrameCapture::new`] cannot record an output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureError {
+    /// The surface does not allow reading back swapchain images.
+    NotTransferSource,
+    /// Only 8-bit RGBA and BGRA swapchains can be captured.
+    UnsupportedFormat(Format),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::NotTransferSource => {
+                write!(f, "the surface does not allow reading back swapchain images")
+            }
+            CaptureError::UnsupportedFormat(format) => {
+                write!(f, "capturing {:?} frames is not supported", format)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+struct PendingCapture {
+    index: u32,
+    buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    fence: Arc<dyn FrameFence>,
+}
+
+struct CapturedFrame {
+    index: u32,
+    pixels: Vec<u8>,
+}
+
+/// Records a number of frames from the swapchain to disk, for trailers and bug reports.
+///
+/// Each frame is copied into a host-visible buffer at the end of its command buffer, and read
+/// back once its fence signaled, so recording never waits for the GPU. Frames are encoded and
+/// written on a separate thread.
+///
+/// While recording, advance the simulation by [`Self::delta_time`] instead of the real frame
+/// time, so the output is the same however long frames take.
+pub struct FrameCapture {
+    pub settings: CaptureSettings,
+    /// Extent of the captured frames, that of the output when recording started. Frames of
+    /// other extents, while the window is resized, are skipped.
+    pub extent: [u32; 2],
+    /// Frames submitted with a copy so far.
+    submitted: u32,
+    /// Buffer of the frame being recorded, until [`Self::end_frame`].
+    current: Option<Arc<CpuAccessibleBuffer<[u8]>>>,
+    pending: VecDeque<PendingCapture>,
+    free: Vec<Arc<CpuAccessibleBuffer<[u8]>>>,
+    sender: Option<Sender<CapturedFrame>>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl FrameCapture {
+    /// Starts recording the frames of `render_output`, unless its swapchain images cannot be
+    /// copied from or are not 8-bit RGBA or BGRA, like those of many HDR surfaces.
+    pub fn new(
+        render_output: &RenderOutput,
+        settings: CaptureSettings,
+    ) -> Result<Self, CaptureError> {
+        let swapchain = &render_output.swapchain;
+        if !swapchain.image_usage().transfer_src {
+            return Err(CaptureError::NotTransferSource);
+        }
+        let swap_red_blue = swaps_red_blue(swapchain.image_format())?;
+        let extent = swapchain.image_extent();
+        let (sender, receiver) = mpsc::channel();
+        let writer = {
+            let settings = settings.clone();
+            std::thread::spawn(move || write_frames(settings, extent, swap_red_blue, receiver))
+        };
+        println!(
+            "Capturing {} frames of {:?} to {:?}",
+            settings.frame_count, extent, settings.output
+        );
+        Ok(Self {
+            settings,
+            extent,
+            submitted: 0,
+            current: None,
+            pending: VecDeque::new(),
+            free: Vec::new(),
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    /// Fixed time step of the captured frames, in seconds.
+    pub fn delta_time(&self) -> f32 {
+        1.0 / self.settings.frame_rate as f32
+    }
+
+    /// Whether frames are still to be recorded. Once not, call [`Self::finish`] when
+    /// [`Self::collect`] returned the last one.
+    pub fn is_recording(&self) -> bool {
+        self.submitted < self.settings.frame_count
+    }
+
+    /// Records a copy of swapchain image `image_index`, after everything drawn to it. Call it
+    /// last before building the command buffer.
+    pub fn record(
+        &mut self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        render_output: &RenderOutput,
+        image_index: u32,
+    ) {
+        if !self.is_recording() {
+            return;
+        }
+        let extent = render_output.swapchain.image_extent();
+        if extent != self.extent {
+            println!("Skipping a frame of {:?} while capturing {:?}", extent, self.extent);
+            return;
+        }
+        // A frame that failed to submit is recorded again.
+        let buffer = self.current.take().unwrap_or_else(|| {
+            self.free.pop().unwrap_or_else(|| {
+                let buffer =
+                    readback_buffer::<u8>(render_device, extent[0] as u64 * extent[1] as u64 * 4);
+                set_buffer_name(buffer.as_ref(), "frame capture");
+                render_device.memory_tracker.track_buffer(
+                    buffer.as_ref(),
+                    MemoryCategory::Staging,
+                    "frame capture",
+                );
+                buffer
+            })
+        });
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                render_output.images[image_index as usize].clone(),
+                buffer.clone(),
+            ))
+            .unwrap();
+        self.current = Some(buffer);
+    }
+
+    /// Ends the frame recorded by [`Self::record`], whose submission signals `fence`. Frames
+    /// that failed to submit should not end.
+    pub fn end_frame(&mut self, fence: Arc<dyn FrameFence>) {
+        if let Some(buffer) = self.current.take() {
+            self.pending.push_back(PendingCapture {
+                index: self.submitted,
+                buffer,
+                fence,
+            });
+            self.submitted += 1;
+        }
+    }
+
+    /// Hands the frames the GPU finished to the writer, returning how many are still pending.
+    /// Cheap enough to call every frame.
+    pub fn collect(&mut self) -> usize {
+        // Frames complete in submission order, so stop at the first incomplete one.
+        while let Some(pending) = self.pending.front() {
+            if !pending.fence.is_complete() {
+                break;
+            }
+            let pending = self.pending.pop_front().unwrap();
+            // The buffer stays locked until the frame's future is cleaned up, which may happen
+            // after the fence signaled, so try again on the next call.
+            if let Err(pending) = self.send(pending) {
+                self.pending.push_front(pending);
+                break;
+            }
+        }
+        self.pending.len()
+    }
+
+    /// Waits for the pending frames and for the writer to write them, and closes the output.
+    /// Frames not yet recorded are not.
+    pub fn finish(mut self) -> io::Result<()> {
+        while let Some(pending) = self.pending.pop_front() {
+            pending.fence.wait();
+            if let Err(pending) = self.send(pending) {
+                let message = format!("frame {} is still locked by the GPU", pending.index);
+                return Err(io::Error::other(message));
+            }
+        }
+        self.sender = None;
+        let result = self.writer.take().unwrap().join().unwrap();
+        if result.is_ok() {
+            println!("Captured {} frames to {:?}", self.submitted, self.settings.output);
+        }
+        result
+    }
+
+    /// Gives the capture back if its buffer is still locked by the GPU.
+    fn send(&mut self, pending: PendingCapture) -> Result<(), PendingCapture> {
+        let pixels = pending.buffer.read().map(|pixels| pixels.to_vec());
+        let Ok(pixels) = pixels else {
+            return Err(pending);
+        };
+        self.free.push(pending.buffer);
+        // The writer only stops early on an error, which `Self::finish` reports.
+        let frame = CapturedFrame {
+            index: pending.index,
+            pixels,
+        };
+        self.sender.as_ref().unwrap().send(frame).ok();
+        Ok(())
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        for pending in self.pending.drain(..) {
+            pending.fence.wait();
+        }
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            writer.join().ok();
+        }
+    }
+}
+
+/// Runs on the writer thread until the capture closes the channel.
+fn write_frames(
+    settings: CaptureSettings,
+    extent: [u32; 2],
+    swap_red_blue: bool,
+    frames: Receiver<CapturedFrame>,
+) -> io::Result<()> {
+    let [width, height] = extent;
+    let rgb_frames = frames
+        .into_iter()
+        .map(|frame| (frame.index, to_rgb(&frame.pixels, swap_red_blue)));
+    match settings.output {
+        CaptureOutput::PngSequence(directory) => {
+            std::fs::create_dir_all(&directory)?;
+            for (index, rgb) in rgb_frames {
+                let path = directory.join(format!("frame_{:05}.png", index));
+                image::save_buffer(path, &rgb, width, height, image::ColorType::Rgb8)
+                    .map_err(io::Error::other)?;
+            }
+        }
+        CaptureOutput::Y4m(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            writeln!(
+                file,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                width, height, settings.frame_rate
+            )?;
+            for (_, rgb) in rgb_frames {
+                file.write_all(b"FRAME\n")?;
+                file.write_all(&to_yuv444(&rgb))?;
+            }
+            file.flush()?;
+        }
+        CaptureOutput::RawRgb(path) => {
+            let mut file = BufWriter::new(File::create(path)?);
+            for (_, rgb) in rgb_frames {
+                file.write_all(&rgb)?;
+            }
+            file.flush()?;
+        }
+        CaptureOutput::Ffmpeg(path) => {
+            let mut ffmpeg = Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pixel_format", "rgb24"])
+                .arg("-video_size")
+                .arg(format!("{}x{}", width, height))
+                .arg("-framerate")
+                .arg(settings.frame_rate.to_string())
+                .args(["-i", "-"])
+                .arg(path)
+                .stdin(Stdio::piped())
+                .spawn()?;
+            let mut stdin = ffmpeg.stdin.take().unwrap();
+            for (_, rgb) in rgb_frames {
+                stdin.write_all(&rgb)?;
+            }
+            // Closing the input tells ffmpeg to finish the file.
+            drop(stdin);
+            let status = ffmpeg.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("ffmpeg failed: {}", status)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether pixels of `format` are stored blue first.
+fn swaps_red_blue(format: Format) -> Result<bool, CaptureError> {
+    match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => Ok(false),
+        Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => Ok(true),
+        format => Err(CaptureError::UnsupportedFormat(format)),
+    }
+}
+
+fn to_rgb(pixels: &[u8], swap_red_blue: bool) -> Vec<u8> {
+    pixels
+        .chunks_exact(4)
+        .flat_map(|p| if swap_red_blue { [p[2], p[1], p[0]] } else { [p[0], p[1], p[2]] })
+        .collect()
+}
+
+/// Planar Y, U and V in BT.601 limited range, what Y4M readers assume without a color range.
+fn to_yuv444(rgb: &[u8]) -> Vec<u8> {
+    let pixel_count = rgb.len() / 3;
+    let mut yuv = vec![0; pixel_count * 3];
+    let (y, uv) = yuv.split_at_mut(pixel_count);
+    let (u, v) = uv.split_at_mut(pixel_count);
+    for (i, p) in rgb.chunks_exact(3).enumerate() {
+        let [r, g, b] = [p[0] as f32, p[1] as f32, p[2] as f32];
+        y[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+        u[i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+        v[i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+    }
+    yuv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_8_bit_rgba_and_bgra_are_captured() {
+        assert_eq!(swaps_red_blue(Format::R8G8B8A8_SRGB), Ok(false));
+        assert_eq!(swaps_red_blue(Format::B8G8R8A8_UNORM), Ok(true));
+        let hdr = Format::A2B10G10R10_UNORM_PACK32;
+        assert_eq!(swaps_red_blue(hdr), Err(CaptureError::UnsupportedFormat(hdr)));
+    }
+
+    #[test]
+    fn rgb_drops_alpha_and_undoes_bgra() {
+        let pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(to_rgb(&pixels, false), [1, 2, 3, 5, 6, 7]);
+        assert_eq!(to_rgb(&pixels, true), [3, 2, 1, 7, 6, 5]);
+    }
+
+    #[test]
+    fn yuv_matches_bt601_limited_range() {
+        // Black, white and pure red, stored as planar Y, then U, then V.
+        let rgb = [0, 0, 0, 255, 255, 255, 255, 0, 0];
+        assert_eq!(to_yuv444(&rgb), [16, 235, 82, 128, 128, 90, 128, 128, 240]);
+    }
+}
@@ -0,0 +1,575 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{BufferContents, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::layout::DescriptorType;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
+use vulkano::pipeline::compute::ComputePipelineCreationError;
+use vulkano::pipeline::{Pipeline, PipelineBindPoint};
+use vulkano::shader::spirv::{
+    BuiltIn, Decoration, ExecutionMode, Id, Instruction, Spirv, SpirvError,
+};
+use vulkano::shader::{ShaderCreationError, ShaderModule};
+use crate::render_device::RenderDevice;
+
+#[derive(Debug)]
+pub enum ComputeError {
+    Io(std::io::Error),
+    /// GLSL failed to compile, with the compiler's messages.
+    Compile(String),
+    Spirv(SpirvError),
+    Shader(ShaderCreationError),
+    /// The module has no compute entry point called `main`.
+    NoEntryPoint,
+    /// The local size is computed by specialization constant operations, which are not
+    /// evaluated.
+    UnknownLocalSize,
+    /// The local size is zero or over the limits of the device along an axis, or has too many
+    /// invocations.
+    InvalidLocalSize([u32; 3]),
+    Pipeline(ComputePipelineCreationError),
+}
+
+impl fmt::Display for ComputeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComputeError::Io(e) => write!(f, "failed to read shader: {}", e),
+            ComputeError::Compile(e) => write!(f, "failed to compile shader: {}", e),
+            ComputeError::Spirv(e) => write!(f, "invalid SPIR-V: {}", e),
+            ComputeError::Shader(e) => write!(f, "failed to create shader module: {}", e),
+            ComputeError::NoEntryPoint => write!(f, "shader has no compute entry point `main`"),
+            ComputeError::UnknownLocalSize => {
+                write!(f, "shader computes its local size at specialization")
+            }
+            ComputeError::InvalidLocalSize(size) => {
+                write!(f, "local size {:?} is not supported by the device", size)
+            }
+            ComputeError::Pipeline(e) => write!(f, "failed to create compute pipeline: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ComputeError {}
+
+impl From<std::io::Error> for ComputeError {
+    fn from(e: std::io::Error) -> Self {
+        ComputeError::Io(e)
+    }
+}
+
+impl From<SpirvError> for ComputeError {
+    fn from(e: SpirvError) -> Self {
+        ComputeError::Spirv(e)
+    }
+}
+
+impl From<ShaderCreationError> for ComputeError {
+    fn from(e: ShaderCreationError) -> Self {
+        ComputeError::Shader(e)
+    }
+}
+
+impl From<ComputePipelineCreationError> for ComputeError {
+    fn from(e: ComputePipelineCreationError) -> Self {
+        ComputeError::Pipeline(e)
+    }
+}
+
+/// Compiles a GLSL compute shader to SPIR-V at runtime. `name` only shows up in errors.
+pub fn compile_glsl(source: &str, name: &str) -> Result<Vec<u32>, ComputeError> {
+    let compiler = shaderc::Compiler::new()
+        .ok_or_else(|| ComputeError::Compile("failed to initialize shaderc".to_owned()))?;
+    let mut options = shaderc::CompileOptions::new()
+        .ok_or_else(|| ComputeError::Compile("failed to initialize shaderc".to_owned()))?;
+    options.set_target_env(
+        shaderc::TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_2 as u32,
+    );
+    let artifact = compiler
+        .compile_into_spirv(source, shaderc::ShaderKind::Compute, name, "main", Some(&options))
+        .map_err(|e| ComputeError::Compile(e.to_string()))?;
+    Ok(artifact.as_binary().to_vec())
+}
+
+/// A resource the shader declares, as reflected from its SPIR-V.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputeBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// Descriptor types the declaration is compatible with.
+    pub descriptor_types: Vec<DescriptorType>,
+    /// `None` for runtime sized arrays.
+    pub descriptor_count: Option<u32>,
+    /// Whether the shader writes to the resource.
+    pub writable: bool,
+}
+
+/// A compute shader with its pipeline, descriptor bindings and work group size reflected from
+/// its SPIR-V.
+///
+/// Descriptor sets are numbered like in the shader. Dispatches are sized in invocations, the
+/// group count being derived from the local size, so shaders must bounds-check the global
+/// invocation ID against the work size.
+pub struct ComputePipeline {
+    pub pipeline: Arc<vulkano::pipeline::ComputePipeline>,
+    /// `local_size_x`, `local_size_y` and `local_size_z` of the shader.
+    pub local_size: [u32; 3],
+    pub bindings: Vec<ComputeBinding>,
+}
+
+impl ComputePipeline {
+    /// Creates the pipeline of the `main` entry point of SPIR-V module `words`.
+    pub fn new(render_device: &RenderDevice, words: &[u32]) -> Result<Self, ComputeError> {
+        let local_size = reflect_local_size(words)?;
+        // Safety: vulkano parses the module for everything it needs, and drivers validate the
+        // rest. Validation layers catch invalid modules during development.
+        let module = unsafe { ShaderModule::from_words(render_device.device.clone(), words)? };
+        Self::from_module(render_device, module, local_size)
+    }
+
+    /// Creates the pipeline of the `main` entry point of `module`, such as one loaded by
+    /// `vulkano_shaders::shader!`. Modules do not keep their SPIR-V, so `local_size` has to
+    /// match the one the shader declares. It is only checked against the limits of the device.
+    pub fn from_module(
+        render_device: &RenderDevice,
+        module: Arc<ShaderModule>,
+        local_size: [u32; 3],
+    ) -> Result<Self, ComputeError> {
+        let properties = render_device.device.physical_device().properties();
+        if !local_size_fits(
+            local_size,
+            properties.max_compute_work_group_size,
+            properties.max_compute_work_group_invocations,
+        ) {
+            return Err(ComputeError::InvalidLocalSize(local_size));
+        }
+        let entry_point = module.entry_point("main").ok_or(ComputeError::NoEntryPoint)?;
+        let mut bindings: Vec<ComputeBinding> = entry_point
+            .descriptor_requirements()
+            .map(|((set, binding), requirements)| ComputeBinding {
+                set,
+                binding,
+                descriptor_types: requirements.descriptor_types.clone(),
+                descriptor_count: requirements.descriptor_count,
+                writable: !requirements.storage_write.is_empty(),
+            })
+            .collect();
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        let pipeline = vulkano::pipeline::ComputePipeline::new(
+            render_device.device.clone(),
+            entry_point,
+            &(),
+            None,
+            |_| {},
+        )?;
+        Ok(Self {
+            pipeline,
+            local_size,
+            bindings,
+        })
+    }
+
+    /// Compiles GLSL `source` and creates its pipeline.
+    pub fn from_glsl(render_device: &RenderDevice, source: &str) -> Result<Self, ComputeError> {
+        Self::new(render_device, &compile_glsl(source, "compute")?)
+    }
+
+    /// Creates the pipeline of a compiled `.spv` file.
+    pub fn open(
+        render_device: &RenderDevice,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, ComputeError> {
+        let words = spirv_words(&std::fs::read(path)?)?;
+        Self::new(render_device, &words)
+    }
+
+    pub fn binding(&self, set: u32, binding: u32) -> Option<&ComputeBinding> {
+        self.bindings
+            .iter()
+            .find(|b| b.set == set && b.binding == binding)
+    }
+
+    /// Number of work groups covering `work_size` invocations along each axis.
+    pub fn group_counts(&self, work_size: [u32; 3]) -> [u32; 3] {
+        group_counts(work_size, self.local_size)
+    }
+
+    /// A descriptor set for set `set` of the shader.
+    pub fn descriptor_set(
+        &self,
+        render_device: &RenderDevice,
+        set: u32,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) -> Arc<PersistentDescriptorSet> {
+        PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            self.pipeline.layout().set_layouts()[set as usize].clone(),
+            writes,
+        )
+        .unwrap()
+    }
+
+    /// Binds the pipeline, and `descriptor_sets` from set 0 on.
+    pub fn bind(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        descriptor_sets: Vec<Arc<PersistentDescriptorSet>>,
+    ) {
+        builder.bind_pipeline_compute(self.pipeline.clone());
+        if !descriptor_sets.is_empty() {
+            builder.bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_sets,
+            );
+        }
+    }
+
+    pub fn push_constants<Pc: BufferContents>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        push_constants: Pc,
+    ) {
+        builder.push_constants(self.pipeline.layout().clone(), 0, push_constants);
+    }
+
+    /// Dispatches at least `work_size` invocations. The pipeline must be bound.
+    pub fn dispatch(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        work_size: [u32; 3],
+    ) {
+        builder.dispatch(self.group_counts(work_size)).unwrap();
+    }
+
+    /// Binds the pipeline and `descriptor_sets`, then dispatches `work_size` invocations.
+    pub fn bind_and_dispatch(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        descriptor_sets: Vec<Arc<PersistentDescriptorSet>>,
+        work_size: [u32; 3],
+    ) {
+        self.bind(builder, descriptor_sets);
+        self.dispatch(builder, work_size);
+    }
+}
+
+/// Splits the bytes of a `.spv` file into little-endian words.
+fn spirv_words(bytes: &[u8]) -> Result<Vec<u32>, ComputeError> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(ComputeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "SPIR-V length is not a multiple of 4 bytes",
+        )));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect())
+}
+
+fn local_size_fits(local_size: [u32; 3], max_size: [u32; 3], max_invocations: u32) -> bool {
+    let invocations = local_size.iter().map(|&size| size as u64).product::<u64>();
+    local_size.iter().zip(max_size).all(|(&size, max)| (1..=max).contains(&size))
+        && invocations <= max_invocations as u64
+}
+
+/// Reads the local size declared by the `main` compute entry point.
+///
+/// Specialization constants count with their default value, as pipelines are created without
+/// specialization.
+pub(crate) fn reflect_local_size(words: &[u32]) -> Result<[u32; 3], ComputeError> {
+    let spirv = Spirv::new(words)?;
+    let entry_point = spirv
+        .iter_entry_point()
+        .find_map(|instruction| match instruction {
+            Instruction::EntryPoint {
+                entry_point, name, ..
+            } if name == "main" => Some(*entry_point),
+            _ => None,
+        })
+        .ok_or(ComputeError::NoEntryPoint)?;
+    let constant = |id: Id| {
+        spirv.iter_global().find_map(|instruction| match instruction {
+            Instruction::Constant {
+                result_id, value, ..
+            }
+            | Instruction::SpecConstant {
+                result_id, value, ..
+            } if *result_id == id => value.first().copied(),
+            _ => None,
+        })
+    };
+    // Results of specialization constant operations are not evaluated.
+    let constants = |ids: [Id; 3]| -> Result<[u32; 3], ComputeError> {
+        let mut local_size = [0; 3];
+        for (size, id) in local_size.iter_mut().zip(ids) {
+            *size = constant(id).ok_or(ComputeError::UnknownLocalSize)?;
+        }
+        Ok(local_size)
+    };
+    // The WorkgroupSize built-in overrides the execution mode.
+    let built_in = spirv.iter_decoration().find_map(|instruction| match instruction {
+        Instruction::Decorate {
+            target,
+            decoration:
+                Decoration::BuiltIn {
+                    built_in: BuiltIn::WorkgroupSize,
+                },
+        } => Some(*target),
+        _ => None,
+    });
+    if let Some(built_in) = built_in {
+        let constituents = spirv.iter_global().find_map(|instruction| match instruction {
+            Instruction::ConstantComposite {
+                result_id,
+                constituents,
+                ..
+            }
+            | Instruction::SpecConstantComposite {
+                result_id,
+                constituents,
+                ..
+            } if *result_id == built_in => Some(constituents),
+            _ => None,
+        });
+        return match constituents.map(|ids| <[Id; 3]>::try_from(ids.as_slice())) {
+            Some(Ok(ids)) => constants(ids),
+            _ => Err(ComputeError::UnknownLocalSize),
+        };
+    }
+    let local_size = spirv
+        .iter_execution_mode()
+        .find_map(|instruction| match instruction {
+            Instruction::ExecutionMode {
+                entry_point: target,
+                mode:
+                    ExecutionMode::LocalSize {
+                        x_size,
+                        y_size,
+                        z_size,
+                    },
+            } if *target == entry_point => Some(Ok([*x_size, *y_size, *z_size])),
+            Instruction::ExecutionMode {
+                entry_point: target,
+                mode:
+                    ExecutionMode::LocalSizeId {
+                        x_size,
+                        y_size,
+                        z_size,
+                    },
+            } if *target == entry_point => Some(constants([*x_size, *y_size, *z_size])),
+            _ => None,
+        })
+        // Without an execution mode, Vulkan defaults to one invocation per group.
+        .unwrap_or(Ok([1, 1, 1]));
+    local_size
+}
+
+/// A device local storage buffer initialized with `data`. Records the upload into `builder`.
+pub fn storage_buffer_from_iter<T, I>(
+    render_device: &RenderDevice,
+    data: I,
+    usage: BufferUsage,
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+) -> Arc<DeviceLocalBuffer<[T]>>
+where
+    [T]: BufferContents,
+    I: IntoIterator<Item = T>,
+    I::IntoIter: ExactSizeIterator,
+{
+    DeviceLocalBuffer::from_iter(
+        &render_device.memory_allocator,
+        data,
+        BufferUsage {
+            storage_buffer: true,
+            ..usage
+        },
+        builder,
+    )
+    .unwrap()
+}
+
+/// An uninitialized device local storage buffer of `len` elements.
+pub fn storage_buffer<T>(
+    render_device: &RenderDevice,
+    len: u64,
+    usage: BufferUsage,
+) -> Arc<DeviceLocalBuffer<[T]>>
+where
+    [T]: BufferContents,
+{
+    DeviceLocalBuffer::array(
+        &render_device.memory_allocator,
+        len,
+        BufferUsage {
+            storage_buffer: true,
+            ..usage
+        },
+        [render_device.present_queue.queue_family_index()],
+    )
+    .unwrap()
+}
+
+/// A host visible buffer of `len` elements to copy results into, for reading them on the CPU
+/// once the copy finished.
+pub fn readback_buffer<T>(render_device: &RenderDevice, len: u64) -> Arc<CpuAccessibleBuffer<[T]>>
+where
+    [T]: BufferContents,
+{
+    // Safety: the buffer is only meant to be read after the GPU wrote it.
+    unsafe {
+        CpuAccessibleBuffer::uninitialized_array(
+            &render_device.memory_allocator,
+            len,
+            BufferUsage {
+                transfer_dst: true,
+                ..BufferUsage::empty()
+            },
+            true,
+        )
+        .unwrap()
+    }
+}
+
+fn group_counts(work_size: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    [0, 1, 2].map(|axis| work_size[axis].div_ceil(local_size[axis]))
+}
+
+/// A 2D image shaders can load from and store to, that can also be sampled and copied from.
+pub fn storage_image(
+    render_device: &RenderDevice,
+    width: u32,
+    height: u32,
+    format: Format,
+) -> Arc<ImageView<StorageImage>> {
+    let image = StorageImage::with_usage(
+        &render_device.memory_allocator,
+        ImageDimensions::Dim2d {
+            width,
+            height,
+            array_layers: 1,
+        },
+        format,
+        ImageUsage {
+            storage: true,
+            sampled: true,
+            transfer_src: true,
+            transfer_dst: true,
+            ..ImageUsage::empty()
+        },
+        ImageCreateFlags::empty(),
+        [render_device.present_queue.queue_family_index()],
+    )
+    .unwrap();
+    ImageView::new_default(image).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_counts_round_up() {
+        assert_eq!(group_counts([1000, 1, 1], [64, 1, 1]), [16, 1, 1]);
+        assert_eq!(group_counts([1024, 1, 1], [64, 1, 1]), [16, 1, 1]);
+        assert_eq!(group_counts([1025, 17, 3], [64, 8, 4]), [17, 3, 1]);
+    }
+
+    #[test]
+    fn group_counts_of_nothing() {
+        assert_eq!(group_counts([0, 0, 0], [64, 1, 1]), [0, 0, 0]);
+    }
+
+    #[test]
+    fn local_size_is_reflected() {
+        let source = "#version 450\n\
+            layout(local_size_x = 8, local_size_y = 4) in;\n\
+            void main() {}\n";
+        let words = compile_glsl(source, "sized.comp").unwrap();
+        assert_eq!(reflect_local_size(&words).unwrap(), [8, 4, 1]);
+    }
+
+    /// Replaces the instruction starting with `from` by `to`, of the same length.
+    fn patch(words: &mut [u32], from: &[u32], to: &[u32]) {
+        let start = words
+            .windows(from.len())
+            .position(|window| window == from)
+            .unwrap();
+        words[start..start + to.len()].copy_from_slice(to);
+    }
+
+    /// Declares local size `[32, 2, 1]` through the execution mode, and through the
+    /// WorkgroupSize built-in made of specialization constant `%7` and constants `%8` and `%9`.
+    fn specialized_words() -> Vec<u32> {
+        let source = "#version 450\n\
+            layout(local_size_x = 32, local_size_x_id = 0, local_size_y = 2) in;\n\
+            void main() {}\n";
+        compile_glsl(source, "specialized.comp").unwrap()
+    }
+
+    const OP_SPEC_CONSTANT: u32 = 4 << 16 | 50;
+    const OP_EXECUTION_MODE: u32 = 6 << 16 | 16;
+    const OP_DECORATE: u32 = 4 << 16 | 71;
+
+    #[test]
+    fn workgroup_size_built_in_overrides_the_execution_mode() {
+        let mut words = specialized_words();
+        assert_eq!(reflect_local_size(&words).unwrap(), [32, 2, 1]);
+        // Change the default of the specialization constant to 16.
+        patch(&mut words, &[OP_SPEC_CONSTANT, 6, 7, 32], &[OP_SPEC_CONSTANT, 6, 7, 16]);
+        assert_eq!(reflect_local_size(&words).unwrap(), [16, 2, 1]);
+    }
+
+    #[test]
+    fn local_size_ids_are_evaluated() {
+        let mut words = specialized_words();
+        // Drop the built-in, by decorating with another one, and refer to the constants from
+        // a LocalSizeId execution mode instead.
+        patch(&mut words, &[OP_DECORATE, 11, 11, 25], &[OP_DECORATE, 11, 11, 28]);
+        patch(&mut words, &[OP_EXECUTION_MODE, 4, 17], &[OP_EXECUTION_MODE, 4, 38, 7, 8, 9]);
+        patch(&mut words, &[OP_SPEC_CONSTANT, 6, 7, 32], &[OP_SPEC_CONSTANT, 6, 7, 8]);
+        assert_eq!(reflect_local_size(&words).unwrap(), [8, 2, 1]);
+        // An id that is not a constant, like the type of the constants.
+        patch(&mut words, &[OP_EXECUTION_MODE, 4, 38], &[OP_EXECUTION_MODE, 4, 38, 6]);
+        assert!(matches!(reflect_local_size(&words), Err(ComputeError::UnknownLocalSize)));
+    }
+
+    #[test]
+    fn local_size_must_fit_the_device() {
+        assert!(local_size_fits([64, 1, 1], [1024, 1024, 64], 1024));
+        assert!(!local_size_fits([0, 1, 1], [1024, 1024, 64], 1024));
+        assert!(!local_size_fits([1, 1, 128], [1024, 1024, 64], 1024));
+        assert!(!local_size_fits([64, 32, 1], [1024, 1024, 64], 1024));
+    }
+
+    #[test]
+    fn spirv_files_are_whole_words() {
+        assert_eq!(spirv_words(&[3, 2, 35, 7]).unwrap(), [0x0723_0203]);
+        assert!(matches!(spirv_words(&[3, 2, 35, 7, 0]), Err(ComputeError::Io(_))));
+    }
+
+    #[test]
+    fn compile_glsl_gives_spirv() {
+        let source = "#version 450\nlayout(local_size_x = 64) in;\nvoid main() {}\n";
+        let words = compile_glsl(source, "empty.comp").unwrap();
+        assert_eq!(words[0], 0x0723_0203, "SPIR-V magic number");
+    }
+
+    #[test]
+    fn compile_glsl_reports_errors() {
+        let source = "#version 450\nlayout(local_size_x = 64) in;\nvoid main() { oops }\n";
+        match compile_glsl(source, "broken.comp") {
+            Err(ComputeError::Compile(message)) => {
+                assert!(message.contains("oops"), "{}", message)
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("invalid GLSL compiled"),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use std::f32::consts::TAU;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use vulkano::buffer::{BufferUsage, CpuBufferPool};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::format::Format;
+use vulkano::impl_vertex;
+use vulkano::memory::allocator::{MemoryUsage, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode};
+use crate::camera::{apply_view_set_layout, ViewUniforms, VIEW_SET};
+use crate::debug_utils::{begin_label, end_label, set_object_name};
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+
+/// Number of segments of circles and spheres.
+const CIRCLE_SEGMENTS: usize = 32;
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/debug/debug_draw.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/debug/debug_draw.frag",
+    }
+}
+
+/// How a debug primitive is drawn. Converts from a bare color for one frame, depth tested
+/// primitives.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DebugStyle {
+    /// Linear color.
+    pub color: [f32; 4],
+    /// Seconds the primitive stays visible. Primitives are drawn at least once, so zero
+    /// means this frame only.
+    pub duration: f32,
+    /// Hide the primitive behind scene geometry. Otherwise it is drawn on top of everything.
+    pub depth_test: bool,
+}
+
+impl Default for DebugStyle {
+    fn default() -> Self {
+        Self {
+            color: [1.0; 4],
+            duration: 0.0,
+            depth_test: true,
+        }
+    }
+}
+
+impl From<[f32; 4]> for DebugStyle {
+    fn from(color: [f32; 4]) -> Self {
+        Self {
+            color,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+impl_vertex!(DebugVertex, position, color);
+
+#[derive(Clone, Copy, Debug)]
+struct DebugLine {
+    start: Vec3,
+    end: Vec3,
+    style: DebugStyle,
+}
+
+/// Collects debug lines from anywhere in the frame and draws them in one go.
+///
+/// Every shape is made of line segments. Segments are rebuilt into a fresh vertex buffer every
+/// frame and drawn in two draws, depth tested ones first.
+pub struct DebugDraw {
+    pub depth_tested_pipeline: Arc<GraphicsPipeline>,
+    pub overlay_pipeline: Arc<GraphicsPipeline>,
+    lines: Vec<DebugLine>,
+    vertex_pool: CpuBufferPool<DebugVertex, StandardMemoryAllocator>,
+}
+
+impl DebugDraw {
+    /// Creates pipelines rendering into a `color_format` attachment, with the depth attachment
+    /// and sample count of `render_output`. Depth tested lines compare with
+    /// `depth_compare_op`, see [`crate::camera::Camera::depth_compare_op`], and never write
+    /// depth.
+    pub fn new(
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        color_format: Format,
+        depth_compare_op: CompareOp,
+    ) -> Self {
+        let vs = vs::load(render_device.device.clone()).unwrap();
+        let fs = fs::load(render_device.device.clone()).unwrap();
+        let pipeline = |depth_stencil_state| {
+            GraphicsPipeline::start()
+                .render_pass(PipelineRenderingCreateInfo {
+                    color_attachment_formats: vec![Some(color_format)],
+                    depth_attachment_format: Some(render_output.depth_format),
+                    ..Default::default()
+                })
+                .vertex_input_state(BuffersDefinition::new().vertex::<DebugVertex>())
+                .input_assembly_state(
+                    InputAssemblyState::new().topology(PrimitiveTopology::LineList),
+                )
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .fragment_shader(fs.entry_point("main").unwrap(), ())
+                .multisample_state(MultisampleState {
+                    rasterization_samples: render_output.samples,
+                    ..Default::default()
+                })
+                .depth_stencil_state(depth_stencil_state)
+                .color_blend_state(ColorBlendState::new(1).blend(AttachmentBlend::alpha()))
+                .with_auto_layout(render_device.device.clone(), apply_view_set_layout)
+                .unwrap()
+        };
+        let depth_tested_pipeline = pipeline(DepthStencilState {
+            depth: Some(DepthState {
+                enable_dynamic: false,
+                compare_op: StateMode::Fixed(depth_compare_op),
+                write_enable: StateMode::Fixed(false),
+            }),
+            ..Default::default()
+        });
+        let overlay_pipeline = pipeline(DepthStencilState::disabled());
+        set_object_name(depth_tested_pipeline.as_ref(), "debug draw depth tested");
+        set_object_name(overlay_pipeline.as_ref(), "debug draw overlay");
+        let vertex_pool = CpuBufferPool::new(
+            render_device.memory_allocator.clone(),
+            BufferUsage {
+                vertex_buffer: true,
+                ..BufferUsage::empty()
+            },
+            MemoryUsage::Upload,
+        );
+        Self {
+            depth_tested_pipeline,
+            overlay_pipeline,
+            lines: Vec::new(),
+            vertex_pool,
+        }
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, style: impl Into<DebugStyle>) {
+        self.lines.push(DebugLine {
+            start,
+            end,
+            style: style.into(),
+        });
+    }
+
+    /// A line with an arrow head at `end`.
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        self.line(start, end, style);
+        let direction = end - start;
+        let length = direction.length();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let head_length = length * 0.2;
+        let back = end - direction / length * head_length;
+        let (side, up) = (direction / length).any_orthonormal_pair();
+        for offset in [side, -side, up, -up] {
+            self.line(end, back + offset * head_length * 0.4, style);
+        }
+    }
+
+    /// An axis aligned box.
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, style: impl Into<DebugStyle>) {
+        let transform =
+            Mat4::from_translation((min + max) * 0.5) * Mat4::from_scale(max - min);
+        self.cube(transform, style);
+    }
+
+    /// The unit cube centered on the origin, transformed by `transform`.
+    pub fn cube(&mut self, transform: Mat4, style: impl Into<DebugStyle>) {
+        let corners = [
+            Vec3::new(-0.5, -0.5, -0.5),
+            Vec3::new(0.5, -0.5, -0.5),
+            Vec3::new(0.5, 0.5, -0.5),
+            Vec3::new(-0.5, 0.5, -0.5),
+            Vec3::new(-0.5, -0.5, 0.5),
+            Vec3::new(0.5, -0.5, 0.5),
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(-0.5, 0.5, 0.5),
+        ]
+        .map(|corner| transform.transform_point3(corner));
+        self.box_edges(&corners, style.into());
+    }
+
+    /// The volume seen by a camera with `view_projection`. Works with either depth convention.
+    pub fn frustum(&mut self, view_projection: Mat4, style: impl Into<DebugStyle>) {
+        let inverse = view_projection.inverse();
+        let corners = [
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(-1.0, 1.0, 0.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(1.0, -1.0, 1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(-1.0, 1.0, 1.0),
+        ]
+        .map(|corner| inverse.project_point3(corner));
+        self.box_edges(&corners, style.into());
+    }
+
+    /// The 12 edges between two quads of corners, each in winding order.
+    fn box_edges(&mut self, corners: &[Vec3; 8], style: DebugStyle) {
+        for i in 0..4 {
+            let j = (i + 1) % 4;
+            self.line(corners[i], corners[j], style);
+            self.line(corners[i + 4], corners[j + 4], style);
+            self.line(corners[i], corners[i + 4], style);
+        }
+    }
+
+    /// A circle in the plane through `center` perpendicular to `normal`.
+    pub fn circle(
+        &mut self,
+        center: Vec3,
+        normal: Vec3,
+        radius: f32,
+        style: impl Into<DebugStyle>,
+    ) {
+        let style = style.into();
+        let (u, v) = normal.normalize().any_orthonormal_pair();
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+            center + (u * angle.cos() + v * angle.sin()) * radius
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), style);
+        }
+    }
+
+    /// A sphere drawn as its three axis aligned great circles.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            self.circle(center, axis, radius, style);
+        }
+    }
+
+    /// A square grid in the XZ plane centered on `center`, `size` wide and split into
+    /// `divisions` cells along each side.
+    pub fn grid(&mut self, center: Vec3, size: f32, divisions: u32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let divisions = divisions.max(1);
+        let half = size * 0.5;
+        for i in 0..=divisions {
+            let offset = i as f32 / divisions as f32 * size - half;
+            self.line(
+                center + Vec3::new(offset, 0.0, -half),
+                center + Vec3::new(offset, 0.0, half),
+                style,
+            );
+            self.line(
+                center + Vec3::new(-half, 0.0, offset),
+                center + Vec3::new(half, 0.0, offset),
+                style,
+            );
+        }
+    }
+
+    /// The X, Y and Z axes of `transform` in red, green and blue.
+    pub fn axes(&mut self, transform: Mat4, size: f32, duration: f32, depth_test: bool) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        for (axis, color) in [
+            (Vec3::X, [1.0, 0.0, 0.0, 1.0]),
+            (Vec3::Y, [0.0, 1.0, 0.0, 1.0]),
+            (Vec3::Z, [0.0, 0.0, 1.0, 1.0]),
+        ] {
+            let style = DebugStyle {
+                color,
+                duration,
+                depth_test,
+            };
+            self.arrow(origin, transform.transform_point3(axis * size), style);
+        }
+    }
+
+    /// Removes every primitive, including those whose duration has not run out.
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Records the draw of every live primitive, then ages them by `delta_time` seconds and
+    /// drops those that expired. Call once per frame, inside dynamic rendering with a viewport
+    /// set and the scene depth attachment loaded, after the scene is drawn.
+    pub fn draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        view_uniforms: &ViewUniforms,
+        delta_time: f32,
+    ) {
+        if !self.lines.is_empty() {
+            // Depth tested lines first, so that each pipeline draws one contiguous range.
+            self.lines.sort_by_key(|line| !line.style.depth_test);
+            let depth_tested = self
+                .lines
+                .iter()
+                .take_while(|line| line.style.depth_test)
+                .count() as u32;
+            let vertices: Vec<DebugVertex> = self
+                .lines
+                .iter()
+                .flat_map(|line| {
+                    [line.start, line.end].map(|position| DebugVertex {
+                        position: position.to_array(),
+                        color: line.style.color,
+                    })
+                })
+                .collect();
+            let vertices = self.vertex_pool.from_iter(vertices).unwrap();
+            let overlay = self.lines.len() as u32 - depth_tested;
+            begin_label(builder, "debug draw");
+            for (pipeline, first, count) in [
+                (&self.depth_tested_pipeline, 0, depth_tested),
+                (&self.overlay_pipeline, depth_tested, overlay),
+            ] {
+                if count == 0 {
+                    continue;
+                }
+                builder
+                    .bind_pipeline_graphics(pipeline.clone())
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        pipeline.layout().clone(),
+                        VIEW_SET,
+                        view_uniforms.descriptor_set.clone(),
+                    )
+                    .bind_vertex_buffers(0, vertices.clone())
+                    .draw(count * 2, 1, first * 2, 0)
+                    .unwrap();
+            }
+            end_label(builder);
+        }
+
+        expire_lines(&mut self.lines, delta_time);
+    }
+}
+
+/// Ages drawn lines by `delta_time` seconds and drops those whose duration ran out.
+fn expire_lines(lines: &mut Vec<DebugLine>, delta_time: f32) {
+    for line in lines.iter_mut() {
+        line.style.duration -= delta_time;
+    }
+    lines.retain(|line| line.style.duration > 0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(duration: f32) -> DebugLine {
+        DebugLine {
+            start: Vec3::ZERO,
+            end: Vec3::X,
+            style: DebugStyle {
+                duration,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn durations(lines: &[DebugLine]) -> Vec<f32> {
+        lines.iter().map(|line| line.style.duration).collect()
+    }
+
+    #[test]
+    fn zero_duration_lines_last_one_frame() {
+        let mut lines = vec![line(0.0), line(0.0)];
+        expire_lines(&mut lines, 0.0);
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn lines_expire_once_their_duration_ran_out() {
+        let mut lines = vec![line(0.5), line(1.0), line(0.25)];
+        expire_lines(&mut lines, 0.25);
+        assert_eq!(durations(&lines), [0.25, 0.75]);
+        expire_lines(&mut lines, 0.25);
+        assert_eq!(durations(&lines), [0.5]);
+        expire_lines(&mut lines, 0.5);
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn long_frames_expire_several_lines_at_once() {
+        let mut lines = vec![line(0.1), line(2.0), line(0.2)];
+        expire_lines(&mut lines, 1.0);
+        assert_eq!(durations(&lines), [1.0]);
+    }
+}
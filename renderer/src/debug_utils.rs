@@ -0,0 +1,67 @@
+use vulkano::buffer::BufferAccess;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::image::ImageAccess;
+use vulkano::instance::debug::DebugUtilsLabel;
+use vulkano::VulkanObject;
+
+/// Whether objects of `device` can be named and command buffers labeled, which requires
+/// `ext_debug_utils` on the instance. Everything in this module does nothing otherwise.
+pub fn debug_utils_enabled(device: &Device) -> bool {
+    device.instance().enabled_extensions().ext_debug_utils
+}
+
+/// Names `object` in validation messages and graphics debuggers.
+pub fn set_object_name<T: VulkanObject + DeviceOwned>(object: &T, name: &str) {
+    let device = object.device();
+    if debug_utils_enabled(device) {
+        device.set_debug_utils_object_name(object, Some(name)).unwrap();
+    }
+}
+
+/// Names the Vulkan buffer behind `buffer`. Buffers allocated from a pool share their name.
+pub fn set_buffer_name(buffer: &(impl BufferAccess + ?Sized), name: &str) {
+    set_object_name(buffer.inner().buffer.as_ref(), name);
+}
+
+/// Names the Vulkan image behind `image`.
+pub fn set_image_name(image: &(impl ImageAccess + ?Sized), name: &str) {
+    set_object_name(image.inner().image.as_ref(), name);
+}
+
+/// Opens a labeled region of commands, closed by [`end_label`]. Regions nest.
+pub fn begin_label(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    name: &str,
+) {
+    if debug_utils_enabled(builder.device()) {
+        builder
+            .begin_debug_utils_label(DebugUtilsLabel {
+                label_name: name.to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+}
+
+/// Closes the region opened by the last [`begin_label`].
+pub fn end_label(builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+    if debug_utils_enabled(builder.device()) {
+        // The region was opened by `begin_label` with the same extension check.
+        unsafe {
+            builder.end_debug_utils_label().unwrap();
+        }
+    }
+}
+
+/// Wraps the commands recorded by `record` in a labeled region.
+pub fn label<R>(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    name: &str,
+    record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> R,
+) -> R {
+    begin_label(builder, name);
+    let result = record(builder);
+    end_label(builder);
+    result
+}
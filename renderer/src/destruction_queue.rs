@@ -0,0 +1,243 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use vulkano::sync::{FenceSignalFuture, GpuFuture};
+
+/// Tells whether the GPU finished a submitted frame.
+pub trait FrameFence {
+    fn is_complete(&self) -> bool;
+    /// Blocks until the frame completed.
+    fn wait(&self);
+}
+
+impl<F> FrameFence for FenceSignalFuture<F>
+where
+    F: GpuFuture,
+{
+    // Errors mean the device is lost or out of memory, and nothing runs on the GPU anymore.
+    fn is_complete(&self) -> bool {
+        self.is_signaled().unwrap_or(true)
+    }
+
+    fn wait(&self) {
+        FenceSignalFuture::wait(self, None).ok();
+    }
+}
+
+struct PendingFrame {
+    frame: u64,
+    fence: Arc<dyn FrameFence>,
+    resources: Vec<Box<dyn Any>>,
+}
+
+/// Keeps retired buffers, images, pipelines and anything else alive until the GPU is done with
+/// the frame that last used them.
+///
+/// Resources given to [`Self::retire`] while recording a frame belong to that frame, and are
+/// dropped by [`Self::collect`] once the fence given to [`Self::end_frame`] and the fences of
+/// all earlier frames signaled. Call [`Self::flush`] on shutdown to wait for the rest.
+///
+/// Wrap the `FenceSignalFuture` of a frame in an `Arc` to give it to [`Self::end_frame`] and
+/// still chain it into the next frame.
+#[derive(Default)]
+pub struct DestructionQueue {
+    frame: u64,
+    completed: Option<u64>,
+    current: Vec<Box<dyn Any>>,
+    pending: VecDeque<PendingFrame>,
+}
+
+impl DestructionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of the frame being recorded, counting calls to [`Self::end_frame`].
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Index of the last frame known to be complete, updated by [`Self::collect`].
+    pub fn completed_frame(&self) -> Option<u64> {
+        self.completed
+    }
+
+    /// Drops `resource` once the frame being recorded completed.
+    pub fn retire<T: 'static>(&mut self, resource: T) {
+        self.current.push(Box::new(resource));
+    }
+
+    /// Number of resources waiting to be dropped.
+    pub fn len(&self) -> usize {
+        self.current.len()
+            + self
+                .pending
+                .iter()
+                .map(|pending| pending.resources.len())
+                .sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Ends the frame being recorded, whose submission signals `fence`.
+    ///
+    /// Frames that failed to submit should not end, so their resources wait for the next fence.
+    pub fn end_frame(&mut self, fence: Arc<dyn FrameFence>) {
+        self.pending.push_back(PendingFrame {
+            frame: self.frame,
+            fence,
+            resources: std::mem::take(&mut self.current),
+        });
+        self.frame += 1;
+    }
+
+    /// Drops the resources of completed frames, returning how many were dropped. Cheap enough to
+    /// call every frame.
+    pub fn collect(&mut self) -> usize {
+        let mut dropped = 0;
+        // Frames complete in submission order, so stop at the first incomplete one.
+        while let Some(pending) = self.pending.front() {
+            if !pending.fence.is_complete() {
+                break;
+            }
+            let pending = self.pending.pop_front().unwrap();
+            self.completed = Some(pending.frame);
+            dropped += pending.resources.len();
+        }
+        dropped
+    }
+
+    /// Waits for every ended frame and drops all retired resources, including those of the frame
+    /// being recorded, which is never going to be submitted.
+    pub fn flush(&mut self) {
+        for pending in self.pending.drain(..) {
+            pending.fence.wait();
+            self.completed = Some(pending.frame);
+        }
+        self.current.clear();
+    }
+
+    /// Drops all retired resources without waiting, after the device was lost and nothing
+    /// submitted to it is ever going to run.
+    pub fn abandon(&mut self) {
+        self.pending.clear();
+        self.current.clear();
+    }
+}
+
+impl Drop for DestructionQueue {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeFence {
+        signaled: AtomicBool,
+        waited: AtomicBool,
+    }
+
+    impl FrameFence for FakeFence {
+        fn is_complete(&self) -> bool {
+            self.signaled.load(Ordering::Relaxed)
+        }
+
+        fn wait(&self) {
+            self.waited.store(true, Ordering::Relaxed);
+            self.signaled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Logs its name when dropped.
+    struct Resource(&'static str, Rc<RefCell<Vec<&'static str>>>);
+
+    impl Drop for Resource {
+        fn drop(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    fn end_frame(queue: &mut DestructionQueue) -> Arc<FakeFence> {
+        let fence = Arc::new(FakeFence::default());
+        queue.end_frame(fence.clone());
+        fence
+    }
+
+    #[test]
+    fn resources_live_until_their_frame_completes() {
+        let dropped = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = DestructionQueue::new();
+        queue.retire(Resource("a", dropped.clone()));
+        queue.retire(Resource("b", dropped.clone()));
+        let first = end_frame(&mut queue);
+        queue.retire(Resource("c", dropped.clone()));
+        let second = end_frame(&mut queue);
+        assert_eq!(queue.frame(), 2);
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.collect(), 0);
+        assert_eq!(queue.completed_frame(), None);
+        first.signaled.store(true, Ordering::Relaxed);
+        assert_eq!(queue.collect(), 2);
+        assert_eq!(*dropped.borrow(), ["a", "b"]);
+        assert_eq!(queue.completed_frame(), Some(0));
+        second.signaled.store(true, Ordering::Relaxed);
+        assert_eq!(queue.collect(), 1);
+        assert_eq!(*dropped.borrow(), ["a", "b", "c"]);
+        assert_eq!(queue.completed_frame(), Some(1));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn frames_complete_in_submission_order() {
+        let dropped = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = DestructionQueue::new();
+        queue.retire(Resource("a", dropped.clone()));
+        let first = end_frame(&mut queue);
+        queue.retire(Resource("b", dropped.clone()));
+        let second = end_frame(&mut queue);
+
+        // A later fence signaling does not release an earlier frame.
+        second.signaled.store(true, Ordering::Relaxed);
+        assert_eq!(queue.collect(), 0);
+        assert!(dropped.borrow().is_empty());
+        first.signaled.store(true, Ordering::Relaxed);
+        assert_eq!(queue.collect(), 2);
+        assert_eq!(*dropped.borrow(), ["a", "b"]);
+    }
+
+    #[test]
+    fn flush_waits_and_drops_everything() {
+        let dropped = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = DestructionQueue::new();
+        queue.retire(Resource("a", dropped.clone()));
+        let fence = end_frame(&mut queue);
+        queue.retire(Resource("recording", dropped.clone()));
+        queue.flush();
+        assert!(fence.waited.load(Ordering::Relaxed));
+        assert_eq!(*dropped.borrow(), ["a", "recording"]);
+        assert_eq!(queue.completed_frame(), Some(0));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn abandon_drops_without_waiting() {
+        let dropped = Rc::new(RefCell::new(Vec::new()));
+        let mut queue = DestructionQueue::new();
+        queue.retire(Resource("a", dropped.clone()));
+        let fence = end_frame(&mut queue);
+        queue.abandon();
+        assert!(!fence.waited.load(Ordering::Relaxed));
+        assert_eq!(*dropped.borrow(), ["a"]);
+        assert_eq!(queue.completed_frame(), None);
+    }
+}
@@ -0,0 +1,743 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use vulkano::command_buffer::{PrimaryCommandBufferAbstract, SemaphoreSubmitInfo, SubmitInfo};
+use vulkano::device::{Device, DeviceExtensions, DeviceOwned, Queue};
+use vulkano::format::Format;
+use vulkano::image::sys::{Image, ImageCreateInfo, RawImage};
+use vulkano::image::traits::ImageContent;
+use vulkano::image::view::ImageView;
+use vulkano::image::{
+    ImageAccess, ImageCreateFlags, ImageDescriptorLayouts, ImageDimensions, ImageInner,
+    ImageLayout, ImageUsage, StorageImage,
+};
+use vulkano::memory::allocator::{MemoryAlloc, MemoryAllocator, MemoryUsage};
+use vulkano::memory::{
+    DedicatedAllocation, DeviceMemory, ExternalMemoryHandleType, ExternalMemoryHandleTypes,
+    MemoryAllocateInfo, MemoryImportInfo,
+};
+use vulkano::sync::{
+    ExternalSemaphoreHandleType, ExternalSemaphoreHandleTypes, Fence, FenceCreateInfo,
+    Semaphore, SemaphoreCreateInfo,
+};
+use vulkano::{DeviceSize, VulkanObject};
+use crate::debug_utils::set_image_name;
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+
+/// Device extensions for exporting and importing frames. [`RenderDevice`] enables them when
+/// supported.
+pub fn device_extensions() -> DeviceExtensions {
+    DeviceExtensions {
+        khr_external_memory_fd: true,
+        khr_external_semaphore_fd: true,
+        ..DeviceExtensions::empty()
+    }
+}
+
+/// Usage of exported frames: rendered to, then copied or sampled by the consumer.
+pub const FRAME_USAGE: ImageUsage = ImageUsage {
+    transfer_src: true,
+    transfer_dst: true,
+    sampled: true,
+    color_attachment: true,
+    ..ImageUsage::empty()
+};
+
+const MAGIC: [u8; 4] = *b"AFRM";
+const HEADER_SIZE: usize = 4 + 4 * 4 + 8 + 16 + 16;
+/// Memory, `rendered` and `released` of every frame.
+const FDS_PER_FRAME: usize = 3;
+/// Frames that fit in one `SCM_RIGHTS` message, which Linux limits to 253 descriptors.
+pub const MAX_FRAMES: u32 = 64;
+
+/// What the consumer needs to know to import the frames, sent ahead of their descriptors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameDescription {
+    pub extent: [u32; 2],
+    /// Raw `VkFormat`, which both sides have to agree on, see [`FrameImporter::new`].
+    pub format: i32,
+    pub frame_count: u32,
+    /// Size of the memory of each frame.
+    pub allocation_size: DeviceSize,
+    /// Memory can only be shared between devices with the same device and driver UUIDs,
+    /// usually the same GPU and driver in two processes.
+    pub device_uuid: [u8; 16],
+    pub driver_uuid: [u8; 16],
+}
+
+impl FrameDescription {
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&self.extent[0].to_le_bytes());
+        bytes.extend_from_slice(&self.extent[1].to_le_bytes());
+        bytes.extend_from_slice(&self.format.to_le_bytes());
+        bytes.extend_from_slice(&self.frame_count.to_le_bytes());
+        bytes.extend_from_slice(&self.allocation_size.to_le_bytes());
+        bytes.extend_from_slice(&self.device_uuid);
+        bytes.extend_from_slice(&self.driver_uuid);
+        bytes.try_into().unwrap()
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> io::Result<Self> {
+        if bytes[0..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a frame export stream",
+            ));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        Ok(Self {
+            extent: [u32_at(4), u32_at(8)],
+            format: u32_at(12) as i32,
+            frame_count: u32_at(16),
+            allocation_size: u64::from_le_bytes(bytes[20..28].try_into().unwrap()),
+            device_uuid: bytes[28..44].try_into().unwrap(),
+            driver_uuid: bytes[44..60].try_into().unwrap(),
+        })
+    }
+
+    fn for_device(device: &Device) -> ([u8; 16], [u8; 16]) {
+        let properties = device.physical_device().properties();
+        (
+            properties.device_uuid.unwrap_or_default(),
+            properties.driver_uuid.unwrap_or_default(),
+        )
+    }
+}
+
+/// A render target whose memory and semaphores the consumer imported.
+pub struct ExportedFrame {
+    pub image: Arc<StorageImage>,
+    pub view: Arc<ImageView<StorageImage>>,
+    /// Signaled when the frame was rendered, waited for by the consumer.
+    rendered: Arc<Semaphore>,
+    /// Signaled by the consumer once it is done with the frame.
+    released: Arc<Semaphore>,
+    /// Signaled when the last submission rendering to the frame completed.
+    fence: Option<Arc<Fence>>,
+    /// The consumer has the frame, and the next submission has to wait for `released`.
+    held_by_consumer: bool,
+    /// The consumer submitted its signal of `released`, which is not yet waited for.
+    release_pending: bool,
+}
+
+/// Render targets allocated with exportable memory, shared zero-copy with another process over
+/// a Unix socket, e.g. a video encoder or a compositor running [`FrameImporter`].
+///
+/// Frames go round a ring: [`Self::acquire`] picks the next one the consumer gave back,
+/// commands render to its `view`, and [`Self::present`] submits them and hands the frame over.
+/// Rendering and consuming are ordered on the GPU by exported semaphores; the socket only
+/// carries frame indices, so neither process waits for the other's GPU work on the CPU.
+///
+/// Images stay in the `General` layout, and are not transferred between queue families. This is
+/// enough for lavapipe and drivers that share images between processes as they are.
+pub struct FrameExporter {
+    pub description: FrameDescription,
+    pub frames: Vec<ExportedFrame>,
+    stream: UnixStream,
+    next: usize,
+}
+
+impl FrameExporter {
+    /// Allocates `frame_count` targets of `extent` and `format`, and sends them to the consumer
+    /// at the other end of `stream`. Fails with [`io::ErrorKind::Unsupported`] if the device
+    /// does not support [`device_extensions`], and with [`io::ErrorKind::InvalidInput`] if
+    /// `frame_count` is 0 or over [`MAX_FRAMES`].
+    pub fn new(
+        render_device: &RenderDevice,
+        stream: UnixStream,
+        extent: [u32; 2],
+        format: Format,
+        frame_count: u32,
+    ) -> io::Result<Self> {
+        if !(1..=MAX_FRAMES).contains(&frame_count) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Between 1 and {} frames can be exported", MAX_FRAMES),
+            ));
+        }
+        let device = &render_device.device;
+        if !device.enabled_extensions().contains(&device_extensions()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Exporting frames needs khr_external_memory_fd and khr_external_semaphore_fd",
+            ));
+        }
+        let frames = (0..frame_count)
+            .map(|i| {
+                let image = StorageImage::new_with_exportable_fd(
+                    &render_device.memory_allocator,
+                    ImageDimensions::Dim2d {
+                        width: extent[0],
+                        height: extent[1],
+                        array_layers: 1,
+                    },
+                    format,
+                    FRAME_USAGE,
+                    ImageCreateFlags::empty(),
+                    [render_device.present_queue.queue_family_index()],
+                )
+                .map_err(io::Error::other)?;
+                let name = format!("exported frame {}", i);
+                set_image_name(image.as_ref(), &name);
+                render_device
+                    .memory_tracker
+                    .track_image(image.as_ref(), MemoryCategory::RenderTarget, &name);
+                Ok(ExportedFrame {
+                    view: ImageView::new_default(image.clone()).map_err(io::Error::other)?,
+                    image,
+                    rendered: exportable_semaphore(device)?,
+                    released: exportable_semaphore(device)?,
+                    fence: None,
+                    held_by_consumer: false,
+                    release_pending: false,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (device_uuid, driver_uuid) = FrameDescription::for_device(device);
+        let description = FrameDescription {
+            extent,
+            format: ash::vk::Format::from(format).as_raw(),
+            frame_count,
+            allocation_size: frames[0].image.mem_size(),
+            device_uuid,
+            driver_uuid,
+        };
+        let mut files = Vec::with_capacity(frames.len() * FDS_PER_FRAME);
+        for frame in &frames {
+            files.push(frame.image.export_posix_fd().map_err(io::Error::other)?);
+            for semaphore in [&frame.rendered, &frame.released] {
+                let file = semaphore.export_fd(ExternalSemaphoreHandleType::OpaqueFd);
+                files.push(file.map_err(io::Error::other)?);
+            }
+        }
+        let fds: Vec<RawFd> = files.iter().map(|file| file.as_raw_fd()).collect();
+        // The consumer gets duplicates, ours close when `files` drops.
+        send_with_fds(&stream, &description.to_bytes(), &fds)?;
+
+        Ok(Self {
+            description,
+            frames,
+            stream,
+            next: 0,
+        })
+    }
+
+    /// Index of the frame to render next. Blocks until the consumer gave it back, and the
+    /// previous rendering to it completed.
+    pub fn acquire(&mut self) -> io::Result<usize> {
+        let index = self.next;
+        while self.frames[index].held_by_consumer {
+            let released = read_index(&mut self.stream, self.frames.len())?;
+            let frame = &mut self.frames[released];
+            frame.held_by_consumer = false;
+            frame.release_pending = true;
+        }
+        if let Some(fence) = self.frames[index].fence.take() {
+            fence.wait(None).unwrap();
+        }
+        self.next = (self.next + 1) % self.frames.len();
+        Ok(index)
+    }
+
+    /// Submits `command_buffer`, which renders to the frame at `index` from [`Self::acquire`],
+    /// then hands the frame to the consumer.
+    pub fn present(
+        &mut self,
+        render_device: &RenderDevice,
+        index: usize,
+        command_buffer: Arc<dyn PrimaryCommandBufferAbstract>,
+    ) -> io::Result<()> {
+        let frame = &mut self.frames[index];
+        let wait = frame.release_pending.then(|| frame.released.clone());
+        frame.fence = Some(submit_between(
+            &render_device.present_queue,
+            wait,
+            command_buffer,
+            frame.rendered.clone(),
+        ));
+        frame.release_pending = false;
+        frame.held_by_consumer = true;
+        write_index(&mut self.stream, index)
+    }
+}
+
+impl Drop for FrameExporter {
+    // The consumer may still read the frames, but it owns its own references to the memory.
+    fn drop(&mut self) {
+        for frame in &mut self.frames {
+            if let Some(fence) = frame.fence.take() {
+                fence.wait(None).ok();
+            }
+        }
+    }
+}
+
+/// An image bound to memory imported from another process. Its layout is `General`, as the
+/// exporter left it.
+pub struct ImportedImage {
+    inner: Arc<Image>,
+}
+
+unsafe impl DeviceOwned for ImportedImage {
+    fn device(&self) -> &Arc<Device> {
+        self.inner.device()
+    }
+}
+
+unsafe impl ImageAccess for ImportedImage {
+    fn inner(&self) -> ImageInner<'_> {
+        ImageInner {
+            image: &self.inner,
+            first_layer: 0,
+            num_layers: 1,
+            first_mipmap_level: 0,
+            num_mipmap_levels: 1,
+        }
+    }
+
+    fn initial_layout_requirement(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    fn final_layout_requirement(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    fn descriptor_layouts(&self) -> Option<ImageDescriptorLayouts> {
+        Some(ImageDescriptorLayouts {
+            storage_image: ImageLayout::General,
+            combined_image_sampler: ImageLayout::General,
+            sampled_image: ImageLayout::General,
+            input_attachment: ImageLayout::General,
+        })
+    }
+
+    // The exporter initialized the layout, so it must not be discarded from `Undefined`.
+    fn is_layout_initialized(&self) -> bool {
+        true
+    }
+}
+
+unsafe impl<P> ImageContent<P> for ImportedImage {
+    fn matches_format(&self) -> bool {
+        true
+    }
+}
+
+impl PartialEq for ImportedImage {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner() == other.inner()
+    }
+}
+
+impl Eq for ImportedImage {}
+
+impl std::hash::Hash for ImportedImage {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.inner().hash(state);
+    }
+}
+
+/// A frame of the exporter, as seen by the consumer.
+pub struct ImportedFrame {
+    pub image: Arc<ImportedImage>,
+    rendered: Arc<Semaphore>,
+    released: Arc<Semaphore>,
+    /// Signaled when the last submission reading the frame completed.
+    fence: Option<Arc<Fence>>,
+}
+
+/// The consumer end of a [`FrameExporter`], in another process.
+///
+/// [`Self::next_frame`] waits for the exporter to present a frame, and [`Self::release`]
+/// submits the commands reading it, e.g. a copy to a buffer for an encoder, before giving it
+/// back.
+pub struct FrameImporter {
+    pub description: FrameDescription,
+    pub frames: Vec<ImportedFrame>,
+    stream: UnixStream,
+}
+
+impl FrameImporter {
+    /// Receives and imports the frames sent by [`FrameExporter::new`], which have to be of
+    /// `format`. Fails with [`io::ErrorKind::InvalidData`] if the exporter runs on another
+    /// device or driver, uses another format or sends descriptors that cannot be imported, and
+    /// with [`io::ErrorKind::Unsupported`] if the device does not support
+    /// [`device_extensions`].
+    pub fn new(
+        render_device: &RenderDevice,
+        stream: UnixStream,
+        format: Format,
+    ) -> io::Result<Self> {
+        let device = &render_device.device;
+        if !device.enabled_extensions().contains(&device_extensions()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Importing frames needs khr_external_memory_fd and khr_external_semaphore_fd",
+            ));
+        }
+        let mut header = [0; HEADER_SIZE];
+        let mut files = recv_with_fds(&stream, &mut header, MAX_FRAMES as usize * FDS_PER_FRAME)?;
+        let description = FrameDescription::from_bytes(&header)?;
+        let exported_from = (description.device_uuid, description.driver_uuid);
+        if exported_from != FrameDescription::for_device(device) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Frames can only be imported on the device and driver they were exported from",
+            ));
+        }
+        if description.format != ash::vk::Format::from(format).as_raw() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The exporter uses another format",
+            ));
+        }
+        if files.len() != description.frame_count as usize * FDS_PER_FRAME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Missing frame descriptors",
+            ));
+        }
+
+        let mut files = files.drain(..);
+        let frames = (0..description.frame_count)
+            .map(|i| {
+                let mut next = || files.next().unwrap();
+                let image = import_image(render_device, &description, format, next())?;
+                let name = format!("imported frame {}", i);
+                set_image_name(image.as_ref(), &name);
+                Ok(ImportedFrame {
+                    image,
+                    rendered: import_semaphore(device, next())?,
+                    released: import_semaphore(device, next())?,
+                    fence: None,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            description,
+            frames,
+            stream,
+        })
+    }
+
+    /// Blocks until the exporter presented a frame, returning its index. Frames come in the
+    /// order they were presented.
+    pub fn next_frame(&mut self) -> io::Result<usize> {
+        let index = read_index(&mut self.stream, self.frames.len())?;
+        if let Some(fence) = self.frames[index].fence.take() {
+            fence.wait(None).unwrap();
+        }
+        Ok(index)
+    }
+
+    /// Submits `command_buffer`, which reads the frame at `index` from [`Self::next_frame`]
+    /// once it was rendered, then gives the frame back. The returned fence signals when the
+    /// commands completed, e.g. to read a copy of the frame on the CPU.
+    pub fn release(
+        &mut self,
+        render_device: &RenderDevice,
+        index: usize,
+        command_buffer: Arc<dyn PrimaryCommandBufferAbstract>,
+    ) -> io::Result<Arc<Fence>> {
+        let frame = &mut self.frames[index];
+        let fence = submit_between(
+            &render_device.present_queue,
+            Some(frame.rendered.clone()),
+            command_buffer,
+            frame.released.clone(),
+        );
+        frame.fence = Some(fence.clone());
+        write_index(&mut self.stream, index)?;
+        Ok(fence)
+    }
+}
+
+impl Drop for FrameImporter {
+    fn drop(&mut self) {
+        for frame in &mut self.frames {
+            if let Some(fence) = frame.fence.take() {
+                fence.wait(None).ok();
+            }
+        }
+    }
+}
+
+fn exportable_semaphore(device: &Arc<Device>) -> io::Result<Arc<Semaphore>> {
+    let semaphore = Semaphore::new(
+        device.clone(),
+        SemaphoreCreateInfo {
+            export_handle_types: ExternalSemaphoreHandleTypes {
+                opaque_fd: true,
+                ..ExternalSemaphoreHandleTypes::empty()
+            },
+            ..Default::default()
+        },
+    )
+    .map_err(io::Error::other)?;
+    Ok(Arc::new(semaphore))
+}
+
+/// An error for descriptors from the other process that cannot be imported.
+fn invalid_import(what: &str, error: impl std::fmt::Debug) -> io::Error {
+    let message = format!("Failed to import {}: {:?}", what, error);
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// vulkano 0.32 does not expose `ImportSemaphoreFdInfo`, so the payload is imported with a raw
+/// call. vulkano does not know the semaphore is shared, which only matters to its own checks of
+/// whether it is signaled.
+fn import_semaphore(device: &Arc<Device>, file: File) -> io::Result<Arc<Semaphore>> {
+    let semaphore =
+        Semaphore::new(device.clone(), SemaphoreCreateInfo::default()).map_err(io::Error::other)?;
+    let info = ash::vk::ImportSemaphoreFdInfoKHR {
+        semaphore: semaphore.handle(),
+        handle_type: ash::vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD,
+        fd: file.as_raw_fd(),
+        ..Default::default()
+    };
+    unsafe {
+        (device.fns().khr_external_semaphore_fd.import_semaphore_fd_khr)(device.handle(), &info)
+            .result()
+            .map_err(|e| invalid_import("a semaphore", e))?;
+    }
+    // On success Vulkan owns the descriptor.
+    let _ = file.into_raw_fd();
+    Ok(Arc::new(semaphore))
+}
+
+/// Binds memory imported from `file` to an image created exactly like the exported one.
+fn import_image(
+    render_device: &RenderDevice,
+    description: &FrameDescription,
+    format: Format,
+    file: File,
+) -> io::Result<Arc<ImportedImage>> {
+    let handle_types = ExternalMemoryHandleTypes {
+        opaque_fd: true,
+        ..ExternalMemoryHandleTypes::empty()
+    };
+    let raw_image = RawImage::new(
+        render_device.device.clone(),
+        ImageCreateInfo {
+            dimensions: ImageDimensions::Dim2d {
+                width: description.extent[0],
+                height: description.extent[1],
+                array_layers: 1,
+            },
+            format: Some(format),
+            usage: FRAME_USAGE,
+            external_memory_handle_types: handle_types,
+            ..Default::default()
+        },
+    )
+    .map_err(|e| invalid_import("an image", e))?;
+    let requirements = raw_image.memory_requirements()[0];
+    if description.allocation_size < requirements.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The exported memory is smaller than the image",
+        ));
+    }
+    let memory_type_index = render_device
+        .memory_allocator
+        .find_memory_type_index(requirements.memory_type_bits, MemoryUsage::GpuOnly.into())
+        .ok_or_else(|| invalid_import("an image", "no device local memory type"))?;
+    // Safety: the descriptor is the memory of an image created with the same parameters, on
+    // the same device and driver, as checked by the caller.
+    let memory = unsafe {
+        DeviceMemory::import(
+            render_device.device.clone(),
+            MemoryAllocateInfo {
+                allocation_size: description.allocation_size,
+                memory_type_index,
+                dedicated_allocation: Some(DedicatedAllocation::Image(&raw_image)),
+                ..Default::default()
+            },
+            MemoryImportInfo::Fd {
+                handle_type: ExternalMemoryHandleType::OpaqueFd,
+                file,
+            },
+        )
+    }
+    .map_err(|e| invalid_import("image memory", e))?;
+    let alloc = MemoryAlloc::new(memory).map_err(io::Error::other)?;
+    let image = raw_image
+        .bind_memory([alloc])
+        .map_err(|(e, _, _)| invalid_import("image memory", e))?;
+    Ok(Arc::new(ImportedImage {
+        inner: Arc::new(image),
+    }))
+}
+
+/// Submits `command_buffer` after `wait` was signaled, signaling `signal` and the returned
+/// fence. vulkano's futures only signal their own semaphores, so this submits directly.
+fn submit_between(
+    queue: &Arc<Queue>,
+    wait: Option<Arc<Semaphore>>,
+    command_buffer: Arc<dyn PrimaryCommandBufferAbstract>,
+    signal: Arc<Semaphore>,
+) -> Arc<Fence> {
+    let device = queue.device();
+    let fence = Arc::new(Fence::new(device.clone(), FenceCreateInfo::default()).unwrap());
+    // Safety: the command buffer is one-time and only accesses the frame and resources it
+    // keeps alive, and the semaphores are signaled before they are waited for, since each side
+    // only sends an index after submitting its signal.
+    queue.with(|mut queue| unsafe {
+        queue
+            .submit_unchecked(
+                [SubmitInfo {
+                    wait_semaphores: wait.into_iter().map(SemaphoreSubmitInfo::semaphore).collect(),
+                    command_buffers: vec![command_buffer],
+                    signal_semaphores: vec![SemaphoreSubmitInfo::semaphore(signal)],
+                    ..Default::default()
+                }],
+                Some(fence.clone()),
+            )
+            .unwrap()
+    });
+    fence
+}
+
+fn write_index(stream: &mut UnixStream, index: usize) -> io::Result<()> {
+    stream.write_all(&(index as u32).to_le_bytes())
+}
+
+fn read_index(stream: &mut UnixStream, frame_count: usize) -> io::Result<usize> {
+    let mut bytes = [0; 4];
+    stream.read_exact(&mut bytes)?;
+    let index = u32::from_le_bytes(bytes) as usize;
+    if index >= frame_count {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame index out of range"));
+    }
+    Ok(index)
+}
+
+/// Sends `bytes` with duplicates of `fds` attached, as `SCM_RIGHTS` ancillary data.
+fn send_with_fds(stream: &UnixStream, bytes: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let fds_size = std::mem::size_of_val(fds);
+    // Safety: the control buffer is sized and aligned for one header with `fds`, and the
+    // message only points into buffers that outlive the call.
+    unsafe {
+        let space = libc::CMSG_SPACE(fds_size as u32) as usize;
+        let mut control = vec![0u64; space.div_ceil(8)];
+        let mut iov = libc::iovec {
+            iov_base: bytes.as_ptr() as *mut libc::c_void,
+            iov_len: bytes.len(),
+        };
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = space as _;
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(fds_size as u32) as _;
+        let data = libc::CMSG_DATA(header) as *mut RawFd;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), data, fds.len());
+        let sent = libc::sendmsg(stream.as_raw_fd(), &message, 0);
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if sent as usize != bytes.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "Short write of the frames"));
+        }
+    }
+    Ok(())
+}
+
+/// Receives exactly `bytes` and up to `max_fds` descriptors sent by [`send_with_fds`].
+fn recv_with_fds(stream: &UnixStream, bytes: &mut [u8], max_fds: usize) -> io::Result<Vec<File>> {
+    let fds_size = max_fds * std::mem::size_of::<RawFd>();
+    let mut files = Vec::new();
+    // Safety: as in `send_with_fds`, and only descriptors the kernel reported are read.
+    let received = unsafe {
+        let space = libc::CMSG_SPACE(fds_size as u32) as usize;
+        let mut control = vec![0u64; space.div_ceil(8)];
+        let mut iov = libc::iovec {
+            iov_base: bytes.as_mut_ptr() as *mut libc::c_void,
+            iov_len: bytes.len(),
+        };
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        message.msg_controllen = space as _;
+        let received = libc::recvmsg(stream.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS
+            {
+                let data = libc::CMSG_DATA(header) as *const RawFd;
+                let count = ((*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize)
+                    / std::mem::size_of::<RawFd>();
+                for i in 0..count {
+                    files.push(File::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+        if message.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Too many descriptors"));
+        }
+        received as usize
+    };
+    // Descriptors only come with the first bytes, the rest of a split message follows as is.
+    let mut rest = stream;
+    rest.read_exact(&mut bytes[received..])?;
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn description() -> FrameDescription {
+        FrameDescription {
+            extent: [1920, 1080],
+            format: ash::vk::Format::B8G8R8A8_SRGB.as_raw(),
+            frame_count: 3,
+            allocation_size: 0x1_0000_0000 + 8_294_400,
+            device_uuid: [7; 16],
+            driver_uuid: *b"0123456789abcdef",
+        }
+    }
+
+    #[test]
+    fn description_survives_a_round_trip() {
+        let bytes = description().to_bytes();
+        assert_eq!(FrameDescription::from_bytes(&bytes).unwrap(), description());
+    }
+
+    #[test]
+    fn other_streams_are_rejected() {
+        let mut bytes = description().to_bytes();
+        bytes[0] = b'X';
+        let error = FrameDescription::from_bytes(&bytes).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn descriptors_come_with_the_header() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let files = [File::open("/dev/null").unwrap(), File::open("/dev/null").unwrap()];
+        let fds: Vec<RawFd> = files.iter().map(|file| file.as_raw_fd()).collect();
+        send_with_fds(&sender, &description().to_bytes(), &fds).unwrap();
+
+        let mut header = [0; HEADER_SIZE];
+        let received = recv_with_fds(&receiver, &mut header, 4).unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(FrameDescription::from_bytes(&header).unwrap(), description());
+    }
+}
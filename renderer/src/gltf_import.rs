@@ -0,0 +1,643 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use glam::Mat4;
+use gltf::image::Format as GltfFormat;
+use gltf::mesh::Mode;
+use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::sampler::{Filter, SamplerAddressMode, SamplerMipmapMode};
+use crate::material::{AlphaMode, PbrMaterial, TextureSlot};
+use crate::mesh::{Mesh, MeshData, MeshVertex};
+use crate::render_device::RenderDevice;
+use crate::texture::{ImageData, SamplerDesc, Texture, TextureData};
+
+/// glTF extensions the importer understands. Any other extension used by a file is reported in
+/// [`GltfScene::unsupported_extensions`] and ignored.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[];
+
+#[derive(Debug)]
+pub enum GltfImportError {
+    Gltf(gltf::Error),
+    /// The file cannot be displayed correctly without these extensions.
+    UnsupportedRequiredExtensions(Vec<String>),
+    /// An index of a primitive does not refer to one of its vertices.
+    InvalidIndex {
+        mesh: usize,
+        primitive: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+    /// A node is its own ancestor, or the child of several nodes.
+    InvalidHierarchy { node: usize },
+}
+
+impl fmt::Display for GltfImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfImportError::Gltf(e) => write!(f, "failed to load glTF: {}", e),
+            GltfImportError::UnsupportedRequiredExtensions(extensions) => write!(
+                f,
+                "glTF requires unsupported extensions: {}",
+                extensions.join(", ")
+            ),
+            GltfImportError::InvalidIndex {
+                mesh,
+                primitive,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "mesh {} primitive {}: index {} is out of bounds for {} vertices",
+                mesh, primitive, index, vertex_count
+            ),
+            GltfImportError::InvalidHierarchy { node } => write!(
+                f,
+                "node {} is its own ancestor or has several parents",
+                node
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GltfImportError {}
+
+impl From<gltf::Error> for GltfImportError {
+    fn from(e: gltf::Error) -> Self {
+        GltfImportError::Gltf(e)
+    }
+}
+
+/// One draw of a glTF mesh: a triangle list with a single material.
+#[derive(Clone, Debug)]
+pub struct GltfPrimitive {
+    pub data: MeshData,
+    /// Index into [`GltfScene::materials`].
+    pub material: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<GltfPrimitive>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    /// Transform relative to the parent node.
+    pub transform: Mat4,
+    /// Index into [`GltfScene::meshes`].
+    pub mesh: Option<usize>,
+    /// Indices into [`GltfScene::nodes`].
+    pub children: Vec<usize>,
+}
+
+/// CPU-side contents of a glTF 2.0 file.
+#[derive(Clone, Debug, Default)]
+pub struct GltfScene {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<PbrMaterial>,
+    pub textures: Vec<TextureData>,
+    pub images: Vec<ImageData>,
+    pub nodes: Vec<GltfNode>,
+    /// Root nodes of the default scene, or of the first scene if none is marked default.
+    pub roots: Vec<usize>,
+    /// Extensions used by the file that were ignored during import.
+    pub unsupported_extensions: Vec<String>,
+    /// Content that was skipped or approximated during import.
+    pub warnings: Vec<String>,
+}
+
+/// Loads a `.gltf` (with external or embedded buffers) or `.glb` file.
+///
+/// Missing normals and tangents are generated. Anything the importer cannot represent is
+/// listed in [`GltfScene::unsupported_extensions`] and [`GltfScene::warnings`], and printed.
+/// Primitives without triangles and textures sampled with a UV set other than the first are
+/// skipped. Out of bounds indices and node hierarchies that are not trees fail the import.
+pub fn import_gltf(path: impl AsRef<Path>) -> Result<GltfScene, GltfImportError> {
+    let (document, buffers, images) = gltf::import(path.as_ref())?;
+
+    let unsupported_required: Vec<String> = document
+        .extensions_required()
+        .filter(|e| !SUPPORTED_EXTENSIONS.contains(e))
+        .map(str::to_owned)
+        .collect();
+    if !unsupported_required.is_empty() {
+        return Err(GltfImportError::UnsupportedRequiredExtensions(
+            unsupported_required,
+        ));
+    }
+
+    let mut scene = GltfScene {
+        unsupported_extensions: document
+            .extensions_used()
+            .filter(|e| !SUPPORTED_EXTENSIONS.contains(e))
+            .map(str::to_owned)
+            .collect(),
+        ..Default::default()
+    };
+
+    scene.images = images.into_iter().map(convert_image).collect();
+
+    scene.textures = document
+        .textures()
+        .map(|texture| {
+            let sampler = texture.sampler();
+            TextureData {
+                name: texture.name().map(str::to_owned),
+                image: texture.source().index(),
+                sampler: SamplerDesc {
+                    mag_filter: match sampler.mag_filter() {
+                        Some(MagFilter::Nearest) => Filter::Nearest,
+                        _ => Filter::Linear,
+                    },
+                    min_filter: match sampler.min_filter() {
+                        Some(
+                            MinFilter::Nearest
+                            | MinFilter::NearestMipmapNearest
+                            | MinFilter::NearestMipmapLinear,
+                        ) => Filter::Nearest,
+                        _ => Filter::Linear,
+                    },
+                    mipmap_mode: match sampler.min_filter() {
+                        Some(MinFilter::NearestMipmapNearest | MinFilter::LinearMipmapNearest) => {
+                            SamplerMipmapMode::Nearest
+                        }
+                        _ => SamplerMipmapMode::Linear,
+                    },
+                    address_mode_u: convert_wrapping(sampler.wrap_s()),
+                    address_mode_v: convert_wrapping(sampler.wrap_t()),
+                },
+            }
+        })
+        .collect();
+
+    let mut material_warnings = Vec::new();
+    scene.materials = document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            // Meshes are only imported with their first UV set.
+            let mut slot = |kind: &str, texture: gltf::Texture, tex_coord: u32| {
+                if tex_coord == 0 {
+                    return Some(TextureSlot {
+                        texture: texture.index(),
+                        tex_coord,
+                    });
+                }
+                material_warnings.push(format!(
+                    "material {} {} texture: UV set {} is not supported, skipped",
+                    material.index().unwrap_or_default(),
+                    kind,
+                    tex_coord
+                ));
+                None
+            };
+            PbrMaterial {
+                name: material.name().map(str::to_owned),
+                base_color_factor: pbr.base_color_factor(),
+                base_color_texture: pbr
+                    .base_color_texture()
+                    .and_then(|info| slot("base color", info.texture(), info.tex_coord())),
+                metallic_factor: pbr.metallic_factor(),
+                roughness_factor: pbr.roughness_factor(),
+                metallic_roughness_texture: pbr.metallic_roughness_texture().and_then(|info| {
+                    slot("metallic roughness", info.texture(), info.tex_coord())
+                }),
+                normal_texture: material
+                    .normal_texture()
+                    .and_then(|info| slot("normal", info.texture(), info.tex_coord())),
+                normal_scale: material.normal_texture().map_or(1.0, |info| info.scale()),
+                occlusion_texture: material
+                    .occlusion_texture()
+                    .and_then(|info| slot("occlusion", info.texture(), info.tex_coord())),
+                occlusion_strength: material
+                    .occlusion_texture()
+                    .map_or(1.0, |info| info.strength()),
+                emissive_factor: material.emissive_factor(),
+                emissive_texture: material
+                    .emissive_texture()
+                    .and_then(|info| slot("emissive", info.texture(), info.tex_coord())),
+                alpha_mode: match material.alpha_mode() {
+                    gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                    gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                    gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                },
+                alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+                double_sided: material.double_sided(),
+            }
+        })
+        .collect();
+    scene.warnings.append(&mut material_warnings);
+
+    for mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for (i, primitive) in mesh.primitives().enumerate() {
+            if primitive.mode() != Mode::Triangles {
+                scene.warnings.push(format!(
+                    "mesh {} primitive {}: {:?} topology is not supported, skipped",
+                    mesh.index(),
+                    i,
+                    primitive.mode()
+                ));
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions = match reader.read_positions() {
+                Some(positions) => positions,
+                None => {
+                    scene.warnings.push(format!(
+                        "mesh {} primitive {}: no positions, skipped",
+                        mesh.index(),
+                        i
+                    ));
+                    continue;
+                }
+            };
+            let mut data = MeshData {
+                vertices: positions
+                    .map(|position| MeshVertex {
+                        position,
+                        ..Default::default()
+                    })
+                    .collect(),
+                indices: Vec::new(),
+            };
+            data.indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..data.vertices.len() as u32).collect(),
+            };
+            if data.vertices.is_empty() || data.indices.is_empty() {
+                scene.warnings.push(format!(
+                    "mesh {} primitive {}: no triangles, skipped",
+                    mesh.index(),
+                    i
+                ));
+                continue;
+            }
+            if let Some(index) = data.first_invalid_index() {
+                return Err(GltfImportError::InvalidIndex {
+                    mesh: mesh.index(),
+                    primitive: i,
+                    index,
+                    vertex_count: data.vertices.len(),
+                });
+            }
+            if let Some(uvs) = reader.read_tex_coords(0) {
+                for (vertex, uv) in data.vertices.iter_mut().zip(uvs.into_f32()) {
+                    vertex.uv = uv;
+                }
+            }
+            if reader.read_tex_coords(1).is_some() {
+                scene.warnings.push(format!(
+                    "mesh {} primitive {}: only the first UV set is imported",
+                    mesh.index(),
+                    i
+                ));
+            }
+            match reader.read_normals() {
+                Some(normals) => {
+                    for (vertex, normal) in data.vertices.iter_mut().zip(normals) {
+                        vertex.normal = normal;
+                    }
+                }
+                None => data.generate_normals(),
+            }
+            match reader.read_tangents() {
+                Some(tangents) => {
+                    for (vertex, tangent) in data.vertices.iter_mut().zip(tangents) {
+                        vertex.tangent = tangent;
+                    }
+                }
+                None => data.generate_tangents(),
+            }
+            primitives.push(GltfPrimitive {
+                data,
+                material: primitive.material().index(),
+            });
+        }
+        scene.meshes.push(GltfMesh {
+            name: mesh.name().map(str::to_owned),
+            primitives,
+        });
+    }
+
+    scene.nodes = document
+        .nodes()
+        .map(|node| GltfNode {
+            name: node.name().map(str::to_owned),
+            transform: Mat4::from_cols_array_2d(&node.transform().matrix()),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            children: node.children().map(|child| child.index()).collect(),
+        })
+        .collect();
+
+    scene.roots = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .map(|s| s.nodes().map(|node| node.index()).collect())
+        .unwrap_or_default();
+    if let Some(node) = first_invalid_node(&scene.nodes) {
+        return Err(GltfImportError::InvalidHierarchy { node });
+    }
+
+    for extension in &scene.unsupported_extensions {
+        println!("glTF: ignoring unsupported extension {}", extension);
+    }
+    for warning in &scene.warnings {
+        println!("glTF: {}", warning);
+    }
+
+    Ok(scene)
+}
+
+impl GltfScene {
+    /// World transform of every node, indexed like [`GltfScene::nodes`]. Nodes not reachable
+    /// from [`GltfScene::roots`] keep their local transform. In a hierarchy that is not a tree,
+    /// nodes get the transform of the first path that reaches them.
+    pub fn world_transforms(&self) -> Vec<Mat4> {
+        let mut transforms: Vec<Mat4> = self.nodes.iter().map(|node| node.transform).collect();
+        let mut visited = vec![false; self.nodes.len()];
+        let mut stack: Vec<(usize, Mat4)> =
+            self.roots.iter().map(|&root| (root, Mat4::IDENTITY)).collect();
+        while let Some((index, parent)) = stack.pop() {
+            if std::mem::replace(&mut visited[index], true) {
+                continue;
+            }
+            let world = parent * self.nodes[index].transform;
+            transforms[index] = world;
+            stack.extend(self.nodes[index].children.iter().map(|&child| (child, world)));
+        }
+        transforms
+    }
+
+    /// Every node that references a mesh, as `(mesh index, world transform)` pairs.
+    pub fn mesh_instances(&self) -> Vec<(usize, Mat4)> {
+        let transforms = self.world_transforms();
+        self.nodes
+            .iter()
+            .zip(transforms)
+            .filter_map(|(node, transform)| node.mesh.map(|mesh| (mesh, transform)))
+            .collect()
+    }
+}
+
+/// A node with several parents, or on a cycle of children.
+fn first_invalid_node(nodes: &[GltfNode]) -> Option<usize> {
+    let mut has_parent = vec![false; nodes.len()];
+    for &child in nodes.iter().flat_map(|node| &node.children) {
+        if std::mem::replace(&mut has_parent[child], true) {
+            return Some(child);
+        }
+    }
+    // With at most one parent each, nodes on a cycle cannot be reached from parentless ones.
+    let mut reached = vec![false; nodes.len()];
+    let mut stack: Vec<usize> = (0..nodes.len()).filter(|&i| !has_parent[i]).collect();
+    while let Some(index) = stack.pop() {
+        reached[index] = true;
+        stack.extend(&nodes[index].children);
+    }
+    reached.iter().position(|&reached| !reached)
+}
+
+/// A [`GltfScene`] uploaded to the GPU.
+pub struct Model {
+    /// Per glTF mesh, one renderer mesh per primitive.
+    pub meshes: Vec<Vec<Mesh>>,
+    pub materials: Vec<PbrMaterial>,
+    pub textures: Vec<Texture>,
+}
+
+impl Model {
+    /// Creates the meshes and records texture uploads into `builder`. The textures are usable
+    /// once the command buffer has executed.
+    pub fn new(
+        render_device: &RenderDevice,
+        scene: &GltfScene,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let srgb_textures: HashSet<usize> = scene
+            .materials
+            .iter()
+            .flat_map(|material| material.srgb_textures())
+            .collect();
+        let textures = scene
+            .textures
+            .iter()
+            .enumerate()
+            .map(|(i, texture)| {
+                Texture::new(
+                    render_device,
+                    &scene.images[texture.image],
+                    &texture.sampler,
+                    srgb_textures.contains(&i),
+                    builder,
+                )
+            })
+            .collect();
+        let meshes = scene
+            .meshes
+            .iter()
+            .map(|mesh| {
+                mesh.primitives
+                    .iter()
+                    .map(|primitive| Mesh::new(render_device, &primitive.data, primitive.material))
+                    .collect()
+            })
+            .collect();
+        Self {
+            meshes,
+            materials: scene.materials.clone(),
+            textures,
+        }
+    }
+}
+
+fn convert_wrapping(mode: WrappingMode) -> SamplerAddressMode {
+    match mode {
+        WrappingMode::ClampToEdge => SamplerAddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+        WrappingMode::Repeat => SamplerAddressMode::Repeat,
+    }
+}
+
+/// Expands any glTF pixel format to 8-bit RGBA.
+fn convert_image(image: gltf::image::Data) -> ImageData {
+    let texel_count = (image.width * image.height) as usize;
+    let (channels, bytes_per_channel) = match image.format {
+        GltfFormat::R8 => (1, 1),
+        GltfFormat::R8G8 => (2, 1),
+        GltfFormat::R8G8B8 => (3, 1),
+        GltfFormat::R8G8B8A8 => (4, 1),
+        GltfFormat::R16 => (1, 2),
+        GltfFormat::R16G16 => (2, 2),
+        GltfFormat::R16G16B16 => (3, 2),
+        GltfFormat::R16G16B16A16 => (4, 2),
+        GltfFormat::R32G32B32FLOAT => (3, 4),
+        GltfFormat::R32G32B32A32FLOAT => (4, 4),
+    };
+    let channel = |texel: &[u8], c: usize| -> u8 {
+        let bytes = &texel[c * bytes_per_channel..(c + 1) * bytes_per_channel];
+        match bytes_per_channel {
+            1 => bytes[0],
+            // Little-endian 16-bit: keep the high byte.
+            2 => bytes[1],
+            _ => {
+                let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (value.clamp(0.0, 1.0) * 255.0).round() as u8
+            }
+        }
+    };
+    let mut pixels = Vec::with_capacity(texel_count * 4);
+    for texel in image.pixels.chunks_exact(channels * bytes_per_channel) {
+        let rgba = match channels {
+            1 => {
+                let r = channel(texel, 0);
+                [r, r, r, 255]
+            }
+            2 => [channel(texel, 0), channel(texel, 1), 0, 255],
+            3 => [channel(texel, 0), channel(texel, 1), channel(texel, 2), 255],
+            _ => [
+                channel(texel, 0),
+                channel(texel, 1),
+                channel(texel, 2),
+                channel(texel, 3),
+            ],
+        };
+        pixels.extend_from_slice(&rgba);
+    }
+    ImageData {
+        width: image.width,
+        height: image.height,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    /// A triangle, drawn by a node and its child, with a 1x1 texture.
+    const TRIANGLE: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{
+            "byteLength": 36,
+            "uri": "POSITIONS"
+        }],
+        "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+        "accessors": [{
+            "bufferView": 0,
+            "componentType": 5126,
+            "count": 3,
+            "type": "VEC3",
+            "min": [0, 0, 0],
+            "max": [1, 1, 0]
+        }],
+        "images": [{
+            "uri": "IMAGE"
+        }],
+        "textures": [{ "source": 0 }],
+        "materials": [{
+            "pbrMetallicRoughness": { "baseColorTexture": { "index": 0, "texCoord": 1 } },
+            "normalTexture": { "index": 0 }
+        }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+        "nodes": [
+            { "mesh": 0, "translation": [1, 0, 0], "children": [1] },
+            { "mesh": 0, "translation": [0, 2, 0] }
+        ],
+        "scenes": [{ "nodes": [0] }],
+        "scene": 0
+    }"#;
+
+    const POSITIONS: &str = concat!(
+        "data:application/octet-stream;base64,",
+        "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA",
+    );
+    /// An orange PNG.
+    const IMAGE: &str = concat!(
+        "data:image/png;base64,",
+        "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR4nGP4",
+        "38DwHwAGgAJ/EEwb4QAAAABJRU5ErkJggg==",
+    );
+
+    fn import_str(name: &str, gltf: &str) -> Result<GltfScene, GltfImportError> {
+        let gltf = gltf.replace("POSITIONS", POSITIONS).replace("IMAGE", IMAGE);
+        let path = std::env::temp_dir().join(format!("{}_{}.gltf", name, std::process::id()));
+        std::fs::write(&path, gltf).unwrap();
+        let scene = import_gltf(&path);
+        std::fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn import_reads_meshes_materials_and_nodes() {
+        let scene = import_str("triangle", TRIANGLE).unwrap();
+        let data = &scene.meshes[0].primitives[0].data;
+        assert_eq!(data.indices, [0, 1, 2]);
+        assert_eq!(data.vertices[1].position, [1.0, 0.0, 0.0]);
+        assert_eq!(data.vertices[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!((scene.images[0].width, scene.images[0].height), (1, 1));
+        assert_eq!(scene.images[0].pixels, [255, 128, 0, 255]);
+
+        // The base color texture samples the second UV set, which is not imported.
+        let material = &scene.materials[0];
+        assert_eq!(material.base_color_texture, None);
+        let normal = TextureSlot {
+            texture: 0,
+            tex_coord: 0,
+        };
+        assert_eq!(material.normal_texture, Some(normal));
+        assert!(scene.warnings.iter().any(|warning| warning.contains("UV set 1")));
+
+        let instances = scene.mesh_instances();
+        let translation = |i: usize| instances[i].1.transform_point3(Vec3::ZERO);
+        assert_eq!(translation(0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(translation(1), Vec3::new(1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn import_rejects_cyclic_nodes() {
+        let cyclic = TRIANGLE.replace(
+            r#"{ "mesh": 0, "translation": [0, 2, 0] }"#,
+            r#"{ "mesh": 0, "translation": [0, 2, 0], "children": [0] }"#,
+        );
+        match import_str("cyclic", &cyclic) {
+            Err(GltfImportError::InvalidHierarchy { .. }) => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("imported a cyclic hierarchy"),
+        }
+    }
+
+    fn node(children: Vec<usize>) -> GltfNode {
+        GltfNode {
+            name: None,
+            transform: Mat4::from_translation(Vec3::X),
+            mesh: None,
+            children,
+        }
+    }
+
+    #[test]
+    fn invalid_hierarchies_are_found() {
+        let tree = [node(vec![1, 2]), node(vec![]), node(vec![])];
+        assert_eq!(first_invalid_node(&tree), None);
+        let shared = [node(vec![2]), node(vec![2]), node(vec![])];
+        assert_eq!(first_invalid_node(&shared), Some(2));
+        let cycle = [node(vec![]), node(vec![2]), node(vec![1])];
+        assert_eq!(first_invalid_node(&cycle), Some(1));
+    }
+
+    #[test]
+    fn world_transforms_terminate_on_cycles() {
+        let scene = GltfScene {
+            nodes: vec![node(vec![1]), node(vec![0])],
+            roots: vec![0],
+            ..Default::default()
+        };
+        let transforms = scene.world_transforms();
+        assert_eq!(transforms[1].transform_point3(Vec3::ZERO), Vec3::new(2.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use egui::epaint::{ImageDelta, Primitive};
+use egui::{ClippedPrimitive, ImageData, TextureFilter, TextureId, TexturesDelta};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BufferImageCopy, CopyBufferToImageInfo, PrimaryAutoCommandBuffer,
+    RenderingInfo,
+};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::{Format, NumericType};
+use vulkano::image::view::{ImageView, ImageViewAbstract};
+use vulkano::image::{ImageAccess, ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
+use vulkano::impl_vertex;
+use vulkano::memory::allocator::{MemoryUsage, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::color_blend::{
+    AttachmentBlend, BlendFactor, BlendOp, ColorBlendState,
+};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::{Scissor, Viewport, ViewportState};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::Window;
+use crate::debug_utils::{begin_label, end_label, set_image_name, set_object_name};
+use crate::memory_tracker::MemoryCategory;
+use crate::recovery::Recoverable;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/gui/gui.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/gui/gui.frag",
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct GuiVertex {
+    pub position: [f32; 2],
+    pub tex_coords: [f32; 2],
+    /// Premultiplied sRGB color.
+    pub color: [f32; 4],
+}
+impl_vertex!(GuiVertex, position, tex_coords, color);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct GuiPushConstants {
+    screen_size: [f32; 2],
+    srgb_output: u32,
+}
+
+struct GuiTexture {
+    /// `None` for user textures, which are never updated by egui.
+    image: Option<Arc<StorageImage>>,
+    descriptor_set: Arc<PersistentDescriptorSet>,
+}
+
+/// egui integration: feeds winit events to egui, runs the UI, and draws its output through
+/// our own pipeline over the swapchain image.
+///
+/// Per frame, call [`Self::on_event`] for every window event, [`Self::run`] once with the UI
+/// code, and [`Self::render`] after everything else was recorded.
+pub struct Gui {
+    pub context: egui::Context,
+    pub state: egui_winit::State,
+    pub pipeline: Arc<GraphicsPipeline>,
+    textures: HashMap<TextureId, GuiTexture>,
+    next_user_texture: u64,
+    primitives: Vec<ClippedPrimitive>,
+    textures_delta: TexturesDelta,
+    vertex_pool: CpuBufferPool<GuiVertex, StandardMemoryAllocator>,
+    index_pool: CpuBufferPool<u32, StandardMemoryAllocator>,
+    srgb_output: bool,
+}
+
+impl Gui {
+    /// Creates a pipeline drawing into the swapchain images of `render_output`.
+    pub fn new<T>(
+        event_loop: &EventLoopWindowTarget<T>,
+        window: &Window,
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+    ) -> Self {
+        let mut state = egui_winit::State::new(event_loop);
+        state.set_pixels_per_point(egui_winit::native_pixels_per_point(window));
+        let max_image_dimension = render_device
+            .device
+            .physical_device()
+            .properties()
+            .max_image_dimension2_d;
+        state.set_max_texture_side(max_image_dimension as usize);
+
+        let format = render_output.swapchain.image_format();
+        Self {
+            context: egui::Context::default(),
+            state,
+            pipeline: create_pipeline(render_device, format),
+            textures: HashMap::new(),
+            next_user_texture: 0,
+            primitives: Vec::new(),
+            textures_delta: TexturesDelta::default(),
+            vertex_pool: create_vertex_pool(render_device),
+            index_pool: create_index_pool(render_device),
+            srgb_output: format.type_color() == Some(NumericType::SRGB),
+        }
+    }
+
+    /// Passes `event` on to egui. Returns true if egui used it, in which case the application
+    /// should usually ignore it.
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        self.state.on_event(&self.context, event).consumed
+    }
+
+    /// Runs `ui` with the input gathered since the last call, and tessellates its output for
+    /// [`Self::render`].
+    pub fn run(&mut self, window: &Window, ui: impl FnOnce(&egui::Context)) {
+        let raw_input = self.state.take_egui_input(window);
+        let output = self.context.run(raw_input, ui);
+        self.state
+            .handle_platform_output(window, &self.context, output.platform_output);
+        self.primitives = self.context.tessellate(output.shapes);
+        self.textures_delta.append(output.textures_delta);
+    }
+
+    /// Makes `view` available to egui images, sampled with `sampler`.
+    pub fn register_texture(
+        &mut self,
+        render_device: &RenderDevice,
+        view: Arc<dyn ImageViewAbstract>,
+        sampler: Arc<Sampler>,
+    ) -> TextureId {
+        let id = TextureId::User(self.next_user_texture);
+        self.next_user_texture += 1;
+        let descriptor_set = self.write_set(render_device, view, sampler);
+        self.textures.insert(
+            id,
+            GuiTexture {
+                image: None,
+                descriptor_set,
+            },
+        );
+        id
+    }
+
+    pub fn unregister_texture(&mut self, id: TextureId) {
+        self.textures.remove(&id);
+    }
+
+    fn write_set(
+        &self,
+        render_device: &RenderDevice,
+        view: Arc<dyn ImageViewAbstract>,
+        sampler: Arc<Sampler>,
+    ) -> Arc<PersistentDescriptorSet> {
+        PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            self.pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::image_view_sampler(0, view, sampler)],
+        )
+        .unwrap()
+    }
+
+    /// Records the texture updates of the last [`Self::run`], then a pass drawing the UI over
+    /// swapchain image `image_index`. Must be called outside of rendering, after the rest of the
+    /// frame.
+    pub fn render(
+        &mut self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        render_output: &RenderOutput,
+        image_index: u32,
+    ) {
+        begin_label(builder, "gui");
+        let textures_delta = std::mem::take(&mut self.textures_delta);
+        for (id, delta) in &textures_delta.set {
+            self.update_texture(render_device, builder, *id, delta);
+        }
+
+        let [width, height] = render_output.swapchain.image_extent();
+        let pixels_per_point = self.context.pixels_per_point();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        // Index range, vertex offset, texture and scissor of every mesh.
+        let mut draws = Vec::new();
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in &self.primitives
+        {
+            let Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+            if mesh.indices.is_empty() || !self.textures.contains_key(&mesh.texture_id) {
+                continue;
+            }
+            // Clip rectangles are in points and may extend past the screen.
+            let min_x = (clip_rect.min.x * pixels_per_point).round().clamp(0.0, width as f32);
+            let min_y = (clip_rect.min.y * pixels_per_point).round().clamp(0.0, height as f32);
+            let max_x = (clip_rect.max.x * pixels_per_point).round().clamp(min_x, width as f32);
+            let max_y = (clip_rect.max.y * pixels_per_point).round().clamp(min_y, height as f32);
+            if max_x <= min_x || max_y <= min_y {
+                continue;
+            }
+            let scissor = Scissor {
+                origin: [min_x as u32, min_y as u32],
+                dimensions: [(max_x - min_x) as u32, (max_y - min_y) as u32],
+            };
+            draws.push((
+                indices.len() as u32,
+                mesh.indices.len() as u32,
+                vertices.len() as i32,
+                mesh.texture_id,
+                scissor,
+            ));
+            indices.extend_from_slice(&mesh.indices);
+            vertices.extend(mesh.vertices.iter().map(|vertex| GuiVertex {
+                position: [vertex.pos.x, vertex.pos.y],
+                tex_coords: [vertex.uv.x, vertex.uv.y],
+                color: vertex.color.to_array().map(|c| c as f32 / 255.0),
+            }));
+        }
+
+        if !draws.is_empty() {
+            let vertex_buffer = self.vertex_pool.from_iter(vertices).unwrap();
+            let index_buffer = self.index_pool.from_iter(indices).unwrap();
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(render_output.overlay_attachment(image_index))],
+                    ..Default::default()
+                })
+                .unwrap()
+                .set_viewport(
+                    0,
+                    [Viewport {
+                        origin: [0.0, 0.0],
+                        dimensions: [width as f32, height as f32],
+                        depth_range: 0.0..1.0,
+                    }],
+                )
+                .bind_pipeline_graphics(self.pipeline.clone())
+                .push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    GuiPushConstants {
+                        screen_size: [
+                            width as f32 / pixels_per_point,
+                            height as f32 / pixels_per_point,
+                        ],
+                        srgb_output: self.srgb_output as u32,
+                    },
+                )
+                .bind_vertex_buffers(0, vertex_buffer)
+                .bind_index_buffer(index_buffer);
+            for (first_index, index_count, vertex_offset, texture_id, scissor) in draws {
+                builder
+                    .set_scissor(0, [scissor])
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        self.pipeline.layout().clone(),
+                        0,
+                        self.textures[&texture_id].descriptor_set.clone(),
+                    )
+                    .draw_indexed(index_count, 1, first_index, vertex_offset, 0)
+                    .unwrap();
+            }
+            builder.end_rendering().unwrap();
+        }
+
+        // Command buffers keep the images they use alive, so freeing right away is fine.
+        for id in &textures_delta.free {
+            self.textures.remove(id);
+        }
+        end_label(builder);
+    }
+
+    /// Creates texture `id` or updates part of it.
+    fn update_texture(
+        &mut self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        id: TextureId,
+        delta: &ImageDelta,
+    ) {
+        let [width, height] = delta.image.size().map(|size| size as u32);
+        let pixels: Vec<u8> = match &delta.image {
+            ImageData::Color(image) => image.pixels.iter().flat_map(|c| c.to_array()).collect(),
+            ImageData::Font(image) => image.srgba_pixels(None).flat_map(|c| c.to_array()).collect(),
+        };
+
+        let image = match (delta.pos, self.textures.get(&id)) {
+            (Some(_), Some(GuiTexture {
+                image: Some(image), ..
+            })) => image.clone(),
+            _ => {
+                let image = StorageImage::with_usage(
+                    &render_device.memory_allocator,
+                    ImageDimensions::Dim2d {
+                        width,
+                        height,
+                        array_layers: 1,
+                    },
+                    Format::R8G8B8A8_SRGB,
+                    ImageUsage {
+                        sampled: true,
+                        transfer_dst: true,
+                        ..ImageUsage::empty()
+                    },
+                    ImageCreateFlags::empty(),
+                    [render_device.present_queue.queue_family_index()],
+                )
+                .unwrap();
+                let name = format!("gui texture {:?}", id);
+                set_image_name(image.as_ref(), &name);
+                render_device
+                    .memory_tracker
+                    .track_image(image.as_ref(), MemoryCategory::Texture, &name);
+                let filter = |filter| match filter {
+                    TextureFilter::Nearest => Filter::Nearest,
+                    TextureFilter::Linear => Filter::Linear,
+                };
+                let sampler = Sampler::new(
+                    render_device.device.clone(),
+                    SamplerCreateInfo {
+                        mag_filter: filter(delta.options.magnification),
+                        min_filter: filter(delta.options.minification),
+                        address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                let view = ImageView::new_default(image.clone()).unwrap();
+                let descriptor_set = self.write_set(render_device, view, sampler);
+                self.textures.insert(
+                    id,
+                    GuiTexture {
+                        image: Some(image.clone()),
+                        descriptor_set,
+                    },
+                );
+                image
+            }
+        };
+
+        let [x, y] = delta.pos.unwrap_or([0, 0]).map(|offset| offset as u32);
+        let buffer = CpuAccessibleBuffer::from_iter(
+            &render_device.memory_allocator,
+            BufferUsage {
+                transfer_src: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            pixels,
+        )
+        .unwrap();
+        render_device
+            .memory_tracker
+            .track_buffer(buffer.as_ref(), MemoryCategory::Staging, "gui texture upload");
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: [BufferImageCopy {
+                    image_subresource: image.subresource_layers(),
+                    image_offset: [x, y, 0],
+                    image_extent: [width, height, 1],
+                    ..Default::default()
+                }]
+                .into(),
+                ..CopyBufferToImageInfo::buffer_image(buffer, image)
+            })
+            .unwrap();
+    }
+}
+
+/// Textures registered with [`Gui::register_texture`] belong to the lost device, so they are
+/// forgotten and have to be registered again.
+impl Recoverable for Gui {
+    fn recreate(&mut self, render_device: &RenderDevice, render_output: &RenderOutput) {
+        let format = render_output.swapchain.image_format();
+        self.pipeline = create_pipeline(render_device, format);
+        self.vertex_pool = create_vertex_pool(render_device);
+        self.index_pool = create_index_pool(render_device);
+        self.textures.clear();
+        self.primitives.clear();
+        self.textures_delta = TexturesDelta::default();
+        self.srgb_output = format.type_color() == Some(NumericType::SRGB);
+        // egui only sends its font texture once per context, so a new one sends it again to
+        // the new device. Memory holds the state of the UI and the style.
+        let context = egui::Context::default();
+        *context.memory() = self.context.memory().clone();
+        self.context = context;
+    }
+}
+
+fn create_pipeline(render_device: &RenderDevice, format: Format) -> Arc<GraphicsPipeline> {
+    let vs = vs::load(render_device.device.clone()).unwrap();
+    let fs = fs::load(render_device.device.clone()).unwrap();
+    // egui outputs premultiplied alpha.
+    let blend = AttachmentBlend {
+        color_op: BlendOp::Add,
+        color_source: BlendFactor::One,
+        color_destination: BlendFactor::OneMinusSrcAlpha,
+        alpha_op: BlendOp::Add,
+        alpha_source: BlendFactor::OneMinusDstAlpha,
+        alpha_destination: BlendFactor::One,
+    };
+    let pipeline = GraphicsPipeline::start()
+        .render_pass(PipelineRenderingCreateInfo {
+            color_attachment_formats: vec![Some(format)],
+            ..Default::default()
+        })
+        .vertex_input_state(BuffersDefinition::new().vertex::<GuiVertex>())
+        .input_assembly_state(InputAssemblyState::new())
+        .vertex_shader(vs.entry_point("main").unwrap(), ())
+        .viewport_state(ViewportState::viewport_dynamic_scissor_dynamic(1))
+        .fragment_shader(fs.entry_point("main").unwrap(), ())
+        .rasterization_state(RasterizationState::new().cull_mode(CullMode::None))
+        .color_blend_state(ColorBlendState::new(1).blend(blend))
+        .build(render_device.device.clone())
+        .unwrap();
+    set_object_name(pipeline.as_ref(), "gui");
+    pipeline
+}
+
+fn create_vertex_pool(
+    render_device: &RenderDevice,
+) -> CpuBufferPool<GuiVertex, StandardMemoryAllocator> {
+    CpuBufferPool::new(
+        render_device.memory_allocator.clone(),
+        BufferUsage {
+            vertex_buffer: true,
+            ..BufferUsage::empty()
+        },
+        MemoryUsage::Upload,
+    )
+}
+
+fn create_index_pool(render_device: &RenderDevice) -> CpuBufferPool<u32, StandardMemoryAllocator> {
+    CpuBufferPool::new(
+        render_device.memory_allocator.clone(),
+        BufferUsage {
+            index_buffer: true,
+            ..BufferUsage::empty()
+        },
+        MemoryUsage::Upload,
+    )
+}
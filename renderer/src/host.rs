@@ -0,0 +1,251 @@
+use std::ffi::{c_char, CStr};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use ash::vk;
+use vulkano::device::{
+    Device, DeviceCreateInfo, DeviceCreationError, DeviceExtensions, Features, Queue,
+    QueueCreateInfo,
+};
+use vulkano::instance::{Instance, InstanceCreateInfo, InstanceCreationError, InstanceExtensions};
+use vulkano::library::{Loader, LoadingError};
+use vulkano::{Version, VulkanError, VulkanLibrary, VulkanObject};
+use crate::render_device::UnsupportedDeviceError;
+
+/// A `VkInstance` the host application created, e.g. an OpenXR runtime or an editor, for
+/// [`crate::render_system::RenderSystem::from_raw_instance`].
+#[derive(Clone, Copy)]
+pub struct HostInstance {
+    pub handle: vk::Instance,
+    /// The `vkGetInstanceProcAddr` the host loaded the instance's functions with.
+    pub get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+    /// `apiVersion` the instance was created with.
+    pub api_version: Version,
+    /// Extensions the instance was created with. Windows need those of
+    /// [`crate::surface::required_extensions`].
+    pub enabled_extensions: InstanceExtensions,
+}
+
+/// A `VkDevice` the host application created from a [`HostInstance`], for
+/// [`crate::render_device::RenderDevice::from_raw_device`].
+#[derive(Clone, Copy)]
+pub struct HostDevice {
+    pub handle: vk::Device,
+    pub physical_device: vk::PhysicalDevice,
+    /// The `vkGetDeviceProcAddr` the host loaded the device's functions with.
+    pub get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
+    /// Extensions the device was created with.
+    pub enabled_extensions: DeviceExtensions,
+    /// Features the device was created with.
+    pub enabled_features: Features,
+    /// Family of the queue to render and present on.
+    pub queue_family_index: u32,
+    /// Index of the queue in its family. The device must have been created with at least
+    /// `queue_index + 1` queues of the family.
+    pub queue_index: u32,
+}
+
+#[derive(Debug)]
+pub enum AdoptionError {
+    Loading(LoadingError),
+    Instance(InstanceCreationError),
+    /// The instance of the device was not adopted from a [`HostInstance`].
+    InstanceNotAdopted,
+    /// The physical device of the [`HostDevice`] is not one of the instance's.
+    UnknownPhysicalDevice,
+    Vulkan(VulkanError),
+    Device(DeviceCreationError),
+    Unsupported(UnsupportedDeviceError),
+}
+
+impl fmt::Display for AdoptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdoptionError::Loading(e) => write!(f, "failed to load Vulkan: {}", e),
+            AdoptionError::Instance(e) => write!(f, "failed to adopt the instance: {}", e),
+            AdoptionError::InstanceNotAdopted => {
+                write!(f, "the instance was not adopted from the host")
+            }
+            AdoptionError::UnknownPhysicalDevice => {
+                write!(f, "the physical device is not one of the instance")
+            }
+            AdoptionError::Vulkan(e) => write!(f, "Vulkan error: {}", e),
+            AdoptionError::Device(e) => write!(f, "failed to adopt the device: {}", e),
+            AdoptionError::Unsupported(e) => write!(f, "unsupported device: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AdoptionError {}
+
+/// Handles returned by the next create call, instead of creating objects.
+struct Adopted {
+    instance: Option<vk::Instance>,
+    device: Option<vk::Device>,
+    get_device_proc_addr: Option<vk::PFN_vkGetDeviceProcAddr>,
+}
+
+static ADOPTED: Mutex<Adopted> = Mutex::new(Adopted {
+    instance: None,
+    device: None,
+    get_device_proc_addr: None,
+});
+/// Held for a whole adoption, so that concurrent ones do not take each other's handles.
+static ADOPTING: Mutex<()> = Mutex::new(());
+
+/// Loads functions through the host's `vkGetInstanceProcAddr`, except those creating and
+/// destroying the instance and device, which hand over the host's handles and leave them to
+/// the host to destroy.
+struct HostLoader {
+    get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+}
+
+unsafe impl Loader for HostLoader {
+    unsafe fn get_instance_proc_addr(
+        &self,
+        instance: vk::Instance,
+        name: *const c_char,
+    ) -> vk::PFN_vkVoidFunction {
+        let intercepted = match CStr::from_ptr(name).to_bytes() {
+            b"vkCreateInstance" => create_instance as *const (),
+            b"vkDestroyInstance" => destroy_instance as *const (),
+            b"vkCreateDevice" => create_device as *const (),
+            b"vkGetDeviceProcAddr" => get_device_proc_addr as *const (),
+            _ => std::ptr::null(),
+        };
+        void_function(intercepted).or_else(|| (self.get_instance_proc_addr)(instance, name))
+    }
+}
+
+/// Erases the signature of `function`, as `vkGet*ProcAddr` do. Null gives `None`.
+unsafe fn void_function(function: *const ()) -> vk::PFN_vkVoidFunction {
+    std::mem::transmute::<*const (), vk::PFN_vkVoidFunction>(function)
+}
+
+unsafe extern "system" fn create_instance(
+    _create_info: *const vk::InstanceCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    instance: *mut vk::Instance,
+) -> vk::Result {
+    match ADOPTED.lock().unwrap().instance.take() {
+        Some(handle) => {
+            *instance = handle;
+            vk::Result::SUCCESS
+        }
+        None => vk::Result::ERROR_INITIALIZATION_FAILED,
+    }
+}
+
+unsafe extern "system" fn destroy_instance(
+    _instance: vk::Instance,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+}
+
+unsafe extern "system" fn create_device(
+    _physical_device: vk::PhysicalDevice,
+    _create_info: *const vk::DeviceCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    device: *mut vk::Device,
+) -> vk::Result {
+    match ADOPTED.lock().unwrap().device.take() {
+        Some(handle) => {
+            *device = handle;
+            vk::Result::SUCCESS
+        }
+        None => vk::Result::ERROR_INITIALIZATION_FAILED,
+    }
+}
+
+unsafe extern "system" fn destroy_device(
+    _device: vk::Device,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+}
+
+/// vulkano only loads device functions while creating the device, when the host's loader is
+/// set.
+unsafe extern "system" fn get_device_proc_addr(
+    device: vk::Device,
+    name: *const c_char,
+) -> vk::PFN_vkVoidFunction {
+    if CStr::from_ptr(name).to_bytes() == b"vkDestroyDevice" {
+        return void_function(destroy_device as *const ());
+    }
+    let get_device_proc_addr = ADOPTED.lock().unwrap().get_device_proc_addr;
+    get_device_proc_addr.and_then(|get_device_proc_addr| get_device_proc_addr(device, name))
+}
+
+/// Wraps the host's instance in a vulkano [`Instance`], which validates the extensions as if
+/// it created it. Dropping it leaves the `VkInstance` alive.
+///
+/// # Safety
+///
+/// `host` must describe a valid instance, that outlives the returned one and every object
+/// created from it.
+pub(crate) unsafe fn adopt_instance(host: HostInstance) -> Result<Arc<Instance>, AdoptionError> {
+    let _adopting = ADOPTING.lock().unwrap();
+    let library = VulkanLibrary::with_loader(HostLoader {
+        get_instance_proc_addr: host.get_instance_proc_addr,
+    })
+    .map_err(AdoptionError::Loading)?;
+    ADOPTED.lock().unwrap().instance = Some(host.handle);
+    let instance = Instance::new(
+        library,
+        InstanceCreateInfo {
+            enabled_extensions: host.enabled_extensions,
+            max_api_version: Some(host.api_version),
+            ..Default::default()
+        },
+    );
+    // Validation may fail before the handle was taken.
+    ADOPTED.lock().unwrap().instance = None;
+    instance.map_err(AdoptionError::Instance)
+}
+
+/// Wraps the host's device in a vulkano [`Device`], and returns the queue of `host`. Dropping
+/// them leaves the `VkDevice` alive.
+///
+/// # Safety
+///
+/// `host` must describe a valid device of `instance`, that outlives the returned one and every
+/// object created from it.
+pub(crate) unsafe fn adopt_device(
+    instance: &Arc<Instance>,
+    host: &HostDevice,
+) -> Result<(Arc<Device>, Arc<Queue>), AdoptionError> {
+    // Devices of other instances would really be created.
+    if !std::ptr::eq(instance.fns().v1_0.create_device as *const (), create_device as *const ()) {
+        return Err(AdoptionError::InstanceNotAdopted);
+    }
+    let physical_device = instance
+        .enumerate_physical_devices()
+        .map_err(AdoptionError::Vulkan)?
+        .find(|p| p.handle() == host.physical_device)
+        .ok_or(AdoptionError::UnknownPhysicalDevice)?;
+    let _adopting = ADOPTING.lock().unwrap();
+    {
+        let mut adopted = ADOPTED.lock().unwrap();
+        adopted.device = Some(host.handle);
+        adopted.get_device_proc_addr = Some(host.get_device_proc_addr);
+    }
+    let device = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            enabled_extensions: host.enabled_extensions,
+            enabled_features: host.enabled_features,
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index: host.queue_family_index,
+                queues: vec![0.5; host.queue_index as usize + 1],
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    );
+    {
+        let mut adopted = ADOPTED.lock().unwrap();
+        adopted.device = None;
+        adopted.get_device_proc_addr = None;
+    }
+    let (device, queues) = device.map_err(AdoptionError::Device)?;
+    Ok((device, queues.last().unwrap()))
+}
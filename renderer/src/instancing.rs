@@ -0,0 +1,337 @@
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use vulkano::buffer::cpu_pool::CpuBufferPoolChunk;
+use vulkano::buffer::{BufferContents, BufferUsage, CpuBufferPool};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::impl_vertex;
+use vulkano::memory::allocator::{MemoryUsage, StandardMemoryAllocator};
+use crate::gltf_import::Model;
+use crate::material::AlphaMode;
+use crate::mesh::Mesh;
+use crate::pbr::{GpuMaterial, ModelMaterials};
+use crate::render_device::RenderDevice;
+
+/// Per-instance vertex data of a mesh: its world transform, and the inverse transpose of it
+/// for transforming normals. Matrices are split in columns, as vertex attributes cannot be
+/// matrices; shaders declare them as `model_x` to `model_w` and `normal_x` to `normal_z`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct ModelInstance {
+    pub model_x: [f32; 4],
+    pub model_y: [f32; 4],
+    pub model_z: [f32; 4],
+    pub model_w: [f32; 4],
+    /// Only `xyz` is used.
+    pub normal_x: [f32; 4],
+    pub normal_y: [f32; 4],
+    pub normal_z: [f32; 4],
+}
+impl_vertex!(ModelInstance, model_x, model_y, model_z, model_w, normal_x, normal_y, normal_z);
+
+impl ModelInstance {
+    pub fn new(model: Mat4) -> Self {
+        let normal_matrix = model.inverse().transpose();
+        Self {
+            model_x: model.x_axis.to_array(),
+            model_y: model.y_axis.to_array(),
+            model_z: model.z_axis.to_array(),
+            model_w: model.w_axis.to_array(),
+            normal_x: normal_matrix.x_axis.to_array(),
+            normal_y: normal_matrix.y_axis.to_array(),
+            normal_z: normal_matrix.z_axis.to_array(),
+        }
+    }
+}
+
+/// Uploads the per-instance data of a frame into a vertex buffer.
+///
+/// Each upload takes a slice of a ring buffer that is recycled once the GPU is done with the
+/// frame using it, and that doubles in size when a frame needs more instances than it has, so
+/// that after a few frames uploading allocates nothing.
+pub struct InstanceBuffer<T>
+where
+    [T]: BufferContents,
+{
+    pool: CpuBufferPool<T, StandardMemoryAllocator>,
+}
+
+impl<T> InstanceBuffer<T>
+where
+    [T]: BufferContents,
+{
+    pub fn new(render_device: &RenderDevice) -> Self {
+        Self {
+            pool: CpuBufferPool::new(
+                render_device.memory_allocator.clone(),
+                BufferUsage {
+                    vertex_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                MemoryUsage::Upload,
+            ),
+        }
+    }
+
+    /// Copies `instances` into a buffer to bind as an instance-rate vertex buffer. The buffer
+    /// stays valid for as long as it is referenced.
+    pub fn upload<I>(&self, instances: I) -> Arc<CpuBufferPoolChunk<T>>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.pool.from_iter(instances).unwrap()
+    }
+
+    /// How many instances fit in the ring buffer before it has to grow.
+    pub fn capacity(&self) -> u64 {
+        self.pool.capacity()
+    }
+}
+
+/// Consecutive instances of an uploaded [`DrawList`] that share a mesh and a material, and
+/// are drawn with a single instanced call.
+pub struct DrawBatch<'a> {
+    pub mesh: &'a Mesh,
+    pub material: &'a GpuMaterial,
+    pub first_instance: u32,
+    pub instance_count: u32,
+}
+
+/// A draw list sorted and merged into batches, with the instance data they index into.
+pub struct DrawBatches<'a> {
+    pub instances: Vec<ModelInstance>,
+    pub batches: Vec<DrawBatch<'a>>,
+}
+
+struct DrawItem<'a> {
+    mesh: &'a Mesh,
+    material: &'a GpuMaterial,
+    transform: Mat4,
+}
+
+/// Meshes to draw in a frame, each with a material and a world transform. Draws of the same
+/// mesh with the same material are merged into one instanced draw, whatever order they were
+/// pushed in.
+///
+/// Meshes and materials are told apart by address, so a mesh drawn twice has to be pushed by
+/// the same reference, as [`Model::meshes`] gives.
+#[derive(Default)]
+pub struct DrawList<'a> {
+    draws: Vec<DrawItem<'a>>,
+}
+
+impl<'a> DrawList<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, mesh: &'a Mesh, material: &'a GpuMaterial, transform: Mat4) {
+        self.draws.push(DrawItem {
+            mesh,
+            material,
+            transform,
+        });
+    }
+
+    /// Pushes every primitive of `model`, once per `(mesh index, world transform)` instance,
+    /// as returned by `GltfScene::mesh_instances`.
+    pub fn push_model(
+        &mut self,
+        model: &'a Model,
+        materials: &'a ModelMaterials,
+        instances: &[(usize, Mat4)],
+    ) {
+        for (mesh_index, transform) in instances {
+            for mesh in &model.meshes[*mesh_index] {
+                self.push(mesh, materials.get(mesh.material), *transform);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.draws.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+
+    /// Sorts the draws and merges them into batches. Opaque and alpha-masked draws come first,
+    /// grouped by pipeline, material and mesh. Blended draws follow, sorted back to front from
+    /// `camera_position`, and only merge with draws next to them in that order.
+    pub fn build(&self, camera_position: Vec3) -> DrawBatches<'a> {
+        let keys: Vec<DrawKey<_>> = self
+            .draws
+            .iter()
+            .map(|draw| DrawKey {
+                key: (
+                    draw.material.double_sided,
+                    draw.material as *const GpuMaterial as usize,
+                    draw.mesh as *const Mesh as usize,
+                ),
+                blended: draw.material.alpha_mode == AlphaMode::Blend,
+                transform: draw.transform,
+            })
+            .collect();
+        let (instances, runs) = merge_draws(&keys, camera_position);
+        let batches = runs
+            .into_iter()
+            .map(|run| DrawBatch {
+                mesh: self.draws[run.draw].mesh,
+                material: self.draws[run.draw].material,
+                first_instance: run.first_instance,
+                instance_count: run.instance_count,
+            })
+            .collect();
+        DrawBatches { instances, batches }
+    }
+}
+
+/// What [`merge_draws`] sorts and merges a draw by.
+struct DrawKey<K> {
+    /// Opaque draws are sorted by key, and consecutive draws with equal keys merge.
+    key: K,
+    blended: bool,
+    transform: Mat4,
+}
+
+/// A batch of [`merge_draws`], made of the instances of `draw` and the draws merged into it.
+#[derive(Debug, PartialEq)]
+struct DrawRun {
+    draw: usize,
+    first_instance: u32,
+    instance_count: u32,
+}
+
+/// Sorts and merges draws as [`DrawList::build`] describes.
+fn merge_draws<K: Ord>(
+    draws: &[DrawKey<K>],
+    camera_position: Vec3,
+) -> (Vec<ModelInstance>, Vec<DrawRun>) {
+    let (mut opaque, mut blended): (Vec<usize>, Vec<usize>) =
+        (0..draws.len()).partition(|&i| !draws[i].blended);
+    // Stable sorts keep the order draws were pushed in within a batch.
+    opaque.sort_by(|&a, &b| draws[a].key.cmp(&draws[b].key));
+    let distance = |i: usize| draws[i].transform.w_axis.truncate().distance(camera_position);
+    blended.sort_by(|&a, &b| distance(b).total_cmp(&distance(a)));
+
+    let mut instances = Vec::with_capacity(draws.len());
+    let mut runs: Vec<DrawRun> = Vec::new();
+    for i in opaque.into_iter().chain(blended) {
+        let first_instance = instances.len() as u32;
+        instances.push(ModelInstance::new(draws[i].transform));
+        match runs.last_mut() {
+            Some(run) if draws[run.draw].key == draws[i].key => run.instance_count += 1,
+            _ => runs.push(DrawRun {
+                draw: i,
+                first_instance,
+                instance_count: 1,
+            }),
+        }
+    }
+    (instances, runs)
+}
+
+/// Binds the mesh of `batch` with the `instances` of its draw list, and draws its instances.
+/// The bound pipeline must take [`crate::mesh::MeshVertex`] per vertex and [`ModelInstance`]
+/// per instance.
+pub fn draw_batch(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    batch: &DrawBatch,
+    instances: Arc<CpuBufferPoolChunk<ModelInstance>>,
+) {
+    let mesh = batch.mesh;
+    builder
+        .bind_vertex_buffers(0, (mesh.vertex_buffer.clone(), instances))
+        .bind_index_buffer(mesh.index_buffer.clone())
+        .draw_indexed(mesh.index_count, batch.instance_count, 0, 0, batch.first_instance)
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(key: u32, blended: bool, x: f32) -> DrawKey<u32> {
+        DrawKey {
+            key,
+            blended,
+            transform: Mat4::from_translation(Vec3::new(x, 0.0, 0.0)),
+        }
+    }
+
+    fn run(draw: usize, first_instance: u32, instance_count: u32) -> DrawRun {
+        DrawRun {
+            draw,
+            first_instance,
+            instance_count,
+        }
+    }
+
+    /// X translation of every instance.
+    fn positions(instances: &[ModelInstance]) -> Vec<f32> {
+        instances.iter().map(|instance| instance.model_w[0]).collect()
+    }
+
+    #[test]
+    fn equal_keys_merge_whatever_the_push_order() {
+        let draws = [
+            draw(2, false, 0.0),
+            draw(1, false, 1.0),
+            draw(2, false, 2.0),
+            draw(1, false, 3.0),
+            draw(2, false, 4.0),
+        ];
+        let (instances, runs) = merge_draws(&draws, Vec3::ZERO);
+        assert_eq!(runs, [run(1, 0, 2), run(0, 2, 3)]);
+        // Instances of a batch keep their push order.
+        assert_eq!(positions(&instances), [1.0, 3.0, 0.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn blended_draws_come_last_back_to_front() {
+        let draws = [
+            draw(1, true, 1.0),
+            draw(2, false, 0.0),
+            draw(1, true, 5.0),
+            draw(3, true, 3.0),
+        ];
+        let (instances, runs) = merge_draws(&draws, Vec3::ZERO);
+        assert_eq!(runs, [run(1, 0, 1), run(2, 1, 1), run(3, 2, 1), run(0, 3, 1)]);
+        assert_eq!(positions(&instances), [0.0, 5.0, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn blended_draws_only_merge_with_neighbours() {
+        let draws = [
+            draw(1, true, 4.0),
+            draw(1, true, 3.0),
+            draw(2, true, 2.0),
+            draw(1, true, 1.0),
+        ];
+        let (_, runs) = merge_draws(&draws, Vec3::new(10.0, 0.0, 0.0));
+        // From x = 10 the order flips to nearest pushed last.
+        assert_eq!(runs, [run(3, 0, 1), run(2, 1, 1), run(1, 2, 2)]);
+    }
+
+    #[test]
+    fn nothing_to_draw() {
+        let (instances, runs) = merge_draws::<u32>(&[], Vec3::ZERO);
+        assert!(instances.is_empty());
+        assert!(runs.is_empty());
+    }
+
+    #[test]
+    fn instances_hold_the_normal_matrix() {
+        let model = Mat4::from_scale(Vec3::new(2.0, 4.0, 1.0));
+        let instance = ModelInstance::new(model);
+        assert_eq!(instance.normal_x, [0.5, 0.0, 0.0, 0.0]);
+        assert_eq!(instance.normal_y, [0.0, 0.25, 0.0, 0.0]);
+        assert_eq!(instance.normal_z, [0.0, 0.0, 1.0, 0.0]);
+    }
+}
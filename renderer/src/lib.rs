@@ -1,3 +1,31 @@
 pub mod render_output;
 pub mod render_system;
-pub mod render_device;
\ No newline at end of file
+pub mod render_device;
+pub mod host;
+pub mod camera;
+pub mod mesh;
+pub mod material;
+pub mod texture;
+pub mod gltf_import;
+pub mod pbr;
+pub mod shadow;
+pub mod post_process;
+pub mod atlas;
+pub mod sprite;
+pub mod text;
+pub mod debug_draw;
+pub mod gui;
+pub mod compute;
+pub mod particles;
+pub mod profiler;
+pub mod debug_utils;
+mod json;
+pub mod memory_tracker;
+pub mod destruction_queue;
+pub mod recovery;
+pub mod breadcrumbs;
+pub mod instancing;
+pub mod surface;
+pub mod capture;
+#[cfg(unix)]
+pub mod frame_export;
\ No newline at end of file
@@ -0,0 +1,74 @@
+/// How the alpha channel of the base color is interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Fragments with alpha below the material's `alpha_cutoff` are discarded.
+    Mask,
+    Blend,
+}
+
+/// A texture referenced by a material.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureSlot {
+    /// Index into the texture list of the model the material belongs to.
+    pub texture: usize,
+    /// Which UV set of the mesh to sample with.
+    pub tex_coord: u32,
+}
+
+/// Metallic-roughness PBR material parameters, as defined by glTF 2.0.
+///
+/// Every factor multiplies the matching texture when one is present.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PbrMaterial {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    /// sRGB encoded.
+    pub base_color_texture: Option<TextureSlot>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Roughness in the green channel, metalness in the blue channel.
+    pub metallic_roughness_texture: Option<TextureSlot>,
+    pub normal_texture: Option<TextureSlot>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureSlot>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    /// sRGB encoded.
+    pub emissive_texture: Option<TextureSlot>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for PbrMaterial {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color_factor: [1.0; 4],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0; 3],
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl PbrMaterial {
+    /// Textures holding color data, which must be sampled as sRGB.
+    pub fn srgb_textures(&self) -> impl Iterator<Item = usize> {
+        [self.base_color_texture, self.emissive_texture]
+            .into_iter()
+            .flatten()
+            .map(|slot| slot.texture)
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use vulkano::buffer::sys::{Buffer, BufferMemory};
+use vulkano::buffer::BufferAccess;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::device::Device;
+use vulkano::image::sys::{Image, ImageMemory};
+use vulkano::image::ImageAccess;
+use vulkano::memory::allocator::MemoryAlloc;
+use vulkano::{DeviceSize, Version, VulkanObject};
+use crate::json::json_string;
+
+/// What an allocation is used for, to break memory usage down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemoryCategory {
+    Texture,
+    Mesh,
+    Uniform,
+    /// Host visible buffers for uploads and readbacks.
+    Staging,
+    /// Attachments sized after the output, like depth and post-processing targets.
+    RenderTarget,
+    /// Storage buffers of compute work.
+    Compute,
+    Other,
+}
+
+/// A live allocation recorded with [`MemoryTracker::track_buffer`] or
+/// [`MemoryTracker::track_image`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackedAllocation {
+    /// Increases with every tracked allocation, so leaks show up as old ids.
+    pub id: u64,
+    pub name: String,
+    pub category: MemoryCategory,
+    /// Bytes of device memory bound to the resource.
+    pub size: DeviceSize,
+    pub memory_type_index: u32,
+    pub heap_index: u32,
+}
+
+/// Usage of one memory heap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapStats {
+    pub heap_index: u32,
+    pub device_local: bool,
+    /// Size of the heap.
+    pub size: DeviceSize,
+    /// Bytes used by tracked allocations.
+    pub tracked: DeviceSize,
+    /// Bytes the driver reports used by this process. Needs `ext_memory_budget`.
+    pub usage: Option<DeviceSize>,
+    /// Bytes the process can allocate from the heap without degrading performance, including
+    /// `usage`. Needs `ext_memory_budget`.
+    pub budget: Option<DeviceSize>,
+}
+
+impl HeapStats {
+    /// Fraction of the budget in use, falling back to tracked bytes over the heap size.
+    pub fn budget_fraction(&self) -> f64 {
+        let used = self.usage.unwrap_or(self.tracked);
+        let limit = self.budget.unwrap_or(self.size);
+        used as f64 / limit.max(1) as f64
+    }
+}
+
+/// A snapshot of memory usage.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub heaps: Vec<HeapStats>,
+    /// Bytes of tracked allocations per category.
+    pub categories: BTreeMap<MemoryCategory, DeviceSize>,
+    /// Tracked allocations still alive, oldest first.
+    pub allocations: Vec<TrackedAllocation>,
+}
+
+impl MemoryStats {
+    /// The snapshot as JSON, with every live allocation, for finding leaks by diffing dumps.
+    pub fn to_json(&self) -> String {
+        let heaps: Vec<String> = self
+            .heaps
+            .iter()
+            .map(|heap| {
+                let optional = |value: Option<DeviceSize>| {
+                    value.map_or("null".to_owned(), |value| value.to_string())
+                };
+                format!(
+                    "{{\"heap\":{},\"device_local\":{},\"size\":{},\"tracked\":{},\
+                     \"usage\":{},\"budget\":{}}}",
+                    heap.heap_index,
+                    heap.device_local,
+                    heap.size,
+                    heap.tracked,
+                    optional(heap.usage),
+                    optional(heap.budget)
+                )
+            })
+            .collect();
+        let categories: Vec<String> = self
+            .categories
+            .iter()
+            .map(|(category, size)| format!("\"{:?}\":{}", category, size))
+            .collect();
+        let allocations: Vec<String> = self
+            .allocations
+            .iter()
+            .map(|allocation| {
+                format!(
+                    "{{\"id\":{},\"name\":{},\"category\":\"{:?}\",\"size\":{},\
+                     \"memory_type\":{},\"heap\":{}}}",
+                    allocation.id,
+                    json_string(&allocation.name),
+                    allocation.category,
+                    allocation.size,
+                    allocation.memory_type_index,
+                    allocation.heap_index
+                )
+            })
+            .collect();
+        format!(
+            "{{\n\"heaps\":[\n{}\n],\n\"categories\":{{{}}},\n\"allocations\":[\n{}\n]\n}}\n",
+            heaps.join(",\n"),
+            categories.join(","),
+            allocations.join(",\n")
+        )
+    }
+}
+
+/// Heaps over `threshold` that were not at the last check, remembered in `warned`.
+fn crossed_threshold<'a>(
+    warned: &mut Vec<bool>,
+    heaps: &'a [HeapStats],
+    threshold: f64,
+) -> Vec<&'a HeapStats> {
+    warned.resize(heaps.len(), false);
+    let mut crossed = Vec::new();
+    for heap in heaps {
+        let over = heap.budget_fraction() > threshold;
+        if over && !warned[heap.heap_index as usize] {
+            crossed.push(heap);
+        }
+        warned[heap.heap_index as usize] = over;
+    }
+    crossed
+}
+
+enum Resource {
+    Buffer(Weak<Buffer>),
+    Image(Weak<Image>),
+}
+
+impl Resource {
+    fn is_alive(&self) -> bool {
+        match self {
+            Resource::Buffer(buffer) => buffer.strong_count() > 0,
+            Resource::Image(image) => image.strong_count() > 0,
+        }
+    }
+}
+
+struct Entry {
+    allocation: TrackedAllocation,
+    resource: Resource,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    entries: Vec<Entry>,
+    next_id: u64,
+    /// Heaps over the warning threshold at the last check, to warn once per crossing.
+    warned: Vec<bool>,
+}
+
+/// Records device memory allocations of the renderer by heap and category, and compares heap
+/// usage against the budget of the driver.
+///
+/// Allocations are held weakly, and drop out of the statistics with their resource. Memory
+/// from buffer pools and swapchains is not tracked, but is included in the budget usage.
+pub struct MemoryTracker {
+    /// Fraction of a heap's budget above which [`Self::check_budget`] warns.
+    pub warning_threshold: f64,
+    physical_device: Arc<PhysicalDevice>,
+    /// Whether the device was created with `ext_memory_budget`.
+    budget_supported: bool,
+    state: Mutex<TrackerState>,
+}
+
+impl MemoryTracker {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            warning_threshold: 0.9,
+            physical_device: device.physical_device().clone(),
+            budget_supported: device.enabled_extensions().ext_memory_budget,
+            state: Mutex::new(TrackerState::default()),
+        }
+    }
+
+    /// Tracks the memory bound to `buffer`. Sparse buffers are ignored.
+    pub fn track_buffer(
+        &self,
+        buffer: &(impl BufferAccess + ?Sized),
+        category: MemoryCategory,
+        name: &str,
+    ) {
+        let buffer = buffer.inner().buffer;
+        if let BufferMemory::Normal(alloc) = buffer.memory() {
+            self.track(alloc, Resource::Buffer(Arc::downgrade(buffer)), category, name);
+        }
+    }
+
+    /// Tracks the memory bound to `image`. Sparse and swapchain images are ignored.
+    pub fn track_image(
+        &self,
+        image: &(impl ImageAccess + ?Sized),
+        category: MemoryCategory,
+        name: &str,
+    ) {
+        let image = image.inner().image;
+        if let ImageMemory::Normal(allocs) = image.memory() {
+            // Disjoint multi-planar images have an allocation per plane.
+            for alloc in allocs {
+                self.track(alloc, Resource::Image(Arc::downgrade(image)), category, name);
+            }
+        }
+    }
+
+    fn track(
+        &self,
+        alloc: &MemoryAlloc,
+        resource: Resource,
+        category: MemoryCategory,
+        name: &str,
+    ) {
+        let memory_type_index = alloc.device_memory().memory_type_index();
+        let heap_index = self.physical_device.memory_properties().memory_types
+            [memory_type_index as usize]
+            .heap_index;
+        let mut state = self.state.lock().unwrap();
+        state.entries.retain(|entry| entry.resource.is_alive());
+        let id = state.next_id;
+        state.next_id += 1;
+        state.entries.push(Entry {
+            allocation: TrackedAllocation {
+                id,
+                name: name.to_owned(),
+                category,
+                size: alloc.size(),
+                memory_type_index,
+                heap_index,
+            },
+            resource,
+        });
+    }
+
+    /// Usage per heap and category, and every live tracked allocation.
+    pub fn stats(&self) -> MemoryStats {
+        let mut state = self.state.lock().unwrap();
+        state.entries.retain(|entry| entry.resource.is_alive());
+        let budgets = self.query_budget();
+        let mut heaps: Vec<HeapStats> = self
+            .physical_device
+            .memory_properties()
+            .memory_heaps
+            .iter()
+            .enumerate()
+            .map(|(i, heap)| HeapStats {
+                heap_index: i as u32,
+                device_local: heap.flags.device_local,
+                size: heap.size,
+                tracked: 0,
+                usage: budgets.as_ref().map(|budgets| budgets[i].0),
+                budget: budgets.as_ref().map(|budgets| budgets[i].1),
+            })
+            .collect();
+        let mut categories = BTreeMap::new();
+        for entry in &state.entries {
+            let allocation = &entry.allocation;
+            heaps[allocation.heap_index as usize].tracked += allocation.size;
+            *categories.entry(allocation.category).or_insert(0) += allocation.size;
+        }
+        MemoryStats {
+            heaps,
+            categories,
+            allocations: state.entries.iter().map(|entry| entry.allocation.clone()).collect(),
+        }
+    }
+
+    /// Warns about heaps whose usage crossed [`Self::warning_threshold`] of their budget since
+    /// the last check. Cheap enough to call every frame.
+    pub fn check_budget(&self) -> MemoryStats {
+        let stats = self.stats();
+        let mut state = self.state.lock().unwrap();
+        for heap in crossed_threshold(&mut state.warned, &stats.heaps, self.warning_threshold) {
+            println!(
+                "Memory heap {} is at {:.0}% of its budget: {} of {} MiB",
+                heap.heap_index,
+                heap.budget_fraction() * 100.0,
+                heap.usage.unwrap_or(heap.tracked) >> 20,
+                heap.budget.unwrap_or(heap.size) >> 20
+            );
+        }
+        stats
+    }
+
+    /// Writes [`MemoryStats::to_json`] of the current stats to `path`.
+    pub fn write_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.stats().to_json())
+    }
+
+    /// `(usage, budget)` per heap from `ext_memory_budget`.
+    fn query_budget(&self) -> Option<Vec<(DeviceSize, DeviceSize)>> {
+        if !self.budget_supported {
+            return None;
+        }
+        let instance = self.physical_device.instance();
+        let mut budget = ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = ash::vk::PhysicalDeviceMemoryProperties2 {
+            p_next: &mut budget as *mut _ as *mut _,
+            ..Default::default()
+        };
+        let handle = self.physical_device.handle();
+        // The extension was enabled on the device, and both structures outlive the call.
+        unsafe {
+            let fns = instance.fns();
+            if instance.api_version() >= Version::V1_1 {
+                (fns.v1_1.get_physical_device_memory_properties2)(handle, &mut properties);
+            } else {
+                (fns.khr_get_physical_device_properties2
+                    .get_physical_device_memory_properties2_khr)(handle, &mut properties);
+            }
+        }
+        let heap_count = properties.memory_properties.memory_heap_count as usize;
+        Some(
+            (0..heap_count)
+                .map(|i| (budget.heap_usage[i], budget.heap_budget[i]))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heap(heap_index: u32, tracked: DeviceSize, budget: Option<DeviceSize>) -> HeapStats {
+        HeapStats {
+            heap_index,
+            device_local: heap_index == 0,
+            size: 1000,
+            tracked,
+            usage: budget.map(|_| tracked + 100),
+            budget,
+        }
+    }
+
+    #[test]
+    fn budget_fraction_prefers_the_driver_budget() {
+        assert_eq!(heap(0, 250, None).budget_fraction(), 0.25);
+        // Usage of 500 in a budget of 400.
+        assert_eq!(heap(0, 400, Some(400)).budget_fraction(), 1.25);
+        let empty = HeapStats {
+            size: 0,
+            ..heap(0, 0, None)
+        };
+        assert_eq!(empty.budget_fraction(), 0.0);
+    }
+
+    #[test]
+    fn warnings_come_once_per_crossing() {
+        let mut warned = Vec::new();
+        let indices = |heaps: Vec<&HeapStats>| -> Vec<u32> {
+            heaps.iter().map(|heap| heap.heap_index).collect()
+        };
+        let low = [heap(0, 100, None), heap(1, 100, None)];
+        let high = [heap(0, 950, None), heap(1, 100, None)];
+        assert!(crossed_threshold(&mut warned, &low, 0.9).is_empty());
+        assert_eq!(indices(crossed_threshold(&mut warned, &high, 0.9)), [0]);
+        assert!(crossed_threshold(&mut warned, &high, 0.9).is_empty());
+        assert!(crossed_threshold(&mut warned, &low, 0.9).is_empty());
+        assert_eq!(indices(crossed_threshold(&mut warned, &high, 0.9)), [0]);
+    }
+
+    #[test]
+    fn stats_as_json() {
+        let stats = MemoryStats {
+            heaps: vec![heap(0, 300, Some(800)), heap(1, 0, None)],
+            categories: BTreeMap::from([
+                (MemoryCategory::Mesh, 100),
+                (MemoryCategory::Texture, 200),
+            ]),
+            allocations: vec![TrackedAllocation {
+                id: 7,
+                name: "albedo \"brick\"".to_owned(),
+                category: MemoryCategory::Texture,
+                size: 200,
+                memory_type_index: 2,
+                heap_index: 0,
+            }],
+        };
+        let expected = "{\n\"heaps\":[\n\
+            {\"heap\":0,\"device_local\":true,\"size\":1000,\"tracked\":300,\"usage\":400,\
+            \"budget\":800},\n\
+            {\"heap\":1,\"device_local\":false,\"size\":1000,\"tracked\":0,\"usage\":null,\
+            \"budget\":null}\n],\n\
+            \"categories\":{\"Texture\":200,\"Mesh\":100},\n\
+            \"allocations\":[\n\
+            {\"id\":7,\"name\":\"albedo \\\"brick\\\"\",\"category\":\"Texture\",\"size\":200,\
+            \"memory_type\":2,\"heap\":0}\n]\n}\n";
+        assert_eq!(stats.to_json(), expected);
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::impl_vertex;
+use crate::debug_utils::set_buffer_name;
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+
+/// Vertex layout shared by every mesh the renderer draws.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    /// Tangent in `xyz`, bitangent sign in `w`.
+    pub tangent: [f32; 4],
+}
+impl_vertex!(MeshVertex, position, normal, uv, tangent);
+
+/// CPU-side description of an indexed triangle list.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// The first index that does not refer to a vertex, if any.
+    pub fn first_invalid_index(&self) -> Option<u32> {
+        self.indices
+            .iter()
+            .copied()
+            .find(|&index| index as usize >= self.vertices.len())
+    }
+
+    /// Replaces the normals with area-weighted face normals.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an index is out of bounds, see [`Self::first_invalid_index`].
+    pub fn generate_normals(&mut self) {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(self.vertices[triangle[i] as usize].position));
+            let normal = (b - a).cross(c - a);
+            for &i in triangle {
+                normals[i as usize] += normal;
+            }
+        }
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.normal = normal.normalize_or_zero().to_array();
+        }
+    }
+
+    /// Derives per-vertex tangents from positions, normals and UVs.
+    ///
+    /// Tangents are accumulated per triangle, then Gram-Schmidt orthogonalized against the
+    /// normal. The sign in `w` records the handedness of the UV mapping.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an index is out of bounds, see [`Self::first_invalid_index`].
+    pub fn generate_tangents(&mut self) {
+        let mut tangents = vec![Vec3::ZERO; self.vertices.len()];
+        let mut bitangents = vec![Vec3::ZERO; self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let vertices = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize]);
+            let [p0, p1, p2] = vertices.map(|v| Vec3::from(v.position));
+            let [uv0, uv1, uv2] = vertices.map(|v| Vec2::from(v.uv));
+            let (edge1, edge2) = (p1 - p0, p2 - p0);
+            let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+            let det = duv1.x * duv2.y - duv2.x * duv1.y;
+            if det.abs() <= f32::EPSILON {
+                continue;
+            }
+            let r = 1.0 / det;
+            let tangent = (edge1 * duv2.y - edge2 * duv1.y) * r;
+            let bitangent = (edge2 * duv1.x - edge1 * duv2.x) * r;
+            for &i in triangle {
+                tangents[i as usize] += tangent;
+                bitangents[i as usize] += bitangent;
+            }
+        }
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            let normal = Vec3::from(vertex.normal);
+            let tangent = (tangents[i] - normal * normal.dot(tangents[i])).normalize_or_zero();
+            let tangent = if tangent == Vec3::ZERO {
+                normal.any_orthonormal_vector()
+            } else {
+                tangent
+            };
+            let sign = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.tangent = tangent.extend(sign).to_array();
+        }
+    }
+}
+
+/// Mesh data uploaded to the GPU.
+pub struct Mesh {
+    pub vertex_buffer: Arc<CpuAccessibleBuffer<[MeshVertex]>>,
+    pub index_buffer: Arc<CpuAccessibleBuffer<[u32]>>,
+    pub index_count: u32,
+    /// Index into the material list of the model this mesh belongs to.
+    pub material: Option<usize>,
+}
+
+impl Mesh {
+    /// # Panics
+    ///
+    /// Panics if `data` has no vertices or no indices, as buffers cannot be empty.
+    pub fn new(render_device: &RenderDevice, data: &MeshData, material: Option<usize>) -> Self {
+        assert!(
+            !data.vertices.is_empty() && !data.indices.is_empty(),
+            "Meshes need at least one vertex and one index"
+        );
+        let vertex_buffer = CpuAccessibleBuffer::from_iter(
+            &render_device.memory_allocator,
+            BufferUsage {
+                vertex_buffer: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            data.vertices.iter().copied(),
+        )
+        .unwrap();
+        let index_buffer = CpuAccessibleBuffer::from_iter(
+            &render_device.memory_allocator,
+            BufferUsage {
+                index_buffer: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            data.indices.iter().copied(),
+        )
+        .unwrap();
+        set_buffer_name(vertex_buffer.as_ref(), "mesh vertices");
+        render_device
+            .memory_tracker
+            .track_buffer(vertex_buffer.as_ref(), MemoryCategory::Mesh, "mesh vertices");
+        set_buffer_name(index_buffer.as_ref(), "mesh indices");
+        render_device
+            .memory_tracker
+            .track_buffer(index_buffer.as_ref(), MemoryCategory::Mesh, "mesh indices");
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: data.indices.len() as u32,
+            material,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit quad in the XY plane facing +Z, with `uv` per corner.
+    fn quad(uvs: [[f32; 2]; 4]) -> MeshData {
+        let positions = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+        MeshData {
+            vertices: positions
+                .iter()
+                .zip(uvs)
+                .map(|(&position, uv)| MeshVertex {
+                    position,
+                    uv,
+                    ..Default::default()
+                })
+                .collect(),
+            indices: vec![0, 1, 2, 0, 2, 3],
+        }
+    }
+
+    #[test]
+    fn normals_of_a_quad() {
+        let mut quad = quad([[0.0; 2]; 4]);
+        quad.generate_normals();
+        for vertex in &quad.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u() {
+        let mut quad = quad([[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        quad.generate_normals();
+        quad.generate_tangents();
+        for vertex in &quad.vertices {
+            assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_bitangent() {
+        let mut quad = quad([[1.0, 0.0], [0.0, 0.0], [0.0, 1.0], [1.0, 1.0]]);
+        quad.generate_normals();
+        quad.generate_tangents();
+        for vertex in &quad.vertices {
+            assert_eq!(vertex.tangent, [-1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs_give_an_orthonormal_tangent() {
+        let mut quad = quad([[0.5; 2]; 4]);
+        quad.generate_normals();
+        quad.generate_tangents();
+        for vertex in &quad.vertices {
+            let tangent = Vec3::from_slice(&vertex.tangent);
+            assert!((tangent.length() - 1.0).abs() < 1e-6);
+            assert!(tangent.dot(Vec3::from(vertex.normal)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn invalid_indices_are_found() {
+        let mut quad = quad([[0.0; 2]; 4]);
+        assert_eq!(quad.first_invalid_index(), None);
+        quad.indices.extend([3, 4, 7]);
+        assert_eq!(quad.first_invalid_index(), Some(4));
+    }
+}
@@ -0,0 +1,580 @@
+use std::fmt;
+use std::ops::{Add, Mul};
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec3, Vec4};
+use vulkano::buffer::{BufferUsage, CpuBufferPool, DeviceLocalBuffer};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, DrawIndirectCommand, FillBufferInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::Format;
+use vulkano::image::SampleCount;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendState};
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use crate::camera::{apply_view_set_layout, ViewUniforms, VIEW_SET};
+use crate::compute::{storage_buffer, ComputePipeline};
+use crate::debug_utils::{begin_label, end_label, set_buffer_name, set_object_name};
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+
+/// Number of samples curves are baked into for the shaders.
+pub const CURVE_SAMPLES: usize = 16;
+/// Descriptor set of the particle buffers and appearance in the billboard pipeline.
+pub const PARTICLE_SET: u32 = 1;
+/// `local_size_x` of the simulation shader.
+const SIMULATION_GROUP_SIZE: u32 = 64;
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "shader/particles/simulate.comp",
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/particles/particle.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/particles/particle.frag",
+    }
+}
+
+mod fs_multisampled {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/particles/particle.frag",
+        define: [("MULTISAMPLED_DEPTH", "1")],
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParticleError {
+    /// A [`Curve`] needs at least one key.
+    EmptyCurve,
+    /// An emitter needs room for at least one particle.
+    ZeroCapacity,
+}
+
+impl fmt::Display for ParticleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParticleError::EmptyCurve => write!(f, "a curve needs at least one key"),
+            ParticleError::ZeroCapacity => {
+                write!(f, "an emitter needs room for at least one particle")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParticleError {}
+
+/// Piecewise linear curve over the normalized age of a particle, from 0 at spawn to 1 at
+/// death. Values before the first and after the last key are held constant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T> {
+    /// `(age, value)` pairs, sorted by age.
+    pub keys: Vec<(f32, T)>,
+}
+
+impl<T: Copy + Add<Output = T> + Mul<f32, Output = T>> Curve<T> {
+    pub fn new(keys: impl IntoIterator<Item = (f32, T)>) -> Result<Self, ParticleError> {
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        if keys.is_empty() {
+            return Err(ParticleError::EmptyCurve);
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self { keys })
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    pub fn linear(start: T, end: T) -> Self {
+        Self {
+            keys: vec![(0.0, start), (1.0, end)],
+        }
+    }
+
+    pub fn sample(&self, age: f32) -> T {
+        let next = self.keys.partition_point(|&(key_age, _)| key_age <= age);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (start_age, start) = self.keys[next - 1];
+        let (end_age, end) = self.keys[next];
+        let t = (age - start_age) / (end_age - start_age);
+        start * (1.0 - t) + end * t
+    }
+
+    /// The curve sampled at [`CURVE_SAMPLES`] evenly spaced ages, including 0 and 1.
+    pub fn bake(&self) -> [T; CURVE_SAMPLES] {
+        std::array::from_fn(|i| self.sample(i as f32 / (CURVE_SAMPLES - 1) as f32))
+    }
+}
+
+/// How an emitter spawns particles, and how they move and look over their lifetime.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmitterSettings {
+    pub position: Vec3,
+    /// Particles spawn at a random point within this distance of `position`.
+    pub spawn_radius: f32,
+    /// Particles per second. Fractions carry over to the next frame.
+    pub spawn_rate: f32,
+    /// Seconds a particle lives.
+    pub lifetime: f32,
+    /// Fraction of `lifetime` randomly taken off each particle, between 0 and 1.
+    pub lifetime_randomness: f32,
+    /// Initial velocity of every particle.
+    pub velocity: Vec3,
+    /// Speed added to the initial velocity, in a random direction.
+    pub velocity_randomness: f32,
+    /// Constant acceleration, like gravity.
+    pub acceleration: Vec3,
+    /// Multiplier of the velocity over the lifetime.
+    pub speed: Curve<f32>,
+    /// Linear color over the lifetime. Alpha is the opacity.
+    pub color: Curve<Vec4>,
+    /// Billboard width over the lifetime.
+    pub size: Curve<f32>,
+    /// Distance over which particles fade out in front of scene geometry, instead of
+    /// intersecting it.
+    pub softness: f32,
+}
+
+impl Default for EmitterSettings {
+    /// A small upwards fountain.
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            spawn_radius: 0.05,
+            spawn_rate: 200.0,
+            lifetime: 2.0,
+            lifetime_randomness: 0.3,
+            velocity: Vec3::new(0.0, 2.5, 0.0),
+            velocity_randomness: 0.5,
+            acceleration: Vec3::new(0.0, -2.0, 0.0),
+            speed: Curve::constant(1.0),
+            color: Curve::new([
+                (0.0, Vec4::new(1.0, 0.8, 0.4, 0.0)),
+                (0.1, Vec4::new(1.0, 0.6, 0.2, 0.8)),
+                (1.0, Vec4::new(0.3, 0.3, 0.3, 0.0)),
+            ])
+            .unwrap(),
+            size: Curve::linear(0.05, 0.2),
+            softness: 0.2,
+        }
+    }
+}
+
+/// A particle as stored in [`ParticleEmitter::particles`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct Particle {
+    /// `w` is the age in seconds.
+    pub position: [f32; 4],
+    /// `w` is the lifetime in seconds, zero for dead particles.
+    pub velocity: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct EmitterUniform {
+    position: [f32; 4],
+    velocity: [f32; 4],
+    acceleration: [f32; 4],
+    speed_curve: [[f32; 4]; CURVE_SAMPLES / 4],
+    delta_time: f32,
+    lifetime: f32,
+    lifetime_randomness: f32,
+    capacity: u32,
+    spawn_start: u32,
+    spawn_count: u32,
+    seed: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Zeroable, Pod)]
+struct AppearanceUniform {
+    colors: [[f32; 4]; CURVE_SAMPLES],
+    sizes: [[f32; 4]; CURVE_SAMPLES / 4],
+    softness: f32,
+    _padding: [f32; 3],
+}
+
+/// Particles of one emitter, living on the GPU. Created by
+/// [`ParticleSystem::create_emitter`].
+///
+/// Particles live in a ring buffer of `capacity` slots, and new particles replace the oldest
+/// ones. For particles to live their full lifetime, `capacity` should be at least
+/// `spawn_rate * lifetime`.
+pub struct ParticleEmitter {
+    pub settings: EmitterSettings,
+    /// Spawns new particles while set. Living particles keep moving either way.
+    pub emitting: bool,
+    pub capacity: u32,
+    pub particles: Arc<DeviceLocalBuffer<[Particle]>>,
+    /// Indices of the particles alive after the last simulation step.
+    pub alive: Arc<DeviceLocalBuffer<[u32]>>,
+    /// Draws a quad per living particle. The simulation counts the instances.
+    pub draw_command: Arc<DeviceLocalBuffer<[DrawIndirectCommand]>>,
+    /// Particles still to spawn, including fractions from previous frames.
+    spawn_accumulator: f32,
+    next_slot: u32,
+    seed: u32,
+    /// Kills every particle in the next simulation step. Buffers start out uninitialized.
+    needs_clear: bool,
+}
+
+impl ParticleEmitter {
+    /// Spawns `count` particles in the next simulation step, on top of the spawn rate.
+    pub fn burst(&mut self, count: u32) {
+        self.spawn_accumulator += count as f32;
+    }
+
+    /// Kills every particle in the next simulation step.
+    pub fn clear(&mut self) {
+        self.needs_clear = true;
+        self.spawn_accumulator = 0.0;
+    }
+}
+
+/// Simulates particles in a compute shader and draws the living ones as soft camera facing
+/// billboards, without reading anything back to the CPU.
+///
+/// Record [`Self::simulate`] outside of rendering, then [`Self::draw`] in a pass after the
+/// scene, with the scene depth stored. Billboards do not use a depth attachment: they sample
+/// [`RenderOutput::sampled_depth_view`] and fade out in front of, and disappear behind, scene
+/// geometry.
+pub struct ParticleSystem {
+    pub simulation: ComputePipeline,
+    pub pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    emitter_pool: CpuBufferPool<EmitterUniform, StandardMemoryAllocator>,
+    appearance_pool: CpuBufferPool<AppearanceUniform, StandardMemoryAllocator>,
+}
+
+impl ParticleSystem {
+    /// Creates the pipelines, drawing into a single-sampled `color_format` attachment.
+    /// `render_output` must have been created with `sampled_depth`.
+    pub fn new(
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        color_format: Format,
+    ) -> Self {
+        let device = render_device.device.clone();
+        let simulation = ComputePipeline::from_module(
+            render_device,
+            cs::load(device.clone()).unwrap(),
+            [SIMULATION_GROUP_SIZE, 1, 1],
+        )
+        .unwrap();
+        let vs = vs::load(device.clone()).unwrap();
+        let fs = if render_output.samples == SampleCount::Sample1 {
+            fs::load(device.clone()).unwrap()
+        } else {
+            fs_multisampled::load(device.clone()).unwrap()
+        };
+        let pipeline = GraphicsPipeline::start()
+            .render_pass(PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(color_format)],
+                ..Default::default()
+            })
+            .vertex_input_state(BuffersDefinition::new())
+            .input_assembly_state(
+                InputAssemblyState::new().topology(PrimitiveTopology::TriangleStrip),
+            )
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .color_blend_state(ColorBlendState::new(1).blend(AttachmentBlend::alpha()))
+            .with_auto_layout(device.clone(), apply_view_set_layout)
+            .unwrap();
+        set_object_name(simulation.pipeline.as_ref(), "particle simulation");
+        set_object_name(pipeline.as_ref(), "particle billboards");
+        let sampler = Sampler::new(
+            device,
+            SamplerCreateInfo {
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Nearest,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Self {
+            simulation,
+            pipeline,
+            sampler,
+            emitter_pool: CpuBufferPool::uniform_buffer(render_device.memory_allocator.clone()),
+            appearance_pool: CpuBufferPool::uniform_buffer(
+                render_device.memory_allocator.clone(),
+            ),
+        }
+    }
+
+    /// Allocates the buffers of an emitter holding up to `capacity` particles.
+    pub fn create_emitter(
+        &self,
+        render_device: &RenderDevice,
+        settings: EmitterSettings,
+        capacity: u32,
+    ) -> Result<ParticleEmitter, ParticleError> {
+        if capacity == 0 {
+            return Err(ParticleError::ZeroCapacity);
+        }
+        let transfer_dst = BufferUsage {
+            transfer_dst: true,
+            ..BufferUsage::empty()
+        };
+        let particles = storage_buffer(render_device, capacity as u64, transfer_dst);
+        let alive = storage_buffer(render_device, capacity as u64, BufferUsage::empty());
+        let draw_command = storage_buffer(
+            render_device,
+            1,
+            BufferUsage {
+                indirect_buffer: true,
+                ..transfer_dst
+            },
+        );
+        set_buffer_name(particles.as_ref(), "particles");
+        render_device
+            .memory_tracker
+            .track_buffer(particles.as_ref(), MemoryCategory::Compute, "particles");
+        set_buffer_name(alive.as_ref(), "alive particles");
+        render_device
+            .memory_tracker
+            .track_buffer(alive.as_ref(), MemoryCategory::Compute, "alive particles");
+        set_buffer_name(draw_command.as_ref(), "particle draw command");
+        render_device
+            .memory_tracker
+            .track_buffer(draw_command.as_ref(), MemoryCategory::Compute, "particle draw command");
+        Ok(ParticleEmitter {
+            settings,
+            emitting: true,
+            capacity,
+            particles,
+            alive,
+            draw_command,
+            spawn_accumulator: 0.0,
+            next_slot: 0,
+            seed: 0,
+            needs_clear: true,
+        })
+    }
+
+    /// Records spawning and moving the particles of `emitter` by `delta_time` seconds. Must be
+    /// recorded outside of rendering.
+    pub fn simulate(
+        &self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        emitter: &mut ParticleEmitter,
+        delta_time: f32,
+    ) {
+        begin_label(builder, "particle simulation");
+        if emitter.needs_clear {
+            builder
+                .fill_buffer(FillBufferInfo::dst_buffer(emitter.particles.clone()))
+                .unwrap();
+            emitter.needs_clear = false;
+        }
+        let settings = &emitter.settings;
+        if emitter.emitting {
+            emitter.spawn_accumulator += settings.spawn_rate * delta_time;
+        }
+        let spawn = emitter.spawn_accumulator.floor();
+        emitter.spawn_accumulator -= spawn;
+        let spawn_count = (spawn as u32).min(emitter.capacity);
+        let spawn_start = emitter.next_slot;
+        emitter.next_slot = (emitter.next_slot + spawn_count) % emitter.capacity;
+        emitter.seed = emitter.seed.wrapping_add(1);
+
+        let speed = settings.speed.bake();
+        let uniform = self
+            .emitter_pool
+            .from_data(EmitterUniform {
+                position: settings.position.extend(settings.spawn_radius).to_array(),
+                velocity: settings
+                    .velocity
+                    .extend(settings.velocity_randomness)
+                    .to_array(),
+                acceleration: settings.acceleration.extend(0.0).to_array(),
+                speed_curve: std::array::from_fn(|i| std::array::from_fn(|j| speed[i * 4 + j])),
+                delta_time,
+                lifetime: settings.lifetime,
+                lifetime_randomness: settings.lifetime_randomness,
+                capacity: emitter.capacity,
+                spawn_start,
+                spawn_count,
+                seed: emitter.seed,
+                _padding: 0,
+            })
+            .unwrap();
+        let descriptor_set = self.simulation.descriptor_set(
+            render_device,
+            0,
+            [
+                WriteDescriptorSet::buffer(0, emitter.particles.clone()),
+                WriteDescriptorSet::buffer(1, emitter.alive.clone()),
+                WriteDescriptorSet::buffer(2, emitter.draw_command.clone()),
+                WriteDescriptorSet::buffer(3, uniform),
+            ],
+        );
+        let draw_command = DrawIndirectCommand {
+            vertex_count: 4,
+            instance_count: 0,
+            first_vertex: 0,
+            first_instance: 0,
+        };
+        builder
+            .update_buffer(
+                vec![draw_command].into_boxed_slice(),
+                emitter.draw_command.clone(),
+                0,
+            )
+            .unwrap();
+        self.simulation.bind_and_dispatch(
+            builder,
+            vec![descriptor_set],
+            [emitter.capacity, 1, 1],
+        );
+        end_label(builder);
+    }
+
+    /// Records drawing the living particles of `emitters`. Must be recorded inside rendering
+    /// into the color attachment given to [`Self::new`], after the scene depth was written.
+    pub fn draw(
+        &self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        render_output: &RenderOutput,
+        view_uniforms: &ViewUniforms,
+        emitters: &[ParticleEmitter],
+    ) {
+        let scene_depth = render_output
+            .sampled_depth_view
+            .clone()
+            .expect("Soft particles need a render output created with sampled_depth");
+        begin_label(builder, "particles");
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                VIEW_SET,
+                view_uniforms.descriptor_set.clone(),
+            );
+        for emitter in emitters {
+            let settings = &emitter.settings;
+            let sizes = settings.size.bake();
+            let appearance = self
+                .appearance_pool
+                .from_data(AppearanceUniform {
+                    colors: settings.color.bake().map(|color| color.to_array()),
+                    sizes: std::array::from_fn(|i| std::array::from_fn(|j| sizes[i * 4 + j])),
+                    softness: settings.softness,
+                    _padding: [0.0; 3],
+                })
+                .unwrap();
+            let descriptor_set = PersistentDescriptorSet::new(
+                &render_device.descriptor_set_allocator,
+                self.pipeline.layout().set_layouts()[PARTICLE_SET as usize].clone(),
+                [
+                    WriteDescriptorSet::buffer(0, emitter.particles.clone()),
+                    WriteDescriptorSet::buffer(1, emitter.alive.clone()),
+                    WriteDescriptorSet::buffer(2, appearance),
+                    WriteDescriptorSet::image_view_sampler(
+                        3,
+                        scene_depth.clone(),
+                        self.sampler.clone(),
+                    ),
+                ],
+            )
+            .unwrap();
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.pipeline.layout().clone(),
+                    PARTICLE_SET,
+                    descriptor_set,
+                )
+                .draw_indirect(emitter.draw_command.clone())
+                .unwrap();
+        }
+        end_label(builder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compute::{compile_glsl, reflect_local_size};
+    use super::*;
+
+    #[test]
+    fn curves_need_keys() {
+        let keys: [(f32, f32); 0] = [];
+        assert_eq!(Curve::new(keys), Err(ParticleError::EmptyCurve));
+    }
+
+    #[test]
+    fn curve_keys_are_sorted() {
+        let curve = Curve::new([(1.0, 2.0), (0.0, 1.0), (0.5, 4.0)]).unwrap();
+        assert_eq!(curve.keys, [(0.0, 1.0), (0.5, 4.0), (1.0, 2.0)]);
+    }
+
+    #[test]
+    fn curves_interpolate_between_keys() {
+        let curve = Curve::new([(0.25, 1.0), (0.5, 3.0), (1.0, -1.0)]).unwrap();
+        assert_eq!(curve.sample(0.375), 2.0);
+        assert_eq!(curve.sample(0.5), 3.0);
+        assert_eq!(curve.sample(0.625), 2.0);
+        let color = Curve::linear(Vec4::ZERO, Vec4::new(2.0, 4.0, 6.0, 8.0));
+        assert_eq!(color.sample(0.25), Vec4::new(0.5, 1.0, 1.5, 2.0));
+    }
+
+    #[test]
+    fn curves_hold_their_end_values() {
+        let curve = Curve::new([(0.2, 1.0), (0.8, 3.0)]).unwrap();
+        assert_eq!(curve.sample(0.0), 1.0);
+        assert_eq!(curve.sample(-1.0), 1.0);
+        assert_eq!(curve.sample(1.0), 3.0);
+        assert_eq!(curve.sample(2.0), 3.0);
+        assert_eq!(Curve::constant(5.0).sample(0.5), 5.0);
+    }
+
+    #[test]
+    fn bake_includes_both_ends() {
+        let baked = Curve::linear(0.0, 15.0).bake();
+        let expected: Vec<f32> = (0..CURVE_SAMPLES).map(|i| i as f32).collect();
+        assert_eq!(baked.as_slice(), expected);
+        assert_eq!(Curve::constant(2.0).bake(), [2.0; CURVE_SAMPLES]);
+    }
+
+    #[test]
+    fn simulation_group_size_matches_the_shader() {
+        let words = compile_glsl(include_str!("../shader/particles/simulate.comp"), "simulate")
+            .unwrap();
+        assert_eq!(reflect_local_size(&words).unwrap(), [SIMULATION_GROUP_SIZE, 1, 1]);
+    }
+}
@@ -0,0 +1,735 @@
+use std::path::Path;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use half::f16;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::layout::{
+    DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorType,
+};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageDimensions, ImmutableImage, MipmapsCount};
+use vulkano::memory::allocator::{MemoryUsage, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode};
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode};
+use vulkano::shader::ShaderStages;
+use crate::camera::{apply_view_set_layout, Camera, ViewUniforms, VIEW_SET};
+use crate::gltf_import::Model;
+use crate::material::{AlphaMode, PbrMaterial, TextureSlot};
+use crate::instancing::{draw_batch, DrawList, InstanceBuffer, ModelInstance};
+use crate::mesh::MeshVertex;
+use crate::debug_utils::{begin_label, end_label, set_image_name, set_object_name};
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+use crate::shadow::{ShadowError, ShadowMaps, ShadowSettings, ShadowUniform};
+use crate::texture::{ImageData, SamplerDesc, Texture};
+
+/// Descriptor set index the lights, the environment map and the shadow maps are bound at.
+pub const LIGHTING_SET: u32 = 1;
+/// Descriptor set index of the per-material parameters and textures.
+pub const MATERIAL_SET: u32 = 2;
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/pbr/pbr.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/pbr/pbr.frag",
+    }
+}
+
+/// A punctual light, as defined by `KHR_lights_punctual`. Intensities are in lux for
+/// directional lights and candela for point and spot lights.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    Directional {
+        /// Direction the light travels in.
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+    },
+    Point {
+        position: Vec3,
+        color: Vec3,
+        intensity: f32,
+        /// Distance at which the light's contribution reaches zero. `None` for the physical
+        /// inverse-square falloff only.
+        range: Option<f32>,
+    },
+    Spot {
+        position: Vec3,
+        /// Direction the light travels in.
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        range: Option<f32>,
+        /// Angle from the axis at which the falloff starts, in radians.
+        inner_cone_angle: f32,
+        /// Angle from the axis at which the light reaches zero, in radians.
+        outer_cone_angle: f32,
+    },
+}
+
+/// A light as stored in the light storage buffer. Matches the std430 layout of `Light` in
+/// `pbr.frag`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct GpuLight {
+    pub position_range: [f32; 4],
+    pub direction_type: [f32; 4],
+    pub color_intensity: [f32; 4],
+    pub spot_cone: [f32; 4],
+}
+
+const LIGHT_DIRECTIONAL: f32 = 0.0;
+const LIGHT_POINT: f32 = 1.0;
+const LIGHT_SPOT: f32 = 2.0;
+
+impl From<&Light> for GpuLight {
+    fn from(light: &Light) -> Self {
+        match *light {
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+            } => Self {
+                position_range: [0.0; 4],
+                direction_type: direction.normalize().extend(LIGHT_DIRECTIONAL).to_array(),
+                color_intensity: color.extend(intensity).to_array(),
+                spot_cone: [0.0; 4],
+            },
+            Light::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => Self {
+                position_range: position.extend(range.unwrap_or(0.0)).to_array(),
+                direction_type: [0.0, 0.0, 0.0, LIGHT_POINT],
+                color_intensity: color.extend(intensity).to_array(),
+                spot_cone: [0.0; 4],
+            },
+            Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => Self {
+                position_range: position.extend(range.unwrap_or(0.0)).to_array(),
+                direction_type: direction.normalize().extend(LIGHT_SPOT).to_array(),
+                color_intensity: color.extend(intensity).to_array(),
+                spot_cone: [inner_cone_angle.cos(), outer_cone_angle.cos(), 0.0, 0.0],
+            },
+        }
+    }
+}
+
+/// Contents of the lighting uniform buffer. Matches the std140 layout of `LightingUniform` in
+/// `pbr.frag`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct LightingUniform {
+    pub light_count: u32,
+    pub environment_intensity: f32,
+    pub _pad: [f32; 2],
+}
+
+/// Linear HDR environment in equirectangular layout, used for image-based ambient lighting.
+///
+/// Its mip chain stands in for prefiltered radiance: rough surfaces sample blurrier mips, and
+/// the last mip approximates the irradiance.
+pub struct EnvironmentMap {
+    pub view: Arc<ImageView<ImmutableImage>>,
+    pub sampler: Arc<Sampler>,
+}
+
+impl EnvironmentMap {
+    /// Loads an equirectangular image, typically a Radiance `.hdr` file. LDR images are
+    /// treated as linear.
+    pub fn from_equirectangular(
+        render_device: &RenderDevice,
+        path: impl AsRef<Path>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> image::ImageResult<Self> {
+        let image = image::open(path)?.into_rgba32f();
+        let (width, height) = image.dimensions();
+        Ok(Self::new(render_device, width, height, image.as_raw(), builder))
+    }
+
+    /// An environment of a single color in every direction.
+    pub fn uniform(
+        render_device: &RenderDevice,
+        color: Vec3,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        Self::new(render_device, 1, 1, &color.extend(1.0).to_array(), builder)
+    }
+
+    /// Uploads `width * height` RGBA texels.
+    pub fn new(
+        render_device: &RenderDevice,
+        width: u32,
+        height: u32,
+        texels: &[f32],
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        // Half floats are guaranteed to support linear filtering and blits for mip generation.
+        let image = ImmutableImage::from_iter(
+            &render_device.memory_allocator,
+            texels.iter().map(|&texel| f16::from_f32(texel)),
+            ImageDimensions::Dim2d {
+                width,
+                height,
+                array_layers: 1,
+            },
+            MipmapsCount::Log2,
+            Format::R16G16B16A16_SFLOAT,
+            builder,
+        )
+        .unwrap();
+        set_image_name(image.as_ref(), "environment map");
+        render_device
+            .memory_tracker
+            .track_image(image.as_ref(), MemoryCategory::Texture, "environment map");
+        let sampler = Sampler::new(
+            render_device.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [
+                    SamplerAddressMode::Repeat,
+                    SamplerAddressMode::ClampToEdge,
+                    SamplerAddressMode::ClampToEdge,
+                ],
+                lod: 0.0..=vulkano::sampler::LOD_CLAMP_NONE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Self {
+            view: ImageView::new_default(image).unwrap(),
+            sampler,
+        }
+    }
+}
+
+/// The lights of a scene, its environment map and its shadow maps, bound at [`LIGHTING_SET`].
+///
+/// Edit [`Lighting::lights`] freely, then call [`Lighting::update`] once per frame to upload
+/// them.
+pub struct Lighting {
+    pub lights: Vec<Light>,
+    /// Multiplier of the image-based ambient light.
+    pub environment_intensity: f32,
+    pub environment: EnvironmentMap,
+    /// Index into [`Self::lights`] of the directional light casting shadows.
+    pub shadow_caster: Option<usize>,
+    pub shadows: ShadowMaps,
+    pub layout: Arc<DescriptorSetLayout>,
+    /// Descriptor set holding this frame's lights. Replaced by every [`Self::update`].
+    pub descriptor_set: Arc<PersistentDescriptorSet>,
+    pools: LightingPools,
+}
+
+struct LightingPools {
+    uniform: CpuBufferPool<LightingUniform, StandardMemoryAllocator>,
+    lights: CpuBufferPool<GpuLight, StandardMemoryAllocator>,
+    shadow: CpuBufferPool<ShadowUniform, StandardMemoryAllocator>,
+}
+
+impl Lighting {
+    pub fn new(
+        render_device: &RenderDevice,
+        environment: EnvironmentMap,
+        shadow_settings: ShadowSettings,
+    ) -> Result<Self, ShadowError> {
+        let layout = DescriptorSetLayout::new(
+            render_device.device.clone(),
+            lighting_set_layout_create_info(),
+        )
+        .unwrap();
+        let pools = LightingPools {
+            uniform: CpuBufferPool::uniform_buffer(render_device.memory_allocator.clone()),
+            lights: CpuBufferPool::new(
+                render_device.memory_allocator.clone(),
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::empty()
+                },
+                MemoryUsage::Upload,
+            ),
+            shadow: CpuBufferPool::uniform_buffer(render_device.memory_allocator.clone()),
+        };
+        let shadows = ShadowMaps::new(render_device, shadow_settings)?;
+        let descriptor_set = Self::write_set(
+            render_device,
+            &layout,
+            &pools,
+            &environment,
+            &shadows,
+            &[],
+            1.0,
+            None,
+        );
+        Ok(Self {
+            lights: Vec::new(),
+            environment_intensity: 1.0,
+            environment,
+            shadow_caster: None,
+            shadows,
+            layout,
+            descriptor_set,
+            pools,
+        })
+    }
+
+    /// Replaces the shadow maps, for example when the quality tier changes. Takes effect at the
+    /// next [`Self::update`]. The current shadow maps stay on error.
+    pub fn set_shadow_settings(
+        &mut self,
+        render_device: &RenderDevice,
+        settings: ShadowSettings,
+    ) -> Result<(), ShadowError> {
+        self.shadows = ShadowMaps::new(render_device, settings)?;
+        Ok(())
+    }
+
+    /// Uploads the current lights and environment intensity, and fits the shadow cascades of
+    /// [`Self::shadow_caster`] to `camera`.
+    pub fn update(
+        &mut self,
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        camera: &Camera,
+    ) {
+        let shadow_direction = match self.shadow_caster.and_then(|index| self.lights.get(index)) {
+            Some(Light::Directional { direction, .. }) => Some(*direction),
+            _ => None,
+        };
+        match shadow_direction {
+            Some(direction) => {
+                let [width, height] = render_output.swapchain.image_extent();
+                let aspect_ratio = width as f32 / height.max(1) as f32;
+                self.shadows.update(camera, aspect_ratio, direction);
+            }
+            None => self.shadows.cascades.clear(),
+        }
+        self.descriptor_set = Self::write_set(
+            render_device,
+            &self.layout,
+            &self.pools,
+            &self.environment,
+            &self.shadows,
+            &self.lights,
+            self.environment_intensity,
+            shadow_direction.and(self.shadow_caster),
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_set(
+        render_device: &RenderDevice,
+        layout: &Arc<DescriptorSetLayout>,
+        pools: &LightingPools,
+        environment: &EnvironmentMap,
+        shadows: &ShadowMaps,
+        lights: &[Light],
+        environment_intensity: f32,
+        shadow_caster: Option<usize>,
+    ) -> Arc<PersistentDescriptorSet> {
+        let uniform = pools
+            .uniform
+            .from_data(LightingUniform {
+                light_count: lights.len() as u32,
+                environment_intensity,
+                _pad: [0.0; 2],
+            })
+            .unwrap();
+        // Storage buffers cannot be empty, so there is always at least one (unused) light.
+        let gpu_lights: Vec<GpuLight> = if lights.is_empty() {
+            vec![GpuLight::default()]
+        } else {
+            lights.iter().map(GpuLight::from).collect()
+        };
+        let lights = pools.lights.from_iter(gpu_lights).unwrap();
+        let shadow = pools.shadow.from_data(shadows.uniform(shadow_caster)).unwrap();
+        PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, uniform),
+                WriteDescriptorSet::buffer(1, lights),
+                WriteDescriptorSet::image_view_sampler(
+                    2,
+                    environment.view.clone(),
+                    environment.sampler.clone(),
+                ),
+                WriteDescriptorSet::buffer(3, shadow),
+                WriteDescriptorSet::image_view_sampler(
+                    4,
+                    shadows.array_view.clone(),
+                    shadows.sampler.clone(),
+                ),
+            ],
+        )
+        .unwrap()
+    }
+}
+
+fn fragment_binding(descriptor_type: DescriptorType) -> DescriptorSetLayoutBinding {
+    DescriptorSetLayoutBinding {
+        stages: ShaderStages {
+            fragment: true,
+            ..ShaderStages::empty()
+        },
+        ..DescriptorSetLayoutBinding::descriptor_type(descriptor_type)
+    }
+}
+
+fn lighting_bindings() -> [(u32, DescriptorSetLayoutBinding); 5] {
+    let binding = fragment_binding;
+    [
+        (0, binding(DescriptorType::UniformBuffer)),
+        (1, binding(DescriptorType::StorageBuffer)),
+        (2, binding(DescriptorType::CombinedImageSampler)),
+        (3, binding(DescriptorType::UniformBuffer)),
+        (4, binding(DescriptorType::CombinedImageSampler)),
+    ]
+}
+
+pub fn lighting_set_layout_create_info() -> DescriptorSetLayoutCreateInfo {
+    DescriptorSetLayoutCreateInfo {
+        bindings: lighting_bindings().into(),
+        ..Default::default()
+    }
+}
+
+/// Makes the lighting set of an automatically inferred pipeline layout identical to the one of
+/// [`Lighting`]. See [`apply_view_set_layout`].
+pub fn apply_lighting_set_layout(set_layouts: &mut [DescriptorSetLayoutCreateInfo]) {
+    if let Some(set_layout) = set_layouts.get_mut(LIGHTING_SET as usize) {
+        set_layout.bindings.extend(lighting_bindings());
+    }
+}
+
+/// Makes the material set of an automatically inferred pipeline layout identical to the one of
+/// [`PbrPipeline`], so that pipelines reading only part of a material can bind its
+/// [`GpuMaterial::descriptor_set`]. See [`apply_view_set_layout`].
+pub fn apply_material_set_layout(set_layouts: &mut [DescriptorSetLayoutCreateInfo]) {
+    if let Some(set_layout) = set_layouts.get_mut(MATERIAL_SET as usize) {
+        set_layout.bindings.insert(0, fragment_binding(DescriptorType::UniformBuffer));
+        for binding in 1..=5 {
+            set_layout
+                .bindings
+                .insert(binding, fragment_binding(DescriptorType::CombinedImageSampler));
+        }
+    }
+}
+
+/// Contents of a material's uniform buffer. Matches the std140 layout of `MaterialUniform` in
+/// `pbr.frag`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct MaterialUniform {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_cutoff: f32,
+    pub alpha_mask: u32,
+    pub _pad: [f32; 2],
+}
+
+impl From<&PbrMaterial> for MaterialUniform {
+    fn from(material: &PbrMaterial) -> Self {
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: Vec3::from(material.emissive_factor).extend(0.0).to_array(),
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            alpha_cutoff: material.alpha_cutoff,
+            alpha_mask: (material.alpha_mode == AlphaMode::Mask) as u32,
+            _pad: [0.0; 2],
+        }
+    }
+}
+
+/// A material ready to be bound at [`MATERIAL_SET`].
+pub struct GpuMaterial {
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+    pub descriptor_set: Arc<PersistentDescriptorSet>,
+}
+
+/// Materials of a [`Model`], indexed like [`Model::materials`].
+pub struct ModelMaterials {
+    pub materials: Vec<GpuMaterial>,
+    /// Used by meshes without a material.
+    pub default_material: GpuMaterial,
+}
+
+impl ModelMaterials {
+    pub fn get(&self, material: Option<usize>) -> &GpuMaterial {
+        material
+            .and_then(|index| self.materials.get(index))
+            .unwrap_or(&self.default_material)
+    }
+}
+
+/// Forward metallic-roughness shading of meshes with punctual lights and image-based ambient
+/// light.
+pub struct PbrPipeline {
+    /// Opaque and alpha-masked materials, back faces culled.
+    pub opaque: Arc<GraphicsPipeline>,
+    /// Opaque and alpha-masked double-sided materials.
+    pub opaque_double_sided: Arc<GraphicsPipeline>,
+    /// Alpha-blended materials. Tests depth without writing it.
+    pub blend: Arc<GraphicsPipeline>,
+    /// Bound in place of missing base color, metallic-roughness, occlusion and emissive
+    /// textures.
+    pub white_texture: Texture,
+    /// Bound in place of a missing normal map.
+    pub flat_normal_texture: Texture,
+    instance_buffer: InstanceBuffer<ModelInstance>,
+}
+
+impl PbrPipeline {
+    /// Creates pipelines rendering into a `color_format` attachment, with the depth attachment
+    /// and sample count of `render_output`. Fallback textures are uploaded through `builder`.
+    pub fn new(
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        color_format: Format,
+        depth_compare_op: CompareOp,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let vs = vs::load(render_device.device.clone()).unwrap();
+        let fs = fs::load(render_device.device.clone()).unwrap();
+        let create = |name: &str, cull_mode: CullMode, blend: bool| {
+            let mut color_blend_state = ColorBlendState::new(1);
+            if blend {
+                color_blend_state = color_blend_state.blend(AttachmentBlend::alpha());
+            }
+            let pipeline = GraphicsPipeline::start()
+                .render_pass(PipelineRenderingCreateInfo {
+                    color_attachment_formats: vec![Some(color_format)],
+                    depth_attachment_format: Some(render_output.depth_format),
+                    ..Default::default()
+                })
+                .vertex_input_state(
+                    BuffersDefinition::new()
+                        .vertex::<MeshVertex>()
+                        .instance::<ModelInstance>(),
+                )
+                .input_assembly_state(InputAssemblyState::new())
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .fragment_shader(fs.entry_point("main").unwrap(), ())
+                .rasterization_state(RasterizationState::new().cull_mode(cull_mode))
+                .multisample_state(MultisampleState {
+                    rasterization_samples: render_output.samples,
+                    ..Default::default()
+                })
+                .depth_stencil_state(DepthStencilState {
+                    depth: Some(DepthState {
+                        enable_dynamic: false,
+                        compare_op: StateMode::Fixed(depth_compare_op),
+                        write_enable: StateMode::Fixed(!blend),
+                    }),
+                    ..Default::default()
+                })
+                .color_blend_state(color_blend_state)
+                .with_auto_layout(render_device.device.clone(), |set_layouts| {
+                    apply_view_set_layout(set_layouts);
+                    apply_lighting_set_layout(set_layouts);
+                })
+                .unwrap();
+            set_object_name(pipeline.as_ref(), name);
+            pipeline
+        };
+        let white = ImageData {
+            width: 1,
+            height: 1,
+            pixels: vec![255; 4],
+        };
+        let flat_normal = ImageData {
+            width: 1,
+            height: 1,
+            pixels: vec![128, 128, 255, 255],
+        };
+        Self {
+            opaque: create("pbr opaque", CullMode::Back, false),
+            opaque_double_sided: create("pbr opaque double sided", CullMode::None, false),
+            blend: create("pbr blend", CullMode::None, true),
+            white_texture: Texture::new(
+                render_device,
+                &white,
+                &SamplerDesc::default(),
+                false,
+                builder,
+            ),
+            flat_normal_texture: Texture::new(
+                render_device,
+                &flat_normal,
+                &SamplerDesc::default(),
+                false,
+                builder,
+            ),
+            instance_buffer: InstanceBuffer::new(render_device),
+        }
+    }
+
+    /// Creates the uniform buffer and descriptor set of `material`. Texture slots index into
+    /// `textures`.
+    pub fn create_material(
+        &self,
+        render_device: &RenderDevice,
+        material: &PbrMaterial,
+        textures: &[Texture],
+    ) -> GpuMaterial {
+        let uniform = CpuAccessibleBuffer::from_data(
+            &render_device.memory_allocator,
+            BufferUsage {
+                uniform_buffer: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            MaterialUniform::from(material),
+        )
+        .unwrap();
+        render_device
+            .memory_tracker
+            .track_buffer(uniform.as_ref(), MemoryCategory::Uniform, "material uniform");
+        let texture = |binding: u32, slot: Option<TextureSlot>, fallback: &Texture| {
+            let texture = slot
+                .and_then(|slot| textures.get(slot.texture))
+                .unwrap_or(fallback);
+            WriteDescriptorSet::image_view_sampler(
+                binding,
+                texture.view.clone(),
+                texture.sampler.clone(),
+            )
+        };
+        let layout = self.opaque.layout().set_layouts()[MATERIAL_SET as usize].clone();
+        let descriptor_set = PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            layout,
+            [
+                WriteDescriptorSet::buffer(0, uniform),
+                texture(1, material.base_color_texture, &self.white_texture),
+                texture(2, material.metallic_roughness_texture, &self.white_texture),
+                texture(3, material.normal_texture, &self.flat_normal_texture),
+                texture(4, material.occlusion_texture, &self.white_texture),
+                texture(5, material.emissive_texture, &self.white_texture),
+            ],
+        )
+        .unwrap();
+        GpuMaterial {
+            alpha_mode: material.alpha_mode,
+            double_sided: material.double_sided,
+            descriptor_set,
+        }
+    }
+
+    pub fn create_model_materials(
+        &self,
+        render_device: &RenderDevice,
+        model: &Model,
+    ) -> ModelMaterials {
+        ModelMaterials {
+            materials: model
+                .materials
+                .iter()
+                .map(|material| self.create_material(render_device, material, &model.textures))
+                .collect(),
+            default_material: self.create_material(
+                render_device,
+                &PbrMaterial::default(),
+                &model.textures,
+            ),
+        }
+    }
+
+    /// Records the draws of `model`, once per `(mesh index, world transform)` instance, as
+    /// returned by `GltfScene::mesh_instances`. Must be called inside dynamic rendering with a
+    /// viewport set. See [`Self::draw_list`].
+    pub fn draw_model(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        view_uniforms: &ViewUniforms,
+        lighting: &Lighting,
+        model: &Model,
+        materials: &ModelMaterials,
+        instances: &[(usize, Mat4)],
+    ) {
+        let mut draw_list = DrawList::new();
+        draw_list.push_model(model, materials, instances);
+        self.draw_list(builder, view_uniforms, lighting, &draw_list);
+    }
+
+    /// Records the draws of `draw_list`, one instanced draw per batch of the same mesh and
+    /// material. Must be called inside dynamic rendering with a viewport set. Blended
+    /// primitives are drawn last, sorted back to front.
+    pub fn draw_list(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        view_uniforms: &ViewUniforms,
+        lighting: &Lighting,
+        draw_list: &DrawList,
+    ) {
+        if draw_list.is_empty() {
+            return;
+        }
+        let camera_position = Vec3::from_slice(&view_uniforms.uniform.position);
+        let draws = draw_list.build(camera_position);
+        let instances = self.instance_buffer.upload(draws.instances);
+
+        begin_label(builder, "pbr");
+        for batch in &draws.batches {
+            let pipeline = match (batch.material.alpha_mode, batch.material.double_sided) {
+                (AlphaMode::Blend, _) => &self.blend,
+                (_, true) => &self.opaque_double_sided,
+                (_, false) => &self.opaque,
+            };
+            builder
+                .bind_pipeline_graphics(pipeline.clone())
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    pipeline.layout().clone(),
+                    VIEW_SET,
+                    vec![
+                        view_uniforms.descriptor_set.clone(),
+                        lighting.descriptor_set.clone(),
+                        batch.material.descriptor_set.clone(),
+                    ],
+                );
+            draw_batch(builder, batch, instances.clone());
+        }
+        end_label(builder);
+    }
+}
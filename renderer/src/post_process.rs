@@ -0,0 +1,757 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo,
+    RenderingAttachmentResolveInfo, RenderingInfo,
+};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::{Format, NumericType};
+use vulkano::image::view::ImageView;
+use vulkano::image::{
+    AttachmentImage, ImageAccess, ImageDimensions, ImageUsage, ImmutableImage, MipmapsCount,
+    SampleCount,
+};
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::render_pass::{LoadOp, StoreOp};
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use crate::debug_utils::{begin_label, end_label, label, set_image_name, set_object_name};
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+use crate::texture::ImageData;
+
+/// Format of the offscreen target scenes are rendered into.
+pub const HDR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+/// Format of the tonemapped image anti-aliased by FXAA.
+const LDR_FORMAT: Format = Format::R8G8B8A8_SRGB;
+/// Upper bound of the number of bloom mips, the first being half the output resolution.
+pub const MAX_BLOOM_MIPS: u32 = 6;
+
+mod fullscreen_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/post/fullscreen.vert",
+    }
+}
+
+mod bloom_downsample_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/post/bloom_downsample.frag",
+    }
+}
+
+mod bloom_upsample_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/post/bloom_upsample.frag",
+    }
+}
+
+mod composite_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/post/composite.frag",
+    }
+}
+
+mod fxaa_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/post/fxaa.frag",
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Stephen Hill's fit of the ACES reference rendering and output transforms.
+    Aces,
+    /// Troy Sobotka's AgX, which desaturates highlights more gracefully than ACES.
+    Agx,
+}
+
+/// Parameters of the post-processing chain. Every stage can be toggled between frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostProcessSettings {
+    pub exposure_enabled: bool,
+    /// Exposure compensation, in stops.
+    pub exposure: f32,
+    /// When disabled, HDR colors are clamped to the displayable range.
+    pub tonemapping_enabled: bool,
+    pub tonemapper: Tonemapper,
+    pub bloom_enabled: bool,
+    /// Brightness above which pixels bloom.
+    pub bloom_threshold: f32,
+    /// Width of the soft transition around the threshold.
+    pub bloom_knee: f32,
+    pub bloom_intensity: f32,
+    /// Spread of the upsampling filter, in source texels.
+    pub bloom_radius: f32,
+    /// Apply [`PostProcess::lut`] after tonemapping.
+    pub color_grading_enabled: bool,
+    pub fxaa_enabled: bool,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            exposure_enabled: true,
+            exposure: 0.0,
+            tonemapping_enabled: true,
+            tonemapper: Tonemapper::Aces,
+            bloom_enabled: true,
+            bloom_threshold: 1.0,
+            bloom_knee: 0.5,
+            bloom_intensity: 0.05,
+            bloom_radius: 1.0,
+            color_grading_enabled: false,
+            fxaa_enabled: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LutError {
+    Image(image::ImageError),
+    /// The strip is not `size * size` by `size` texels with `size` at least 2, or its pixels do
+    /// not fill it.
+    Dimensions { width: u32, height: u32 },
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LutError::Image(e) => write!(f, "failed to load LUT: {}", e),
+            LutError::Dimensions { width, height } => write!(
+                f,
+                "LUT strips must be size^2 x size texels with size at least 2, not {} x {}",
+                width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LutError {}
+
+impl From<image::ImageError> for LutError {
+    fn from(e: image::ImageError) -> Self {
+        LutError::Image(e)
+    }
+}
+
+/// A 3D color lookup table, mapping sRGB-encoded colors to graded sRGB-encoded colors.
+pub struct ColorGradingLut {
+    pub view: Arc<ImageView<ImmutableImage>>,
+    /// Number of entries along each axis.
+    pub size: u32,
+}
+
+impl ColorGradingLut {
+    /// A LUT that leaves colors unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is below 2.
+    pub fn identity(
+        render_device: &RenderDevice,
+        size: u32,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        assert!(size >= 2, "Color grading LUTs need at least 2 entries per axis");
+        let scale = |i: u32| (i * 255 / (size - 1)) as u8;
+        let mut texels = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    texels.extend([scale(r), scale(g), scale(b), 255]);
+                }
+            }
+        }
+        Self::new(render_device, size, texels, builder)
+    }
+
+    /// Loads a LUT stored as a horizontal strip of `size` slices of `size * size` texels, blue
+    /// increasing from slice to slice, the usual layout exported by grading tools.
+    pub fn from_strip(
+        render_device: &RenderDevice,
+        image: &ImageData,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Self, LutError> {
+        let (size, texels) = strip_texels(image)?;
+        Ok(Self::new(render_device, size, texels, builder))
+    }
+
+    /// Loads a strip LUT (see [`Self::from_strip`]) from an image file.
+    pub fn open(
+        render_device: &RenderDevice,
+        path: impl AsRef<Path>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Self, LutError> {
+        let image = image::open(path)?.into_rgba8();
+        let image = ImageData {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+        };
+        Self::from_strip(render_device, &image, builder)
+    }
+
+    fn new(
+        render_device: &RenderDevice,
+        size: u32,
+        texels: Vec<u8>,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let image = ImmutableImage::from_iter(
+            &render_device.memory_allocator,
+            texels,
+            ImageDimensions::Dim3d {
+                width: size,
+                height: size,
+                depth: size,
+            },
+            MipmapsCount::One,
+            Format::R8G8B8A8_UNORM,
+            builder,
+        )
+        .unwrap();
+        Self {
+            view: ImageView::new_default(image).unwrap(),
+            size,
+        }
+    }
+}
+
+/// The size of the strip LUT `image`, and its texels reordered into a `size`^3 volume.
+fn strip_texels(image: &ImageData) -> Result<(u32, Vec<u8>), LutError> {
+    let size = image.height;
+    let valid = size >= 2
+        && size.checked_mul(size) == Some(image.width)
+        && image.pixels.len() == (image.width * size) as usize * 4;
+    if !valid {
+        return Err(LutError::Dimensions {
+            width: image.width,
+            height: image.height,
+        });
+    }
+    let mut texels = Vec::with_capacity(image.pixels.len());
+    for b in 0..size {
+        for g in 0..size {
+            let row = (g * image.width + b * size) as usize * 4;
+            texels.extend_from_slice(&image.pixels[row..row + size as usize * 4]);
+        }
+    }
+    Ok((size, texels))
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct BloomDownsamplePushConstants {
+    source_texel_size: [f32; 2],
+    threshold: f32,
+    knee: f32,
+    prefilter: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct BloomUpsamplePushConstants {
+    source_texel_size: [f32; 2],
+    radius: f32,
+}
+
+const TONEMAPPER_NONE: u32 = 0;
+const TONEMAPPER_ACES: u32 = 1;
+const TONEMAPPER_AGX: u32 = 2;
+
+const COMPOSITE_BLOOM: u32 = 1;
+const COMPOSITE_COLOR_GRADING: u32 = 2;
+const COMPOSITE_ENCODE_SRGB: u32 = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct CompositePushConstants {
+    exposure: f32,
+    bloom_intensity: f32,
+    tonemapper: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct FxaaPushConstants {
+    texel_size: [f32; 2],
+    encode_srgb: u32,
+}
+
+/// HDR offscreen target and the chain of full-screen passes that brings it to the swapchain:
+/// bloom, exposure, tonemapping, color grading and FXAA.
+///
+/// Scenes render into [`Self::hdr_attachment`] together with the depth attachment of the
+/// [`RenderOutput`], then [`Self::render`] writes the final image.
+pub struct PostProcess {
+    pub settings: PostProcessSettings,
+    /// Used when color grading is enabled. Replace it to change the grade.
+    pub lut: ColorGradingLut,
+    /// Resolved HDR scene color, sampled by the post-processing passes.
+    pub hdr_view: Arc<ImageView<AttachmentImage>>,
+    /// Multisampled HDR attachment resolved into [`Self::hdr_view`]. `None` without MSAA.
+    pub hdr_msaa_view: Option<Arc<ImageView<AttachmentImage>>>,
+    /// Bloom mip chain, each half the size of the previous one.
+    pub bloom_views: Vec<Arc<ImageView<AttachmentImage>>>,
+    /// Tonemapped image FXAA reads from.
+    pub ldr_view: Arc<ImageView<AttachmentImage>>,
+    sampler: Arc<Sampler>,
+    bloom_downsample: Arc<GraphicsPipeline>,
+    bloom_upsample: Arc<GraphicsPipeline>,
+    composite_to_output: Arc<GraphicsPipeline>,
+    composite_to_ldr: Arc<GraphicsPipeline>,
+    fxaa: Arc<GraphicsPipeline>,
+    /// Whether the swapchain format needs the shaders to encode sRGB themselves.
+    encode_srgb: bool,
+}
+
+impl PostProcess {
+    /// Creates the targets matching `render_output`, and records the upload of the identity
+    /// LUT into `builder`.
+    pub fn new(
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        settings: PostProcessSettings,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let device = render_device.device.clone();
+        let vs = fullscreen_vs::load(device.clone()).unwrap();
+        let bloom_downsample_fs = bloom_downsample_fs::load(device.clone()).unwrap();
+        let bloom_upsample_fs = bloom_upsample_fs::load(device.clone()).unwrap();
+        let composite_fs = composite_fs::load(device.clone()).unwrap();
+        let fxaa_fs = fxaa_fs::load(device.clone()).unwrap();
+        let output_format = render_output.swapchain.image_format();
+        let create = |name: &str, fs: &Arc<vulkano::shader::ShaderModule>, format: Format, blend| {
+            let mut color_blend_state = ColorBlendState::new(1);
+            if let Some(blend) = blend {
+                color_blend_state = color_blend_state.blend(blend);
+            }
+            let pipeline = GraphicsPipeline::start()
+                .render_pass(PipelineRenderingCreateInfo {
+                    color_attachment_formats: vec![Some(format)],
+                    ..Default::default()
+                })
+                .vertex_input_state(BuffersDefinition::new())
+                .input_assembly_state(InputAssemblyState::new())
+                .vertex_shader(vs.entry_point("main").unwrap(), ())
+                .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+                .fragment_shader(fs.entry_point("main").unwrap(), ())
+                .color_blend_state(color_blend_state)
+                .build(device.clone())
+                .unwrap();
+            set_object_name(pipeline.as_ref(), name);
+            pipeline
+        };
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let targets = Targets::new(render_device, render_output);
+        Self {
+            settings,
+            lut: ColorGradingLut::identity(render_device, 16, builder),
+            hdr_view: targets.hdr_view,
+            hdr_msaa_view: targets.hdr_msaa_view,
+            bloom_views: targets.bloom_views,
+            ldr_view: targets.ldr_view,
+            sampler,
+            bloom_downsample: create("bloom downsample", &bloom_downsample_fs, HDR_FORMAT, None),
+            bloom_upsample: create(
+                "bloom upsample",
+                &bloom_upsample_fs,
+                HDR_FORMAT,
+                Some(AttachmentBlend::additive()),
+            ),
+            composite_to_output: create("composite to output", &composite_fs, output_format, None),
+            composite_to_ldr: create("composite to ldr", &composite_fs, LDR_FORMAT, None),
+            fxaa: create("fxaa", &fxaa_fs, output_format, None),
+            encode_srgb: output_format.type_color() != Some(NumericType::SRGB),
+        }
+    }
+
+    /// Recreates the targets after [`RenderOutput::recreate`].
+    pub fn resize(&mut self, render_device: &RenderDevice, render_output: &RenderOutput) {
+        let targets = Targets::new(render_device, render_output);
+        self.hdr_view = targets.hdr_view;
+        self.hdr_msaa_view = targets.hdr_msaa_view;
+        self.bloom_views = targets.bloom_views;
+        self.ldr_view = targets.ldr_view;
+    }
+
+    /// HDR color attachment for rendering the scene, cleared to `clear_color`. Pipelines must
+    /// use [`HDR_FORMAT`] and the sample count of the render output.
+    pub fn hdr_attachment(&self, clear_color: [f32; 4]) -> RenderingAttachmentInfo {
+        match &self.hdr_msaa_view {
+            Some(hdr_msaa_view) => RenderingAttachmentInfo {
+                load_op: LoadOp::Clear,
+                store_op: StoreOp::DontCare,
+                clear_value: Some(clear_color.into()),
+                resolve_info: Some(RenderingAttachmentResolveInfo::image_view(
+                    self.hdr_view.clone(),
+                )),
+                ..RenderingAttachmentInfo::image_view(hdr_msaa_view.clone())
+            },
+            None => RenderingAttachmentInfo {
+                load_op: LoadOp::Clear,
+                store_op: StoreOp::Store,
+                clear_value: Some(clear_color.into()),
+                ..RenderingAttachmentInfo::image_view(self.hdr_view.clone())
+            },
+        }
+    }
+
+    /// Single-sampled attachment drawing over the resolved HDR scene, for passes recorded after
+    /// the scene rendering ended, like soft particles sampling the scene depth.
+    pub fn hdr_overlay_attachment(&self) -> RenderingAttachmentInfo {
+        RenderingAttachmentInfo {
+            load_op: LoadOp::Load,
+            store_op: StoreOp::Store,
+            ..RenderingAttachmentInfo::image_view(self.hdr_view.clone())
+        }
+    }
+
+    /// Records the enabled stages, reading the HDR target and writing swapchain image
+    /// `image_index`. Must be recorded after, and outside of, the scene rendering.
+    pub fn render(
+        &self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        render_output: &RenderOutput,
+        image_index: u32,
+    ) {
+        let settings = &self.settings;
+        if settings.bloom_enabled {
+            label(builder, "bloom", |builder| self.render_bloom(render_device, builder));
+        }
+
+        let mut flags = 0;
+        if settings.bloom_enabled {
+            flags |= COMPOSITE_BLOOM;
+        }
+        if settings.color_grading_enabled {
+            flags |= COMPOSITE_COLOR_GRADING;
+        }
+        let (pipeline, target) = if settings.fxaa_enabled {
+            let target = RenderingAttachmentInfo {
+                load_op: LoadOp::DontCare,
+                store_op: StoreOp::Store,
+                ..RenderingAttachmentInfo::image_view(self.ldr_view.clone())
+            };
+            (&self.composite_to_ldr, target)
+        } else {
+            if self.encode_srgb {
+                flags |= COMPOSITE_ENCODE_SRGB;
+            }
+            (
+                &self.composite_to_output,
+                render_output.present_attachment(image_index),
+            )
+        };
+        let composite = CompositePushConstants {
+            exposure: if settings.exposure_enabled {
+                settings.exposure.exp2()
+            } else {
+                1.0
+            },
+            bloom_intensity: settings.bloom_intensity,
+            tonemapper: match (settings.tonemapping_enabled, settings.tonemapper) {
+                (false, _) => TONEMAPPER_NONE,
+                (true, Tonemapper::Aces) => TONEMAPPER_ACES,
+                (true, Tonemapper::Agx) => TONEMAPPER_AGX,
+            },
+            flags,
+        };
+        begin_label(builder, "composite");
+        self.fullscreen_pass(
+            render_device,
+            builder,
+            pipeline,
+            target,
+            [
+                WriteDescriptorSet::image_view_sampler(
+                    0,
+                    self.hdr_view.clone(),
+                    self.sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    1,
+                    self.bloom_views[0].clone(),
+                    self.sampler.clone(),
+                ),
+                WriteDescriptorSet::image_view_sampler(
+                    2,
+                    self.lut.view.clone(),
+                    self.sampler.clone(),
+                ),
+            ],
+            composite,
+        );
+        end_label(builder);
+
+        if settings.fxaa_enabled {
+            let fxaa = FxaaPushConstants {
+                texel_size: texel_size(&self.ldr_view),
+                encode_srgb: self.encode_srgb as u32,
+            };
+            begin_label(builder, "fxaa");
+            self.fullscreen_pass(
+                render_device,
+                builder,
+                &self.fxaa,
+                render_output.present_attachment(image_index),
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    self.ldr_view.clone(),
+                    self.sampler.clone(),
+                )],
+                fxaa,
+            );
+            end_label(builder);
+        }
+    }
+
+    /// Downsamples the bright parts of the HDR image through the bloom chain, then upsamples
+    /// back, accumulating every level into the first one.
+    fn render_bloom(
+        &self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        let settings = &self.settings;
+        let mut source = self.hdr_view.clone();
+        for (i, target) in self.bloom_views.iter().enumerate() {
+            let push_constants = BloomDownsamplePushConstants {
+                source_texel_size: texel_size(&source),
+                threshold: settings.bloom_threshold,
+                knee: settings.bloom_knee,
+                prefilter: (i == 0) as u32,
+            };
+            self.fullscreen_pass(
+                render_device,
+                builder,
+                &self.bloom_downsample,
+                RenderingAttachmentInfo {
+                    load_op: LoadOp::DontCare,
+                    store_op: StoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(target.clone())
+                },
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    source,
+                    self.sampler.clone(),
+                )],
+                push_constants,
+            );
+            source = target.clone();
+        }
+        for pair in self.bloom_views.windows(2).rev() {
+            let (target, source) = (&pair[0], &pair[1]);
+            let push_constants = BloomUpsamplePushConstants {
+                source_texel_size: texel_size(source),
+                radius: settings.bloom_radius,
+            };
+            self.fullscreen_pass(
+                render_device,
+                builder,
+                &self.bloom_upsample,
+                RenderingAttachmentInfo {
+                    load_op: LoadOp::Load,
+                    store_op: StoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(target.clone())
+                },
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    source.clone(),
+                    self.sampler.clone(),
+                )],
+                push_constants,
+            );
+        }
+    }
+
+    /// Draws a full-screen triangle with `pipeline` into `target`.
+    #[allow(clippy::too_many_arguments)]
+    fn fullscreen_pass<Pc: Pod + Send + Sync>(
+        &self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        pipeline: &Arc<GraphicsPipeline>,
+        target: RenderingAttachmentInfo,
+        descriptor_writes: impl IntoIterator<Item = WriteDescriptorSet>,
+        push_constants: Pc,
+    ) {
+        let [width, height] = target.image_view.image().dimensions().width_height();
+        let descriptor_set = PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            pipeline.layout().set_layouts()[0].clone(),
+            descriptor_writes,
+        )
+        .unwrap();
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(target)],
+                ..Default::default()
+            })
+            .unwrap()
+            .set_viewport(
+                0,
+                [Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                }],
+            )
+            .bind_pipeline_graphics(pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .push_constants(pipeline.layout().clone(), 0, push_constants)
+            .draw(3, 1, 0, 0)
+            .unwrap()
+            .end_rendering()
+            .unwrap();
+    }
+}
+
+/// The images of a [`PostProcess`] that depend on the output extent.
+struct Targets {
+    hdr_view: Arc<ImageView<AttachmentImage>>,
+    hdr_msaa_view: Option<Arc<ImageView<AttachmentImage>>>,
+    bloom_views: Vec<Arc<ImageView<AttachmentImage>>>,
+    ldr_view: Arc<ImageView<AttachmentImage>>,
+}
+
+impl Targets {
+    fn new(render_device: &RenderDevice, render_output: &RenderOutput) -> Self {
+        let allocator = &render_device.memory_allocator;
+        let extent = render_output.swapchain.image_extent();
+        let usage = ImageUsage {
+            color_attachment: true,
+            sampled: true,
+            ..ImageUsage::empty()
+        };
+        let hdr_image = AttachmentImage::with_usage(allocator, extent, HDR_FORMAT, usage).unwrap();
+        set_image_name(hdr_image.as_ref(), "hdr scene color");
+        render_device
+            .memory_tracker
+            .track_image(hdr_image.as_ref(), MemoryCategory::RenderTarget, "hdr scene color");
+        let hdr_msaa_view = (render_output.samples != SampleCount::Sample1).then(|| {
+            let image = AttachmentImage::transient_multisampled(
+                allocator,
+                extent,
+                render_output.samples,
+                HDR_FORMAT,
+            )
+            .unwrap();
+            set_image_name(image.as_ref(), "hdr msaa scene color");
+            render_device
+                .memory_tracker
+                .track_image(image.as_ref(), MemoryCategory::RenderTarget, "hdr msaa scene color");
+            ImageView::new_default(image).unwrap()
+        });
+        let bloom_mips = (extent[0].min(extent[1]).max(2).ilog2()).clamp(1, MAX_BLOOM_MIPS);
+        let bloom_views = (1..=bloom_mips)
+            .map(|mip| {
+                let extent = extent.map(|size| (size >> mip).max(1));
+                let image = AttachmentImage::with_usage(allocator, extent, HDR_FORMAT, usage).unwrap();
+                let name = format!("bloom mip {}", mip);
+                set_image_name(image.as_ref(), &name);
+                render_device
+                    .memory_tracker
+                    .track_image(image.as_ref(), MemoryCategory::RenderTarget, &name);
+                ImageView::new_default(image).unwrap()
+            })
+            .collect();
+        let ldr_image = AttachmentImage::with_usage(allocator, extent, LDR_FORMAT, usage).unwrap();
+        set_image_name(ldr_image.as_ref(), "ldr color");
+        render_device
+            .memory_tracker
+            .track_image(ldr_image.as_ref(), MemoryCategory::RenderTarget, "ldr color");
+        Self {
+            hdr_view: ImageView::new_default(hdr_image).unwrap(),
+            hdr_msaa_view,
+            bloom_views,
+            ldr_view: ImageView::new_default(ldr_image).unwrap(),
+        }
+    }
+}
+
+fn texel_size(view: &ImageView<AttachmentImage>) -> [f32; 2] {
+    let [width, height] = view.image().dimensions().width_height();
+    [1.0 / width as f32, 1.0 / height as f32]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_slices_become_blue_layers() {
+        // Texel (r, g) of slice b holds [r, g, b, 255].
+        let size = 2;
+        let mut pixels = Vec::new();
+        for g in 0..size {
+            for b in 0..size {
+                for r in 0..size {
+                    pixels.extend([r, g, b, 255]);
+                }
+            }
+        }
+        let image = ImageData {
+            width: 4,
+            height: 2,
+            pixels,
+        };
+        let (size, texels) = strip_texels(&image).unwrap();
+        assert_eq!(size, 2);
+        let expected: Vec<u8> = (0..8u8).flat_map(|i| [i & 1, (i >> 1) & 1, i >> 2, 255]).collect();
+        assert_eq!(texels, expected);
+    }
+
+    #[test]
+    fn strips_of_other_dimensions_are_rejected() {
+        for (width, height) in [(1, 1), (8, 2), (4, 3), (0, 0)] {
+            let image = ImageData {
+                width,
+                height,
+                pixels: vec![0; (width * height * 4) as usize],
+            };
+            assert!(matches!(
+                strip_texels(&image),
+                Err(LutError::Dimensions { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn strips_missing_pixels_are_rejected() {
+        let image = ImageData {
+            width: 4,
+            height: 2,
+            pixels: vec![0; 4 * 4],
+        };
+        assert!(strip_texels(&image).is_err());
+    }
+}
@@ -0,0 +1,558 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType};
+use vulkano::sync::PipelineStage;
+use crate::destruction_queue::FrameFence;
+use crate::json::json_string;
+use crate::render_device::RenderDevice;
+
+/// Frames recorded before the queries of a frame are reused. Results are read back once the
+/// GPU is this many frames behind, so reading them never waits.
+pub const PROFILER_FRAME_LATENCY: usize = 4;
+/// Profiled frames kept for [`GpuProfiler::chrome_trace`].
+pub const PROFILER_HISTORY: usize = 600;
+
+/// Timing of one scope of a [`ProfiledFrame`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScopeTiming {
+    pub name: String,
+    /// Nesting depth, zero for top level scopes.
+    pub depth: usize,
+    /// Index of the enclosing scope in [`ProfiledFrame::scopes`].
+    pub parent: Option<usize>,
+    /// Start on the GPU clock, in milliseconds. Only differences are meaningful.
+    pub start_ms: f64,
+    pub duration_ms: f64,
+}
+
+/// GPU timings of the scopes recorded in one frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfiledFrame {
+    /// Index of the frame, counted by [`GpuProfiler::begin_frame`].
+    pub frame: u64,
+    /// Scopes in the order they began, so every scope comes before its children.
+    pub scopes: Vec<ScopeTiming>,
+}
+
+impl ProfiledFrame {
+    /// Time from the start of the first to the end of the last top level scope.
+    pub fn total_ms(&self) -> f64 {
+        let start = self.scopes.iter().map(|scope| scope.start_ms).fold(f64::MAX, f64::min);
+        let end = self
+            .scopes
+            .iter()
+            .map(|scope| scope.start_ms + scope.duration_ms)
+            .fold(f64::MIN, f64::max);
+        (end - start).max(0.0)
+    }
+
+    /// Indices of the direct children of scope `parent`, or of the top level scopes.
+    pub fn children(&self, parent: Option<usize>) -> impl Iterator<Item = usize> + '_ {
+        (0..self.scopes.len()).filter(move |&i| self.scopes[i].parent == parent)
+    }
+
+    /// First scope called `name`.
+    pub fn find(&self, name: &str) -> Option<&ScopeTiming> {
+        self.scopes.iter().find(|scope| scope.name == name)
+    }
+}
+
+/// Prints the scopes as an indented tree.
+impl fmt::Display for ProfiledFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "GPU frame {}: {:.3} ms", self.frame, self.total_ms())?;
+        for scope in &self.scopes {
+            writeln!(
+                f,
+                "{:indent$}{} {:.3} ms",
+                "",
+                scope.name,
+                scope.duration_ms,
+                indent = 2 * (scope.depth + 1)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct PendingScope {
+    name: String,
+    parent: Option<usize>,
+    /// First of the begin and end queries. `None` if the pool was full.
+    query: Option<u32>,
+}
+
+/// Queries of one frame in flight.
+struct FrameQueries {
+    pool: Arc<QueryPool>,
+    frame: u64,
+    scopes: Vec<PendingScope>,
+    /// Whether the frame was recorded and its results are still to be read.
+    pending: bool,
+    /// Signaled once the submission of the frame, and so its reset and timestamps, ran.
+    fence: Option<Arc<dyn FrameFence>>,
+}
+
+/// Measures how long render passes take on the GPU, with pairs of timestamp queries around
+/// scopes of a command buffer.
+///
+/// Results arrive once the fence of the frame given to [`Self::submitted`] signaled, in
+/// [`Self::latest`]. Devices whose queue cannot write timestamps, and profilers without room
+/// for any scope, record nothing.
+pub struct GpuProfiler {
+    /// Scopes are skipped while not set.
+    pub enabled: bool,
+    /// The last frame whose results were read back.
+    pub latest: Option<ProfiledFrame>,
+    /// Read back frames, oldest first, up to [`PROFILER_HISTORY`].
+    pub history: Vec<ProfiledFrame>,
+    /// Whether the queue family of the render device supports timestamps.
+    pub supported: bool,
+    /// Frames whose results were not read back before their queries were reused, because the
+    /// GPU was more than [`PROFILER_FRAME_LATENCY`] frames behind.
+    pub dropped_frames: u64,
+    /// Scopes that were not measured because the frame had more than `max_scopes`.
+    pub dropped_scopes: u64,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f64,
+    /// Bits of the timestamps that are meaningful.
+    timestamp_mask: u64,
+    max_scopes: u32,
+    frames: Vec<FrameQueries>,
+    frame: u64,
+    /// Index in the current frame's scopes of the open scopes.
+    stack: Vec<usize>,
+    next_query: u32,
+    recording: bool,
+}
+
+impl GpuProfiler {
+    /// Creates a profiler for the present queue of `render_device`, with room for
+    /// `max_scopes` scopes per frame. Scopes over the limit are not measured.
+    pub fn new(render_device: &RenderDevice, max_scopes: u32) -> Self {
+        let device = &render_device.device;
+        let physical_device = device.physical_device();
+        let queue_family_index = render_device.present_queue.queue_family_index();
+        let valid_bits = physical_device.queue_family_properties()[queue_family_index as usize]
+            .timestamp_valid_bits;
+        let supported = valid_bits.is_some();
+        if !supported {
+            println!("The queue family does not support timestamps, GPU profiling is disabled");
+        }
+        let timestamp_mask = match valid_bits {
+            Some(bits) if bits < 64 => (1 << bits) - 1,
+            _ => u64::MAX,
+        };
+        // A pool without queries cannot be created.
+        let frames = if supported && max_scopes > 0 {
+            (0..PROFILER_FRAME_LATENCY)
+                .map(|_| FrameQueries {
+                    pool: QueryPool::new(
+                        device.clone(),
+                        QueryPoolCreateInfo {
+                            query_count: max_scopes * 2,
+                            ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                        },
+                    )
+                    .unwrap(),
+                    frame: 0,
+                    scopes: Vec::new(),
+                    pending: false,
+                    fence: None,
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            enabled: supported,
+            latest: None,
+            history: Vec::new(),
+            supported,
+            dropped_frames: 0,
+            dropped_scopes: 0,
+            timestamp_period: physical_device.properties().timestamp_period as f64,
+            timestamp_mask,
+            max_scopes,
+            frames,
+            frame: 0,
+            stack: Vec::new(),
+            next_query: 0,
+            recording: false,
+        }
+    }
+
+    /// Reads back finished frames and starts recording a new one. Must be recorded at the
+    /// start of the command buffer, outside of rendering.
+    pub fn begin_frame(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        self.poll();
+        self.frame += 1;
+        self.stack.clear();
+        self.next_query = 0;
+        self.recording = self.enabled && !self.frames.is_empty();
+        if !self.recording {
+            return;
+        }
+        let slot = self.frame as usize % self.frames.len();
+        let frame = &mut self.frames[slot];
+        // Frames that were never submitted have no results to lose.
+        if frame.pending && frame.fence.is_some() {
+            self.dropped_frames += 1;
+        }
+        frame.frame = self.frame;
+        frame.scopes.clear();
+        frame.pending = false;
+        frame.fence = None;
+        // Resetting is a command, so it happens after the GPU wrote the previous results.
+        unsafe {
+            builder
+                .reset_query_pool(frame.pool.clone(), 0..self.max_scopes * 2)
+                .unwrap();
+        }
+    }
+
+    /// Marks the frame as recorded. Its results are read back by a later
+    /// [`Self::begin_frame`], once it was [`Self::submitted`].
+    pub fn end_frame(&mut self) {
+        assert!(self.stack.is_empty(), "GPU profiler scopes left open at the end of the frame");
+        if self.recording {
+            let slot = self.frame as usize % self.frames.len();
+            self.frames[slot].pending = true;
+        }
+        self.recording = false;
+    }
+
+    /// Gives the fence of the submission of the last recorded frame. Frames are only read back
+    /// after their fence signaled, as results of the previous use of their queries are still
+    /// available until the GPU ran the reset.
+    ///
+    /// Wrap the `FenceSignalFuture` of the frame in an `Arc` to give it here and still chain
+    /// it into the next frame.
+    pub fn submitted(&mut self, fence: Arc<dyn FrameFence>) {
+        if self.frames.is_empty() {
+            return;
+        }
+        let slot = self.frame as usize % self.frames.len();
+        let frame = &mut self.frames[slot];
+        if frame.pending && frame.frame == self.frame {
+            frame.fence = Some(fence);
+        }
+    }
+
+    /// Starts a scope, nested in the currently open one.
+    pub fn begin_scope(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        name: impl Into<String>,
+    ) {
+        if !self.recording {
+            return;
+        }
+        let slot = self.frame as usize % self.frames.len();
+        let frame = &mut self.frames[slot];
+        let query = (self.next_query < self.max_scopes * 2).then_some(self.next_query);
+        if query.is_none() {
+            self.dropped_scopes += 1;
+        }
+        if let Some(query) = query {
+            self.next_query += 2;
+            unsafe {
+                builder
+                    .write_timestamp(frame.pool.clone(), query, PipelineStage::TopOfPipe)
+                    .unwrap();
+            }
+        }
+        self.stack.push(frame.scopes.len());
+        frame.scopes.push(PendingScope {
+            name: name.into(),
+            parent: self.stack.iter().rev().nth(1).copied(),
+            query,
+        });
+    }
+
+    /// Ends the innermost open scope.
+    pub fn end_scope(&mut self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) {
+        if !self.recording {
+            return;
+        }
+        let frame = &self.frames[self.frame as usize % self.frames.len()];
+        let scope = self.stack.pop().expect("No GPU profiler scope to end");
+        if let Some(query) = frame.scopes[scope].query {
+            unsafe {
+                builder
+                    .write_timestamp(frame.pool.clone(), query + 1, PipelineStage::BottomOfPipe)
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Wraps the commands recorded by `record` in a scope.
+    pub fn scope<R>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        name: impl Into<String>,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> R,
+    ) -> R {
+        self.begin_scope(builder, name);
+        let result = record(builder);
+        self.end_scope(builder);
+        result
+    }
+
+    /// Reads the results of every recorded frame the GPU has finished, without waiting.
+    pub fn poll(&mut self) {
+        let (timestamp_period, timestamp_mask) = (self.timestamp_period, self.timestamp_mask);
+        let mut finished = Vec::new();
+        for frame in &mut self.frames {
+            match &frame.fence {
+                Some(fence) if frame.pending && fence.is_complete() => {}
+                _ => continue,
+            }
+            frame.pending = false;
+            let query_count = frame.scopes.iter().filter(|scope| scope.query.is_some()).count();
+            // Each query is followed by its availability.
+            let mut results = vec![0u64; query_count * 2 * 2];
+            let flags = QueryResultFlags {
+                with_availability: true,
+                ..QueryResultFlags::empty()
+            };
+            let available = frame
+                .pool
+                .queries_range(0..query_count as u32 * 2)
+                .map_or(Ok(true), |queries| queries.get_results(&mut results, flags));
+            // Results of a finished submission are all available, unless the device was lost,
+            // which fails the read or leaves results unavailable.
+            if !matches!(available, Ok(true)) || results.chunks(2).any(|result| result[1] == 0) {
+                self.dropped_frames += 1;
+                continue;
+            }
+            finished.push(resolve(
+                frame.frame,
+                &frame.scopes,
+                &results,
+                timestamp_period,
+                timestamp_mask,
+            ));
+        }
+        finished.sort_by_key(|frame| frame.frame);
+        for frame in finished {
+            self.latest = Some(frame.clone());
+            self.history.push(frame);
+        }
+        let excess = self.history.len().saturating_sub(PROFILER_HISTORY);
+        self.history.drain(..excess);
+    }
+
+    /// The frames in [`Self::history`] in the Trace Event Format read by `chrome://tracing`
+    /// and Perfetto.
+    pub fn chrome_trace(&self) -> String {
+        chrome_trace(&self.history)
+    }
+
+    /// Writes [`Self::chrome_trace`] to `path`.
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.chrome_trace())
+    }
+}
+
+/// `history` in the Trace Event Format, see [`GpuProfiler::chrome_trace`].
+fn chrome_trace(history: &[ProfiledFrame]) -> String {
+    let origin = history
+        .iter()
+        .flat_map(|frame| frame.scopes.iter().map(|scope| scope.start_ms))
+        .fold(f64::MAX, f64::min);
+    let events: Vec<String> = history
+        .iter()
+        .flat_map(|frame| {
+            frame.scopes.iter().map(move |scope| {
+                format!(
+                    "{{\"name\":{},\"cat\":\"gpu\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\
+                     \"pid\":0,\"tid\":0,\"args\":{{\"frame\":{}}}}}",
+                    json_string(&scope.name),
+                    (scope.start_ms - origin) * 1000.0,
+                    scope.duration_ms * 1000.0,
+                    frame.frame
+                )
+            })
+        })
+        .collect();
+    format!(
+        "{{\"traceEvents\":[\n{}\n],\"displayTimeUnit\":\"ms\"}}\n",
+        events.join(",\n")
+    )
+}
+
+/// Timings of the `scopes` of `frame` from their query `results`, each followed by its
+/// availability.
+fn resolve(
+    frame: u64,
+    pending_scopes: &[PendingScope],
+    results: &[u64],
+    timestamp_period: f64,
+    timestamp_mask: u64,
+) -> ProfiledFrame {
+    let to_ms = |ticks: u64| ticks as f64 * timestamp_period / 1.0e6;
+    // Drop scopes that got no queries, and point children at their remaining ancestors.
+    let mut new_index = vec![None; pending_scopes.len()];
+    let mut scopes: Vec<ScopeTiming> = Vec::new();
+    for (i, scope) in pending_scopes.iter().enumerate() {
+        let query = match scope.query {
+            Some(query) => query,
+            None => continue,
+        };
+        let begin = results[query as usize * 2] & timestamp_mask;
+        let end = results[(query as usize + 1) * 2] & timestamp_mask;
+        let mut parent = scope.parent;
+        while let Some(p) = parent {
+            if new_index[p].is_some() {
+                break;
+            }
+            parent = pending_scopes[p].parent;
+        }
+        let parent = parent.and_then(|p| new_index[p]);
+        new_index[i] = Some(scopes.len());
+        scopes.push(ScopeTiming {
+            name: scope.name.clone(),
+            depth: parent.map_or(0, |p| scopes[p].depth + 1),
+            parent,
+            start_ms: to_ms(begin),
+            // Timestamps wrap around after the valid bits.
+            duration_ms: to_ms(end.wrapping_sub(begin) & timestamp_mask),
+        });
+    }
+    ProfiledFrame { frame, scopes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(name: &str, parent: Option<usize>, query: Option<u32>) -> PendingScope {
+        PendingScope {
+            name: name.to_owned(),
+            parent,
+            query,
+        }
+    }
+
+    /// Query results with every timestamp available.
+    fn available(timestamps: &[u64]) -> Vec<u64> {
+        timestamps.iter().flat_map(|&timestamp| [timestamp, 1]).collect()
+    }
+
+    fn timing(
+        name: &str,
+        depth: usize,
+        parent: Option<usize>,
+        start: f64,
+        duration: f64,
+    ) -> ScopeTiming {
+        ScopeTiming {
+            name: name.to_owned(),
+            depth,
+            parent,
+            start_ms: start,
+            duration_ms: duration,
+        }
+    }
+
+    #[test]
+    fn timestamps_are_masked_and_wrap_around() {
+        let scopes = [pending("frame", None, Some(0))];
+        // Bits above the 32 valid ones are garbage, and the counter wrapped inside the scope.
+        let results = available(&[0xab << 40 | 0xffff_fff0, 0xcd << 40 | 0x10]);
+        // One millisecond per tick.
+        let frame = resolve(7, &scopes, &results, 1.0e6, u32::MAX as u64);
+        assert_eq!(frame.frame, 7);
+        assert_eq!(frame.scopes[0].start_ms, 0xffff_fff0u32 as f64);
+        assert_eq!(frame.scopes[0].duration_ms, 32.0);
+    }
+
+    #[test]
+    fn full_mask_keeps_every_bit() {
+        let scopes = [pending("frame", None, Some(0))];
+        let results = available(&[1 << 40, (1 << 40) + 500]);
+        let frame = resolve(0, &scopes, &results, 2.0, u64::MAX);
+        assert_eq!(frame.scopes[0].duration_ms, 0.001);
+    }
+
+    #[test]
+    fn children_of_dropped_scopes_move_up() {
+        let scopes = [
+            pending("frame", None, Some(0)),
+            pending("dropped", Some(0), None),
+            pending("pass", Some(1), Some(2)),
+            pending("dropped root", None, None),
+            pending("orphan", Some(3), Some(4)),
+        ];
+        let results = available(&[0, 10, 2, 4, 12, 13]);
+        let frame = resolve(0, &scopes, &results, 1.0e6, u64::MAX);
+        assert_eq!(
+            frame.scopes,
+            [
+                timing("frame", 0, None, 0.0, 10.0),
+                timing("pass", 1, Some(0), 2.0, 2.0),
+                timing("orphan", 0, None, 12.0, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_add_up_their_top_level_scopes() {
+        let frame = ProfiledFrame {
+            frame: 3,
+            scopes: vec![
+                timing("shadows", 0, None, 1.0, 2.0),
+                timing("cascade", 1, Some(0), 1.5, 1.0),
+                timing("scene", 0, None, 3.5, 4.0),
+            ],
+        };
+        assert_eq!(frame.total_ms(), 6.5);
+        assert_eq!(frame.children(None).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(frame.children(Some(0)).collect::<Vec<_>>(), [1]);
+        assert_eq!(frame.find("cascade").unwrap().duration_ms, 1.0);
+        assert_eq!(
+            frame.to_string(),
+            "GPU frame 3: 6.500 ms\n  shadows 2.000 ms\n    cascade 1.000 ms\n  scene 4.000 ms\n"
+        );
+        assert_eq!(ProfiledFrame::default().total_ms(), 0.0);
+    }
+
+    #[test]
+    fn trace_events_start_at_the_first_scope() {
+        let history = [
+            ProfiledFrame {
+                frame: 1,
+                scopes: vec![timing("frame", 0, None, 2.0, 1.5)],
+            },
+            ProfiledFrame {
+                frame: 2,
+                scopes: vec![timing("shadow \"pass\"", 0, None, 10.0, 0.25)],
+            },
+        ];
+        assert_eq!(
+            chrome_trace(&history),
+            "{\"traceEvents\":[\n\
+             {\"name\":\"frame\",\"cat\":\"gpu\",\"ph\":\"X\",\"ts\":0.000,\"dur\":1500.000,\
+             \"pid\":0,\"tid\":0,\"args\":{\"frame\":1}},\n\
+             {\"name\":\"shadow \\\"pass\\\"\",\"cat\":\"gpu\",\"ph\":\"X\",\"ts\":8000.000,\
+             \"dur\":250.000,\"pid\":0,\"tid\":0,\"args\":{\"frame\":2}}\n\
+             ],\"displayTimeUnit\":\"ms\"}\n"
+        );
+    }
+
+    #[test]
+    fn empty_trace_has_no_events() {
+        assert_eq!(
+            chrome_trace(&[]),
+            "{\"traceEvents\":[\n\n],\"displayTimeUnit\":\"ms\"}\n"
+        );
+    }
+}
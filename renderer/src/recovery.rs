@@ -0,0 +1,464 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, CStr};
+use std::fmt;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, OnceLock};
+use ash::vk;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use vulkano::library::{DynamicLibraryLoader, Loader, LoadingError};
+use vulkano::swapchain::{AcquireError, Surface, SwapchainCreationError};
+use vulkano::sync::FlushError;
+use vulkano::VulkanLibrary;
+use crate::render_device::{DeviceSelectionError, RenderDevice};
+use crate::render_output::{RenderOutput, RenderOutputCreateInfo, RenderOutputError};
+use crate::render_system::RenderSystem;
+use crate::surface::SurfaceError;
+
+/// Errors that can report the loss of the device, after which nothing submitted to it runs
+/// anymore and every object created from it is useless.
+pub trait DeviceLostError {
+    fn is_device_lost(&self) -> bool;
+}
+
+impl DeviceLostError for FlushError {
+    fn is_device_lost(&self) -> bool {
+        matches!(self, FlushError::DeviceLost)
+    }
+}
+
+impl DeviceLostError for AcquireError {
+    fn is_device_lost(&self) -> bool {
+        matches!(self, AcquireError::DeviceLost)
+    }
+}
+
+impl DeviceLostError for SwapchainCreationError {
+    fn is_device_lost(&self) -> bool {
+        matches!(self, SwapchainCreationError::DeviceLost)
+    }
+}
+
+/// GPU state that can be rebuilt from its CPU-side description, like the vertices a vertex
+/// buffer was filled from.
+pub trait Recoverable {
+    /// Recreates every GPU object on `render_device`, replacing those of the lost device.
+    fn recreate(&mut self, render_device: &RenderDevice, render_output: &RenderOutput);
+}
+
+/// The functions of the Vulkan loader that [`LossTolerantLoader`] wraps.
+struct Waits {
+    get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
+    wait_for_fences: vk::PFN_vkWaitForFences,
+    get_fence_status: vk::PFN_vkGetFenceStatus,
+    queue_wait_idle: vk::PFN_vkQueueWaitIdle,
+    device_wait_idle: vk::PFN_vkDeviceWaitIdle,
+}
+
+static WAITS: OnceLock<Waits> = OnceLock::new();
+static LOSS_TOLERANT_LIBRARY: OnceLock<Arc<VulkanLibrary>> = OnceLock::new();
+
+/// The system's Vulkan library, with waits that report a lost device as done. Loaded once,
+/// every instance of it shares the same wrapped waits.
+///
+/// vulkano waits for the GPU when dropping futures and queues, and panics if that fails, which
+/// it does once the device is lost. Nothing of a lost device runs anymore, so its work is done
+/// as far as waiting goes, and everything can be dropped normally. The flip side is that every
+/// fence of a lost device reads as signaled, so buffers read back after a loss hold whatever
+/// the GPU never wrote. The loss itself shows up in the errors of submissions and swapchain
+/// calls, see [`RenderContext::report`].
+pub(crate) fn loss_tolerant_library() -> Result<Arc<VulkanLibrary>, LoadingError> {
+    if let Some(library) = LOSS_TOLERANT_LIBRARY.get() {
+        return Ok(library.clone());
+    }
+
+    #[cfg(windows)]
+    const PATH: &str = "vulkan-1.dll";
+    #[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
+    const PATH: &str = "libvulkan.so.1";
+    #[cfg(target_os = "macos")]
+    const PATH: &str = "libvulkan.1.dylib";
+    #[cfg(target_os = "android")]
+    const PATH: &str = "libvulkan.so";
+
+    let inner = unsafe { DynamicLibraryLoader::new(Path::new(PATH))? };
+    let library = VulkanLibrary::with_loader(LossTolerantLoader { inner })?;
+    // A concurrent first call may have won, keep whichever library was stored.
+    Ok(LOSS_TOLERANT_LIBRARY.get_or_init(|| library).clone())
+}
+
+struct LossTolerantLoader {
+    inner: DynamicLibraryLoader,
+}
+
+unsafe impl Loader for LossTolerantLoader {
+    unsafe fn get_instance_proc_addr(
+        &self,
+        instance: vk::Instance,
+        name: *const c_char,
+    ) -> vk::PFN_vkVoidFunction {
+        // vulkano loads device functions through `vkGetDeviceProcAddr`, which is loaded along
+        // with the instance functions.
+        if CStr::from_ptr(name).to_bytes() != b"vkGetDeviceProcAddr" {
+            return self.inner.get_instance_proc_addr(instance, name);
+        }
+        WAITS.get_or_init(|| {
+            let load = |name: &CStr| {
+                let function = self.inner.get_instance_proc_addr(instance, name.as_ptr());
+                function.expect("The Vulkan loader lacks a core function")
+            };
+            // These are the loader's trampolines, which dispatch on the device or queue handle
+            // they are given, so they serve every instance of the one library.
+            Waits {
+                get_device_proc_addr: std::mem::transmute::<
+                    unsafe extern "system" fn(),
+                    vk::PFN_vkGetDeviceProcAddr,
+                >(load(c"vkGetDeviceProcAddr")),
+                wait_for_fences: std::mem::transmute::<
+                    unsafe extern "system" fn(),
+                    vk::PFN_vkWaitForFences,
+                >(load(c"vkWaitForFences")),
+                get_fence_status: std::mem::transmute::<
+                    unsafe extern "system" fn(),
+                    vk::PFN_vkGetFenceStatus,
+                >(load(c"vkGetFenceStatus")),
+                queue_wait_idle: std::mem::transmute::<
+                    unsafe extern "system" fn(),
+                    vk::PFN_vkQueueWaitIdle,
+                >(load(c"vkQueueWaitIdle")),
+                device_wait_idle: std::mem::transmute::<
+                    unsafe extern "system" fn(),
+                    vk::PFN_vkDeviceWaitIdle,
+                >(load(c"vkDeviceWaitIdle")),
+            }
+        });
+        void_function(get_device_proc_addr as *const ())
+    }
+}
+
+/// Erases the signature of `function`, as `vkGet*ProcAddr` do.
+unsafe fn void_function(function: *const ()) -> vk::PFN_vkVoidFunction {
+    std::mem::transmute::<*const (), vk::PFN_vkVoidFunction>(function)
+}
+
+fn waits() -> &'static Waits {
+    WAITS.get().unwrap()
+}
+
+fn tolerate_loss(result: vk::Result) -> vk::Result {
+    if result == vk::Result::ERROR_DEVICE_LOST {
+        vk::Result::SUCCESS
+    } else {
+        result
+    }
+}
+
+unsafe extern "system" fn get_device_proc_addr(
+    device: vk::Device,
+    name: *const c_char,
+) -> vk::PFN_vkVoidFunction {
+    let wait = match CStr::from_ptr(name).to_bytes() {
+        b"vkWaitForFences" => wait_for_fences as *const (),
+        b"vkGetFenceStatus" => get_fence_status as *const (),
+        b"vkQueueWaitIdle" => queue_wait_idle as *const (),
+        b"vkDeviceWaitIdle" => device_wait_idle as *const (),
+        _ => return (waits().get_device_proc_addr)(device, name),
+    };
+    void_function(wait)
+}
+
+unsafe extern "system" fn wait_for_fences(
+    device: vk::Device,
+    fence_count: u32,
+    fences: *const vk::Fence,
+    wait_all: vk::Bool32,
+    timeout: u64,
+) -> vk::Result {
+    tolerate_loss((waits().wait_for_fences)(device, fence_count, fences, wait_all, timeout))
+}
+
+unsafe extern "system" fn get_fence_status(device: vk::Device, fence: vk::Fence) -> vk::Result {
+    tolerate_loss((waits().get_fence_status)(device, fence))
+}
+
+unsafe extern "system" fn queue_wait_idle(queue: vk::Queue) -> vk::Result {
+    tolerate_loss((waits().queue_wait_idle)(queue))
+}
+
+unsafe extern "system" fn device_wait_idle(device: vk::Device) -> vk::Result {
+    tolerate_loss((waits().device_wait_idle)(device))
+}
+
+#[derive(Debug)]
+pub enum RenderContextError {
+    Surface(SurfaceError),
+    Device(DeviceSelectionError),
+    Output(RenderOutputError),
+}
+
+impl fmt::Display for RenderContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderContextError::Surface(e) => write!(f, "{}", e),
+            RenderContextError::Device(e) => write!(f, "failed to create the device: {}", e),
+            RenderContextError::Output(RenderOutputError::Swapchain(
+                SwapchainCreationError::SurfaceInUse | SwapchainCreationError::NativeWindowInUse,
+            )) => write!(
+                f,
+                "the swapchain of the lost device is still alive, drop every future and image \
+                 of the old output before recovering"
+            ),
+            RenderContextError::Output(e) => write!(f, "failed to create the output: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderContextError {}
+
+type RecoveryCallback = Box<dyn FnMut(&RenderDevice, &RenderOutput)>;
+
+const NOT_RECOVERED: &str = "The device was lost and is not recovered";
+
+/// Resources to rebuild on every new device, and whether the current one was lost.
+struct Registry<T: ?Sized> {
+    resources: Vec<Weak<RefCell<T>>>,
+    lost: Cell<bool>,
+    generation: u64,
+}
+
+impl<T: ?Sized> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            resources: Vec::new(),
+            lost: Cell::new(false),
+            generation: 0,
+        }
+    }
+}
+
+impl<T: ?Sized> Registry<T> {
+    fn register(&mut self, resource: &Rc<RefCell<T>>) {
+        self.resources.push(Rc::downgrade(resource));
+    }
+
+    fn report(&self, error: &impl DeviceLostError) -> bool {
+        let lost = error.is_device_lost();
+        if lost {
+            self.lost.set(true);
+        }
+        lost
+    }
+
+    /// Rebuilds the resources still alive with `recreate`, forgets the others, and starts a
+    /// new generation.
+    fn recovered(&mut self, mut recreate: impl FnMut(&mut T)) {
+        self.resources.retain(|resource| match resource.upgrade() {
+            Some(resource) => {
+                recreate(&mut *resource.borrow_mut());
+                true
+            }
+            None => false,
+        });
+        self.generation += 1;
+        self.lost.set(false);
+    }
+}
+
+/// The surface, device and output of a window. When the device is lost, the device and output
+/// are replaced, and resources registered with [`Self::register`] are rebuilt on the new ones.
+///
+/// Losses are noticed through the errors given to [`Self::report`]. Check
+/// [`Self::is_device_lost`] outside of a frame, and [`Self::recover`] then. Dropping the
+/// futures of a lost device panics in vulkano unless the render system was created with
+/// [`RenderSystem::new_loss_tolerant`].
+pub struct RenderContext {
+    pub surface: Arc<Surface>,
+    render_device: Option<RenderDevice>,
+    render_output: Option<RenderOutput>,
+    output_create_info: RenderOutputCreateInfo,
+    registry: Registry<dyn Recoverable>,
+    callbacks: Vec<RecoveryCallback>,
+}
+
+impl RenderContext {
+    /// Renders to `window` of any toolkit, see [`crate::surface::create_surface`].
+    pub fn new<W>(
+        render_system: &RenderSystem,
+        window: Arc<W>,
+        image_extent: [u32; 2],
+        output_create_info: RenderOutputCreateInfo,
+    ) -> Result<Self, RenderContextError>
+    where
+        W: HasRawWindowHandle + HasRawDisplayHandle + Send + Sync + 'static,
+    {
+        let surface = crate::surface::create_surface(&render_system.instance, window)
+            .map_err(RenderContextError::Surface)?;
+        let render_device =
+            RenderDevice::try_new(render_system, &surface).map_err(RenderContextError::Device)?;
+        let render_output = RenderOutput::try_new(
+            &render_device,
+            &surface,
+            image_extent,
+            output_create_info.clone(),
+        )
+        .map_err(RenderContextError::Output)?;
+        Ok(Self {
+            surface,
+            render_device: Some(render_device),
+            render_output: Some(render_output),
+            output_create_info,
+            registry: Registry::default(),
+            callbacks: Vec::new(),
+        })
+    }
+
+    /// Number of times the device was replaced, to tell resources of an older device apart.
+    pub fn generation(&self) -> u64 {
+        self.registry.generation
+    }
+
+    /// # Panics
+    ///
+    /// If the device was lost and [`Self::recover`] failed.
+    pub fn render_device(&self) -> &RenderDevice {
+        self.render_device.as_ref().expect(NOT_RECOVERED)
+    }
+
+    /// # Panics
+    ///
+    /// If the device was lost and [`Self::recover`] failed.
+    pub fn render_output(&self) -> &RenderOutput {
+        self.render_output.as_ref().expect(NOT_RECOVERED)
+    }
+
+    /// The device and output, with the output mutable to recreate its swapchain.
+    ///
+    /// # Panics
+    ///
+    /// If the device was lost and [`Self::recover`] failed.
+    pub fn parts_mut(&mut self) -> (&RenderDevice, &mut RenderOutput) {
+        (
+            self.render_device.as_ref().expect(NOT_RECOVERED),
+            self.render_output.as_mut().expect(NOT_RECOVERED),
+        )
+    }
+
+    /// Rebuilds `resource` on every new device, for as long as it is alive elsewhere.
+    pub fn register<R: Recoverable + 'static>(&mut self, resource: &Rc<RefCell<R>>) {
+        let resource: Rc<RefCell<dyn Recoverable>> = resource.clone();
+        self.registry.register(&resource);
+    }
+
+    /// Calls `callback` with the new device and output after every recovery, once registered
+    /// resources were rebuilt, for state that is not a [`Recoverable`].
+    pub fn on_recovered(&mut self, callback: impl FnMut(&RenderDevice, &RenderOutput) + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Returns whether `error` reports the loss of the device, which is then remembered until
+    /// the next recovery.
+    pub fn report(&self, error: &impl DeviceLostError) -> bool {
+        self.registry.report(error)
+    }
+
+    /// Whether an error was reported as a loss since the device was created.
+    pub fn is_device_lost(&self) -> bool {
+        self.registry.lost.get()
+    }
+
+    /// Replaces the device and the output, then rebuilds `resources` and the registered ones on
+    /// them, and calls the [`Self::on_recovered`] callbacks.
+    ///
+    /// The swapchain of the lost device has to be destroyed before the surface gets a new one,
+    /// so every future, image and readback of the old output must be dropped beforehand, in
+    /// that order. If the device cannot be recreated, the context has none until a later call
+    /// succeeds.
+    pub fn recover(
+        &mut self,
+        render_system: &RenderSystem,
+        image_extent: [u32; 2],
+        resources: &mut [&mut dyn Recoverable],
+    ) -> Result<(), RenderContextError> {
+        println!("The device was lost, recreating the renderer");
+        self.render_output = None;
+        self.render_device = None;
+        let render_device = RenderDevice::try_new(render_system, &self.surface)
+            .map_err(RenderContextError::Device)?;
+        let render_output = RenderOutput::try_new(
+            &render_device,
+            &self.surface,
+            image_extent,
+            self.output_create_info.clone(),
+        )
+        .map_err(RenderContextError::Output)?;
+
+        for resource in resources {
+            resource.recreate(&render_device, &render_output);
+        }
+        self.registry
+            .recovered(|resource| resource.recreate(&render_device, &render_output));
+        for callback in &mut self.callbacks {
+            callback(&render_device, &render_output);
+        }
+        self.render_device = Some(render_device);
+        self.render_output = Some(render_output);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_device_loss_is_remembered() {
+        let registry = Registry::<u32>::default();
+        assert!(!registry.report(&FlushError::OutOfDate));
+        assert!(!registry.lost.get());
+        assert!(registry.report(&AcquireError::DeviceLost));
+        assert!(registry.lost.get());
+        // Later errors do not clear the loss.
+        assert!(!registry.report(&FlushError::OutOfDate));
+        assert!(registry.lost.get());
+    }
+
+    #[test]
+    fn recovery_rebuilds_live_resources_and_forgets_dropped_ones() {
+        let mut registry = Registry::<u32>::default();
+        let kept = Rc::new(RefCell::new(1));
+        let dropped = Rc::new(RefCell::new(2));
+        registry.register(&kept);
+        registry.register(&dropped);
+        drop(dropped);
+        registry.report(&SwapchainCreationError::DeviceLost);
+
+        let mut rebuilt = Vec::new();
+        registry.recovered(|resource| {
+            rebuilt.push(*resource);
+            *resource += 10;
+        });
+        assert_eq!(rebuilt, [1]);
+        assert_eq!(*kept.borrow(), 11);
+        assert_eq!(registry.resources.len(), 1);
+        assert_eq!(registry.generation, 1);
+        assert!(!registry.lost.get());
+
+        registry.recovered(|resource| *resource += 10);
+        assert_eq!(*kept.borrow(), 21);
+        assert_eq!(registry.generation, 2);
+    }
+
+    #[test]
+    fn resources_dropped_later_are_forgotten_on_the_next_recovery() {
+        let mut registry = Registry::<u32>::default();
+        let resource = Rc::new(RefCell::new(0));
+        registry.register(&resource);
+        registry.recovered(|_| {});
+        assert_eq!(registry.resources.len(), 1);
+        drop(resource);
+        let mut calls = 0;
+        registry.recovered(|_| calls += 1);
+        assert_eq!(calls, 0);
+        assert!(registry.resources.is_empty());
+    }
+}
@@ -1,40 +1,107 @@
+use std::fmt;
 use std::sync::Arc;
-use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo};
-use vulkano::device::physical::PhysicalDeviceType;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::device::{
+    Device, DeviceCreateInfo, DeviceCreationError, DeviceExtensions, DeviceOwned, Features, Queue,
+    QueueCreateInfo, QueueFamilyProperties,
+};
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
 use vulkano::memory::allocator::StandardMemoryAllocator;
 use vulkano::swapchain::Surface;
-use vulkano::Version;
+use vulkano::{Version, VulkanError};
+use crate::debug_utils::set_object_name;
+use crate::host::{adopt_device, AdoptionError, HostDevice};
+use crate::memory_tracker::MemoryTracker;
 use crate::render_system::RenderSystem;
 
 pub struct RenderDevice {
     pub device: Arc<Device>,
-    pub memory_allocator: StandardMemoryAllocator,
+    pub memory_allocator: Arc<StandardMemoryAllocator>,
+    pub descriptor_set_allocator: StandardDescriptorSetAllocator,
     /// One queue to present to swapchain. Supports graphics and compute.
     pub present_queue: Arc<Queue>,
+    /// Memory usage of the resources created by the renderer.
+    pub memory_tracker: MemoryTracker,
 }
 
 impl RenderDevice {
     pub fn new(system: &RenderSystem, surface: &Arc<Surface>) -> Self {
+        Self::try_new(system, surface).unwrap()
+    }
+
+    /// Like [`Self::new`], but returns an error instead of panicking, e.g. when recreating the
+    /// device after it was lost.
+    pub fn try_new(
+        system: &RenderSystem,
+        surface: &Arc<Surface>,
+    ) -> Result<Self, DeviceSelectionError> {
+        Self::try_for_surfaces(system, std::slice::from_ref(surface))
+    }
+
+    /// A device for several windows, whose `present_queue` can present to every one of
+    /// `surfaces`.
+    pub fn for_surfaces(system: &RenderSystem, surfaces: &[Arc<Surface>]) -> Self {
+        Self::try_for_surfaces(system, surfaces).unwrap()
+    }
+
+    fn try_for_surfaces(
+        system: &RenderSystem,
+        surfaces: &[Arc<Surface>],
+    ) -> Result<Self, DeviceSelectionError> {
         let device_extensions = DeviceExtensions {
             khr_swapchain: true,
             khr_dynamic_rendering: true,
             // ext_descriptor_indexing: true,
             ..DeviceExtensions::empty()
         };
+        Self::with_queue_family(system, device_extensions, |p, i, q| {
+            q.queue_flags.graphics
+                && q.queue_flags.compute
+                && surfaces
+                    .iter()
+                    .all(|surface| p.surface_support(i, surface).unwrap_or(false))
+        })
+    }
+
+    /// Whether `present_queue` can present to `surface`. Windows opened after the device was
+    /// created are not guaranteed to be supported.
+    pub fn supports_surface(&self, surface: &Surface) -> bool {
+        self.device
+            .physical_device()
+            .surface_support(self.present_queue.queue_family_index(), surface)
+            .unwrap_or(false)
+    }
+
+    /// A device that cannot present, for offscreen rendering and compute. `present_queue` is
+    /// then just the graphics and compute queue.
+    pub fn new_headless(system: &RenderSystem) -> Self {
+        let device_extensions = DeviceExtensions {
+            khr_dynamic_rendering: true,
+            ..DeviceExtensions::empty()
+        };
+        Self::with_queue_family(system, device_extensions, |_, _, q| {
+            q.queue_flags.graphics && q.queue_flags.compute
+        })
+        .unwrap()
+    }
+
+    /// Creates a device with one queue, from the first family accepted by `queue_filter`.
+    fn with_queue_family(
+        system: &RenderSystem,
+        device_extensions: DeviceExtensions,
+        queue_filter: impl Fn(&PhysicalDevice, u32, &QueueFamilyProperties) -> bool,
+    ) -> Result<Self, DeviceSelectionError> {
         let (physical_device, queue_family_index) = system
             .instance
             .enumerate_physical_devices()
-            .unwrap()
+            .map_err(DeviceSelectionError::Vulkan)?
             .filter(|p| p.api_version() >= Version::V1_2)
             .filter(|p| p.supported_extensions().contains(&device_extensions))
             .filter_map(|p| {
                 p.queue_family_properties()
                     .iter()
                     .enumerate()
-                    .position(|(i, q)| {
-                        q.queue_flags.graphics
-                            && p.surface_support(i as u32, surface).unwrap_or(false)
-                    })
+                    .position(|(i, q)| queue_filter(&p, i as u32, q))
                     .map(|i| (p, i as u32))
             })
             .min_by_key(|(p, _)| {
@@ -48,12 +115,25 @@ impl RenderDevice {
                     _ => 5,
                 }
             })
-            .expect("No suitable physical device found");
+            .ok_or(DeviceSelectionError::NoSuitableDevice)?;
         println!(
             "Using device: {} (type: {:?})",
             physical_device.properties().device_name,
             physical_device.properties().device_type,
         );
+        // Optional, for the budgets of `MemoryTracker`.
+        let device_extensions = DeviceExtensions {
+            ext_memory_budget: physical_device.supported_extensions().ext_memory_budget,
+            ..device_extensions
+        };
+        // Optional, for sharing frames with other processes, see `crate::frame_export`.
+        let device_extensions = DeviceExtensions {
+            khr_external_memory_fd: physical_device.supported_extensions().khr_external_memory_fd,
+            khr_external_semaphore_fd: physical_device
+                .supported_extensions()
+                .khr_external_semaphore_fd,
+            ..device_extensions
+        };
         let (device, mut queues) = Device::new(
             // Which physical device to connect to.
             physical_device,
@@ -74,14 +154,141 @@ impl RenderDevice {
                 ..Default::default()
             },
         )
-            .unwrap();
+            .map_err(DeviceSelectionError::Creation)?;
         let queue = queues.next().unwrap();
-        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+        set_object_name(queue.as_ref(), "present queue");
+        // The device was created to meet the requirements, so it cannot fail.
+        Ok(Self::from_device(device, queue, &[]).unwrap())
+    }
 
-        Self {
+    /// Wraps `device` created by the host application through vulkano, rendering and
+    /// presenting on its `queue`. The device has to be Vulkan 1.2 or later with
+    /// `dynamic_rendering` enabled, and `queue` has to support graphics and compute. When the
+    /// renderer presents to `surfaces`, `khr_swapchain` has to be enabled and `queue` able to
+    /// present to each of them.
+    ///
+    /// See [`Self::from_raw_device`] for a `VkDevice` not created through vulkano.
+    pub fn from_device(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        surfaces: &[Arc<Surface>],
+    ) -> Result<Self, UnsupportedDeviceError> {
+        if device.api_version() < Version::V1_2 {
+            return Err(UnsupportedDeviceError::ApiVersion(device.api_version()));
+        }
+        if !device.enabled_features().dynamic_rendering {
+            return Err(UnsupportedDeviceError::DynamicRendering);
+        }
+        if !Arc::ptr_eq(queue.device(), &device) {
+            return Err(UnsupportedDeviceError::ForeignQueue);
+        }
+        let physical_device = device.physical_device();
+        let queue_family_index = queue.queue_family_index();
+        let queue_flags =
+            &physical_device.queue_family_properties()[queue_family_index as usize].queue_flags;
+        if !(queue_flags.graphics && queue_flags.compute) {
+            return Err(UnsupportedDeviceError::QueueFlags);
+        }
+        if !surfaces.is_empty() && !device.enabled_extensions().khr_swapchain {
+            return Err(UnsupportedDeviceError::Swapchain);
+        }
+        if !surfaces.iter().all(|surface| {
+            physical_device
+                .surface_support(queue_family_index, surface)
+                .unwrap_or(false)
+        }) {
+            return Err(UnsupportedDeviceError::SurfaceSupport);
+        }
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone());
+        let memory_tracker = MemoryTracker::new(&device);
+
+        Ok(Self {
             device,
             memory_allocator,
+            descriptor_set_allocator,
             present_queue: queue,
+            memory_tracker,
+        })
+    }
+
+    /// Wraps the raw `VkDevice` of `host`, created by the host application from the instance
+    /// of [`RenderSystem::from_raw_instance`], with the requirements of [`Self::from_device`].
+    /// The device stays the host's: dropping the render device does not destroy it.
+    ///
+    /// # Safety
+    ///
+    /// `host` must describe the device as it was created, and the device must outlive the
+    /// render device and every resource created with it.
+    pub unsafe fn from_raw_device(
+        system: &RenderSystem,
+        host: &HostDevice,
+        surfaces: &[Arc<Surface>],
+    ) -> Result<Self, AdoptionError> {
+        let (device, queue) = adopt_device(&system.instance, host)?;
+        Self::from_device(device, queue, surfaces).map_err(AdoptionError::Unsupported)
+    }
+}
+
+/// Why [`RenderDevice::try_new`] could not create a device.
+#[derive(Debug)]
+pub enum DeviceSelectionError {
+    Vulkan(VulkanError),
+    /// No physical device has the required version, extensions and queue family.
+    NoSuitableDevice,
+    Creation(DeviceCreationError),
+}
+
+impl fmt::Display for DeviceSelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelectionError::Vulkan(e) => write!(f, "failed to list devices: {}", e),
+            DeviceSelectionError::NoSuitableDevice => write!(f, "no suitable physical device"),
+            DeviceSelectionError::Creation(e) => write!(f, "failed to create the device: {}", e),
         }
     }
 }
+
+impl std::error::Error for DeviceSelectionError {}
+
+/// Why [`RenderDevice::from_device`] cannot use a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsupportedDeviceError {
+    /// The device is older than Vulkan 1.2.
+    ApiVersion(Version),
+    /// `dynamic_rendering` is not enabled.
+    DynamicRendering,
+    /// The queue is of another device.
+    ForeignQueue,
+    /// The queue family lacks graphics or compute.
+    QueueFlags,
+    /// There are surfaces to present to, but `khr_swapchain` is not enabled.
+    Swapchain,
+    /// The queue family cannot present to one of the surfaces.
+    SurfaceSupport,
+}
+
+impl fmt::Display for UnsupportedDeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnsupportedDeviceError::ApiVersion(version) => {
+                write!(f, "the device is Vulkan {}, not 1.2 or later", version)
+            }
+            UnsupportedDeviceError::DynamicRendering => {
+                write!(f, "the device does not enable dynamic_rendering")
+            }
+            UnsupportedDeviceError::ForeignQueue => write!(f, "the queue is of another device"),
+            UnsupportedDeviceError::QueueFlags => {
+                write!(f, "the queue does not support graphics and compute")
+            }
+            UnsupportedDeviceError::Swapchain => {
+                write!(f, "the device does not enable khr_swapchain")
+            }
+            UnsupportedDeviceError::SurfaceSupport => {
+                write!(f, "the queue cannot present to the surface")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedDeviceError {}
@@ -1,46 +1,166 @@
-use crate::render_system::RenderSystem;
+use std::fmt;
 use std::sync::Arc;
-use vulkano::image::{ImageUsage, SwapchainImage};
-use vulkano::swapchain::{Surface, Swapchain, SwapchainCreateInfo};
-use winit::window::Window;
+use vulkano::command_buffer::{RenderingAttachmentInfo, RenderingAttachmentResolveInfo};
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceError};
+use vulkano::format::{ClearValue, Format};
+use vulkano::image::view::{ImageView, ImageViewCreateInfo};
+use vulkano::image::{
+    AttachmentImage, ImageAspects, ImageUsage, SampleCount, SampleCounts, SwapchainImage,
+};
+use vulkano::render_pass::{LoadOp, StoreOp};
+use vulkano::swapchain::{
+    PresentMode, Surface, Swapchain, SwapchainCreateInfo, SwapchainCreationError,
+};
+use crate::debug_utils::{set_image_name, set_object_name};
+use crate::memory_tracker::MemoryCategory;
 use crate::render_device::RenderDevice;
 
+/// Depth formats we are willing to render with, in order of preference.
+pub const DEPTH_FORMAT_CANDIDATES: [Format; 4] = [
+    Format::D32_SFLOAT,
+    Format::D32_SFLOAT_S8_UINT,
+    Format::D24_UNORM_S8_UINT,
+    Format::D16_UNORM,
+];
+
+/// Parameters of a [`RenderOutput`].
+#[derive(Clone, Debug)]
+pub struct RenderOutputCreateInfo {
+    /// Samples per pixel of the color and depth attachments. Anything above one renders into
+    /// transient multisampled attachments that are resolved into the swapchain image.
+    ///
+    /// Must be one of [`supported_sample_counts`]. [`choose_sample_count`] picks the highest
+    /// supported count up to a limit.
+    pub samples: SampleCount,
+    /// Keep the depth attachment after rendering so that later passes can sample it through
+    /// [`RenderOutput::sampled_depth_view`], for example for soft particles.
+    pub sampled_depth: bool,
+    /// How presented images reach the screen. Falls back to `Fifo`, which is always supported,
+    /// if the surface does not support it.
+    pub present_mode: PresentMode,
+}
+
+impl Default for RenderOutputCreateInfo {
+    fn default() -> Self {
+        Self {
+            samples: SampleCount::Sample1,
+            sampled_depth: false,
+            present_mode: PresentMode::Fifo,
+        }
+    }
+}
+
+/// Why [`RenderOutput::try_new`] could not create an output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RenderOutputError {
+    /// The present queue of the device cannot present to the surface.
+    UnsupportedSurface,
+    /// The surface cannot be queried for reasons other than a Vulkan error.
+    SurfaceQuery(PhysicalDeviceError),
+    /// None of [`DEPTH_FORMAT_CANDIDATES`] can be used as a depth attachment.
+    NoDepthFormat,
+    /// The device does not support the requested sample count.
+    UnsupportedSampleCount(SampleCount),
+    Swapchain(SwapchainCreationError),
+}
+
+impl fmt::Display for RenderOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderOutputError::UnsupportedSurface => {
+                write!(f, "the present queue of the device cannot present to the surface")
+            }
+            RenderOutputError::SurfaceQuery(e) => write!(f, "failed to query the surface: {}", e),
+            RenderOutputError::NoDepthFormat => write!(f, "no supported depth format"),
+            RenderOutputError::UnsupportedSampleCount(samples) => {
+                write!(f, "{:?} MSAA is not supported", samples)
+            }
+            RenderOutputError::Swapchain(e) => write!(f, "failed to create the swapchain: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RenderOutputError {}
+
+impl From<SwapchainCreationError> for RenderOutputError {
+    fn from(error: SwapchainCreationError) -> Self {
+        RenderOutputError::Swapchain(error)
+    }
+}
+
 pub struct RenderOutput {
     pub swapchain: Arc<Swapchain>,
     pub images: Vec<Arc<SwapchainImage>>,
+    pub image_views: Vec<Arc<ImageView<SwapchainImage>>>,
+    /// Samples per pixel of the attachments rendered to. Pipelines must use the same count.
+    pub samples: SampleCount,
+    /// Multisampled color attachment resolved into the swapchain image. `None` without MSAA.
+    pub msaa_color_view: Option<Arc<ImageView<AttachmentImage>>>,
+    /// Format of the depth(-stencil) attachment. Chosen once, kept across resizes.
+    pub depth_format: Format,
+    /// Depth attachment matching the swapchain extent. Recreated together with the swapchain.
+    pub depth_image: Arc<AttachmentImage>,
+    pub depth_view: Arc<ImageView<AttachmentImage>>,
+    /// Depth aspect of the depth attachment, for sampling in a shader. Multisampled like the
+    /// attachment itself. `None` unless created with `sampled_depth`.
+    pub sampled_depth_view: Option<Arc<ImageView<AttachmentImage>>>,
 }
 
 impl RenderOutput {
+    /// Creates the swapchain of `surface` with `image_extent`, the size of the window in physical
+    /// pixels, which only the toolkit that owns the window knows.
     pub fn new(
-        _render_system: &RenderSystem,
         render_device: &RenderDevice,
         surface: &Arc<Surface>,
+        image_extent: [u32; 2],
+        create_info: RenderOutputCreateInfo,
     ) -> Self {
+        Self::try_new(render_device, surface, image_extent, create_info).unwrap()
+    }
+
+    /// Like [`Self::new`], but returns an error instead of panicking when the output cannot be
+    /// created, e.g. because the device cannot present to the surface, the device was lost or
+    /// the surface still has a swapchain.
+    pub fn try_new(
+        render_device: &RenderDevice,
+        surface: &Arc<Surface>,
+        image_extent: [u32; 2],
+        create_info: RenderOutputCreateInfo,
+    ) -> Result<Self, RenderOutputError> {
+        if !render_device.supports_surface(surface) {
+            return Err(RenderOutputError::UnsupportedSurface);
+        }
+        let physical_device = render_device.device.physical_device();
+        let samples = create_info.samples;
+        if !supported_sample_counts(physical_device).contains_count(samples) {
+            return Err(RenderOutputError::UnsupportedSampleCount(samples));
+        }
         let (swapchain, images) = {
             let surface_capabilities = render_device
                 .device
                 .physical_device()
                 .surface_capabilities(surface, Default::default())
-                .unwrap();
+                .map_err(surface_query_error)?;
             let image_format = Some(
                 render_device
                     .device
                     .physical_device()
                     .surface_formats(surface, Default::default())
-                    .unwrap()[0]
+                    .map_err(surface_query_error)?[0]
                     .0,
             );
-            let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
             Swapchain::new(
                 render_device.device.clone(),
                 surface.clone(),
                 SwapchainCreateInfo {
                     min_image_count: surface_capabilities.min_image_count,
                     image_format,
-                    image_extent: window.inner_size().into(),
+                    image_extent,
 
                     image_usage: ImageUsage {
                         color_attachment: true,
+                        // For reading frames back, see `crate::capture`.
+                        transfer_src: surface_capabilities.supported_usage_flags.transfer_src,
                         ..ImageUsage::empty()
                     },
                     composite_alpha: surface_capabilities
@@ -48,11 +168,317 @@ impl RenderOutput {
                         .iter()
                         .next()
                         .unwrap(),
+                    present_mode: choose_present_mode(
+                        render_device.device.physical_device(),
+                        surface,
+                        create_info.present_mode,
+                    ),
                     ..Default::default()
                 },
-            )
-            .unwrap()
+            )?
+        };
+        set_object_name(swapchain.as_ref(), "swapchain");
+        let depth_format =
+            choose_depth_format(physical_device).ok_or(RenderOutputError::NoDepthFormat)?;
+        let extent = swapchain.image_extent();
+        let depth_image = create_depth_image(
+            render_device,
+            extent,
+            depth_format,
+            samples,
+            create_info.sampled_depth,
+        );
+        Ok(Self {
+            image_views: create_image_views(&images),
+            msaa_color_view: create_msaa_color_view(render_device, &swapchain, samples),
+            depth_view: ImageView::new_default(depth_image.clone()).unwrap(),
+            sampled_depth_view: create_info
+                .sampled_depth
+                .then(|| create_sampled_depth_view(&depth_image)),
+            depth_image,
+            swapchain,
+            images,
+            samples,
+            depth_format,
+        })
+    }
+
+    /// Recreates the swapchain and every attachment that depends on its extent.
+    pub fn recreate(
+        &mut self,
+        render_device: &RenderDevice,
+        image_extent: [u32; 2],
+    ) -> Result<(), SwapchainCreationError> {
+        let (swapchain, images) = self.swapchain.recreate(SwapchainCreateInfo {
+            image_extent,
+            ..self.swapchain.create_info()
+        })?;
+        set_object_name(swapchain.as_ref(), "swapchain");
+        self.image_views = create_image_views(&images);
+        self.msaa_color_view = create_msaa_color_view(render_device, &swapchain, self.samples);
+        self.depth_image = create_depth_image(
+            render_device,
+            swapchain.image_extent(),
+            self.depth_format,
+            self.samples,
+            self.sampled_depth_view.is_some(),
+        );
+        self.depth_view = ImageView::new_default(self.depth_image.clone()).unwrap();
+        if self.sampled_depth_view.is_some() {
+            self.sampled_depth_view = Some(create_sampled_depth_view(&self.depth_image));
+        }
+        self.swapchain = swapchain;
+        self.images = images;
+        Ok(())
+    }
+
+    /// Switches to `present_mode`, or `Fifo` if the surface does not support it, recreating the
+    /// swapchain.
+    pub fn set_present_mode(
+        &mut self,
+        render_device: &RenderDevice,
+        present_mode: PresentMode,
+    ) -> Result<(), SwapchainCreationError> {
+        let present_mode = choose_present_mode(
+            render_device.device.physical_device(),
+            self.swapchain.surface(),
+            present_mode,
+        );
+        let (swapchain, images) = self.swapchain.recreate(SwapchainCreateInfo {
+            present_mode,
+            ..self.swapchain.create_info()
+        })?;
+        set_object_name(swapchain.as_ref(), "swapchain");
+        self.image_views = create_image_views(&images);
+        self.swapchain = swapchain;
+        self.images = images;
+        Ok(())
+    }
+
+    /// Color attachment for rendering into swapchain image `image_index`, cleared to
+    /// `clear_color`. With MSAA, this renders into the multisampled attachment and resolves into
+    /// the swapchain image at the end of rendering.
+    pub fn color_attachment(
+        &self,
+        image_index: u32,
+        clear_color: [f32; 4],
+    ) -> RenderingAttachmentInfo {
+        let image_view = self.image_views[image_index as usize].clone();
+        match &self.msaa_color_view {
+            Some(msaa_color_view) => RenderingAttachmentInfo {
+                load_op: LoadOp::Clear,
+                store_op: StoreOp::DontCare,
+                clear_value: Some(clear_color.into()),
+                resolve_info: Some(RenderingAttachmentResolveInfo::image_view(image_view)),
+                ..RenderingAttachmentInfo::image_view(msaa_color_view.clone())
+            },
+            None => RenderingAttachmentInfo {
+                load_op: LoadOp::Clear,
+                store_op: StoreOp::Store,
+                clear_value: Some(clear_color.into()),
+                ..RenderingAttachmentInfo::image_view(image_view)
+            },
+        }
+    }
+
+    /// Single-sampled attachment writing straight into swapchain image `image_index`, for
+    /// passes that overwrite every pixel, like the final post-processing pass.
+    pub fn present_attachment(&self, image_index: u32) -> RenderingAttachmentInfo {
+        RenderingAttachmentInfo {
+            load_op: LoadOp::DontCare,
+            store_op: StoreOp::Store,
+            ..RenderingAttachmentInfo::image_view(self.image_views[image_index as usize].clone())
+        }
+    }
+
+    /// Single-sampled attachment drawing over the current content of swapchain image
+    /// `image_index`, for overlays rendered after everything else.
+    pub fn overlay_attachment(&self, image_index: u32) -> RenderingAttachmentInfo {
+        RenderingAttachmentInfo {
+            load_op: LoadOp::Load,
+            store_op: StoreOp::Store,
+            ..RenderingAttachmentInfo::image_view(self.image_views[image_index as usize].clone())
+        }
+    }
+
+    /// Whether the depth attachment also carries a stencil aspect.
+    pub fn has_stencil(&self) -> bool {
+        self.depth_format.aspects().stencil
+    }
+
+    /// Depth attachment cleared to `clear_depth` at the start of rendering. Its content is only
+    /// stored if it can be sampled afterwards.
+    pub fn depth_attachment(&self, clear_depth: f32) -> RenderingAttachmentInfo {
+        let clear_value = if self.has_stencil() {
+            ClearValue::DepthStencil((clear_depth, 0))
+        } else {
+            ClearValue::Depth(clear_depth)
+        };
+        RenderingAttachmentInfo {
+            load_op: LoadOp::Clear,
+            store_op: if self.sampled_depth_view.is_some() {
+                StoreOp::Store
+            } else {
+                StoreOp::DontCare
+            },
+            clear_value: Some(clear_value),
+            ..RenderingAttachmentInfo::image_view(self.depth_view.clone())
+        }
+    }
+}
+
+/// Sample counts usable for both color and depth attachments on `physical_device`.
+pub fn supported_sample_counts(physical_device: &PhysicalDevice) -> SampleCounts {
+    let properties = physical_device.properties();
+    properties.framebuffer_color_sample_counts & properties.framebuffer_depth_sample_counts
+}
+
+/// The highest supported sample count that does not exceed `requested`.
+pub fn choose_sample_count(physical_device: &PhysicalDevice, requested: SampleCount) -> SampleCount {
+    highest_sample_count(supported_sample_counts(physical_device), requested)
+}
+
+fn highest_sample_count(supported: SampleCounts, requested: SampleCount) -> SampleCount {
+    [
+        SampleCount::Sample64,
+        SampleCount::Sample32,
+        SampleCount::Sample16,
+        SampleCount::Sample8,
+        SampleCount::Sample4,
+        SampleCount::Sample2,
+    ]
+    .into_iter()
+    .find(|&samples| samples as u32 <= requested as u32 && supported.contains_count(samples))
+    .unwrap_or(SampleCount::Sample1)
+}
+
+/// `requested` if presenting to `surface` supports it, otherwise `Fifo`.
+pub fn choose_present_mode(
+    physical_device: &PhysicalDevice,
+    surface: &Surface,
+    requested: PresentMode,
+) -> PresentMode {
+    let supported = physical_device
+        .surface_present_modes(surface)
+        .map(|mut modes| modes.any(|mode| mode == requested))
+        .unwrap_or(false);
+    if supported {
+        requested
+    } else {
+        PresentMode::Fifo
+    }
+}
+
+/// Picks the first of [`DEPTH_FORMAT_CANDIDATES`] usable as an optimally tiled depth attachment.
+pub fn choose_depth_format(physical_device: &PhysicalDevice) -> Option<Format> {
+    DEPTH_FORMAT_CANDIDATES.into_iter().find(|&format| {
+        physical_device
+            .format_properties(format)
+            .map(|p| p.optimal_tiling_features.depth_stencil_attachment)
+            .unwrap_or(false)
+    })
+}
+
+fn create_image_views(images: &[Arc<SwapchainImage>]) -> Vec<Arc<ImageView<SwapchainImage>>> {
+    images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            set_image_name(image.as_ref(), &format!("swapchain image {}", i));
+            ImageView::new_default(image.clone()).unwrap()
+        })
+        .collect()
+}
+
+fn create_msaa_color_view(
+    render_device: &RenderDevice,
+    swapchain: &Swapchain,
+    samples: SampleCount,
+) -> Option<Arc<ImageView<AttachmentImage>>> {
+    if samples == SampleCount::Sample1 {
+        return None;
+    }
+    let image = AttachmentImage::transient_multisampled(
+        &render_device.memory_allocator,
+        swapchain.image_extent(),
+        samples,
+        swapchain.image_format(),
+    )
+    .unwrap();
+    set_image_name(image.as_ref(), "msaa color attachment");
+    render_device
+        .memory_tracker
+        .track_image(image.as_ref(), MemoryCategory::RenderTarget, "msaa color attachment");
+    Some(ImageView::new_default(image).unwrap())
+}
+
+fn create_depth_image(
+    render_device: &RenderDevice,
+    extent: [u32; 2],
+    format: Format,
+    samples: SampleCount,
+    sampled: bool,
+) -> Arc<AttachmentImage> {
+    let allocator = &render_device.memory_allocator;
+    let image = if sampled {
+        let usage = ImageUsage {
+            depth_stencil_attachment: true,
+            sampled: true,
+            ..ImageUsage::empty()
+        };
+        AttachmentImage::multisampled_with_usage(allocator, extent, samples, format, usage)
+    } else {
+        AttachmentImage::transient_multisampled(allocator, extent, samples, format)
+    }
+    .unwrap();
+    set_image_name(image.as_ref(), "depth attachment");
+    render_device
+        .memory_tracker
+        .track_image(image.as_ref(), MemoryCategory::RenderTarget, "depth attachment");
+    image
+}
+
+/// A view of only the depth aspect, as shaders cannot sample depth and stencil together.
+fn create_sampled_depth_view(
+    depth_image: &Arc<AttachmentImage>,
+) -> Arc<ImageView<AttachmentImage>> {
+    let mut create_info = ImageViewCreateInfo::from_image(depth_image);
+    create_info.subresource_range.aspects = ImageAspects {
+        depth: true,
+        ..ImageAspects::empty()
+    };
+    ImageView::new(depth_image.clone(), create_info).unwrap()
+}
+
+/// Keeps Vulkan errors as swapchain errors, so that a lost device is reported as such.
+fn surface_query_error(error: PhysicalDeviceError) -> RenderOutputError {
+    match error {
+        PhysicalDeviceError::VulkanError(e) => RenderOutputError::Swapchain(e.into()),
+        e => RenderOutputError::SurfaceQuery(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_count_falls_back_to_the_highest_supported_count_below() {
+        let supported = SampleCounts {
+            sample1: true,
+            sample2: true,
+            sample8: true,
+            ..SampleCounts::empty()
         };
-        Self { swapchain, images }
+        let highest = |requested| highest_sample_count(supported, requested);
+        assert_eq!(highest(SampleCount::Sample1), SampleCount::Sample1);
+        assert_eq!(highest(SampleCount::Sample2), SampleCount::Sample2);
+        assert_eq!(highest(SampleCount::Sample4), SampleCount::Sample2);
+        assert_eq!(highest(SampleCount::Sample8), SampleCount::Sample8);
+        assert_eq!(highest(SampleCount::Sample64), SampleCount::Sample8);
+        assert_eq!(
+            highest_sample_count(SampleCounts::empty(), SampleCount::Sample4),
+            SampleCount::Sample1
+        );
     }
 }
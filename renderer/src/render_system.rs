@@ -1,12 +1,9 @@
 use std::sync::Arc;
-use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
-use vulkano::device::{
-    Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo,
-};
+use vulkano::device::physical::PhysicalDevice;
 use vulkano::instance::{Instance, InstanceCreateInfo, InstanceExtensions};
-use vulkano::memory::allocator::StandardMemoryAllocator;
-use vulkano::swapchain::Surface;
 use vulkano::*;
+use crate::host::{adopt_instance, AdoptionError, HostInstance};
+use crate::recovery::loss_tolerant_library;
 use vulkano::instance::debug::{DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger, DebugUtilsMessengerCreateInfo};
 
 /// A basic, single-device render system
@@ -19,12 +16,67 @@ pub struct RenderSystem {
 impl RenderSystem {
     pub fn new() -> Self {
         let library = VulkanLibrary::new().unwrap();
+        let window_extensions = crate::surface::required_extensions(&library);
+        Self::with_extensions(library, window_extensions)
+    }
+
+    /// A render system whose waits for the GPU succeed once the device is lost, so that
+    /// futures and queues of a lost device can be dropped before
+    /// [`crate::recovery::RenderContext::recover`]. Fences of a lost device then read as
+    /// signaled, so anything read back after a loss must be thrown away.
+    pub fn new_loss_tolerant() -> Self {
+        let library = loss_tolerant_library().unwrap();
+        let window_extensions = crate::surface::required_extensions(&library);
+        Self::with_extensions(library, window_extensions)
+    }
+
+    /// A render system without window system extensions, for offscreen rendering and compute,
+    /// e.g. on a software implementation in CI.
+    pub fn new_headless() -> Self {
+        let library = VulkanLibrary::new().unwrap();
+        Self::with_extensions(library, InstanceExtensions::empty())
+    }
+
+    /// Wraps `instance` created by the host application through vulkano, so the renderer runs
+    /// inside it. Windows need the extensions of [`crate::surface::required_extensions`] to be
+    /// enabled. Messages go wherever the host sends them, so there is no debug callback.
+    ///
+    /// See [`Self::from_raw_instance`] for a `VkInstance` not created through vulkano.
+    pub fn from_instance(instance: Arc<Instance>) -> Self {
+        let physical_devices = instance.enumerate_physical_devices().unwrap().collect();
+        Self {
+            instance,
+            physical_devices,
+            debug_callback: None,
+        }
+    }
+
+    /// Wraps the raw `VkInstance` of `host`, created by the host application, e.g. an OpenXR
+    /// runtime or an editor, as [`Self::from_instance`] does. The instance stays the host's:
+    /// dropping the render system does not destroy it.
+    ///
+    /// # Safety
+    ///
+    /// `host` must describe the instance as it was created, and the instance must outlive the
+    /// render system and every object created from it.
+    pub unsafe fn from_raw_instance(host: HostInstance) -> Result<Self, AdoptionError> {
+        Ok(Self::from_instance(adopt_instance(host)?))
+    }
+
+    fn with_extensions(library: Arc<VulkanLibrary>, extensions: InstanceExtensions) -> Self {
+        // Debug utils give validation messages and object names, see `crate::debug_utils`.
         let required_extensions = InstanceExtensions {
-            ext_debug_utils: true,
-            ..vulkano_win::required_extensions(&library)
+            ext_debug_utils: library.supported_extensions().ext_debug_utils,
+            ..extensions
         };
         //  TODO: more choices
-        let layers = vec!["VK_LAYER_KHRONOS_validation".to_owned()];
+        // Validation is enabled when installed, which it often is not on build machines.
+        let layers: Vec<String> = library
+            .layer_properties()
+            .unwrap()
+            .filter(|layer| layer.name() == "VK_LAYER_KHRONOS_validation")
+            .map(|layer| layer.name().to_owned())
+            .collect();
         let instance = Instance::new(
             library,
             InstanceCreateInfo {
@@ -98,3 +150,8 @@ impl RenderSystem {
     }
 }
 
+impl Default for RenderSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
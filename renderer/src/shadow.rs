@@ -0,0 +1,635 @@
+use std::fmt;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+};
+use vulkano::format::Format;
+use vulkano::image::view::{ImageView, ImageViewCreateInfo, ImageViewType};
+use vulkano::image::{ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
+use vulkano::pipeline::graphics::depth_stencil::{CompareOp, DepthState, DepthStencilState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::rasterization::{CullMode, RasterizationState};
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode};
+use vulkano::render_pass::{LoadOp, StoreOp};
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::shader::ShaderModule;
+use crate::camera::{Camera, Projection};
+use crate::gltf_import::Model;
+use crate::instancing::{draw_batch, DrawList, InstanceBuffer, ModelInstance};
+use crate::material::AlphaMode;
+use crate::mesh::MeshVertex;
+use crate::pbr::{apply_material_set_layout, ModelMaterials, MATERIAL_SET};
+use crate::debug_utils::{begin_label, end_label, set_image_name, set_object_name};
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+
+/// Upper bound of [`ShadowSettings::cascade_count`], fixed by the size of the cascade arrays in
+/// the shaders.
+pub const MAX_CASCADES: usize = 4;
+
+const SHADOW_FORMAT_CANDIDATES: [Format; 2] = [Format::D32_SFLOAT, Format::D16_UNORM];
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/shadow/shadow.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/shadow/shadow.frag",
+    }
+}
+
+mod masked_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/shadow/shadow_masked.vert",
+    }
+}
+
+mod masked_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/shadow/shadow_masked.frag",
+    }
+}
+
+/// Far splits of logarithmic cascades start at least this far from the camera, as a near
+/// plane at or behind the camera has no logarithmic split.
+const MIN_LOGARITHMIC_NEAR: f32 = 1.0e-3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShadowError {
+    /// [`ShadowSettings::cascade_count`] is not between 1 and [`MAX_CASCADES`].
+    CascadeCount(u32),
+    /// The device supports none of the depth formats shadow maps can be rendered to and
+    /// sampled from.
+    NoDepthFormat,
+}
+
+impl fmt::Display for ShadowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShadowError::CascadeCount(count) => write!(
+                f,
+                "{} shadow cascades requested, between 1 and {} are supported",
+                count, MAX_CASCADES
+            ),
+            ShadowError::NoDepthFormat => write!(f, "no depth format usable for shadow maps"),
+        }
+    }
+}
+
+impl std::error::Error for ShadowError {}
+
+/// Preset shadow configurations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowQuality {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowSettings {
+    /// Number of cascades, between 1 and [`MAX_CASCADES`].
+    pub cascade_count: u32,
+    /// Width and height of each cascade, in texels.
+    pub resolution: u32,
+    /// View distance beyond which nothing is shadowed.
+    pub max_distance: f32,
+    /// Blend between uniform (0) and logarithmic (1) cascade splits.
+    pub split_lambda: f32,
+    /// Half size of the PCF kernel in texels. 0 samples a single (bilinearly compared) tap.
+    pub pcf_radius: u32,
+    /// Subtracted from the receiver's depth in shadow map space.
+    pub depth_bias: f32,
+    /// Offset of the receiver along its normal, in shadow map texels.
+    pub normal_bias: f32,
+    /// How far behind a cascade's bounds casters are still rendered, in world units.
+    pub caster_distance: f32,
+}
+
+impl From<ShadowQuality> for ShadowSettings {
+    fn from(quality: ShadowQuality) -> Self {
+        let (cascade_count, resolution, pcf_radius) = match quality {
+            ShadowQuality::Low => (2, 1024, 0),
+            ShadowQuality::Medium => (3, 2048, 1),
+            ShadowQuality::High => (4, 2048, 1),
+            ShadowQuality::Ultra => (4, 4096, 2),
+        };
+        Self {
+            cascade_count,
+            resolution,
+            pcf_radius,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            cascade_count: 3,
+            resolution: 2048,
+            max_distance: 100.0,
+            split_lambda: 0.8,
+            pcf_radius: 1,
+            depth_bias: 0.0005,
+            normal_bias: 1.5,
+            caster_distance: 50.0,
+        }
+    }
+}
+
+/// One slice of the camera frustum and the light projection covering it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cascade {
+    /// World to shadow map clip space.
+    pub view_projection: Mat4,
+    /// View-space distance from the camera at which the cascade ends.
+    pub split_depth: f32,
+    /// World-space size of one shadow map texel.
+    pub texel_size: f32,
+}
+
+/// Contents of the shadow uniform buffer. Matches the std140 layout of `ShadowUniform` in
+/// `pbr.frag`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct ShadowUniform {
+    pub cascade_view_projection: [[[f32; 4]; 4]; MAX_CASCADES],
+    pub cascade_splits: [f32; 4],
+    pub cascade_texel_sizes: [f32; 4],
+    pub cascade_count: u32,
+    pub light_index: i32,
+    pub pcf_radius: i32,
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+    pub _pad: [f32; 3],
+}
+
+/// Cascaded shadow maps of a directional light, stored as the layers of one depth image.
+pub struct ShadowMaps {
+    pub settings: ShadowSettings,
+    pub format: Format,
+    pub image: Arc<StorageImage>,
+    /// Every cascade, for sampling.
+    pub array_view: Arc<ImageView<StorageImage>>,
+    /// One view per cascade, for rendering.
+    pub cascade_views: Vec<Arc<ImageView<StorageImage>>>,
+    /// Comparison sampler for `sampler2DArrayShadow`.
+    pub sampler: Arc<Sampler>,
+    /// Cascades computed by the last [`Self::update`].
+    pub cascades: Vec<Cascade>,
+    pipeline: Arc<GraphicsPipeline>,
+    /// Discards texels of alpha-masked materials below their cutoff.
+    masked_pipeline: Arc<GraphicsPipeline>,
+    instance_buffer: InstanceBuffer<ModelInstance>,
+}
+
+impl ShadowMaps {
+    pub fn new(
+        render_device: &RenderDevice,
+        settings: ShadowSettings,
+    ) -> Result<Self, ShadowError> {
+        if !(1..=MAX_CASCADES as u32).contains(&settings.cascade_count) {
+            return Err(ShadowError::CascadeCount(settings.cascade_count));
+        }
+        let physical_device = render_device.device.physical_device();
+        let format = SHADOW_FORMAT_CANDIDATES
+            .into_iter()
+            .find(|&format| {
+                physical_device
+                    .format_properties(format)
+                    .map(|properties| {
+                        let features = properties.optimal_tiling_features;
+                        features.depth_stencil_attachment && features.sampled_image
+                    })
+                    .unwrap_or(false)
+            })
+            .ok_or(ShadowError::NoDepthFormat)?;
+        let filter_linear = physical_device
+            .format_properties(format)
+            .unwrap()
+            .optimal_tiling_features
+            .sampled_image_filter_linear;
+
+        let image = StorageImage::with_usage(
+            &render_device.memory_allocator,
+            ImageDimensions::Dim2d {
+                width: settings.resolution,
+                height: settings.resolution,
+                array_layers: settings.cascade_count,
+            },
+            format,
+            ImageUsage {
+                depth_stencil_attachment: true,
+                sampled: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags::empty(),
+            [render_device.present_queue.queue_family_index()],
+        )
+        .unwrap();
+        set_image_name(image.as_ref(), "shadow map");
+        render_device
+            .memory_tracker
+            .track_image(image.as_ref(), MemoryCategory::RenderTarget, "shadow map");
+        let array_view = ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .unwrap();
+        let cascade_views = (0..settings.cascade_count)
+            .map(|layer| {
+                let mut create_info = ImageViewCreateInfo::from_image(&image);
+                create_info.view_type = ImageViewType::Dim2d;
+                create_info.subresource_range.array_layers = layer..layer + 1;
+                ImageView::new(image.clone(), create_info).unwrap()
+            })
+            .collect();
+        // Linear filtering of a comparison sampler blends the results of four comparisons,
+        // which smooths the PCF kernel for free.
+        let filter = if filter_linear {
+            Filter::Linear
+        } else {
+            Filter::Nearest
+        };
+        let sampler = Sampler::new(
+            render_device.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: filter,
+                min_filter: filter,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                compare: Some(CompareOp::LessOrEqual),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let pipeline = create_pipeline(
+            render_device,
+            format,
+            settings.resolution,
+            vs::load(render_device.device.clone()).unwrap(),
+            fs::load(render_device.device.clone()).unwrap(),
+        );
+        set_object_name(pipeline.as_ref(), "shadow");
+        let masked_pipeline = create_pipeline(
+            render_device,
+            format,
+            settings.resolution,
+            masked_vs::load(render_device.device.clone()).unwrap(),
+            masked_fs::load(render_device.device.clone()).unwrap(),
+        );
+        set_object_name(masked_pipeline.as_ref(), "shadow masked");
+
+        Ok(Self {
+            settings,
+            format,
+            image,
+            array_view,
+            cascade_views,
+            sampler,
+            cascades: Vec::new(),
+            pipeline,
+            masked_pipeline,
+            instance_buffer: InstanceBuffer::new(render_device),
+        })
+    }
+
+    /// Fits the cascades to the view frustum of `camera` for a light travelling along
+    /// `light_direction`.
+    ///
+    /// Each cascade covers the bounding sphere of its frustum slice, so its size does not
+    /// change as the camera rotates, and its origin is snapped to whole texels, so that
+    /// shadow edges do not shimmer as the camera moves.
+    pub fn update(&mut self, camera: &Camera, aspect_ratio: f32, light_direction: Vec3) {
+        let settings = &self.settings;
+        // Orthographic depth precision is the same at any distance, so their cascades are
+        // split uniformly.
+        let (near, far, split_lambda) = match camera.projection {
+            Projection::Perspective { near, far, .. } => (near, far, settings.split_lambda),
+            Projection::Orthographic { near, far, .. } => (near, far, 0.0),
+        };
+        let far = far.min(settings.max_distance);
+        let splits = cascade_splits(near, far, settings.cascade_count, split_lambda);
+
+        let direction = light_direction.normalize();
+        let up = if direction.abs_diff_eq(Vec3::Y, 1e-3) || direction.abs_diff_eq(-Vec3::Y, 1e-3) {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        let light_view = Mat4::look_at_rh(Vec3::ZERO, direction, up);
+        let camera_to_world = Mat4::from_rotation_translation(camera.rotation, camera.position);
+
+        self.cascades = splits
+            .windows(2)
+            .map(|split| {
+                let corners = frustum_slice_corners(camera, aspect_ratio, split[0], split[1])
+                    .map(|corner| camera_to_world.transform_point3(corner));
+                let (view_projection, texel_size) = fit_cascade(
+                    &corners,
+                    light_view,
+                    settings.resolution,
+                    settings.caster_distance,
+                );
+                Cascade {
+                    view_projection,
+                    split_depth: split[1],
+                    texel_size,
+                }
+            })
+            .collect();
+    }
+
+    /// Shadow uniform for shading, with `light_index` the index of the shadowed light in the
+    /// light buffer.
+    pub fn uniform(&self, light_index: Option<usize>) -> ShadowUniform {
+        let mut uniform = ShadowUniform {
+            cascade_count: self.cascades.len() as u32,
+            light_index: light_index.map_or(-1, |index| index as i32),
+            pcf_radius: self.settings.pcf_radius as i32,
+            depth_bias: self.settings.depth_bias,
+            normal_bias: self.settings.normal_bias,
+            ..Default::default()
+        };
+        for (i, cascade) in self.cascades.iter().enumerate() {
+            uniform.cascade_view_projection[i] = cascade.view_projection.to_cols_array_2d();
+            uniform.cascade_splits[i] = cascade.split_depth;
+            uniform.cascade_texel_sizes[i] = cascade.texel_size;
+        }
+        uniform
+    }
+
+    /// Renders the depth of every opaque and alpha-masked primitive of `model` into each
+    /// cascade, one instanced draw per mesh. Alpha-masked primitives only cast shadows where
+    /// their base color is opaque enough. Must be recorded before, and outside of, the
+    /// rendering that samples the shadow maps. Every cascade is cleared even when there is
+    /// nothing to draw.
+    pub fn render(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        model: &Model,
+        materials: &ModelMaterials,
+        instances: &[(usize, Mat4)],
+    ) {
+        let mut draw_list = DrawList::new();
+        for (mesh_index, transform) in instances {
+            for mesh in &model.meshes[*mesh_index] {
+                let material = materials.get(mesh.material);
+                if material.alpha_mode != AlphaMode::Blend {
+                    draw_list.push(mesh, material, *transform);
+                }
+            }
+        }
+        // Without blended draws, the order of batches does not depend on the camera.
+        let draws = draw_list.build(Vec3::ZERO);
+        let instance_buffer =
+            (!draws.instances.is_empty()).then(|| self.instance_buffer.upload(draws.instances));
+        for (i, view) in self.cascade_views.iter().enumerate() {
+            begin_label(builder, &format!("shadow cascade {}", i));
+            builder
+                .begin_rendering(RenderingInfo {
+                    depth_attachment: Some(RenderingAttachmentInfo {
+                        load_op: LoadOp::Clear,
+                        store_op: StoreOp::Store,
+                        clear_value: Some(1.0.into()),
+                        ..RenderingAttachmentInfo::image_view(view.clone())
+                    }),
+                    ..Default::default()
+                })
+                .unwrap();
+            let (Some(cascade), Some(instance_buffer)) = (self.cascades.get(i), &instance_buffer)
+            else {
+                builder.end_rendering().unwrap();
+                end_label(builder);
+                continue;
+            };
+            let view_projection = cascade.view_projection.to_cols_array_2d();
+            for batch in &draws.batches {
+                let masked = batch.material.alpha_mode == AlphaMode::Mask;
+                let pipeline = if masked {
+                    &self.masked_pipeline
+                } else {
+                    &self.pipeline
+                };
+                builder
+                    .bind_pipeline_graphics(pipeline.clone())
+                    .push_constants(pipeline.layout().clone(), 0, view_projection);
+                if masked {
+                    builder.bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        pipeline.layout().clone(),
+                        MATERIAL_SET,
+                        batch.material.descriptor_set.clone(),
+                    );
+                }
+                draw_batch(builder, batch, instance_buffer.clone());
+            }
+            builder.end_rendering().unwrap();
+            end_label(builder);
+        }
+    }
+}
+
+fn create_pipeline(
+    render_device: &RenderDevice,
+    format: Format,
+    resolution: u32,
+    vs: Arc<ShaderModule>,
+    fs: Arc<ShaderModule>,
+) -> Arc<GraphicsPipeline> {
+    GraphicsPipeline::start()
+        .render_pass(PipelineRenderingCreateInfo {
+            depth_attachment_format: Some(format),
+            ..Default::default()
+        })
+        .vertex_input_state(
+            BuffersDefinition::new()
+                .vertex::<MeshVertex>()
+                .instance::<ModelInstance>(),
+        )
+        .input_assembly_state(InputAssemblyState::new())
+        .vertex_shader(vs.entry_point("main").unwrap(), ())
+        .viewport_state(ViewportState::viewport_fixed_scissor_irrelevant([Viewport {
+            origin: [0.0, 0.0],
+            dimensions: [resolution as f32; 2],
+            depth_range: 0.0..1.0,
+        }]))
+        .fragment_shader(fs.entry_point("main").unwrap(), ())
+        // Both faces cast shadows, so that open and double-sided meshes do too.
+        .rasterization_state(RasterizationState::new().cull_mode(CullMode::None))
+        .depth_stencil_state(DepthStencilState {
+            depth: Some(DepthState {
+                enable_dynamic: false,
+                compare_op: StateMode::Fixed(CompareOp::Less),
+                write_enable: StateMode::Fixed(true),
+            }),
+            ..Default::default()
+        })
+        // Masked materials are bound as they are for shading.
+        .with_auto_layout(render_device.device.clone(), apply_material_set_layout)
+        .unwrap()
+}
+
+/// View distances splitting `near..far` into `count` cascades, blending uniform and
+/// logarithmic splits by `split_lambda`. Starts with `near` and ends with `far`.
+fn cascade_splits(near: f32, far: f32, count: u32, split_lambda: f32) -> Vec<f32> {
+    let logarithmic_near = near.max(MIN_LOGARITHMIC_NEAR);
+    let logarithmic_far = far.max(logarithmic_near);
+    (0..=count)
+        .map(|i| {
+            if i == 0 {
+                return near;
+            }
+            let t = i as f32 / count as f32;
+            let logarithmic = logarithmic_near * (logarithmic_far / logarithmic_near).powf(t);
+            let uniform = near + (far - near) * t;
+            split_lambda * logarithmic + (1.0 - split_lambda) * uniform
+        })
+        .collect()
+}
+
+/// World to shadow map clip space of a cascade covering the world-space `corners` of its
+/// frustum slice, as [`ShadowMaps::update`] describes, and the world-space size of its texels.
+fn fit_cascade(
+    corners: &[Vec3; 8],
+    light_view: Mat4,
+    resolution: u32,
+    caster_distance: f32,
+) -> (Mat4, f32) {
+    let center = corners.iter().copied().sum::<Vec3>() / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0.0, f32::max);
+    // Round up so that floating point noise does not change the texel size.
+    let radius = (radius * 16.0).ceil() / 16.0;
+    let texel_size = 2.0 * radius / resolution as f32;
+
+    let light_center = light_view.transform_point3(center);
+    let snapped_x = (light_center.x / texel_size).floor() * texel_size;
+    let snapped_y = (light_center.y / texel_size).floor() * texel_size;
+    // The light looks down -Z, so depths are negated view-space Z.
+    let projection = Mat4::orthographic_rh(
+        snapped_x - radius,
+        snapped_x + radius,
+        snapped_y - radius,
+        snapped_y + radius,
+        -light_center.z - radius - caster_distance,
+        -light_center.z + radius,
+    );
+    (projection * light_view, texel_size)
+}
+
+/// Camera-space corners of the part of the view volume between view distances `near` and
+/// `far`.
+fn frustum_slice_corners(camera: &Camera, aspect_ratio: f32, near: f32, far: f32) -> [Vec3; 8] {
+    let half_extents = |distance: f32| match camera.projection {
+        Projection::Perspective { fov_y, .. } => {
+            let half_height = (fov_y * 0.5).tan() * distance;
+            (half_height * aspect_ratio, half_height)
+        }
+        Projection::Orthographic { height, .. } => (height * 0.5 * aspect_ratio, height * 0.5),
+    };
+    let mut corners = [Vec3::ZERO; 8];
+    for (i, distance) in [near, far].into_iter().enumerate() {
+        let (half_width, half_height) = half_extents(distance);
+        for (j, (x, y)) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .into_iter()
+            .enumerate()
+        {
+            corners[i * 4 + j] = Vec3::new(x * half_width, y * half_height, -distance);
+        }
+    }
+    corners
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_splits(splits: &[f32], expected: &[f32]) {
+        assert_eq!(splits.len(), expected.len());
+        for (split, expected) in splits.iter().zip(expected) {
+            assert!((split - expected).abs() < 1.0e-4, "{:?} != {:?}", splits, expected);
+        }
+    }
+
+    #[test]
+    fn splits_blend_uniform_and_logarithmic() {
+        assert_splits(&cascade_splits(1.0, 4.0, 3, 0.0), &[1.0, 2.0, 3.0, 4.0]);
+        assert_splits(&cascade_splits(1.0, 8.0, 3, 1.0), &[1.0, 2.0, 4.0, 8.0]);
+        assert_splits(&cascade_splits(1.0, 8.0, 1, 0.5), &[1.0, 8.0]);
+        assert_splits(
+            &cascade_splits(1.0, 8.0, 3, 0.5),
+            &[1.0, (2.0 + 10.0 / 3.0) * 0.5, (4.0 + 17.0 / 3.0) * 0.5, 8.0],
+        );
+    }
+
+    #[test]
+    fn splits_of_a_near_plane_at_or_behind_the_camera_are_finite() {
+        for near in [0.0, -10.0] {
+            let splits = cascade_splits(near, 100.0, 4, 0.8);
+            assert_eq!(splits[0], near);
+            assert!((splits[4] - 100.0).abs() < 1.0e-3, "{:?}", splits);
+            assert!(splits.iter().all(|split| split.is_finite()), "{:?}", splits);
+            assert!(splits.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", splits);
+        }
+    }
+
+    /// Corners of a cube of half size `half_size` around `center`.
+    fn cube(center: Vec3, half_size: f32) -> [Vec3; 8] {
+        std::array::from_fn(|i| {
+            let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+            center + Vec3::new(sign(1), sign(2), sign(4)) * half_size
+        })
+    }
+
+    #[test]
+    fn cascades_cover_their_slice() {
+        let corners = cube(Vec3::new(3.0, -2.0, 5.0), 1.0);
+        let light_view = Mat4::look_at_rh(Vec3::ZERO, Vec3::new(-0.3, -1.0, -0.5), Vec3::Y);
+        let (view_projection, texel_size) = fit_cascade(&corners, light_view, 1024, 10.0);
+        // The bounding sphere of the cube, rounded up to sixteenths.
+        let radius = (3.0f32.sqrt() * 16.0).ceil() / 16.0;
+        assert_eq!(texel_size, 2.0 * radius / 1024.0);
+        for corner in corners {
+            let clip = view_projection.project_point3(corner);
+            assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "{}", clip);
+            assert!((0.0..=1.0).contains(&clip.z), "{}", clip);
+        }
+    }
+
+    #[test]
+    fn cascades_move_in_whole_texels() {
+        // The light looks down -Z, so world X and Y are shadow map X and Y.
+        let fit = |x: f32| {
+            fit_cascade(&cube(Vec3::new(x, 0.0, -5.0), 1.0), Mat4::IDENTITY, 64, 0.0)
+        };
+        let (view_projection, texel_size) = fit(0.0);
+        // Moving by less than a texel keeps the projection.
+        assert_eq!(fit(texel_size * 0.25).0, view_projection);
+        assert_eq!(fit(texel_size * 0.75).0, view_projection);
+        // Moving by a texel moves every texel center onto the next one.
+        let (moved, _) = fit(texel_size * 1.25);
+        let shift = moved.project_point3(Vec3::ZERO) - view_projection.project_point3(Vec3::ZERO);
+        assert!((shift.x + 2.0 / 64.0).abs() < 1.0e-5, "{}", shift);
+        assert_eq!(shift.y, 0.0);
+    }
+}
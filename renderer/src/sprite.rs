@@ -0,0 +1,361 @@
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::Format;
+use vulkano::impl_vertex;
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use crate::atlas::{AtlasRegion, TextureAtlas, UvRect};
+use crate::camera::{apply_view_set_layout, Camera, ViewUniforms, VIEW_SET};
+use crate::debug_utils::{begin_label, end_label, set_object_name};
+use crate::instancing::InstanceBuffer;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+
+/// Descriptor set index of the atlas texture sprites sample.
+pub const ATLAS_SET: u32 = 1;
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/sprite/sprite.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/sprite/sprite.frag",
+    }
+}
+
+/// A textured quad in the XY plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprite {
+    pub position: Vec2,
+    /// Counter-clockwise rotation around the anchor, in radians.
+    pub rotation: f32,
+    /// Size of the unscaled sprite, in world units.
+    pub size: Vec2,
+    pub scale: Vec2,
+    /// Point the sprite is positioned, rotated and scaled around, from `(0, 0)` at the bottom
+    /// left to `(1, 1)` at the top right.
+    pub anchor: Vec2,
+    /// Index into [`SpriteRenderer::atlases`].
+    pub atlas: usize,
+    pub uv_rect: UvRect,
+    /// Linear color multiplied with the texture.
+    pub tint: [f32; 4],
+    /// Sprites are drawn by increasing layer, then increasing depth within a layer.
+    pub layer: i32,
+    pub depth: f32,
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            rotation: 0.0,
+            size: Vec2::ONE,
+            scale: Vec2::ONE,
+            anchor: Vec2::splat(0.5),
+            atlas: 0,
+            uv_rect: UvRect::FULL,
+            tint: [1.0; 4],
+            layer: 0,
+            depth: 0.0,
+        }
+    }
+}
+
+impl Sprite {
+    /// A sprite showing `region` of atlas `atlas`, sized so that one texel covers
+    /// `1 / pixels_per_unit` world units.
+    pub fn from_region(atlas: usize, region: &AtlasRegion, pixels_per_unit: f32) -> Self {
+        Self {
+            size: Vec2::new(region.width as f32, region.height as f32) / pixels_per_unit,
+            atlas,
+            uv_rect: region.uv_rect,
+            ..Default::default()
+        }
+    }
+}
+
+/// Per-instance vertex data of a sprite.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct SpriteInstance {
+    pub position: [f32; 3],
+    pub rotation: f32,
+    pub size: [f32; 2],
+    pub anchor: [f32; 2],
+    pub uv_rect: [f32; 4],
+    pub tint: [f32; 4],
+}
+impl_vertex!(SpriteInstance, position, rotation, size, anchor, uv_rect, tint);
+
+impl From<&Sprite> for SpriteInstance {
+    fn from(sprite: &Sprite) -> Self {
+        Self {
+            position: [sprite.position.x, sprite.position.y, 0.0],
+            rotation: sprite.rotation,
+            size: (sprite.size * sprite.scale).to_array(),
+            anchor: sprite.anchor.to_array(),
+            uv_rect: [
+                sprite.uv_rect.min.x,
+                sprite.uv_rect.min.y,
+                sprite.uv_rect.max.x,
+                sprite.uv_rect.max.y,
+            ],
+            tint: sprite.tint,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct SpritePushConstants {
+    pixel_snap: u32,
+}
+
+/// Draws sprites in as few instanced draws as possible: sprites are sorted by layer and depth,
+/// and every run of consecutive sprites sharing an atlas becomes one draw.
+pub struct SpriteRenderer {
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub atlases: Vec<TextureAtlas>,
+    /// Snap sprite corners to whole pixels. Use together with [`pixel_perfect_camera`] and
+    /// nearest filtering for crisp pixel art.
+    pub pixel_snap: bool,
+    atlas_sets: Vec<Arc<PersistentDescriptorSet>>,
+    instance_buffer: InstanceBuffer<SpriteInstance>,
+}
+
+impl SpriteRenderer {
+    /// Creates a pipeline rendering into a `color_format` attachment, with the depth attachment
+    /// and sample count of `render_output`. Sprites neither test nor write depth.
+    pub fn new(
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        color_format: Format,
+    ) -> Self {
+        let vs = vs::load(render_device.device.clone()).unwrap();
+        let fs = fs::load(render_device.device.clone()).unwrap();
+        let pipeline = GraphicsPipeline::start()
+            .render_pass(PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(color_format)],
+                depth_attachment_format: Some(render_output.depth_format),
+                ..Default::default()
+            })
+            .vertex_input_state(BuffersDefinition::new().instance::<SpriteInstance>())
+            .input_assembly_state(
+                InputAssemblyState::new().topology(PrimitiveTopology::TriangleStrip),
+            )
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .multisample_state(MultisampleState {
+                rasterization_samples: render_output.samples,
+                ..Default::default()
+            })
+            .depth_stencil_state(DepthStencilState::disabled())
+            .color_blend_state(ColorBlendState::new(1).blend(AttachmentBlend::alpha()))
+            .with_auto_layout(render_device.device.clone(), apply_view_set_layout)
+            .unwrap();
+        set_object_name(pipeline.as_ref(), "sprite");
+        Self {
+            pipeline,
+            atlases: Vec::new(),
+            pixel_snap: false,
+            atlas_sets: Vec::new(),
+            instance_buffer: InstanceBuffer::new(render_device),
+        }
+    }
+
+    /// Makes `atlas` available to sprites, and returns the index they refer to it by.
+    pub fn add_atlas(&mut self, render_device: &RenderDevice, atlas: TextureAtlas) -> usize {
+        let layout = self.pipeline.layout().set_layouts()[ATLAS_SET as usize].clone();
+        let descriptor_set = PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            layout,
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                atlas.texture.view.clone(),
+                atlas.texture.sampler.clone(),
+            )],
+        )
+        .unwrap();
+        self.atlases.push(atlas);
+        self.atlas_sets.push(descriptor_set);
+        self.atlases.len() - 1
+    }
+
+    /// Records the draws of `sprites`. Must be called inside dynamic rendering with a viewport
+    /// set.
+    pub fn draw(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        view_uniforms: &ViewUniforms,
+        sprites: &[Sprite],
+    ) {
+        if sprites.is_empty() {
+            return;
+        }
+        let (order, batches) = draw_batches(sprites);
+        let instances = self
+            .instance_buffer
+            .upload(order.iter().map(|&sprite| SpriteInstance::from(sprite)));
+
+        begin_label(builder, "sprites");
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                VIEW_SET,
+                view_uniforms.descriptor_set.clone(),
+            )
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                SpritePushConstants {
+                    pixel_snap: self.pixel_snap as u32,
+                },
+            )
+            .bind_vertex_buffers(0, instances.clone());
+        for (atlas, first, count) in batches {
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.pipeline.layout().clone(),
+                    ATLAS_SET,
+                    self.atlas_sets[atlas].clone(),
+                )
+                .draw(4, count, 0, first)
+                .unwrap();
+        }
+        end_label(builder);
+    }
+}
+
+/// Sorts `sprites` into draw order and splits it into `(atlas, first, count)` runs.
+fn draw_batches(sprites: &[Sprite]) -> (Vec<&Sprite>, Vec<(usize, u32, u32)>) {
+    // Sprites at the same layer and depth are grouped by atlas to merge their draws. A stable
+    // sort keeps the submission order of sprites that compare equal.
+    let mut order: Vec<&Sprite> = sprites.iter().collect();
+    order.sort_by(|a, b| {
+        a.layer
+            .cmp(&b.layer)
+            .then(a.depth.total_cmp(&b.depth))
+            .then(a.atlas.cmp(&b.atlas))
+    });
+    let mut batches = Vec::new();
+    let mut first = 0;
+    while first < order.len() {
+        let atlas = order[first].atlas;
+        let count = order[first..]
+            .iter()
+            .take_while(|sprite| sprite.atlas == atlas)
+            .count();
+        batches.push((atlas, first as u32, count as u32));
+        first += count;
+    }
+    (order, batches)
+}
+
+/// An orthographic camera mapping one texel of sprites made with `pixels_per_unit` to exactly
+/// `zoom` by `zoom` screen pixels, centered on `position` snapped to the pixel grid.
+pub fn pixel_perfect_camera(
+    position: Vec2,
+    viewport_size: [u32; 2],
+    pixels_per_unit: f32,
+    zoom: u32,
+) -> Camera {
+    let screen_pixels_per_unit = pixels_per_unit * zoom.max(1) as f32;
+    let mut snapped = (position * screen_pixels_per_unit).round() / screen_pixels_per_unit;
+    // With an odd viewport size, the center of the screen lies in the middle of a pixel.
+    if viewport_size[0] % 2 == 1 {
+        snapped.x += 0.5 / screen_pixels_per_unit;
+    }
+    if viewport_size[1] % 2 == 1 {
+        snapped.y += 0.5 / screen_pixels_per_unit;
+    }
+    let mut camera = Camera::orthographic(
+        viewport_size[1] as f32 / screen_pixels_per_unit,
+        -1000.0,
+        1000.0,
+    );
+    camera.position = Vec3::new(snapped.x, snapped.y, 0.0);
+    camera
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Projection;
+
+    fn sprite(layer: i32, depth: f32, atlas: usize, x: f32) -> Sprite {
+        Sprite {
+            position: Vec2::new(x, 0.0),
+            layer,
+            depth,
+            atlas,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sprites_sort_by_layer_depth_and_atlas() {
+        let sprites = [
+            sprite(1, 0.0, 0, 0.0),
+            sprite(0, 2.0, 1, 1.0),
+            sprite(0, 1.0, 1, 2.0),
+            sprite(0, 1.0, 0, 3.0),
+            sprite(0, 1.0, 1, 4.0),
+        ];
+        let (order, _) = draw_batches(&sprites);
+        let xs: Vec<f32> = order.iter().map(|sprite| sprite.position.x).collect();
+        // Equal sprites keep their submission order.
+        assert_eq!(xs, [3.0, 2.0, 4.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn consecutive_sprites_of_an_atlas_share_a_draw() {
+        let sprites = [
+            sprite(0, 0.0, 1, 0.0),
+            sprite(0, 0.0, 0, 1.0),
+            sprite(0, 0.0, 1, 2.0),
+            sprite(1, 0.0, 1, 3.0),
+            sprite(2, 0.0, 0, 4.0),
+        ];
+        let (_, batches) = draw_batches(&sprites);
+        assert_eq!(batches, [(0, 0, 1), (1, 1, 3), (0, 4, 1)]);
+        assert!(draw_batches(&[]).1.is_empty());
+    }
+
+    #[test]
+    fn pixel_perfect_camera_snaps_to_screen_pixels() {
+        // 16 pixels per unit at zoom 2 puts a screen pixel every 1/32 units.
+        let camera = pixel_perfect_camera(Vec2::new(0.51, -0.26), [640, 360], 16.0, 2);
+        assert_eq!(camera.position, Vec3::new(16.0 / 32.0, -8.0 / 32.0, 0.0));
+        match camera.projection {
+            Projection::Orthographic { height, .. } => assert_eq!(height, 360.0 / 32.0),
+            _ => panic!("expected an orthographic projection"),
+        }
+    }
+
+    #[test]
+    fn pixel_perfect_camera_centers_odd_viewports_on_a_pixel() {
+        let camera = pixel_perfect_camera(Vec2::ZERO, [641, 360], 16.0, 0);
+        assert_eq!(camera.position, Vec3::new(0.5 / 16.0, 0.0, 0.0));
+        let camera = pixel_perfect_camera(Vec2::ZERO, [640, 361], 16.0, 1);
+        assert_eq!(camera.position, Vec3::new(0.0, 0.5 / 16.0, 0.0));
+    }
+}
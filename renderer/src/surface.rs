@@ -0,0 +1,98 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use raw_window_handle::{
+    HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
+};
+use vulkano::instance::{Instance, InstanceExtensions};
+use vulkano::swapchain::{Surface, SurfaceCreationError};
+use vulkano::VulkanLibrary;
+
+/// Instance extensions for presenting to windows on any supported platform, as far as
+/// `library` supports them.
+pub fn required_extensions(library: &VulkanLibrary) -> InstanceExtensions {
+    let ideal = InstanceExtensions {
+        khr_surface: true,
+        khr_xlib_surface: true,
+        khr_xcb_surface: true,
+        khr_wayland_surface: true,
+        khr_android_surface: true,
+        khr_win32_surface: true,
+        mvk_ios_surface: true,
+        mvk_macos_surface: true,
+        khr_get_physical_device_properties2: true,
+        khr_get_surface_capabilities2: true,
+        ..InstanceExtensions::empty()
+    };
+    library.supported_extensions().intersection(&ideal)
+}
+
+/// Why [`create_surface`] could not create a surface.
+#[derive(Clone, Debug)]
+pub enum SurfaceError {
+    /// The window and display handles, described here, are of a platform without a Vulkan
+    /// surface extension, or of iOS, where vulkano needs the layers of the view instead of the
+    /// view itself.
+    UnsupportedWindow(String),
+    Creation(SurfaceCreationError),
+}
+
+impl fmt::Display for SurfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SurfaceError::UnsupportedWindow(handles) => {
+                write!(f, "unsupported window: {}", handles)
+            }
+            SurfaceError::Creation(e) => write!(f, "failed to create the surface: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SurfaceError {}
+
+/// Creates a surface for `window` of any toolkit that exposes raw window and display handles,
+/// like winit, SDL2 or GTK. The surface keeps `window` alive, and returns it from
+/// [`Surface::object`].
+///
+/// On macOS the view has to be backed by a `CAMetalLayer`, as MoltenVK requires.
+pub fn create_surface<W>(
+    instance: &Arc<Instance>,
+    window: Arc<W>,
+) -> Result<Arc<Surface>, SurfaceError>
+where
+    W: HasRawWindowHandle + HasRawDisplayHandle + Send + Sync + 'static,
+{
+    let instance = instance.clone();
+    let window_handle = window.raw_window_handle();
+    let display_handle = window.raw_display_handle();
+    let object: Option<Arc<dyn Any + Send + Sync>> = Some(window);
+    // The handles stay valid while `object` keeps the window alive.
+    let surface = unsafe {
+        match (window_handle, display_handle) {
+            (RawWindowHandle::Xlib(window), RawDisplayHandle::Xlib(display)) => {
+                Surface::from_xlib(instance, display.display, window.window, object)
+            }
+            (RawWindowHandle::Xcb(window), RawDisplayHandle::Xcb(display)) => {
+                Surface::from_xcb(instance, display.connection, window.window, object)
+            }
+            (RawWindowHandle::Wayland(window), RawDisplayHandle::Wayland(display)) => {
+                Surface::from_wayland(instance, display.display, window.surface, object)
+            }
+            (RawWindowHandle::Win32(window), _) => {
+                Surface::from_win32(instance, window.hinstance, window.hwnd, object)
+            }
+            (RawWindowHandle::AndroidNdk(window), _) => {
+                Surface::from_android(instance, window.a_native_window, object)
+            }
+            #[cfg(target_os = "macos")]
+            (RawWindowHandle::AppKit(window), _) => {
+                Surface::from_mac_os(instance, window.ns_view, object)
+            }
+            (window_handle, display_handle) => {
+                let handles = format!("{:?} with {:?}", window_handle, display_handle);
+                return Err(SurfaceError::UnsupportedWindow(handles));
+            }
+        }
+    };
+    surface.map_err(SurfaceError::Creation)
+}
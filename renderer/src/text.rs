@@ -0,0 +1,969 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec2;
+use vulkano::buffer::{BufferUsage, CpuAccessibleBuffer};
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BufferImageCopy, ClearColorImageInfo, CopyBufferToImageInfo,
+    PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageAccess, ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
+use vulkano::impl_vertex;
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendState};
+use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::{InputAssemblyState, PrimitiveTopology};
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::render_pass::PipelineRenderingCreateInfo;
+use vulkano::pipeline::graphics::vertex_input::BuffersDefinition;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use crate::atlas::UvRect;
+use crate::debug_utils::{begin_label, end_label, set_image_name, set_object_name};
+use crate::instancing::InstanceBuffer;
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+use crate::render_output::RenderOutput;
+
+/// Pixel size signed distance field glyphs are rasterized at, whatever size they are drawn at.
+pub const SDF_SIZE: f32 = 48.0;
+/// Distance, in pixels at [`SDF_SIZE`], covered by the field on either side of a glyph edge.
+pub const SDF_SPREAD: u32 = 6;
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "shader/text/text.vert",
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shader/text/text.frag",
+    }
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(std::io::Error),
+    Parse(&'static str),
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontError::Io(e) => write!(f, "failed to read font: {}", e),
+            FontError::Parse(e) => write!(f, "failed to parse font: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+impl From<std::io::Error> for FontError {
+    fn from(e: std::io::Error) -> Self {
+        FontError::Io(e)
+    }
+}
+
+/// A TrueType or OpenType font.
+pub struct Font {
+    pub font: fontdue::Font,
+}
+
+impl Font {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, FontError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FontError> {
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(FontError::Parse)?;
+        Ok(Self { font })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    /// Index into [`TextRenderer::fonts`].
+    pub font: usize,
+    /// Font size, in pixels.
+    pub size: f32,
+    /// Linear color.
+    pub color: [f32; 4],
+    /// Lines are wrapped at word boundaries, or inside words longer than a line, to fit this
+    /// width in pixels.
+    pub max_width: Option<f32>,
+    /// Lines are aligned within `max_width`, or within the widest line without it.
+    pub align: TextAlign,
+    /// Multiplier of the line spacing of the font.
+    pub line_height: f32,
+    /// Draw with signed distance field glyphs, which stay sharp at any size and share one
+    /// rasterization between all sizes. Bitmap glyphs are rasterized per size, and look best
+    /// at small sizes.
+    pub sdf: bool,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            font: 0,
+            size: 16.0,
+            color: [1.0; 4],
+            max_width: None,
+            align: TextAlign::Left,
+            line_height: 1.0,
+            sdf: false,
+        }
+    }
+}
+
+/// A glyph placed by [`layout_text`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LaidOutGlyph {
+    pub glyph_index: u16,
+    /// Pen position, relative to the top left of the text, y pointing down.
+    pub x: f32,
+    pub baseline: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TextLayout {
+    pub glyphs: Vec<LaidOutGlyph>,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Lays out `text` with kerning, breaking lines at `\n` and wrapping them to
+/// `style.max_width`.
+pub fn layout_text(font: &Font, text: &str, style: &TextStyle) -> TextLayout {
+    layout_glyphs(&font.font, text, style)
+}
+
+/// The font metrics [`layout_text`] needs.
+trait GlyphMetrics {
+    fn glyph_index(&self, c: char) -> u16;
+    fn advance(&self, glyph_index: u16, size: f32) -> f32;
+    fn kern(&self, left: u16, right: u16, size: f32) -> Option<f32>;
+    /// Ascent and line spacing.
+    fn line_metrics(&self, size: f32) -> Option<(f32, f32)>;
+}
+
+impl GlyphMetrics for fontdue::Font {
+    fn glyph_index(&self, c: char) -> u16 {
+        self.lookup_glyph_index(c)
+    }
+
+    fn advance(&self, glyph_index: u16, size: f32) -> f32 {
+        self.metrics_indexed(glyph_index, size).advance_width
+    }
+
+    fn kern(&self, left: u16, right: u16, size: f32) -> Option<f32> {
+        self.horizontal_kern_indexed(left, right, size)
+    }
+
+    fn line_metrics(&self, size: f32) -> Option<(f32, f32)> {
+        self.horizontal_line_metrics(size)
+            .map(|metrics| (metrics.ascent, metrics.new_line_size))
+    }
+}
+
+fn layout_glyphs(font: &impl GlyphMetrics, text: &str, style: &TextStyle) -> TextLayout {
+    struct Placed {
+        glyph_index: u16,
+        x: f32,
+        advance: f32,
+        whitespace: bool,
+    }
+
+    let mut lines: Vec<Vec<Placed>> = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line: Vec<Placed> = Vec::new();
+        let mut pen = 0.0;
+        for c in paragraph.chars() {
+            let glyph_index = font.glyph_index(c);
+            let advance = font.advance(glyph_index, style.size);
+            let whitespace = c.is_whitespace();
+            if let Some(max_width) = style.max_width {
+                let kern = kerning(font, line.last(), glyph_index, style.size);
+                if !whitespace && !line.is_empty() && pen + kern + advance > max_width {
+                    // Break after the last whitespace, or before this glyph if the line is a
+                    // single word.
+                    let split = line
+                        .iter()
+                        .rposition(|placed| placed.whitespace)
+                        .map_or(line.len(), |i| i + 1);
+                    let mut rest = line.split_off(split);
+                    let offset = rest.first().map_or(0.0, |placed| placed.x);
+                    for placed in &mut rest {
+                        placed.x -= offset;
+                    }
+                    lines.push(line);
+                    line = rest;
+                    pen = line.last().map_or(0.0, |placed| placed.x + placed.advance);
+                }
+            }
+            pen += kerning(font, line.last(), glyph_index, style.size);
+            line.push(Placed {
+                glyph_index,
+                x: pen,
+                advance,
+                whitespace,
+            });
+            pen += advance;
+        }
+        lines.push(line);
+    }
+
+    fn kerning(
+        font: &impl GlyphMetrics,
+        previous: Option<&Placed>,
+        glyph_index: u16,
+        size: f32,
+    ) -> f32 {
+        previous
+            .and_then(|previous| font.kern(previous.glyph_index, glyph_index, size))
+            .unwrap_or(0.0)
+    }
+
+    // Trailing whitespace does not count towards the width of a line.
+    let line_widths: Vec<f32> = lines
+        .iter()
+        .map(|line| {
+            line.iter()
+                .rev()
+                .find(|placed| !placed.whitespace)
+                .map_or(0.0, |placed| placed.x + placed.advance)
+        })
+        .collect();
+    let width = line_widths.iter().copied().fold(0.0, f32::max);
+    let align_width = style.max_width.unwrap_or(width);
+    let (ascent, line_spacing) = match font.line_metrics(style.size) {
+        Some((ascent, line_spacing)) => (ascent, line_spacing * style.line_height),
+        None => (style.size * 0.8, style.size * 1.2 * style.line_height),
+    };
+
+    let mut glyphs = Vec::new();
+    for (i, (line, line_width)) in lines.iter().zip(&line_widths).enumerate() {
+        let offset = match style.align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => (align_width - line_width) * 0.5,
+            TextAlign::Right => align_width - line_width,
+        };
+        let baseline = ascent + i as f32 * line_spacing;
+        glyphs.extend(line.iter().map(|placed| LaidOutGlyph {
+            glyph_index: placed.glyph_index,
+            x: offset + placed.x,
+            baseline,
+        }));
+    }
+    TextLayout {
+        glyphs,
+        width,
+        height: lines.len() as f32 * line_spacing,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: usize,
+    glyph_index: u16,
+    /// Distance field glyphs are rasterized once, at [`SDF_SIZE`].
+    sdf: bool,
+    /// Bits of the pixel size of bitmap glyphs, or 0 for distance field glyphs.
+    size_bits: u32,
+}
+
+/// A glyph rasterized into a [`GlyphAtlas`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphEntry {
+    pub uv_rect: UvRect,
+    /// Top left corner of the bitmap relative to the pen position on the baseline, in
+    /// rasterized pixels, y pointing down.
+    pub offset: Vec2,
+    /// Size of the bitmap, in rasterized pixels. Zero for glyphs without an outline.
+    pub size: Vec2,
+}
+
+struct PendingGlyph {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Single channel texture that glyphs are rasterized into on first use.
+///
+/// Glyphs are allocated on shelves and stay until the atlas is full, at which point
+/// [`TextRenderer`] clears it and rasterizes the glyphs of the current frame again.
+pub struct GlyphAtlas {
+    pub size: u32,
+    pub image: Arc<StorageImage>,
+    pub view: Arc<ImageView<StorageImage>>,
+    pub sampler: Arc<Sampler>,
+    entries: HashMap<GlyphKey, GlyphEntry>,
+    shelf_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+    pending: Vec<PendingGlyph>,
+}
+
+impl GlyphAtlas {
+    /// Records the clear of a new `size` by `size` atlas into `builder`.
+    pub fn new(
+        render_device: &RenderDevice,
+        size: u32,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let image = StorageImage::with_usage(
+            &render_device.memory_allocator,
+            ImageDimensions::Dim2d {
+                width: size,
+                height: size,
+                array_layers: 1,
+            },
+            Format::R8_UNORM,
+            ImageUsage {
+                sampled: true,
+                transfer_dst: true,
+                ..ImageUsage::empty()
+            },
+            ImageCreateFlags::empty(),
+            [render_device.present_queue.queue_family_index()],
+        )
+        .unwrap();
+        set_image_name(image.as_ref(), "glyph atlas");
+        render_device
+            .memory_tracker
+            .track_image(image.as_ref(), MemoryCategory::Texture, "glyph atlas");
+        builder
+            .clear_color_image(ClearColorImageInfo::image(image.clone()))
+            .unwrap();
+        let sampler = Sampler::new(
+            render_device.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Self {
+            size,
+            view: ImageView::new_default(image.clone()).unwrap(),
+            image,
+            sampler,
+            entries: HashMap::new(),
+            shelf_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Forgets every glyph, making the whole atlas available again.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pending.clear();
+        self.shelf_x = 0;
+        self.shelf_y = 0;
+        self.shelf_height = 0;
+    }
+
+    /// Looks up a glyph, rasterizing it if needed. Returns `None` if the atlas is full.
+    fn glyph(&mut self, fonts: &[Font], key: GlyphKey) -> Option<GlyphEntry> {
+        if let Some(entry) = self.entries.get(&key) {
+            return Some(*entry);
+        }
+        let font = &fonts[key.font].font;
+        let (offset, width, height, pixels) = if key.sdf {
+            let (metrics, coverage) = font.rasterize_indexed(key.glyph_index, SDF_SIZE);
+            if metrics.width == 0 || metrics.height == 0 {
+                (Vec2::ZERO, 0, 0, Vec::new())
+            } else {
+                let spread = SDF_SPREAD as i32;
+                let offset = Vec2::new(
+                    (metrics.xmin - spread) as f32,
+                    -(metrics.ymin + metrics.height as i32 + spread) as f32,
+                );
+                let width = metrics.width as u32 + 2 * SDF_SPREAD;
+                let height = metrics.height as u32 + 2 * SDF_SPREAD;
+                let field = signed_distance_field(&coverage, metrics.width, metrics.height);
+                (offset, width, height, field)
+            }
+        } else {
+            let (metrics, coverage) =
+                font.rasterize_indexed(key.glyph_index, f32::from_bits(key.size_bits));
+            let offset = Vec2::new(
+                metrics.xmin as f32,
+                -(metrics.ymin + metrics.height as i32) as f32,
+            );
+            (offset, metrics.width as u32, metrics.height as u32, coverage)
+        };
+
+        let uv_rect = if width == 0 || height == 0 {
+            UvRect::default()
+        } else {
+            let (x, y) = self.allocate(width, height)?;
+            self.pending.push(PendingGlyph {
+                x,
+                y,
+                width,
+                height,
+                pixels,
+            });
+            UvRect {
+                min: Vec2::new(x as f32, y as f32) / self.size as f32,
+                max: Vec2::new((x + width) as f32, (y + height) as f32) / self.size as f32,
+            }
+        };
+        let entry = GlyphEntry {
+            uv_rect,
+            offset,
+            size: Vec2::new(width as f32, height as f32),
+        };
+        self.entries.insert(key, entry);
+        Some(entry)
+    }
+
+    /// Shelf allocation of a `width` by `height` rectangle with a one pixel gap around it, so
+    /// that linear filtering never picks up neighbouring glyphs.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (padded_width, padded_height) = (width + 2, height + 2);
+        if self.shelf_x + padded_width > self.size {
+            self.shelf_y += self.shelf_height;
+            self.shelf_x = 0;
+            self.shelf_height = 0;
+        }
+        if padded_width > self.size || self.shelf_y + padded_height > self.size {
+            return None;
+        }
+        let position = (self.shelf_x + 1, self.shelf_y + 1);
+        self.shelf_x += padded_width;
+        self.shelf_height = self.shelf_height.max(padded_height);
+        Some(position)
+    }
+
+    /// Records the copy of glyphs rasterized since the last upload. Must be called outside of
+    /// rendering.
+    pub fn upload(
+        &mut self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        if self.pending.is_empty() {
+            return;
+        }
+        // Every glyph is uploaded with its gap, which may still hold glyphs from before the
+        // last clear.
+        let mut data = Vec::new();
+        let mut regions = Vec::with_capacity(self.pending.len());
+        for glyph in self.pending.drain(..) {
+            let (width, height) = (glyph.width + 2, glyph.height + 2);
+            regions.push(BufferImageCopy {
+                buffer_offset: data.len() as u64,
+                image_subresource: self.image.subresource_layers(),
+                image_offset: [glyph.x - 1, glyph.y - 1, 0],
+                image_extent: [width, height, 1],
+                ..Default::default()
+            });
+            data.resize(data.len() + width as usize, 0);
+            for row in glyph.pixels.chunks_exact(glyph.width as usize) {
+                data.push(0);
+                data.extend_from_slice(row);
+                data.push(0);
+            }
+            data.resize(data.len() + width as usize, 0);
+        }
+        let buffer = CpuAccessibleBuffer::from_iter(
+            &render_device.memory_allocator,
+            BufferUsage {
+                transfer_src: true,
+                ..BufferUsage::empty()
+            },
+            false,
+            data,
+        )
+        .unwrap();
+        render_device
+            .memory_tracker
+            .track_buffer(buffer.as_ref(), MemoryCategory::Staging, "glyph upload");
+        builder
+            .copy_buffer_to_image(CopyBufferToImageInfo {
+                regions: regions.into(),
+                ..CopyBufferToImageInfo::buffer_image(buffer, self.image.clone())
+            })
+            .unwrap();
+    }
+}
+
+/// Converts `width * height` coverage values into a field of the signed distance to the glyph
+/// edge, padded by [`SDF_SPREAD`] on every side. 0.5 lies on the edge, higher values inside.
+fn signed_distance_field(coverage: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let spread = SDF_SPREAD as usize;
+    let (field_width, field_height) = (width + 2 * spread, height + 2 * spread);
+    let inside: Vec<bool> = (0..field_width * field_height)
+        .map(|i| {
+            let (x, y) = (i % field_width, i / field_width);
+            x >= spread
+                && y >= spread
+                && x < spread + width
+                && y < spread + height
+                && coverage[(y - spread) * width + x - spread] >= 128
+        })
+        .collect();
+    let to_inside = squared_distance_transform(&inside, field_width, field_height, true);
+    let to_outside = squared_distance_transform(&inside, field_width, field_height, false);
+    inside
+        .iter()
+        .zip(to_inside.iter().zip(&to_outside))
+        .map(|(&inside, (&to_inside, &to_outside))| {
+            // Pixel centers lie half a pixel from the edge between two pixels.
+            let distance = if inside {
+                to_outside.sqrt() - 0.5
+            } else {
+                0.5 - to_inside.sqrt()
+            };
+            let value = 0.5 + distance / (2.0 * SDF_SPREAD as f32);
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Squared euclidean distance of every pixel to the nearest pixel whose mask equals `target`,
+/// computed separably after Felzenszwalb and Huttenlocher.
+fn squared_distance_transform(
+    mask: &[bool],
+    width: usize,
+    height: usize,
+    target: bool,
+) -> Vec<f32> {
+    // Large but finite, so that the parabola intersections stay well defined.
+    const FAR: f32 = 1.0e20;
+    let mut distances: Vec<f32> = mask
+        .iter()
+        .map(|&value| if value == target { 0.0 } else { FAR })
+        .collect();
+    let n = width.max(height);
+    let (mut f, mut d) = (vec![0.0; n], vec![0.0; n]);
+    let (mut v, mut z) = (vec![0; n], vec![0.0; n + 1]);
+    for x in 0..width {
+        for y in 0..height {
+            f[y] = distances[y * width + x];
+        }
+        distance_transform_1d(&f[..height], &mut d, &mut v, &mut z);
+        for y in 0..height {
+            distances[y * width + x] = d[y];
+        }
+    }
+    for y in 0..height {
+        f[..width].copy_from_slice(&distances[y * width..(y + 1) * width]);
+        distance_transform_1d(&f[..width], &mut d, &mut v, &mut z);
+        distances[y * width..(y + 1) * width].copy_from_slice(&d[..width]);
+    }
+    distances
+}
+
+/// Lower envelope of the parabolas rooted at `f`.
+fn distance_transform_1d(f: &[f32], d: &mut [f32], v: &mut [usize], z: &mut [f32]) {
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = f32::NEG_INFINITY;
+    z[1] = f32::INFINITY;
+    for q in 1..f.len() {
+        let intersection = |k: usize| {
+            let p = v[k];
+            ((f[q] + (q * q) as f32) - (f[p] + (p * p) as f32)) / (2 * (q - p)) as f32
+        };
+        let mut s = intersection(k);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(k);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f32::INFINITY;
+    }
+    k = 0;
+    for (q, distance) in d.iter_mut().enumerate().take(f.len()) {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let offset = q as f32 - v[k] as f32;
+        *distance = offset * offset + f[v[k]];
+    }
+}
+
+/// Per-instance vertex data of a glyph quad.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+pub struct GlyphInstance {
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub uv_rect: [f32; 4],
+    pub color: [f32; 4],
+    pub sdf: f32,
+}
+impl_vertex!(GlyphInstance, position, size, uv_rect, color, sdf);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct TextPushConstants {
+    viewport_size: [f32; 2],
+}
+
+struct QueuedText {
+    text: String,
+    position: Vec2,
+    style: TextStyle,
+}
+
+/// Draws screen space text, in pixels from the top left of the viewport.
+///
+/// Text is queued during the frame, [`Self::prepare`] rasterizes missing glyphs and uploads
+/// them before rendering, and [`Self::draw`] records one instanced draw for all of it.
+pub struct TextRenderer {
+    pub pipeline: Arc<GraphicsPipeline>,
+    pub fonts: Vec<Font>,
+    pub atlas: GlyphAtlas,
+    descriptor_set: Arc<PersistentDescriptorSet>,
+    instance_buffer: InstanceBuffer<GlyphInstance>,
+    queued: Vec<QueuedText>,
+    instances: Vec<GlyphInstance>,
+}
+
+impl TextRenderer {
+    /// Creates a pipeline rendering into a `color_format` attachment, with the depth attachment
+    /// and sample count of `render_output`, and records the creation of a 1024 by 1024 glyph
+    /// atlas into `builder`.
+    pub fn new(
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        color_format: Format,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let vs = vs::load(render_device.device.clone()).unwrap();
+        let fs = fs::load(render_device.device.clone()).unwrap();
+        let pipeline = GraphicsPipeline::start()
+            .render_pass(PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(color_format)],
+                depth_attachment_format: Some(render_output.depth_format),
+                ..Default::default()
+            })
+            .vertex_input_state(BuffersDefinition::new().instance::<GlyphInstance>())
+            .input_assembly_state(
+                InputAssemblyState::new().topology(PrimitiveTopology::TriangleStrip),
+            )
+            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .multisample_state(MultisampleState {
+                rasterization_samples: render_output.samples,
+                ..Default::default()
+            })
+            .depth_stencil_state(DepthStencilState::disabled())
+            .color_blend_state(ColorBlendState::new(1).blend(AttachmentBlend::alpha()))
+            .build(render_device.device.clone())
+            .unwrap();
+        set_object_name(pipeline.as_ref(), "text");
+        let atlas = GlyphAtlas::new(render_device, 1024, builder);
+        let descriptor_set = PersistentDescriptorSet::new(
+            &render_device.descriptor_set_allocator,
+            pipeline.layout().set_layouts()[0].clone(),
+            [WriteDescriptorSet::image_view_sampler(
+                0,
+                atlas.view.clone(),
+                atlas.sampler.clone(),
+            )],
+        )
+        .unwrap();
+        Self {
+            pipeline,
+            fonts: Vec::new(),
+            atlas,
+            descriptor_set,
+            instance_buffer: InstanceBuffer::new(render_device),
+            queued: Vec::new(),
+            instances: Vec::new(),
+        }
+    }
+
+    /// Makes `font` available to text styles, and returns the index they refer to it by.
+    pub fn add_font(&mut self, font: Font) -> usize {
+        self.fonts.push(font);
+        self.fonts.len() - 1
+    }
+
+    /// Size of `text` laid out with `style`, in pixels.
+    pub fn measure(&self, text: &str, style: &TextStyle) -> Vec2 {
+        let layout = layout_text(&self.fonts[style.font], text, style);
+        Vec2::new(layout.width, layout.height)
+    }
+
+    /// Queues `text` with its top left corner at `position`, to be drawn this frame. Text of a
+    /// size that is not positive is not drawn.
+    pub fn queue(&mut self, text: &str, position: Vec2, style: &TextStyle) {
+        if style.size.is_nan() || style.size <= 0.0 {
+            return;
+        }
+        self.queued.push(QueuedText {
+            text: text.to_owned(),
+            position,
+            style: *style,
+        });
+    }
+
+    /// Lays out the queued text and records the upload of newly rasterized glyphs. Must be
+    /// called outside of rendering, before [`Self::draw`].
+    pub fn prepare(
+        &mut self,
+        render_device: &RenderDevice,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) {
+        self.instances.clear();
+        if !self.build_instances() {
+            // Make room by evicting the glyphs of previous frames.
+            self.atlas.clear();
+            self.instances.clear();
+            if !self.build_instances() {
+                println!("Glyph atlas is too small for this frame's text, some glyphs are missing");
+            }
+        }
+        self.atlas.upload(render_device, builder);
+    }
+
+    /// Returns false if the atlas ran out of space.
+    fn build_instances(&mut self) -> bool {
+        for queued in &self.queued {
+            let style = &queued.style;
+            let layout = layout_text(&self.fonts[style.font], &queued.text, style);
+            let (size_bits, scale) = if style.sdf {
+                (0, style.size / SDF_SIZE)
+            } else {
+                (style.size.to_bits(), 1.0)
+            };
+            for glyph in &layout.glyphs {
+                let key = GlyphKey {
+                    font: style.font,
+                    glyph_index: glyph.glyph_index,
+                    sdf: style.sdf,
+                    size_bits,
+                };
+                let Some(entry) = self.atlas.glyph(&self.fonts, key) else {
+                    return false;
+                };
+                if entry.size == Vec2::ZERO {
+                    continue;
+                }
+                let mut position =
+                    queued.position + Vec2::new(glyph.x, glyph.baseline) + entry.offset * scale;
+                // Bitmaps map one to one to pixels only on whole pixel positions.
+                if !style.sdf {
+                    position = position.round();
+                }
+                self.instances.push(GlyphInstance {
+                    position: position.to_array(),
+                    size: (entry.size * scale).to_array(),
+                    uv_rect: [
+                        entry.uv_rect.min.x,
+                        entry.uv_rect.min.y,
+                        entry.uv_rect.max.x,
+                        entry.uv_rect.max.y,
+                    ],
+                    color: style.color,
+                    sdf: style.sdf as u32 as f32,
+                });
+            }
+        }
+        true
+    }
+
+    /// Records the draw of the text prepared this frame and clears the queue. Must be called
+    /// inside dynamic rendering with a viewport of `viewport_size` set.
+    pub fn draw(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        viewport_size: [f32; 2],
+    ) {
+        self.queued.clear();
+        if self.instances.is_empty() {
+            return;
+        }
+        let count = self.instances.len() as u32;
+        let instances = self.instance_buffer.upload(self.instances.drain(..));
+        begin_label(builder, "text");
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                self.descriptor_set.clone(),
+            )
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                TextPushConstants { viewport_size },
+            )
+            .bind_vertex_buffers(0, instances)
+            .draw(4, count, 0, 0)
+            .unwrap();
+        end_label(builder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every glyph advances half the font size, "AV" kerns by a tenth of it.
+    struct Monospace;
+
+    impl GlyphMetrics for Monospace {
+        fn glyph_index(&self, c: char) -> u16 {
+            c as u16
+        }
+
+        fn advance(&self, _glyph_index: u16, size: f32) -> f32 {
+            size * 0.5
+        }
+
+        fn kern(&self, left: u16, right: u16, size: f32) -> Option<f32> {
+            (left == 'A' as u16 && right == 'V' as u16).then_some(size * -0.1)
+        }
+
+        fn line_metrics(&self, size: f32) -> Option<(f32, f32)> {
+            Some((size * 0.8, size * 1.25))
+        }
+    }
+
+    fn style(max_width: Option<f32>, align: TextAlign) -> TextStyle {
+        TextStyle {
+            size: 10.0,
+            max_width,
+            align,
+            ..TextStyle::default()
+        }
+    }
+
+    /// `(character, x, baseline)` of every glyph.
+    fn placed(layout: &TextLayout) -> Vec<(char, f32, f32)> {
+        layout
+            .glyphs
+            .iter()
+            .map(|glyph| (glyph.glyph_index as u8 as char, glyph.x, glyph.baseline))
+            .collect()
+    }
+
+    #[test]
+    fn kerning_pairs_move_the_pen() {
+        let layout = layout_glyphs(&Monospace, "AVA", &style(None, TextAlign::Left));
+        assert_eq!(
+            placed(&layout),
+            [('A', 0.0, 8.0), ('V', 4.0, 8.0), ('A', 9.0, 8.0)]
+        );
+        assert_eq!(layout.width, 14.0);
+        assert_eq!(layout.height, 12.5);
+    }
+
+    #[test]
+    fn lines_wrap_after_whitespace() {
+        let layout = layout_glyphs(&Monospace, "ab cd", &style(Some(12.0), TextAlign::Left));
+        assert_eq!(
+            placed(&layout),
+            [
+                ('a', 0.0, 8.0),
+                ('b', 5.0, 8.0),
+                (' ', 10.0, 8.0),
+                ('c', 0.0, 20.5),
+                ('d', 5.0, 20.5),
+            ]
+        );
+        // The trailing space of the first line does not count.
+        assert_eq!(layout.width, 10.0);
+        assert_eq!(layout.height, 25.0);
+    }
+
+    #[test]
+    fn words_longer_than_a_line_are_split() {
+        let layout = layout_glyphs(&Monospace, "abcd", &style(Some(12.0), TextAlign::Left));
+        assert_eq!(
+            placed(&layout),
+            [
+                ('a', 0.0, 8.0),
+                ('b', 5.0, 8.0),
+                ('c', 0.0, 20.5),
+                ('d', 5.0, 20.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn newlines_always_break() {
+        let layout = layout_glyphs(&Monospace, "a\n\nb", &style(None, TextAlign::Left));
+        assert_eq!(placed(&layout), [('a', 0.0, 8.0), ('b', 0.0, 33.0)]);
+        assert_eq!(layout.height, 37.5);
+    }
+
+    #[test]
+    fn lines_align_within_the_widest_line() {
+        let text = "a \nabc";
+        let center = layout_glyphs(&Monospace, text, &style(None, TextAlign::Center));
+        assert_eq!(center.glyphs[0].x, 5.0);
+        assert_eq!(center.glyphs[2].x, 0.0);
+        let right = layout_glyphs(&Monospace, text, &style(None, TextAlign::Right));
+        assert_eq!(right.glyphs[0].x, 10.0);
+        assert_eq!(right.glyphs[2].x, 0.0);
+    }
+
+    #[test]
+    fn lines_align_within_max_width() {
+        let layout = layout_glyphs(&Monospace, "ab", &style(Some(30.0), TextAlign::Right));
+        assert_eq!(placed(&layout), [('a', 20.0, 8.0), ('b', 25.0, 8.0)]);
+        assert_eq!(layout.width, 10.0);
+    }
+
+    #[test]
+    fn distance_field_is_half_on_the_edge() {
+        let field = signed_distance_field(&[255; 9], 3, 3);
+        let field_width = 3 + 2 * SDF_SPREAD as usize;
+        assert_eq!(field.len(), field_width * field_width);
+        let at = |x: usize, y: usize| field[y * field_width + x];
+        let spread = SDF_SPREAD as usize;
+        // Half a pixel inside and outside of the edge, and 1.5 pixels inside at the center.
+        assert_eq!(at(spread, spread + 1), 138);
+        assert_eq!(at(spread - 1, spread + 1), 117);
+        assert_eq!(at(spread + 1, spread + 1), 159);
+        assert_eq!(at(0, 0), 0);
+        // The field is symmetric like the square.
+        for y in 0..field_width {
+            for x in 0..field_width {
+                assert_eq!(at(x, y), at(field_width - 1 - x, y));
+                assert_eq!(at(x, y), at(y, x));
+            }
+        }
+    }
+
+    #[test]
+    fn distance_field_of_nothing_is_outside() {
+        let field = signed_distance_field(&[0, 127, 0, 0], 2, 2);
+        assert!(field.iter().all(|&value| value == 0));
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageDimensions, ImmutableImage, MipmapsCount};
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode};
+use crate::debug_utils::set_image_name;
+use crate::memory_tracker::MemoryCategory;
+use crate::render_device::RenderDevice;
+
+/// Decoded 8-bit RGBA pixels.
+#[derive(Clone, Debug)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed rows of RGBA texels.
+    pub pixels: Vec<u8>,
+}
+
+/// Sampling parameters of a texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamplerDesc {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mipmap_mode: SamplerMipmapMode,
+    pub address_mode_u: SamplerAddressMode,
+    pub address_mode_v: SamplerAddressMode,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_mode: SamplerMipmapMode::Linear,
+            address_mode_u: SamplerAddressMode::Repeat,
+            address_mode_v: SamplerAddressMode::Repeat,
+        }
+    }
+}
+
+/// CPU-side description of a texture: an image plus how to sample it.
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    pub name: Option<String>,
+    /// Index into the image list of the model the texture belongs to.
+    pub image: usize,
+    pub sampler: SamplerDesc,
+}
+
+/// Texture uploaded to the GPU.
+pub struct Texture {
+    pub view: Arc<ImageView<ImmutableImage>>,
+    pub sampler: Arc<Sampler>,
+}
+
+impl Texture {
+    /// Records the upload of `image` into `builder`, with a full mip chain. The texture may be
+    /// sampled once the command buffer has executed.
+    pub fn new(
+        render_device: &RenderDevice,
+        image: &ImageData,
+        sampler: &SamplerDesc,
+        srgb: bool,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        Self::with_mipmaps(render_device, image, sampler, srgb, MipmapsCount::Log2, builder)
+    }
+
+    /// Like [`Self::new`], with the base level only.
+    pub fn without_mipmaps(
+        render_device: &RenderDevice,
+        image: &ImageData,
+        sampler: &SamplerDesc,
+        srgb: bool,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        Self::with_mipmaps(render_device, image, sampler, srgb, MipmapsCount::One, builder)
+    }
+
+    fn with_mipmaps(
+        render_device: &RenderDevice,
+        image: &ImageData,
+        sampler: &SamplerDesc,
+        srgb: bool,
+        mipmaps: MipmapsCount,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Self {
+        let format = if srgb {
+            Format::R8G8B8A8_SRGB
+        } else {
+            Format::R8G8B8A8_UNORM
+        };
+        let image = ImmutableImage::from_iter(
+            &render_device.memory_allocator,
+            image.pixels.iter().copied(),
+            ImageDimensions::Dim2d {
+                width: image.width,
+                height: image.height,
+                array_layers: 1,
+            },
+            mipmaps,
+            format,
+            builder,
+        )
+        .unwrap();
+        set_image_name(image.as_ref(), "texture");
+        render_device
+            .memory_tracker
+            .track_image(image.as_ref(), MemoryCategory::Texture, "texture");
+        let sampler = Sampler::new(
+            render_device.device.clone(),
+            SamplerCreateInfo {
+                mag_filter: sampler.mag_filter,
+                min_filter: sampler.min_filter,
+                mipmap_mode: sampler.mipmap_mode,
+                address_mode: [
+                    sampler.address_mode_u,
+                    sampler.address_mode_v,
+                    SamplerAddressMode::Repeat,
+                ],
+                lod: 0.0..=vulkano::sampler::LOD_CLAMP_NONE,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Self {
+            view: ImageView::new_default(image).unwrap(),
+            sampler,
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use glam::{Mat4, Vec3, Vec4};
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        PrimaryCommandBufferAbstract, RenderingInfo,
+    },
+    image::SampleCount,
+    pipeline::graphics::viewport::Viewport,
+    swapchain::{acquire_next_image, AcquireError, SwapchainCreationError, SwapchainPresentInfo},
+    sync::{self, FlushError, GpuFuture},
+};
+use winit::{
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use renderer::camera::{Camera, ViewUniforms};
+use renderer::debug_draw::{DebugDraw, DebugStyle};
+use renderer::gltf_import::{import_gltf, Model};
+use renderer::particles::{Curve, EmitterSettings, ParticleSystem};
+use renderer::pbr::{EnvironmentMap, Light, Lighting, PbrPipeline};
+use renderer::render_device::RenderDevice;
+use renderer::profiler::GpuProfiler;
+use renderer::post_process::{PostProcess, PostProcessSettings, Tonemapper, HDR_FORMAT};
+use renderer::render_output::{choose_sample_count, RenderOutput, RenderOutputCreateInfo};
+use renderer::render_system::RenderSystem;
+use renderer::shadow::{ShadowQuality, ShadowSettings};
+use renderer::surface::create_surface;
+
+/// Usage: `gltf_viewer <model.gltf|model.glb> [environment.hdr]`
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let model_path = args.next().expect("usage: gltf_viewer <model> [environment.hdr]");
+    let environment_path = args.next();
+
+    let scene = import_gltf(&model_path).unwrap();
+    let instances = scene.mesh_instances();
+
+    let render_system = RenderSystem::new();
+    let event_loop = EventLoop::new();
+    let window = Arc::new(
+        WindowBuilder::new()
+            .with_title("glTF viewer")
+            .build(&event_loop)
+            .unwrap(),
+    );
+    let surface = create_surface(&render_system.instance, window.clone()).unwrap();
+    let render_device = RenderDevice::new(&render_system, &surface);
+    let mut render_output = RenderOutput::new(
+        &render_device,
+        &surface,
+        window.inner_size().into(),
+        RenderOutputCreateInfo {
+            samples: choose_sample_count(
+                render_device.device.physical_device(),
+                SampleCount::Sample4,
+            ),
+            sampled_depth: true,
+            ..Default::default()
+        },
+    );
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+
+    let mut camera = Camera::perspective(std::f32::consts::FRAC_PI_3, 0.05, 200.0);
+    camera.reverse_z = true;
+    let mut view_uniforms = ViewUniforms::new(&render_device);
+
+    // Upload the model, the environment, the fallback textures and the LUT in one go.
+    let mut builder = AutoCommandBufferBuilder::primary(
+        &command_buffer_allocator,
+        render_device.present_queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    let model = Model::new(&render_device, &scene, &mut builder);
+    let environment = match environment_path {
+        Some(path) => EnvironmentMap::from_equirectangular(&render_device, path, &mut builder)
+            .expect("Failed to load the environment map"),
+        None => EnvironmentMap::uniform(&render_device, Vec3::new(0.3, 0.35, 0.4), &mut builder),
+    };
+    let pbr_pipeline = PbrPipeline::new(
+        &render_device,
+        &render_output,
+        HDR_FORMAT,
+        camera.depth_compare_op(),
+        &mut builder,
+    );
+    let mut debug_draw = DebugDraw::new(
+        &render_device,
+        &render_output,
+        HDR_FORMAT,
+        camera.depth_compare_op(),
+    );
+    let mut post_process = PostProcess::new(
+        &render_device,
+        &render_output,
+        PostProcessSettings::default(),
+        &mut builder,
+    );
+    let particle_system = ParticleSystem::new(&render_device, &render_output, HDR_FORMAT);
+    // Sparks falling off the orbiting point light.
+    let mut sparks = particle_system.create_emitter(
+        &render_device,
+        EmitterSettings {
+            spawn_rate: 300.0,
+            lifetime: 1.5,
+            velocity: Vec3::new(0.0, 0.5, 0.0),
+            velocity_randomness: 0.6,
+            acceleration: Vec3::new(0.0, -3.0, 0.0),
+            speed: Curve::linear(1.0, 0.3),
+            color: Curve::new([
+                (0.0, Vec4::new(8.0, 4.0, 1.5, 1.0)),
+                (0.5, Vec4::new(4.0, 1.2, 0.3, 0.8)),
+                (1.0, Vec4::new(1.0, 0.2, 0.0, 0.0)),
+            ])
+            .unwrap(),
+            size: Curve::linear(0.04, 0.01),
+            softness: 0.05,
+            ..Default::default()
+        },
+        500,
+    )
+    .unwrap();
+    let mut profiler = GpuProfiler::new(&render_device, 16);
+    let upload = builder
+        .build()
+        .unwrap()
+        .execute(render_device.present_queue.clone())
+        .unwrap();
+    let materials = pbr_pipeline.create_model_materials(&render_device, &model);
+
+    let mut lighting = Lighting::new(
+        &render_device,
+        environment,
+        ShadowSettings::from(ShadowQuality::High),
+    )
+    .unwrap();
+    lighting.lights = vec![
+        Light::Directional {
+            direction: Vec3::new(-0.3, -1.0, -0.5),
+            color: Vec3::ONE,
+            intensity: 3.0,
+        },
+        Light::Point {
+            position: Vec3::ZERO,
+            color: Vec3::new(1.0, 0.6, 0.3),
+            intensity: 20.0,
+            range: Some(10.0),
+        },
+    ];
+    lighting.shadow_caster = Some(0);
+
+    let mut viewport = Viewport {
+        origin: [0.0, 0.0],
+        dimensions: [0.0, 0.0],
+        depth_range: 0.0..1.0,
+    };
+    window_size_dependent_setup(&render_output, &mut viewport);
+
+    let start_time = Instant::now();
+    let mut last_frame_time = 0.0;
+    let mut last_light_position = None;
+    let mut recreate_swapchain = false;
+    let mut previous_frame_end = Some(upload.boxed());
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent {
+            event: WindowEvent::CloseRequested,
+            ..
+        } => {
+            *control_flow = ControlFlow::Exit;
+        }
+        Event::WindowEvent {
+            event: WindowEvent::Resized(_),
+            ..
+        } => {
+            recreate_swapchain = true;
+        }
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } => match key {
+            // Print the last GPU timings, or write them all as a Chrome trace.
+            VirtualKeyCode::P => match &profiler.latest {
+                Some(frame) => print!("{}", frame),
+                None => println!("No GPU timings yet"),
+            },
+            VirtualKeyCode::C => {
+                profiler.write_chrome_trace("gpu_trace.json").unwrap();
+                println!("Wrote {} frames to gpu_trace.json", profiler.history.len());
+            }
+            // Dump every tracked allocation, to diff for leaks.
+            VirtualKeyCode::M => {
+                render_device.memory_tracker.write_json("memory.json").unwrap();
+                println!("Wrote memory usage to memory.json");
+            }
+            _ => toggle_post_process(&mut post_process.settings, key),
+        },
+        Event::RedrawEventsCleared => {
+            previous_frame_end.as_mut().unwrap().cleanup_finished();
+            render_device.memory_tracker.check_budget();
+            if recreate_swapchain {
+                match render_output.recreate(&render_device, window.inner_size().into()) {
+                    Ok(()) => {}
+                    Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                    Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+                };
+                post_process.resize(&render_device, &render_output);
+                window_size_dependent_setup(&render_output, &mut viewport);
+                recreate_swapchain = false;
+            }
+
+            let (image_index, suboptimal, acquire_future) =
+                match acquire_next_image(render_output.swapchain.clone(), None) {
+                    Ok(r) => r,
+                    Err(AcquireError::OutOfDate) => {
+                        recreate_swapchain = true;
+                        return;
+                    }
+                    Err(e) => panic!("Failed to acquire next image: {:?}", e),
+                };
+            if suboptimal {
+                recreate_swapchain = true;
+            }
+
+            // Orbit the camera around the origin, and the point light around the model.
+            let time = start_time.elapsed().as_secs_f32();
+            camera.position = Vec3::new((time * 0.3).sin() * 3.0, 1.0, (time * 0.3).cos() * 3.0);
+            camera.look_at(Vec3::ZERO, Vec3::Y);
+            view_uniforms.update(&render_device, &render_output, &camera, time);
+            if let Light::Point { position, .. } = &mut lighting.lights[1] {
+                *position = Vec3::new(time.cos() * 1.5, 1.0, time.sin() * 1.5);
+            }
+            lighting.update(&render_device, &render_output, &camera);
+
+            // A ground grid, the world axes on top of the model, and the point light leaving a
+            // one second trail.
+            debug_draw.grid(Vec3::ZERO, 4.0, 8, [0.5, 0.5, 0.5, 1.0]);
+            debug_draw.axes(Mat4::IDENTITY, 0.5, 0.0, false);
+            if let Light::Point { position, .. } = lighting.lights[1] {
+                debug_draw.sphere(position, 0.05, [1.0, 0.6, 0.3, 1.0]);
+                if let Some(last_position) = last_light_position {
+                    let trail = DebugStyle {
+                        color: [1.0, 0.6, 0.3, 1.0],
+                        duration: 1.0,
+                        depth_test: true,
+                    };
+                    debug_draw.line(last_position, position, trail);
+                }
+                last_light_position = Some(position);
+                sparks.settings.position = position;
+            }
+
+            let mut builder = AutoCommandBufferBuilder::primary(
+                &command_buffer_allocator,
+                render_device.present_queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .unwrap();
+            let delta_time = time - last_frame_time;
+            last_frame_time = time;
+            profiler.begin_frame(&mut builder);
+            profiler.scope(&mut builder, "particles", |builder| {
+                particle_system.simulate(&render_device, builder, &mut sparks, delta_time)
+            });
+            profiler.scope(&mut builder, "shadows", |builder| {
+                lighting.shadows.render(builder, &model, &materials, &instances)
+            });
+            profiler.begin_scope(&mut builder, "scene");
+            profiler.begin_scope(&mut builder, "opaque");
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(post_process.hdr_attachment([0.0, 0.0, 0.0, 1.0]))],
+                    depth_attachment: Some(render_output.depth_attachment(camera.clear_depth())),
+                    ..Default::default()
+                })
+                .unwrap()
+                .set_viewport(0, [viewport.clone()]);
+            pbr_pipeline.draw_model(
+                &mut builder,
+                &view_uniforms,
+                &lighting,
+                &model,
+                &materials,
+                &instances,
+            );
+            debug_draw.draw(&mut builder, &view_uniforms, delta_time);
+            builder.end_rendering().unwrap();
+            profiler.end_scope(&mut builder);
+            profiler.begin_scope(&mut builder, "soft particles");
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(post_process.hdr_overlay_attachment())],
+                    ..Default::default()
+                })
+                .unwrap()
+                .set_viewport(0, [viewport.clone()]);
+            particle_system.draw(
+                &render_device,
+                &mut builder,
+                &render_output,
+                &view_uniforms,
+                std::slice::from_ref(&sparks),
+            );
+            builder.end_rendering().unwrap();
+            profiler.end_scope(&mut builder);
+            profiler.end_scope(&mut builder);
+            profiler.scope(&mut builder, "post process", |builder| {
+                post_process.render(&render_device, builder, &render_output, image_index)
+            });
+            profiler.end_frame();
+            let command_buffer = builder.build().unwrap();
+
+            let future = previous_frame_end
+                .take()
+                .unwrap()
+                .join(acquire_future)
+                .then_execute(render_device.present_queue.clone(), command_buffer)
+                .unwrap()
+                .then_swapchain_present(
+                    render_device.present_queue.clone(),
+                    SwapchainPresentInfo::swapchain_image_index(
+                        render_output.swapchain.clone(),
+                        image_index,
+                    ),
+                )
+                .then_signal_fence_and_flush();
+
+            match future {
+                Ok(future) => {
+                    // vulkano implements `GpuFuture` for `Arc` of a fence future, not `Rc`.
+                    #[allow(clippy::arc_with_non_send_sync)]
+                    let future = Arc::new(future);
+                    profiler.submitted(future.clone());
+                    previous_frame_end = Some(future.boxed());
+                }
+                Err(FlushError::OutOfDate) => {
+                    recreate_swapchain = true;
+                    previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+                }
+                Err(e) => {
+                    println!("Failed to flush future: {:?}", e);
+                    previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+                }
+            }
+        }
+        _ => (),
+    });
+}
+
+fn window_size_dependent_setup(render_output: &RenderOutput, viewport: &mut Viewport) {
+    let dimensions = render_output.swapchain.image_extent();
+    viewport.dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+}
+
+/// Number keys 1 to 5 toggle exposure, tonemapping, bloom, color grading and FXAA. `T` switches
+/// between the ACES and AgX tonemappers.
+fn toggle_post_process(settings: &mut PostProcessSettings, key: VirtualKeyCode) {
+    match key {
+        VirtualKeyCode::Key1 => settings.exposure_enabled = !settings.exposure_enabled,
+        VirtualKeyCode::Key2 => settings.tonemapping_enabled = !settings.tonemapping_enabled,
+        VirtualKeyCode::Key3 => settings.bloom_enabled = !settings.bloom_enabled,
+        VirtualKeyCode::Key4 => settings.color_grading_enabled = !settings.color_grading_enabled,
+        VirtualKeyCode::Key5 => settings.fxaa_enabled = !settings.fxaa_enabled,
+        VirtualKeyCode::T => {
+            settings.tonemapper = match settings.tonemapper {
+                Tonemapper::Aces => Tonemapper::Agx,
+                Tonemapper::Agx => Tonemapper::Aces,
+            }
+        }
+        _ => return,
+    }
+    println!("{:?}", settings);
+}
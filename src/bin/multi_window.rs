@@ -0,0 +1,246 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        RenderingInfo,
+    },
+    swapchain::{
+        acquire_next_image, AcquireError, PresentMode, Surface, SwapchainCreationError,
+        SwapchainPresentInfo,
+    },
+    sync::{self, FlushError, GpuFuture},
+};
+use winit::{
+    dpi::LogicalSize,
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+use renderer::render_device::RenderDevice;
+use renderer::render_output::{RenderOutput, RenderOutputCreateInfo, RenderOutputError};
+use renderer::render_system::RenderSystem;
+use renderer::surface::create_surface;
+
+/// A window with its own output and chain of frames. Fields drop in order, so the last frame is
+/// waited for before the swapchain and the window go away.
+struct WindowOutput {
+    name: &'static str,
+    clear_color: [f32; 4],
+    recreate_swapchain: bool,
+    previous_frame_end: Option<Box<dyn GpuFuture>>,
+    render_output: RenderOutput,
+    window: Arc<Window>,
+}
+
+impl WindowOutput {
+    fn new(
+        render_device: &RenderDevice,
+        window: Arc<Window>,
+        surface: &Arc<Surface>,
+        name: &'static str,
+        present_mode: PresentMode,
+        clear_color: [f32; 4],
+    ) -> Result<Self, RenderOutputError> {
+        let render_output = RenderOutput::try_new(
+            render_device,
+            surface,
+            window.inner_size().into(),
+            RenderOutputCreateInfo {
+                present_mode,
+                ..Default::default()
+            },
+        )?;
+        println!("{}: {:?}", name, render_output.swapchain.present_mode());
+        Ok(Self {
+            name,
+            clear_color,
+            recreate_swapchain: false,
+            previous_frame_end: Some(sync::now(render_device.device.clone()).boxed()),
+            render_output,
+            window,
+        })
+    }
+
+    /// Switches between `Fifo` and `Mailbox`, without affecting the other windows.
+    fn toggle_present_mode(&mut self, render_device: &RenderDevice) {
+        let present_mode = match self.render_output.swapchain.present_mode() {
+            PresentMode::Fifo => PresentMode::Mailbox,
+            _ => PresentMode::Fifo,
+        };
+        match self.render_output.set_present_mode(render_device, present_mode) {
+            Ok(()) => println!("{}: {:?}", self.name, self.render_output.swapchain.present_mode()),
+            Err(e) => println!("Failed to change the present mode: {:?}", e),
+        }
+    }
+
+    fn render(
+        &mut self,
+        render_device: &RenderDevice,
+        command_buffer_allocator: &StandardCommandBufferAllocator,
+        time: f32,
+    ) {
+        self.previous_frame_end.as_mut().unwrap().cleanup_finished();
+        if self.recreate_swapchain {
+            let image_extent = self.window.inner_size().into();
+            match self.render_output.recreate(render_device, image_extent) {
+                Ok(()) => {}
+                // Minimized windows have no extent, and skip frames until they are restored.
+                Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+            };
+            self.recreate_swapchain = false;
+        }
+
+        let (image_index, suboptimal, acquire_future) =
+            match acquire_next_image(self.render_output.swapchain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return;
+                }
+                Err(e) => panic!("Failed to acquire next image: {:?}", e),
+            };
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+
+        let pulse = 0.75 + 0.25 * time.sin();
+        let [r, g, b, a] = self.clear_color;
+        let clear_color = [r * pulse, g * pulse, b * pulse, a];
+        let mut builder = AutoCommandBufferBuilder::primary(
+            command_buffer_allocator,
+            render_device.present_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(
+                    self.render_output.color_attachment(image_index, clear_color),
+                )],
+                ..Default::default()
+            })
+            .unwrap()
+            .end_rendering()
+            .unwrap();
+        let command_buffer = builder.build().unwrap();
+
+        let future = self
+            .previous_frame_end
+            .take()
+            .unwrap()
+            .join(acquire_future)
+            .then_execute(render_device.present_queue.clone(), command_buffer)
+            .unwrap()
+            .then_swapchain_present(
+                render_device.present_queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(
+                    self.render_output.swapchain.clone(),
+                    image_index,
+                ),
+            )
+            .then_signal_fence_and_flush();
+
+        match future {
+            Ok(future) => {
+                self.previous_frame_end = Some(future.boxed());
+            }
+            Err(FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                self.previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+            }
+            Err(e) => {
+                println!("Failed to flush future: {:?}", e);
+                self.previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+            }
+        }
+    }
+}
+
+/// A main window and a tool palette rendered by one device, each resized on its own. `V`
+/// toggles the present mode of the focused window. Closing the palette keeps the main window
+/// running.
+fn main() {
+    let render_system = RenderSystem::new();
+    let event_loop = EventLoop::new();
+    let main_window = Arc::new(
+        WindowBuilder::new()
+            .with_title("Main window")
+            .build(&event_loop)
+            .unwrap(),
+    );
+    let palette_window = Arc::new(
+        WindowBuilder::new()
+            .with_title("Palette")
+            .with_inner_size(LogicalSize::new(320.0, 480.0))
+            .build(&event_loop)
+            .unwrap(),
+    );
+    let main_surface = create_surface(&render_system.instance, main_window.clone()).unwrap();
+    let palette_surface = create_surface(&render_system.instance, palette_window.clone()).unwrap();
+    // The present queue has to support both windows, which may be on different monitors.
+    let render_device = RenderDevice::for_surfaces(
+        &render_system,
+        &[main_surface.clone(), palette_surface.clone()],
+    );
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+
+    let mut windows = vec![WindowOutput::new(
+        &render_device,
+        main_window,
+        &main_surface,
+        "Main window",
+        PresentMode::Fifo,
+        [0.1, 0.2, 0.4, 1.0],
+    )
+    .unwrap()];
+    // The main window keeps running without the palette.
+    match WindowOutput::new(
+        &render_device,
+        palette_window,
+        &palette_surface,
+        "Palette",
+        PresentMode::Mailbox,
+        [0.4, 0.25, 0.1, 1.0],
+    ) {
+        Ok(palette) => windows.push(palette),
+        Err(e) => println!("Cannot render the palette: {}", e),
+    }
+
+    let start_time = Instant::now();
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { window_id, event } => {
+            let index = match windows.iter().position(|w| w.window.id() == window_id) {
+                Some(index) => index,
+                None => return,
+            };
+            match event {
+                WindowEvent::CloseRequested if index == 0 => *control_flow = ControlFlow::Exit,
+                WindowEvent::CloseRequested => {
+                    windows.remove(index);
+                }
+                WindowEvent::Resized(_) => windows[index].recreate_swapchain = true,
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::V),
+                            ..
+                        },
+                    ..
+                } => windows[index].toggle_present_mode(&render_device),
+                _ => (),
+            }
+        }
+        Event::RedrawEventsCleared => {
+            let time = start_time.elapsed().as_secs_f32();
+            for window in &mut windows {
+                window.render(&render_device, &command_buffer_allocator, time);
+            }
+        }
+        _ => (),
+    });
+}
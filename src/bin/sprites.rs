@@ -0,0 +1,260 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use glam::Vec2;
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        PrimaryCommandBufferAbstract, RenderingInfo,
+    },
+    pipeline::graphics::viewport::Viewport,
+    sampler::Filter,
+    swapchain::{acquire_next_image, AcquireError, SwapchainCreationError, SwapchainPresentInfo},
+    sync::{self, FlushError, GpuFuture},
+};
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use renderer::atlas::{AtlasPacker, TextureAtlas};
+use renderer::camera::ViewUniforms;
+use renderer::render_device::RenderDevice;
+use renderer::render_output::{RenderOutput, RenderOutputCreateInfo};
+use renderer::render_system::RenderSystem;
+use renderer::sprite::{pixel_perfect_camera, Sprite, SpriteRenderer};
+use renderer::surface::create_surface;
+use renderer::texture::ImageData;
+
+const PIXELS_PER_UNIT: f32 = 16.0;
+const ZOOM: u32 = 3;
+
+/// Usage: `sprites [image ...]`. Packs the given images, or a few generated ones, into an atlas
+/// and draws a field of pixel-perfect sprites.
+fn main() {
+    let mut packer = AtlasPacker::new();
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        packer.add(checker(16, 4));
+        packer.add(circle(12));
+        packer.add(checker(8, 1));
+    }
+    for path in &paths {
+        packer.add_file(path).unwrap();
+    }
+    let packed = packer.pack().unwrap();
+
+    let render_system = RenderSystem::new();
+    let event_loop = EventLoop::new();
+    let window = Arc::new(
+        WindowBuilder::new()
+            .with_title("Sprites")
+            .build(&event_loop)
+            .unwrap(),
+    );
+    let surface = create_surface(&render_system.instance, window.clone()).unwrap();
+    let render_device = RenderDevice::new(&render_system, &surface);
+    let mut render_output = RenderOutput::new(
+        &render_device,
+        &surface,
+        window.inner_size().into(),
+        RenderOutputCreateInfo::default(),
+    );
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        &command_buffer_allocator,
+        render_device.present_queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    let atlas = TextureAtlas::new(&render_device, &packed, Filter::Nearest, &mut builder);
+    let upload = builder
+        .build()
+        .unwrap()
+        .execute(render_device.present_queue.clone())
+        .unwrap();
+
+    let mut sprite_renderer = SpriteRenderer::new(
+        &render_device,
+        &render_output,
+        render_output.swapchain.image_format(),
+    );
+    sprite_renderer.pixel_snap = true;
+    let atlas_index = sprite_renderer.add_atlas(&render_device, atlas);
+
+    // A grid of sprites cycling through the atlas regions, in alternating layers.
+    let regions = sprite_renderer.atlases[atlas_index].regions.clone();
+    let mut sprites: Vec<Sprite> = (0..200)
+        .map(|i| {
+            let region = &regions[i % regions.len()];
+            Sprite {
+                position: Vec2::new((i % 20) as f32 - 10.0, (i / 20) as f32 - 5.0) * 1.5,
+                layer: (i % 2) as i32,
+                tint: [1.0, 1.0 - (i % 3) as f32 * 0.3, 1.0, 1.0],
+                ..Sprite::from_region(atlas_index, region, PIXELS_PER_UNIT)
+            }
+        })
+        .collect();
+
+    let mut view_uniforms = ViewUniforms::new(&render_device);
+    let mut viewport = Viewport {
+        origin: [0.0, 0.0],
+        dimensions: [0.0, 0.0],
+        depth_range: 0.0..1.0,
+    };
+    window_size_dependent_setup(&render_output, &mut viewport);
+
+    let start_time = Instant::now();
+    let mut recreate_swapchain = false;
+    let mut previous_frame_end = Some(upload.boxed());
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent {
+            event: WindowEvent::CloseRequested,
+            ..
+        } => {
+            *control_flow = ControlFlow::Exit;
+        }
+        Event::WindowEvent {
+            event: WindowEvent::Resized(_),
+            ..
+        } => {
+            recreate_swapchain = true;
+        }
+        Event::RedrawEventsCleared => {
+            previous_frame_end.as_mut().unwrap().cleanup_finished();
+            if recreate_swapchain {
+                match render_output.recreate(&render_device, window.inner_size().into()) {
+                    Ok(()) => {}
+                    Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                    Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+                };
+                window_size_dependent_setup(&render_output, &mut viewport);
+                recreate_swapchain = false;
+            }
+
+            let (image_index, suboptimal, acquire_future) =
+                match acquire_next_image(render_output.swapchain.clone(), None) {
+                    Ok(r) => r,
+                    Err(AcquireError::OutOfDate) => {
+                        recreate_swapchain = true;
+                        return;
+                    }
+                    Err(e) => panic!("Failed to acquire next image: {:?}", e),
+                };
+            if suboptimal {
+                recreate_swapchain = true;
+            }
+
+            // Spin every other sprite and pan the camera slowly.
+            let time = start_time.elapsed().as_secs_f32();
+            for (i, sprite) in sprites.iter_mut().enumerate() {
+                if i % 2 == 1 {
+                    sprite.rotation = time;
+                }
+            }
+            let camera = pixel_perfect_camera(
+                Vec2::new(time.sin() * 2.0, 0.0),
+                render_output.swapchain.image_extent(),
+                PIXELS_PER_UNIT,
+                ZOOM,
+            );
+            view_uniforms.update(&render_device, &render_output, &camera, time);
+
+            let mut builder = AutoCommandBufferBuilder::primary(
+                &command_buffer_allocator,
+                render_device.present_queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .unwrap();
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(
+                        render_output.color_attachment(image_index, [0.1, 0.1, 0.15, 1.0]),
+                    )],
+                    depth_attachment: Some(render_output.depth_attachment(camera.clear_depth())),
+                    ..Default::default()
+                })
+                .unwrap()
+                .set_viewport(0, [viewport.clone()]);
+            sprite_renderer.draw(&mut builder, &view_uniforms, &sprites);
+            builder.end_rendering().unwrap();
+            let command_buffer = builder.build().unwrap();
+
+            let future = previous_frame_end
+                .take()
+                .unwrap()
+                .join(acquire_future)
+                .then_execute(render_device.present_queue.clone(), command_buffer)
+                .unwrap()
+                .then_swapchain_present(
+                    render_device.present_queue.clone(),
+                    SwapchainPresentInfo::swapchain_image_index(
+                        render_output.swapchain.clone(),
+                        image_index,
+                    ),
+                )
+                .then_signal_fence_and_flush();
+
+            match future {
+                Ok(future) => {
+                    previous_frame_end = Some(future.boxed());
+                }
+                Err(FlushError::OutOfDate) => {
+                    recreate_swapchain = true;
+                    previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+                }
+                Err(e) => {
+                    println!("Failed to flush future: {:?}", e);
+                    previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+                }
+            }
+        }
+        _ => (),
+    });
+}
+
+fn window_size_dependent_setup(render_output: &RenderOutput, viewport: &mut Viewport) {
+    let dimensions = render_output.swapchain.image_extent();
+    viewport.dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+}
+
+fn checker(size: u32, cell: u32) -> ImageData {
+    let pixels = (0..size * size)
+        .flat_map(|i| {
+            let (x, y) = (i % size / cell, i / size / cell);
+            if (x + y) % 2 == 0 {
+                [255, 200, 60, 255]
+            } else {
+                [60, 120, 255, 255]
+            }
+        })
+        .collect();
+    ImageData {
+        width: size,
+        height: size,
+        pixels,
+    }
+}
+
+fn circle(size: u32) -> ImageData {
+    let radius = size as f32 / 2.0;
+    let pixels = (0..size * size)
+        .flat_map(|i| {
+            let x = (i % size) as f32 + 0.5 - radius;
+            let y = (i / size) as f32 + 0.5 - radius;
+            if x * x + y * y <= radius * radius {
+                [255, 255, 255, 255]
+            } else {
+                [0, 0, 0, 0]
+            }
+        })
+        .collect();
+    ImageData {
+        width: size,
+        height: size,
+        pixels,
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use glam::Vec2;
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+        PrimaryCommandBufferAbstract, RenderingInfo,
+    },
+    pipeline::graphics::viewport::Viewport,
+    swapchain::{acquire_next_image, AcquireError, SwapchainCreationError, SwapchainPresentInfo},
+    sync::{self, FlushError, GpuFuture},
+};
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use renderer::render_device::RenderDevice;
+use renderer::render_output::{RenderOutput, RenderOutputCreateInfo};
+use renderer::render_system::RenderSystem;
+use renderer::surface::create_surface;
+use renderer::text::{Font, TextAlign, TextRenderer, TextStyle};
+
+const PARAGRAPH: &str = "The quick brown fox jumps over the lazy dog. \
+    AVA WAVE Tokyo — kerning pairs, UTF-8 (äöü ß ñ) and word wrapping.\n\
+    A second paragraph after an explicit line break.";
+
+/// Usage: `text <font.ttf|font.otf>`. Draws wrapped, aligned paragraphs with bitmap glyphs
+/// and a zooming headline with distance field glyphs.
+fn main() {
+    let font_path = std::env::args().nth(1).expect("usage: text <font>");
+    let font = Font::open(&font_path).unwrap();
+
+    let render_system = RenderSystem::new();
+    let event_loop = EventLoop::new();
+    let window = Arc::new(
+        WindowBuilder::new()
+            .with_title("Text")
+            .build(&event_loop)
+            .unwrap(),
+    );
+    let surface = create_surface(&render_system.instance, window.clone()).unwrap();
+    let render_device = RenderDevice::new(&render_system, &surface);
+    let mut render_output = RenderOutput::new(
+        &render_device,
+        &surface,
+        window.inner_size().into(),
+        RenderOutputCreateInfo::default(),
+    );
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        &command_buffer_allocator,
+        render_device.present_queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    let mut text_renderer = TextRenderer::new(
+        &render_device,
+        &render_output,
+        render_output.swapchain.image_format(),
+        &mut builder,
+    );
+    let font = text_renderer.add_font(font);
+    let upload = builder
+        .build()
+        .unwrap()
+        .execute(render_device.present_queue.clone())
+        .unwrap();
+
+    let mut viewport = Viewport {
+        origin: [0.0, 0.0],
+        dimensions: [0.0, 0.0],
+        depth_range: 0.0..1.0,
+    };
+    window_size_dependent_setup(&render_output, &mut viewport);
+
+    let start_time = Instant::now();
+    let mut recreate_swapchain = false;
+    let mut previous_frame_end = Some(upload.boxed());
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent {
+            event: WindowEvent::CloseRequested,
+            ..
+        } => {
+            *control_flow = ControlFlow::Exit;
+        }
+        Event::WindowEvent {
+            event: WindowEvent::Resized(_),
+            ..
+        } => {
+            recreate_swapchain = true;
+        }
+        Event::RedrawEventsCleared => {
+            previous_frame_end.as_mut().unwrap().cleanup_finished();
+            if recreate_swapchain {
+                match render_output.recreate(&render_device, window.inner_size().into()) {
+                    Ok(()) => {}
+                    Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                    Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+                };
+                window_size_dependent_setup(&render_output, &mut viewport);
+                recreate_swapchain = false;
+            }
+
+            let (image_index, suboptimal, acquire_future) =
+                match acquire_next_image(render_output.swapchain.clone(), None) {
+                    Ok(r) => r,
+                    Err(AcquireError::OutOfDate) => {
+                        recreate_swapchain = true;
+                        return;
+                    }
+                    Err(e) => panic!("Failed to acquire next image: {:?}", e),
+                };
+            if suboptimal {
+                recreate_swapchain = true;
+            }
+
+            let time = start_time.elapsed().as_secs_f32();
+            let headline = TextStyle {
+                font,
+                size: 48.0 + 32.0 * time.sin(),
+                color: [1.0, 0.8, 0.3, 1.0],
+                sdf: true,
+                ..Default::default()
+            };
+            text_renderer.queue("Distance fields", Vec2::new(20.0, 20.0), &headline);
+            let column_width = (viewport.dimensions[0] - 80.0) / 3.0;
+            for (i, align) in [TextAlign::Left, TextAlign::Center, TextAlign::Right]
+                .into_iter()
+                .enumerate()
+            {
+                let style = TextStyle {
+                    font,
+                    size: 16.0,
+                    max_width: Some(column_width),
+                    align,
+                    ..Default::default()
+                };
+                let position = Vec2::new(20.0 + i as f32 * (column_width + 20.0), 140.0);
+                text_renderer.queue(PARAGRAPH, position, &style);
+            }
+
+            let mut builder = AutoCommandBufferBuilder::primary(
+                &command_buffer_allocator,
+                render_device.present_queue.queue_family_index(),
+                CommandBufferUsage::OneTimeSubmit,
+            )
+            .unwrap();
+            text_renderer.prepare(&render_device, &mut builder);
+            builder
+                .begin_rendering(RenderingInfo {
+                    color_attachments: vec![Some(
+                        render_output.color_attachment(image_index, [0.1, 0.1, 0.15, 1.0]),
+                    )],
+                    depth_attachment: Some(render_output.depth_attachment(1.0)),
+                    ..Default::default()
+                })
+                .unwrap()
+                .set_viewport(0, [viewport.clone()]);
+            text_renderer.draw(&mut builder, viewport.dimensions);
+            builder.end_rendering().unwrap();
+            let command_buffer = builder.build().unwrap();
+
+            let future = previous_frame_end
+                .take()
+                .unwrap()
+                .join(acquire_future)
+                .then_execute(render_device.present_queue.clone(), command_buffer)
+                .unwrap()
+                .then_swapchain_present(
+                    render_device.present_queue.clone(),
+                    SwapchainPresentInfo::swapchain_image_index(
+                        render_output.swapchain.clone(),
+                        image_index,
+                    ),
+                )
+                .then_signal_fence_and_flush();
+
+            match future {
+                Ok(future) => {
+                    previous_frame_end = Some(future.boxed());
+                }
+                Err(FlushError::OutOfDate) => {
+                    recreate_swapchain = true;
+                    previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+                }
+                Err(e) => {
+                    println!("Failed to flush future: {:?}", e);
+                    previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+                }
+            }
+        }
+        _ => (),
+    });
+}
+
+fn window_size_dependent_setup(render_output: &RenderOutput, viewport: &mut Viewport) {
+    let dimensions = render_output.swapchain.image_extent();
+    viewport.dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+}
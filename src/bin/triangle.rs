@@ -1,137 +1,196 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Instant;
 
 use bytemuck::{Pod, Zeroable};
 use vulkano::{
     buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess},
     command_buffer::{
         allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
-        RenderingAttachmentInfo, RenderingInfo,
+        PrimaryCommandBufferAbstract, RenderingInfo,
     },
-    image::{ImageAccess, SwapchainImage, view::ImageView},
+    image::SampleCount,
     impl_vertex,
     pipeline::{
         graphics::{
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
             input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
             render_pass::PipelineRenderingCreateInfo,
             vertex_input::BuffersDefinition,
             viewport::{Viewport, ViewportState},
         },
-        GraphicsPipeline,
-    },
-    render_pass::{LoadOp, StoreOp},
-    swapchain::{
-        acquire_next_image, AcquireError, SwapchainCreateInfo, SwapchainCreationError,
-        SwapchainPresentInfo,
+        GraphicsPipeline, Pipeline, PipelineBindPoint, StateMode,
     },
+    swapchain::{acquire_next_image, AcquireError, SwapchainCreationError, SwapchainPresentInfo},
     sync::{self, FlushError, GpuFuture},
 };
-use vulkano_win::VkSurfaceBuild;
 use winit::{
-    event::{Event, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::WindowBuilder,
 };
+use glam::Vec3;
+use renderer::breadcrumbs::Breadcrumbs;
+use renderer::capture::{CaptureOutput, CaptureSettings, FrameCapture};
+use renderer::camera::{apply_view_set_layout, Camera, ViewUniforms, VIEW_SET};
+use renderer::destruction_queue::DestructionQueue;
+use renderer::gui::Gui;
+use renderer::instancing::InstanceBuffer;
+use renderer::recovery::{Recoverable, RenderContext};
 use renderer::render_device::RenderDevice;
 
-use renderer::render_output::RenderOutput;
+use renderer::post_process::{PostProcess, PostProcessSettings, Tonemapper, HDR_FORMAT};
+use renderer::render_output::{RenderOutput, RenderOutputCreateInfo};
 use renderer::render_system::RenderSystem;
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
 struct Vertex {
-    position: [f32; 2],
+    position: [f32; 3],
 }
 impl_vertex!(Vertex, position);
 
+/// Per-instance vertex data: where to put a copy of the triangle, and its color.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
+struct TriangleInstance {
+    offset: [f32; 3],
+    color: [f32; 3],
+}
+impl_vertex!(TriangleInstance, offset, color);
+
+/// A row of triangles, drawn with a single instanced draw.
+fn triangle_instances(time: f32) -> Vec<TriangleInstance> {
+    const COUNT: usize = 5;
+    (0..COUNT)
+        .map(|i| {
+            let x = i as f32 - (COUNT - 1) as f32 / 2.0;
+            let hue = i as f32 / COUNT as f32;
+            TriangleInstance {
+                offset: [x * 0.6, (time + x).sin() * 0.2, -x.abs() * 0.3],
+                color: [1.0, hue, 1.0 - hue],
+            }
+        })
+        .collect()
+}
+
+/// Everything the demo renders with, and the CPU-side data it is built from, so that it can be
+/// rebuilt after the device was lost.
+struct Scene {
+    vertices: [Vertex; 3],
+    depth_compare_op: CompareOp,
+    vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    instance_buffer: InstanceBuffer<TriangleInstance>,
+    pipeline: Arc<GraphicsPipeline>,
+    view_uniforms: ViewUniforms,
+    post_process: PostProcess,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    /// Tell which pass was running if the device is lost.
+    breadcrumbs: Breadcrumbs,
+}
+
+impl Scene {
+    fn new(
+        render_device: &RenderDevice,
+        render_output: &RenderOutput,
+        vertices: [Vertex; 3],
+        depth_compare_op: CompareOp,
+        post_process_settings: PostProcessSettings,
+    ) -> Self {
+        let command_buffer_allocator =
+            StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+
+        // The scene is rendered into an HDR target, which the post-processing chain then brings
+        // to the swapchain image. Its color grading LUT is uploaded once, up front.
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            render_device.present_queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        let post_process =
+            PostProcess::new(render_device, render_output, post_process_settings, &mut builder);
+        builder
+            .build()
+            .unwrap()
+            .execute(render_device.present_queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        Self {
+            vertices,
+            depth_compare_op,
+            vertex_buffer: create_vertex_buffer(render_device, vertices),
+            instance_buffer: InstanceBuffer::new(render_device),
+            pipeline: create_pipeline(render_device, render_output, depth_compare_op),
+            view_uniforms: ViewUniforms::new(render_device),
+            post_process,
+            command_buffer_allocator,
+            breadcrumbs: Breadcrumbs::new(render_device),
+        }
+    }
+}
+
+impl Recoverable for Scene {
+    fn recreate(&mut self, render_device: &RenderDevice, render_output: &RenderOutput) {
+        *self = Scene::new(
+            render_device,
+            render_output,
+            self.vertices,
+            self.depth_compare_op,
+            self.post_process.settings,
+        );
+    }
+}
+
 fn main() {
-    let render_system = RenderSystem::new();
+    // Lets the futures of a lost device drop, so that it can be recovered from.
+    let render_system = RenderSystem::new_loss_tolerant();
     let event_loop = EventLoop::new();
-    let surface = WindowBuilder::new()
-        .build_vk_surface(&event_loop, render_system.instance.clone())
-        .unwrap();
-    let render_device = RenderDevice::new(&render_system, &surface);
-    let mut render_output = RenderOutput::new(&render_system, &render_device, &surface);
+    let window = Arc::new(WindowBuilder::new().build(&event_loop).unwrap());
+    // Owns the device and the output, and replaces both if the device is lost.
+    let mut render_context = RenderContext::new(
+        &render_system,
+        window.clone(),
+        window.inner_size().into(),
+        RenderOutputCreateInfo {
+            samples: SampleCount::Sample4,
+            ..Default::default()
+        },
+    )
+    .unwrap();
 
     let vertices = [
         Vertex {
-            position: [-0.5, -0.25],
+            position: [-0.5, -0.25, 0.5],
         },
         Vertex {
-            position: [0.0, 0.5],
+            position: [0.0, 0.5, 0.5],
         },
         Vertex {
-            position: [0.25, -0.1],
+            position: [0.25, -0.1, 0.5],
         },
     ];
-    let vertex_buffer = CpuAccessibleBuffer::from_iter(
-        &render_device.memory_allocator,
-        BufferUsage {
-            vertex_buffer: true,
-            ..BufferUsage::empty()
-        },
-        false,
-        vertices,
-    )
-    .unwrap();
 
-    mod vs {
-        vulkano_shaders::shader! {
-            ty: "vertex",
-            src: "
-				#version 450
-
-				layout(location = 0) in vec2 position;
-
-				void main() {
-					gl_Position = vec4(position, 0.0, 1.0);
-				}
-			"
-        }
-    }
-
-    mod fs {
-        vulkano_shaders::shader! {
-            ty: "fragment",
-            src: "
-				#version 450
-
-				layout(location = 0) out vec4 f_color;
-
-				void main() {
-					f_color = vec4(1.0, 0.0, 0.0, 1.0);
-				}
-			"
-        }
-    }
-
-    let vs = vs::load(render_device.device.clone()).unwrap();
-    let fs = fs::load(render_device.device.clone()).unwrap();
-
-    let pipeline = GraphicsPipeline::start()
-        // We describe the formats of attachment images where the colors, depth and/or stencil
-        // information will be written. The pipeline will only be usable with this particular
-        // configuration of the attachment images.
-        .render_pass(PipelineRenderingCreateInfo {
-            // We specify a single color attachment that will be rendered to. When we begin
-            // rendering, we will specify a swapchain image to be used as this attachment, so here
-            // we set its format to be the same format as the swapchain.
-            color_attachment_formats: vec![Some(render_output.swapchain.image_format())],
-            ..Default::default()
-        })
-        // We need to indicate the layout of the vertices.
-        .vertex_input_state(BuffersDefinition::new().vertex::<Vertex>())
-        // The content of the vertex buffer describes a list of triangles.
-        .input_assembly_state(InputAssemblyState::new())
-        // A Vulkan shader can in theory contain multiple entry points, so we have to specify
-        // which one.
-        .vertex_shader(vs.entry_point("main").unwrap(), ())
-        // Use a resizable viewport set to draw over the entire window
-        .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
-        // See `vertex_shader`.
-        .fragment_shader(fs.entry_point("main").unwrap(), ())
-        // Now that our builder is filled, we call `build()` to obtain an actual pipeline.
-        .build(render_device.device.clone())
-        .unwrap();
+    let mut camera = Camera::perspective(std::f32::consts::FRAC_PI_3, 0.1, 100.0);
+    camera.reverse_z = true;
+    // Rebuilt by the render context on a new device if the device is lost.
+    let scene = Rc::new(RefCell::new(Scene::new(
+        render_context.render_device(),
+        render_context.render_output(),
+        vertices,
+        camera.depth_compare_op(),
+        PostProcessSettings::default(),
+    )));
+    render_context.register(&scene);
+    // Seconds since the start, advanced by a fixed step while capturing frames.
+    let mut time = 0.0;
+    let mut last_frame_time = Instant::now();
 
     // Dynamic viewports allow us to recreate just the viewport when the window is resized
     // Otherwise we would have to recreate the whole pipeline.
@@ -141,19 +200,9 @@ fn main() {
         depth_range: 0.0..1.0,
     };
 
-    // When creating the swapchain, we only created plain images. To use them as an attachment for
-    // rendering, we must wrap then in an image view.
-    //
-    // Since we need to draw to multiple images, we are going to create a different image view for
-    // each image.
-    let mut attachment_image_views =
-        window_size_dependent_setup(&render_output.images, &mut viewport);
-
-    // Before we can start creating and recording command buffers, we need a way of allocating
-    // them. Vulkano provides a command buffer allocator, which manages raw Vulkan command pools
-    // underneath and provides a safe interface for them.
-    let command_buffer_allocator =
-        StandardCommandBufferAllocator::new(render_device.device.clone(), Default::default());
+    // The render output wraps every swapchain image in an image view for us, and owns the
+    // multisampled and depth attachments that go with them.
+    window_size_dependent_setup(render_context.render_output(), &mut viewport);
 
     // Initialization is finally finished!
 
@@ -168,16 +217,32 @@ fn main() {
     // Here, we remember that we need to do this for the next loop iteration.
     let mut recreate_swapchain = false;
 
+    // The developer UI is drawn last, straight over the swapchain image.
+    let gui = Rc::new(RefCell::new(Gui::new(
+        &event_loop,
+        &window,
+        render_context.render_device(),
+        render_context.render_output(),
+    )));
+    render_context.register(&gui);
+
     // In the loop below we are going to submit commands to the GPU. Submitting a command produces
     // an object that implements the `GpuFuture` trait, which holds the resources for as long as
     // they are in use by the GPU.
     //
     // Destroying the `GpuFuture` blocks until the GPU is finished executing it. In order to avoid
     // that, we store the submission of the previous frame here.
-    let mut previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+    let mut previous_frame_end =
+        Some(sync::now(render_context.render_device().device.clone()).boxed());
+    // Resources replaced while frames that use them may still be in flight.
+    let mut destruction_queue = DestructionQueue::new();
+    // Frames being recorded to disk, started with `C`.
+    let mut capture: Option<FrameCapture> = None;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
+            // Input the UI uses, like typing into a text field, does not reach the app.
+            Event::WindowEvent { event, .. } if gui.borrow_mut().on_event(&event) => {}
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
@@ -190,27 +255,92 @@ fn main() {
             } => {
                 recreate_swapchain = true;
             }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => match key {
+                // Stream in a mirrored triangle. Frames in flight may still read the old vertex
+                // buffer, so it is retired rather than dropped.
+                VirtualKeyCode::Space => {
+                    let scene = &mut *scene.borrow_mut();
+                    for vertex in &mut scene.vertices {
+                        vertex.position[0] = -vertex.position[0];
+                    }
+                    let mirrored =
+                        create_vertex_buffer(render_context.render_device(), scene.vertices);
+                    destruction_queue.retire(std::mem::replace(&mut scene.vertex_buffer, mirrored));
+                }
+                // Record the next frames as a PNG sequence.
+                VirtualKeyCode::C if capture.is_none() => {
+                    let settings = CaptureSettings {
+                        output: CaptureOutput::PngSequence("capture".into()),
+                        frame_count: 120,
+                        frame_rate: 60,
+                    };
+                    match FrameCapture::new(render_context.render_output(), settings) {
+                        Ok(started) => capture = Some(started),
+                        Err(e) => println!("Cannot capture frames: {}", e),
+                    }
+                }
+                _ => toggle_post_process(&mut scene.borrow_mut().post_process.settings, key),
+            },
             Event::RedrawEventsCleared => {
+                if render_context.is_device_lost() {
+                    // Nothing submitted to the lost device runs anymore, so its futures and
+                    // retired resources are dropped without waiting. They hold the old
+                    // swapchain, which has to go before the window gets a new one.
+                    println!("{}", scene.borrow().breadcrumbs.report());
+                    previous_frame_end = None;
+                    destruction_queue.abandon();
+                    if capture.take().is_some() {
+                        println!("The capture stopped with the lost device");
+                    }
+                    // Rebuilds the scene and the gui, which are registered.
+                    if let Err(e) =
+                        render_context.recover(&render_system, window.inner_size().into(), &mut [])
+                    {
+                        panic!("Failed to recover from the lost device: {}", e);
+                    }
+                    window_size_dependent_setup(render_context.render_output(), &mut viewport);
+                    previous_frame_end =
+                        Some(sync::now(render_context.render_device().device.clone()).boxed());
+                }
+                let scene = &mut *scene.borrow_mut();
+                let mut gui = gui.borrow_mut();
+
                 previous_frame_end.as_mut().unwrap().cleanup_finished();
+                destruction_queue.collect();
+                if let Some(recording) = &mut capture {
+                    if recording.collect() == 0 && !recording.is_recording() {
+                        if let Err(e) = capture.take().unwrap().finish() {
+                            println!("Failed to capture frames: {}", e);
+                        }
+                    }
+                }
                 if recreate_swapchain {
+                    let (render_device, render_output) = render_context.parts_mut();
                     // Get the new dimensions of the window.
-                    let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
-
-                    let (new_swapchain, new_images) =
-                        match render_output.swapchain.recreate(SwapchainCreateInfo {
-                            image_extent: window.inner_size().into(),
-                            ..render_output.swapchain.create_info()
-                        }) {
-                            Ok(r) => r,
-                            Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
-                            Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
-                        };
-
-                    render_output.swapchain = new_swapchain;
-                    attachment_image_views =
-                        window_size_dependent_setup(&new_images, &mut viewport);
+                    match render_output.recreate(render_device, window.inner_size().into()) {
+                        Ok(()) => {}
+                        Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
+                        Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+                    };
+
+                    scene.post_process.resize(render_device, render_output);
+                    window_size_dependent_setup(render_output, &mut viewport);
                     recreate_swapchain = false;
                 }
+                let render_device = render_context.render_device();
+                let render_output = render_context.render_output();
 
                 let (image_index, suboptimal, acquire_future) =
                     match acquire_next_image(render_output.swapchain.clone(), None) {
@@ -219,38 +349,72 @@ fn main() {
                             recreate_swapchain = true;
                             return;
                         }
+                        Err(e) if render_context.report(&e) => return,
                         Err(e) => panic!("Failed to acquire next image: {:?}", e),
                     };
                 if suboptimal {
                     recreate_swapchain = true;
                 }
+
+                // Orbit the camera around the triangle.
+                let now = Instant::now();
+                time += match &capture {
+                    Some(capture) if capture.is_recording() => capture.delta_time(),
+                    _ => (now - last_frame_time).as_secs_f32(),
+                };
+                last_frame_time = now;
+                camera.position = Vec3::new(time.sin() * 2.0, 0.5, time.cos() * 2.0);
+                camera.look_at(Vec3::ZERO, Vec3::Y);
+                scene.view_uniforms.update(render_device, render_output, &camera, time);
+
+                gui.run(&window, |context| {
+                    post_process_window(context, &mut scene.post_process.settings)
+                });
+
+                let instances = scene.instance_buffer.upload(triangle_instances(time));
+
                 let mut builder = AutoCommandBufferBuilder::primary(
-                    &command_buffer_allocator,
+                    &scene.command_buffer_allocator,
                     render_device.present_queue.queue_family_index(),
                     CommandBufferUsage::OneTimeSubmit,
                 )
                 .unwrap();
 
+                scene.breadcrumbs.begin(&mut builder, "triangle");
                 builder
                     .begin_rendering(RenderingInfo {
-                        color_attachments: vec![Some(RenderingAttachmentInfo {
-                            load_op: LoadOp::Clear,
-                            store_op: StoreOp::Store,
-                            clear_value: Some([0.0, 0.0, 1.0, 1.0].into()),
-                            ..RenderingAttachmentInfo::image_view(
-                                attachment_image_views[image_index as usize].clone(),
-                            )
-                        })],
+                        color_attachments: vec![Some(
+                            scene.post_process.hdr_attachment([0.0, 0.0, 1.0, 1.0]),
+                        )],
+                        depth_attachment: Some(
+                            render_output.depth_attachment(camera.clear_depth()),
+                        ),
                         ..Default::default()
                     })
                     .unwrap()
                     .set_viewport(0, [viewport.clone()])
-                    .bind_pipeline_graphics(pipeline.clone())
-                    .bind_vertex_buffers(0, vertex_buffer.clone())
-                    .draw(vertex_buffer.len() as u32, 1, 0, 0)
+                    .bind_pipeline_graphics(scene.pipeline.clone())
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        scene.pipeline.layout().clone(),
+                        VIEW_SET,
+                        scene.view_uniforms.descriptor_set.clone(),
+                    )
+                    .bind_vertex_buffers(0, (scene.vertex_buffer.clone(), instances.clone()))
+                    .draw(scene.vertex_buffer.len() as u32, instances.len() as u32, 0, 0)
                     .unwrap()
                     .end_rendering()
                     .unwrap();
+                scene.breadcrumbs.end(&mut builder);
+                scene.breadcrumbs.pass(&mut builder, "post process", |builder| {
+                    scene.post_process.render(render_device, builder, render_output, image_index)
+                });
+                scene.breadcrumbs.pass(&mut builder, "gui", |builder| {
+                    gui.render(render_device, builder, render_output, image_index)
+                });
+                if let Some(capture) = &mut capture {
+                    capture.record(render_device, &mut builder, render_output, image_index);
+                }
                 let command_buffer = builder.build().unwrap();
 
                 let future = previous_frame_end
@@ -270,33 +434,200 @@ fn main() {
 
                 match future {
                     Ok(future) => {
+                        // vulkano implements `GpuFuture` for `Arc` of a fence future, not `Rc`.
+                        #[allow(clippy::arc_with_non_send_sync)]
+                        let future = Arc::new(future);
+                        destruction_queue.end_frame(future.clone());
+                        if let Some(capture) = &mut capture {
+                            capture.end_frame(future.clone());
+                        }
                         previous_frame_end = Some(future.boxed());
                     }
                     Err(FlushError::OutOfDate) => {
                         recreate_swapchain = true;
                         previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
                     }
+                    Err(e) if render_context.report(&e) => {
+                        previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
+                    }
                     Err(e) => {
                         println!("Failed to flush future: {:?}", e);
                         previous_frame_end = Some(sync::now(render_device.device.clone()).boxed());
                     }
                 }
             }
+            Event::LoopDestroyed => {
+                if let Some(Err(e)) = capture.take().map(FrameCapture::finish) {
+                    println!("Failed to capture frames: {}", e);
+                }
+                destruction_queue.flush();
+            }
             _ => (),
         }
     });
 }
 
+fn create_vertex_buffer(
+    render_device: &RenderDevice,
+    vertices: [Vertex; 3],
+) -> Arc<CpuAccessibleBuffer<[Vertex]>> {
+    CpuAccessibleBuffer::from_iter(
+        &render_device.memory_allocator,
+        BufferUsage {
+            vertex_buffer: true,
+            ..BufferUsage::empty()
+        },
+        false,
+        vertices,
+    )
+    .unwrap()
+}
+
+fn create_pipeline(
+    render_device: &RenderDevice,
+    render_output: &RenderOutput,
+    depth_compare_op: CompareOp,
+) -> Arc<GraphicsPipeline> {
+    mod vs {
+        vulkano_shaders::shader! {
+            ty: "vertex",
+            src: "
+				#version 450
+
+				layout(location = 0) in vec3 position;
+				layout(location = 1) in vec3 offset;
+				layout(location = 2) in vec3 color;
+
+				layout(location = 0) out vec3 v_color;
+
+				layout(set = 0, binding = 0) uniform ViewUniform {
+					mat4 view;
+					mat4 projection;
+					mat4 view_projection;
+					mat4 inverse_view;
+					mat4 inverse_projection;
+					vec4 position;
+					vec2 viewport_size;
+					float time;
+				} u_view;
+
+				void main() {
+					v_color = color;
+					gl_Position = u_view.view_projection * vec4(position + offset, 1.0);
+				}
+			"
+        }
+    }
+
+    mod fs {
+        vulkano_shaders::shader! {
+            ty: "fragment",
+            src: "
+				#version 450
+
+				layout(location = 0) in vec3 v_color;
+
+				layout(location = 0) out vec4 f_color;
+
+				void main() {
+					f_color = vec4(v_color, 1.0);
+				}
+			"
+        }
+    }
+
+    let vs = vs::load(render_device.device.clone()).unwrap();
+    let fs = fs::load(render_device.device.clone()).unwrap();
+
+    GraphicsPipeline::start()
+        // We describe the formats of attachment images where the colors, depth and/or stencil
+        // information will be written. The pipeline will only be usable with this particular
+        // configuration of the attachment images.
+        .render_pass(PipelineRenderingCreateInfo {
+            // We specify a single color attachment that will be rendered to. When we begin
+            // rendering, we will specify the HDR target of the post-processing chain to be used
+            // as this attachment, so here we set its format to be the HDR format.
+            color_attachment_formats: vec![Some(HDR_FORMAT)],
+            // The depth attachment is allocated by the render output alongside the swapchain.
+            depth_attachment_format: Some(render_output.depth_format),
+            ..Default::default()
+        })
+        // We need to indicate the layout of the vertices, and of the data that changes per
+        // instance of the triangle instead.
+        .vertex_input_state(
+            BuffersDefinition::new()
+                .vertex::<Vertex>()
+                .instance::<TriangleInstance>(),
+        )
+        // The content of the vertex buffer describes a list of triangles.
+        .input_assembly_state(InputAssemblyState::new())
+        // A Vulkan shader can in theory contain multiple entry points, so we have to specify
+        // which one.
+        .vertex_shader(vs.entry_point("main").unwrap(), ())
+        // Use a resizable viewport set to draw over the entire window
+        .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
+        // See `vertex_shader`.
+        .fragment_shader(fs.entry_point("main").unwrap(), ())
+        // Rasterize with as many samples as the render output's attachments have.
+        .multisample_state(MultisampleState {
+            rasterization_samples: render_output.samples,
+            ..Default::default()
+        })
+        // Keep the closest fragment and record its depth.
+        .depth_stencil_state(DepthStencilState {
+            depth: Some(DepthState {
+                enable_dynamic: false,
+                compare_op: StateMode::Fixed(depth_compare_op),
+                write_enable: StateMode::Fixed(true),
+            }),
+            ..Default::default()
+        })
+        // Now that our builder is filled, we build the pipeline, sharing the view set layout
+        // so that the per-view uniforms can be bound to it.
+        .with_auto_layout(render_device.device.clone(), apply_view_set_layout)
+        .unwrap()
+}
+
 /// This method is called once during initialization, then again whenever the window is resized
-fn window_size_dependent_setup(
-    images: &[Arc<SwapchainImage>],
-    viewport: &mut Viewport,
-) -> Vec<Arc<ImageView<SwapchainImage>>> {
-    let dimensions = images[0].dimensions().width_height();
+fn window_size_dependent_setup(render_output: &RenderOutput, viewport: &mut Viewport) {
+    let dimensions = render_output.swapchain.image_extent();
     viewport.dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+}
 
-    images
-        .iter()
-        .map(|image| ImageView::new_default(image.clone()).unwrap())
-        .collect::<Vec<_>>()
+/// Number keys 1 to 5 toggle exposure, tonemapping, bloom, color grading and FXAA. `T` switches
+/// between the ACES and AgX tonemappers.
+fn toggle_post_process(settings: &mut PostProcessSettings, key: VirtualKeyCode) {
+    match key {
+        VirtualKeyCode::Key1 => settings.exposure_enabled = !settings.exposure_enabled,
+        VirtualKeyCode::Key2 => settings.tonemapping_enabled = !settings.tonemapping_enabled,
+        VirtualKeyCode::Key3 => settings.bloom_enabled = !settings.bloom_enabled,
+        VirtualKeyCode::Key4 => settings.color_grading_enabled = !settings.color_grading_enabled,
+        VirtualKeyCode::Key5 => settings.fxaa_enabled = !settings.fxaa_enabled,
+        VirtualKeyCode::T => {
+            settings.tonemapper = match settings.tonemapper {
+                Tonemapper::Aces => Tonemapper::Agx,
+                Tonemapper::Agx => Tonemapper::Aces,
+            }
+        }
+        _ => return,
+    }
+    println!("{:?}", settings);
+}
+
+/// The post-processing settings, editable from the UI.
+fn post_process_window(context: &egui::Context, settings: &mut PostProcessSettings) {
+    egui::Window::new("Post-processing").show(context, |ui| {
+        ui.checkbox(&mut settings.exposure_enabled, "Exposure");
+        ui.add(egui::Slider::new(&mut settings.exposure, -4.0..=4.0).text("Stops"));
+        ui.checkbox(&mut settings.tonemapping_enabled, "Tonemapping");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut settings.tonemapper, Tonemapper::Aces, "ACES");
+            ui.radio_value(&mut settings.tonemapper, Tonemapper::Agx, "AgX");
+        });
+        ui.checkbox(&mut settings.bloom_enabled, "Bloom");
+        ui.add(egui::Slider::new(&mut settings.bloom_intensity, 0.0..=1.0).text("Intensity"));
+        ui.add(egui::Slider::new(&mut settings.bloom_threshold, 0.0..=4.0).text("Threshold"));
+        ui.checkbox(&mut settings.color_grading_enabled, "Color grading");
+        ui.checkbox(&mut settings.fxaa_enabled, "FXAA");
+    });
 }